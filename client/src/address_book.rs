@@ -0,0 +1,64 @@
+use std::fs;
+use std::io;
+use std::path::PathBuf;
+
+use serde::{Serialize, Deserialize};
+
+use crate::config::data_dir;
+use crate::constants::MAX_SAVED_SERVERS;
+
+
+
+/// A server the user has connected to before
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct SavedServer {
+    /// The address exactly as the user typed it so it can go through the same parsing again
+    pub address: String,
+    pub nickname: Option<String>,
+}
+
+/// Previously used servers, most recent first
+#[derive(Serialize, Deserialize, Debug, Default)]
+pub struct AddressBook {
+    pub servers: Vec<SavedServer>,
+}
+
+impl AddressBook {
+    fn path() -> Option<PathBuf> {
+        data_dir().map(|dir| dir.join("servers.json"))
+    }
+
+    /// Load the address book from the data directory. If it doesn't exist or can't be read, an
+    /// empty address book is returned since losing it isn't worth interrupting the user.
+    pub fn load() -> Self {
+        Self::path()
+            .and_then(|path| fs::read_to_string(path).ok())
+            .and_then(|contents| serde_json::from_str(&contents).ok())
+            .unwrap_or_default()
+    }
+
+    /// Write the address book to the data directory
+    pub fn save(&self) -> io::Result<()> {
+        let path = Self::path().ok_or_else(|| io::Error::new(
+            io::ErrorKind::NotFound,
+            "Unable to determine the data directory"
+        ))?;
+        if let Some(dir) = path.parent() {
+            fs::create_dir_all(dir)?;
+        }
+        fs::write(path, serde_json::to_string_pretty(self)?)
+    }
+
+    /// Move `address` to the front of the list, adding it if it's new. A new nickname replaces the
+    /// old one, but not giving one keeps whatever nickname it had before.
+    pub fn remember(&mut self, address: &str, nickname: Option<String>) {
+        let old = self.servers.iter()
+            .position(|s| s.address == address)
+            .map(|i| self.servers.remove(i));
+
+        let nickname = nickname.or_else(|| old.and_then(|s| s.nickname));
+
+        self.servers.insert(0, SavedServer { address: address.to_string(), nickname });
+        self.servers.truncate(MAX_SAVED_SERVERS);
+    }
+}
@@ -0,0 +1,55 @@
+// Attachments: reading a file to send, and keeping the ones that arrive
+
+use std::fs;
+use std::io;
+use std::path::{Path, PathBuf};
+
+use tcp_chat_proto::constants::MAX_ATTACHMENT_LEN;
+use tcp_chat_proto::helpers::unix_time_ms;
+use tcp_chat_proto::packet::Attachment;
+
+use crate::config::data_dir;
+use crate::helpers::safe_file_component;
+use crate::constants::ATTACHMENT_DIR_NAME;
+
+
+
+/// Read the file at `path` to attach, with its bytes in `data` ready to go out after the message
+pub fn prepare(path: &str) -> Result<Attachment, String> {
+    let name = Path::new(path).file_name()
+        .and_then(|name| name.to_str())
+        .unwrap_or_default()
+        .to_string();
+    let data = fs::read(path).map_err(|e| format!("Unable to read {path}: {e}"))?;
+    let attachment = Attachment { name, len: data.len() as u64, data };
+
+    if !attachment.has_valid_name() {
+        return Err(format!("{path} doesn't have a name it can be sent under"));
+    }
+    if attachment.data.is_empty() {
+        return Err(format!("{path} is empty"));
+    }
+    if attachment.data.len() > MAX_ATTACHMENT_LEN {
+        return Err(format!(
+            "{path} is too big to send ({} KiB, the most is {} KiB)",
+            attachment.data.len().div_ceil(1024),
+            MAX_ATTACHMENT_LEN / 1024
+        ));
+    }
+    Ok(attachment)
+}
+
+/// Save an attachment `from` sent, returning where it was saved. The time goes in front of the
+/// name, so a file sent twice doesn't overwrite the first one.
+pub fn save(from: &str, attachment: &Attachment) -> io::Result<PathBuf> {
+    let dir = data_dir()
+        .map(|dir| dir.join(ATTACHMENT_DIR_NAME))
+        .ok_or_else(|| io::Error::other("There's nowhere to save attachments"))?;
+    fs::create_dir_all(&dir)?;
+
+    let from = safe_file_component(from);
+    let name = if attachment.has_valid_name() { attachment.name.as_str() } else { "attachment" };
+    let path = dir.join(format!("{}-{from}-{name}", unix_time_ms()));
+    fs::write(&path, &attachment.data)?;
+    Ok(path)
+}
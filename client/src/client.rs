@@ -0,0 +1,1517 @@
+use std::{io, mem, panic, thread};
+use std::collections::HashMap;
+use std::sync::{Arc, Mutex, OnceLock};
+use std::sync::atomic::{AtomicBool, AtomicU64, Ordering};
+use std::net::SocketAddr;
+use std::process::exit;
+use std::thread::sleep;
+use std::time::{Duration, Instant};
+
+use chrono::{Local, TimeZone};
+
+use crate::commands::{parse_command, Command::*, CLIENT_COMMANDS, HOST_COMMANDS};
+use tcp_chat_proto::packet::{ConnectionChange, Presence, RoomInfo, ClientMessage::{self, *}, ServerMessage::{self, *}};
+use tcp_chat_proto::constants::*;
+use crate::constants::*;
+use tcp_chat_proto::tcp_conn::{CancelToken, RecvError, TcpConn, TcpConnBuilder};
+use tcp_chat_proto::typed_conn::{ClientConn, ClientReader, ClientWriter};
+use tcp_chat_proto::transport::{Address, Socket, QUIC_PREFIX};
+use tcp_chat_proto::local::LocalConnector;
+use tcp_chat_proto::codec::WireFormat;
+use tcp_chat_proto::capability::{Capabilities, Capability};
+use tcp_chat_proto::e2e::RoomKey;
+use tcp_chat_proto::noise::{fingerprint, Keypair};
+use tcp_chat_proto::packet::{Attachment, Limits, Sealed, VoiceClip};
+use tcp_chat_proto::helpers::{humanize_duration, unix_time_ms};
+use crate::helpers::{input, split_text, validate_yn, CmdResponse};
+use crate::result_repeat::UntilValid;
+use crate::address_book::AddressBook;
+use crate::known_servers::KnownServers;
+use crate::config::{ClientConfig, secret_store};
+use crate::display::{Display, Spoilers, reveal_spoiler};
+use crate::notify::Notifier;
+use crate::terminal;
+use crate::draft::Draft;
+use crate::presence::{Activity, watch_presence};
+use crate::tracker::browse;
+use crate::clock::Clock;
+use crate::sequence::{Arrival, Sequence};
+use crate::echo::Echoes;
+use crate::quote::{Quotes, quote_reply};
+use crate::json_output::{emit, JsonEvent};
+use crate::rooms::{Encryption, Room, Rooms};
+use crate::filter::{Filters, FilterAction};
+use crate::voice::VoiceClips;
+use crate::attachments;
+
+/// The halves of a connection to a server, for receiving from it and sending to it
+type ServerReceiver = ClientReader<WireFormat, Socket>;
+type ServerSender = ClientWriter<WireFormat, Socket>;
+
+/// Where a room was joined and how, so the connection can be picked back up if it drops
+struct Rejoin {
+    socket: Address,
+    options: TcpConnBuilder,
+    wire_format: WireFormat,
+    status: Option<String>,
+}
+
+/// Every room the client has joined, set up once so Ctrl-C can say goodbye to each of them instead
+/// of just vanishing
+static ROOMS: OnceLock<Arc<Rooms>> = OnceLock::new();
+
+/// Gives each room joined an id of its own
+static NEXT_ROOM_ID: AtomicU64 = AtomicU64::new(0);
+
+/// What every room is joined with
+struct JoinSettings<'a> {
+    name: &'a str,
+    config: &'a ClientConfig,
+    ansi: bool,
+    activity: Arc<Activity>,
+    filters: Arc<Filters>,
+    voice: Arc<VoiceClips>,
+}
+
+/// Ask the user to input a domain name or ip address, or pick one of the servers from the address
+/// book. Returns the address as it was typed, the nickname to save it under (if any), and where
+/// it resolved to.
+fn prompt_address(
+    book: &AddressBook,
+    tracker_url: Option<&str>
+) -> (String, Option<String>, Address) {
+
+    println!("Enter the address of the server, optionally followed by a nickname to save it as");
+
+    if !book.servers.is_empty() {
+        println!("Or pick a recent server:");
+        for (i, server) in book.servers.iter().enumerate() {
+            match &server.nickname {
+                Some(nickname) => println!("  {}) {nickname} ({})", i + 1, server.address),
+                None => println!("  {}) {}", i + 1, server.address),
+            }
+        }
+    }
+    if tracker_url.is_some() {
+        println!("Or enter 'b' to browse public rooms");
+    }
+
+    loop {
+        let unparsed_str = input();
+
+        let (address, nickname) = match unparsed_str.parse::<usize>() {
+            Ok(n) if (1..=book.servers.len()).contains(&n) => {
+                (book.servers[n - 1].address.clone(), None)
+            },
+            _ if unparsed_str == "b" && tracker_url.is_some() => {
+                match pick_public_room(tracker_url.unwrap()) {
+                    Some(address) => (address, None),
+                    None => {
+                        println!("Enter the address of the server");
+                        continue
+                    },
+                }
+            },
+            _ => match unparsed_str.split_once(' ') {
+                Some((address, nickname)) => {
+                    (address.to_string(), Some(nickname.trim().to_string()))
+                },
+                None => (unparsed_str, None),
+            },
+        };
+
+        let Some(sockets) = resolve_address(&address) else { continue };
+
+        // let the user see what they're getting into before joining
+        match query_info(&sockets) {
+            Ok(info) => {
+                let password = if info.password_required {", password required"} else {""};
+                let encrypted = if info.encrypted {", end-to-end encrypted"} else {""};
+                println!("Room '{}' ({} online{password}{encrypted})", info.room_name, info.user_count);
+                println!("Join this room? (y/n)");
+
+                if input.until_valid(validate_yn).is_yes() {
+                    break (address, nickname, sockets)
+                }
+                println!("Enter the address of the server");
+            },
+            // older servers don't know about info queries, so this isn't a reason not to join
+            Err(e) => {
+                println!("Unable to get room info ({e}), joining anyway");
+                break (address, nickname, sockets)
+            },
+        }
+    }
+}
+
+/// List the rooms on a tracker and let the user pick one, returning its address. Returns `None` if
+/// the tracker couldn't be reached, has no rooms, or the user backs out.
+fn pick_public_room(tracker_url: &str) -> Option<String> {
+    println!("Fetching public rooms...");
+
+    let mut rooms = match browse(tracker_url) {
+        Ok(rooms) => rooms,
+        Err(e) => {
+            println!("Unable to reach the tracker: {e}");
+            return None;
+        },
+    };
+
+    if rooms.is_empty() {
+        println!("There are no public rooms right now");
+        return None;
+    }
+
+    // closest rooms first, unreachable ones last
+    rooms.sort_by_key(|(_, latency)| latency.unwrap_or(Duration::MAX));
+
+    for (i, (room, latency)) in rooms.iter().enumerate() {
+        let latency = match latency {
+            Some(latency) => format!("{}ms", latency.as_millis()),
+            None => String::from("unreachable"),
+        };
+        println!("  {}) {} - {} online, {latency}", i + 1, room.name, room.user_count);
+    }
+    println!("Pick a room, or press enter to go back");
+
+    let choice = input().parse::<usize>().ok()?;
+    rooms.get(choice.checked_sub(1)?).map(|(room, _)| room.address.to_string())
+}
+
+/// Ask a server about its room without joining it
+fn query_info(addr: &Address) -> io::Result<RoomInfo> {
+    let mut conn = TcpConn::new(addr.connect()?)?;
+    conn.send(&ClientQueryInfo)?;
+
+    match conn.receive_timeout(INFO_QUERY_TIMEOUT)? {
+        ServerInfo(info) => Ok(info),
+        other => Err(io::Error::new(
+            io::ErrorKind::InvalidData,
+            format!("Expected room info, got {other:?}")
+        )),
+    }
+}
+
+/// Resolve a "host" or "host:port" string into socket addresses, using the default port if one
+/// isn't given. "unix:<path>" is the Unix socket at that path instead (a named pipe on Windows),
+/// and "quic:" in front of the rest is the same addresses over QUIC.
+fn resolve_address(unparsed_str: &str) -> Option<Address> {
+    if let Some(address) = Address::unix(unparsed_str) {
+        return Some(address);
+    }
+    if let Some(rest) = unparsed_str.strip_prefix(QUIC_PREFIX) {
+        return match resolve_address(rest)? {
+            Address::Tcp(addrs) => Some(Address::Quic(addrs)),
+            _ => None,
+        };
+    }
+
+    let temp: Vec<_> = unparsed_str.split(':').take(2).collect();
+
+    let &addr_str = temp.first()?;
+
+    let port: u16 = match temp.get(1) {
+        Some(&ps) => ps.parse().ok()?,
+        None => PORT,
+    };
+
+    let ips = dns_lookup::lookup_host(addr_str).ok()?;
+
+    Some(Address::Tcp(ips.iter().map(|&x| SocketAddr::new(x, port)).collect()))
+}
+
+/// Console interface for client. `host` is the room the user is hosting, which is joined without
+/// leaving the process. `address` skips asking the user which server to join. More rooms can be
+/// joined once in, see `rooms`.
+pub fn client(name: &str, host: Option<LocalConnector>, address: Option<&str>, config: ClientConfig) {
+    let is_host = host.is_some();
+
+    // Ask the user for the host address, unless they're the host
+    let mut book = AddressBook::load();
+    let (socket, to_remember) = if let Some(host) = host {
+        (Address::Local(host), None)
+    } else if let Some(address) = address {
+        let Some(socket) = resolve_address(address) else {
+            fail(config.json, &format!("Unable to resolve {address}"), EXIT_UNREACHABLE);
+        };
+        (socket, None)
+    } else {
+        let (address, nickname, socket) = prompt_address(&book, config.tracker_url.as_deref());
+        (socket, Some((address, nickname)))
+    };
+
+    // rooms are told apart, and their drafts kept, by the address the user gave
+    let label = match to_remember.as_ref().map(|(address, _)| address.as_str()).or(address) {
+        Some(address) => address.to_string(),
+        None => LOOPBACK_SOCKET.to_string(),
+    };
+
+    let rooms = Arc::clone(ROOMS.get_or_init(Arc::default));
+
+    // a crash shouldn't lose what the user was typing or leave the terminal in raw mode
+    let default_hook = panic::take_hook();
+    panic::set_hook(Box::new(move |info| {
+        if let Some(rooms) = ROOMS.get() {
+            rooms.save_drafts();
+        }
+        terminal::restore();
+        default_hook(info);
+    }));
+
+    let settings = JoinSettings {
+        name,
+        config: &config,
+        ansi: terminal::init() && config.color,
+        activity: Arc::new(Activity::new()),
+        filters: Arc::new(Filters::new(&config.filters)),
+        voice: Arc::new(VoiceClips::new(config.voice.clone())),
+    };
+
+    let first = match join_room(&rooms, label, socket, is_host, &settings) {
+        Ok(room) => room,
+        Err(e) => fail(config.json, &format!("Problem connecting to server: {e}"), EXIT_UNREACHABLE),
+    };
+
+    // only remember servers that were actually reachable
+    if let Some((address, nickname)) = to_remember {
+        book.remember(&address, nickname);
+        if let Err(e) = book.save() {
+            println!("[error] Unable to save the server to the address book: {e}");
+        }
+    }
+
+    if !first.draft.text().is_empty() && !config.json {
+        println!("Restored your unsent message from last time.");
+    }
+    drop(first);
+
+    // begin the messaging loop
+    loop {
+        // every room has closed, which exits the client, so this is only until that finishes
+        let Some(room) = rooms.active() else { interrupt() };
+
+        let raw_msg = read_message(&room.draft, &settings.activity);
+
+        // whatever was typed goes to the room it was typed in, even if another is switched to
+        let Room { conn, display, echoes, notifier, spoilers, quotes, is_host, is_operator, .. } = &*room;
+
+        if raw_msg.starts_with('!') {
+            match parse_command(&raw_msg, *is_host, is_operator.load(Ordering::Relaxed)) {
+                Some(cmd) => {
+                    match cmd {
+                        Help => {
+                            println!("Commands: {}", CLIENT_COMMANDS.join(", "));
+                        },
+                        HostHelp => {
+                            let list = [&CLIENT_COMMANDS[..], &HOST_COMMANDS[..]]
+                                .concat()
+                                .join(", ");
+                            println!("Commands: {}", list);
+                        },
+                        Exit if rooms.len() > 1 => leave_one(&rooms, &room),
+                        Exit => leave_all(&rooms),
+                        Shutdown => shut_down(&room),
+                        Join(address) => join_another(&rooms, &address, &settings),
+                        Switch(number) => match rooms.switch(number) {
+                            Some(room) => show_room(&room),
+                            None => println!("There is no room {number}, !rooms lists them"),
+                        },
+                        ListRooms => list_rooms(&rooms),
+                        FilterList => list_filters(&room.filters),
+                        FilterAdd(pattern, action) => match room.filters.add(&pattern, "", action) {
+                            Ok(()) => println!("Filtering messages matching `{pattern}` until you exit"),
+                            Err(e) => println!("[error] That pattern doesn't work: {e}"),
+                        },
+                        FilterRemove(number) => if room.filters.remove(number) {
+                            println!("Removed filter {number}");
+                        } else {
+                            println!("There is no filter {number}, !filter lists them");
+                        },
+                        // the name changes once the server says it has, see `ServerRenamed`
+                        Rename(new_name) => {
+                            send_command(&room, &ClientRename(new_name));
+                        },
+                        Kick(who) => {
+                            send_command(&room, &ClientKick(who));
+                        },
+                        Shadowban(who) => {
+                            send_command(&room, &ClientShadowban(who));
+                        },
+                        RequestIDs => {
+                            send_command(&room, &ClientRequestIDs);
+                        },
+                        Connections => {
+                            send_command(&room, &ClientRequestConnections);
+                        },
+                        Status(status) => {
+                            send_command(&room, &ClientSetStatus(status));
+                        },
+                        Seen(name) => {
+                            send_command(&room, &ClientSeen(name));
+                        },
+                        Report(who, reason) => {
+                            send_command(&room, &ClientReport(who, reason));
+                        },
+                        Urgent(text) => {
+                            for piece in split_text(&text, room.limits.lock().unwrap().max_message_len) {
+                                echoes.send(conn, &ClientUrgentText(piece.to_string()), piece);
+                            }
+                            continue;
+                        },
+                        Ephemeral(secs, text) => {
+                            for piece in split_text(&text, room.limits.lock().unwrap().max_message_len) {
+                                echoes.send(conn, &ClientEphemeralText(secs, piece.to_string()), piece);
+                            }
+                            continue;
+                        },
+                        Passphrase(passphrase) => unlock_room(&room, &passphrase),
+                        Voice(path) => match room.voice.prepare(path.as_deref()).and_then(|clip| check_voice(&room, clip)) {
+                            Ok(clip) => {
+                                let text = format!("[voice clip, {} KiB]", clip.data.len().div_ceil(1024));
+                                echoes.send(conn, &ClientVoice(clip), &text);
+                                continue;
+                            },
+                            Err(e) => println!("[error] {e}"),
+                        },
+                        Attach(path) => match attachments::prepare(&path).and_then(|attachment| check_attachment(&room, attachment)) {
+                            Ok(attachment) => send_attachment(&room, attachment),
+                            Err(e) => println!("[error] {e}"),
+                        },
+                        Play(number) => match room.voice.play(number) {
+                            Ok(text) => println!("{text}"),
+                            Err(e) => println!("[error] {e}"),
+                        },
+                        Reveal(number) => match reveal_spoiler(spoilers, number) {
+                            Some(text) => println!("Spoiler #{number}: {text}"),
+                            None => println!("There is no spoiler #{number}"),
+                        },
+                        Quote(id, reply) => match quote_reply(quotes, id, &reply) {
+                            Some(text) => {
+                                send_text(&room, &text);
+                                continue;
+                            },
+                            None => println!("There is no message #{id} to quote"),
+                        },
+                        More => display.show_earlier(),
+                        History(count) => request_history(&room, count.unwrap_or(HISTORY_PAGE)),
+                        Clear => display.clear(),
+                        Mute => if notifier.toggle_mute() {
+                            println!("Notification sounds muted. Urgent messages still ring.");
+                        } else {
+                            println!("Notification sounds unmuted.");
+                        },
+                    }
+                },
+                None => {
+                    println!("Invalid command, try !help for available commands.");
+                },
+            }
+
+
+            // whatever the command printed is now below any pending messages, and comes between
+            // messages that would otherwise be grouped
+            echoes.interrupt();
+            display.break_group();
+
+        } else {
+            send_text(&room, &raw_msg);
+        }
+    }
+}
+
+/// Read the next message from the user. A line starting a code block keeps reading lines until the
+/// closing fence. Exits the client if input is interrupted or runs out.
+fn read_message(draft: &Draft, activity: &Activity) -> String {
+    let mut msg = read_line_or_leave(draft, activity).trim().to_string();
+
+    // messages are echoed once they're sent, but commands and code being typed out aren't
+    if msg.starts_with('!') {
+        terminal::show_typed(&msg);
+    }
+
+    if msg.starts_with("```") && (msg.len() < 6 || !msg.ends_with("```")) {
+        terminal::show_typed(&msg);
+        loop {
+            // indentation matters in code, so these lines aren't trimmed
+            let line = read_line_or_leave(draft, activity);
+            terminal::show_typed(&line);
+            msg.push('\n');
+            msg.push_str(&line);
+
+            if line.trim_end().ends_with("```") {
+                break;
+            }
+        }
+    }
+
+    msg
+}
+
+fn read_line_or_leave(draft: &Draft, activity: &Activity) -> String {
+    match terminal::read_line(draft, activity) {
+        Ok(line) => line,
+        Err(e) if e.kind() == io::ErrorKind::Interrupted => interrupt(),
+        Err(e) if e.kind() == io::ErrorKind::UnexpectedEof => interrupt(),
+        Err(e) => {
+            println!("[error] Unable to read input: {e}");
+            leave(draft, EXIT_ERROR)
+        },
+    }
+}
+
+/// Leave `room` without exiting the client, moving on to another room if it was on the screen
+fn leave_one(rooms: &Rooms, room: &Room) {
+    room.left.store(true, Ordering::Relaxed);
+    say_goodbye(room);
+    stop_receiving(room);
+    room.draft.save();
+    println!("Left {}", room.label);
+
+    if let (_, Some(next)) = rooms.remove(room.id) {
+        show_room(&next);
+    }
+}
+
+/// Leave every room and exit the client. When this process hosts one of them, the room is kept
+/// running for everyone else instead of exiting, until the user interrupts again.
+fn leave_all(rooms: &Rooms) -> ! {
+    let all = rooms.all();
+    for room in &all {
+        room.left.store(true, Ordering::Relaxed);
+        say_goodbye(room);
+    }
+    // give time for message to send
+    sleep(Duration::from_secs(1));
+
+    // the threads receiving from each room are woken up and finish before the client exits. One
+    // that's partway through reconnecting may not notice until the server answers, so it's only
+    // waited on for so long.
+    for room in &all {
+        stop_receiving(room);
+    }
+    let deadline = Instant::now() + STOP_RECEIVING_TIMEOUT;
+    for room in &all {
+        let Some(receiving) = room.receiving.lock().unwrap().take() else {
+            continue;
+        };
+        while !receiving.is_finished() && Instant::now() < deadline {
+            sleep(Duration::from_millis(10));
+        }
+        if receiving.is_finished() {
+            let _ = receiving.join();
+        }
+    }
+
+    rooms.save_drafts();
+    terminal::restore();
+
+    // exiting would take the room down with the process, which is what !shutdown is for
+    if all.iter().any(|room| room.is_host) {
+        for room in &all {
+            rooms.remove(room.id);
+        }
+        println!("Still hosting the room for everyone else, press Ctrl-C to close it");
+        loop {
+            thread::park();
+        }
+    }
+    exit(EXIT_LEFT)
+}
+
+/// Wake up the thread receiving from `room` so it stops, whether it's waiting for a message or
+/// for its next try at reconnecting
+fn stop_receiving(room: &Room) {
+    room.cancel.cancel();
+    if let Some(receiving) = &*room.receiving.lock().unwrap() {
+        receiving.thread().unpark();
+    }
+}
+
+/// Let `room` know we're leaving, so we don't linger in the roster. The host leaving doesn't close
+/// the room, someone else stands in for them.
+fn say_goodbye(room: &Room) {
+    if room.conn.send(&ClientGoodbye).is_err() {
+        println!("[error] Failed to gracefully leave {}.", room.label)
+    }
+}
+
+/// Close the room `room` for everyone, for the host's `!shutdown`. The client leaves once the
+/// server says the room is closed, the same as everyone else.
+fn shut_down(room: &Room) {
+    if room.conn.send(&ClientShutdown).is_err() {
+        println!("[error] Failed to gracefully shutdown the server.");
+    }
+}
+
+/// `room` closed or dropped us, for `reason`. The client exits with `code` if it was the last room,
+/// otherwise it carries on in the others.
+fn room_closed(rooms: &Rooms, room: &Room, reason: &str, code: i32) {
+    // the user already left, so this is just the connection closing behind them
+    if room.left.swap(true, Ordering::Relaxed) {
+        return;
+    }
+
+    let (_, next) = rooms.remove(room.id);
+    if rooms.is_empty() {
+        room.display.print(reason);
+        leave(&room.draft, code);
+    }
+
+    room.draft.save();
+    // the room might not be the one on the screen, so this goes straight to it
+    terminal::print_line(&format!("{reason} ({})", room.label));
+
+    if let Some(next) = next {
+        show_room(&next);
+    }
+}
+
+/// Put `room` on the screen after switching to it, including whatever it received in the
+/// background
+fn show_room(room: &Room) {
+    terminal::print_line(&format!("--- {} ---", room.label));
+    room.display.catch_up();
+}
+
+/// Show every room joined for `!rooms`
+fn list_rooms(rooms: &Rooms) {
+    println!("Rooms:");
+    for (i, (label, active, unseen)) in rooms.list().into_iter().enumerate() {
+        let mut line = format!("  {}) {label}", i + 1);
+        if active {
+            line.push_str(" (here)");
+        } else if unseen > 0 {
+            line.push_str(&format!(" - {unseen} new"));
+        }
+        println!("{line}");
+    }
+}
+
+/// Show every filter in use for `!filter`
+fn list_filters(filters: &Filters) {
+    let list = filters.list();
+    if list.is_empty() {
+        println!("No filters, add one with !filter add <pattern>");
+        return;
+    }
+
+    println!("Filters:");
+    for (i, (pattern, from, action)) in list.into_iter().enumerate() {
+        let mut line = format!("  {}) `{pattern}`", i + 1);
+        if !from.is_empty() {
+            line.push_str(&format!(" from `{from}`"));
+        }
+        if action == FilterAction::Collapse {
+            line.push_str(" (collapsed)");
+        }
+        println!("{line}");
+    }
+}
+
+/// Join another room for `!join`, leaving it in the background until it's switched to
+fn join_another(rooms: &Arc<Rooms>, address: &str, settings: &JoinSettings) {
+    // events from different rooms would be mixed together with nothing to tell them apart
+    if settings.config.json {
+        emit(&JsonEvent::Error { message: String::from("Only one room can be joined with --json") });
+        return;
+    }
+
+    let Some(socket) = resolve_address(address) else {
+        println!("Unable to resolve {address}");
+        return;
+    };
+
+    match join_room(rooms, address.to_string(), socket, false, settings) {
+        Ok(_) => println!("Joined {address}, !switch {} to go there", rooms.len()),
+        Err(e) => println!("[error] Problem connecting to {address}: {e}"),
+    }
+}
+
+/// Exit the client as if the user had typed !exit in every room. Called on Ctrl-C, and safe to call
+/// before the client has joined a room, or after it's left them all, in which case it just exits.
+pub fn interrupt() -> ! {
+    match ROOMS.get() {
+        Some(rooms) if !rooms.is_empty() => leave_all(rooms),
+        _ => {
+            terminal::restore();
+            exit(EXIT_LEFT)
+        },
+    }
+}
+
+/// Exit the client with `code`, saving whatever hasn't been sent yet and putting the terminal back
+/// to normal
+fn leave(draft: &Draft, code: i32) -> ! {
+    draft.save();
+    terminal::restore();
+    exit(code)
+}
+
+/// Exit the client before it's joined the room, explaining why in whichever form output is in
+fn fail(json: bool, message: &str, code: i32) -> ! {
+    if json {
+        emit(&JsonEvent::Error { message: message.to_string() });
+    } else {
+        println!("[error] {message}");
+    }
+    exit(code)
+}
+
+/// Connect to the room at `socket` and say hello, adding it to `rooms`. The first room joined is
+/// the active one, later ones start out in the background.
+fn join_room(
+    rooms: &Arc<Rooms>,
+    label: String,
+    socket: Address,
+    is_host: bool,
+    settings: &JoinSettings
+) -> io::Result<Arc<Room>> {
+    let config = settings.config;
+
+    let spoilers = Spoilers::default();
+    let quotes = Quotes::default();
+    let clock = Arc::new(Clock::default());
+    let display = Display::new(
+        Arc::clone(&spoilers),
+        Arc::clone(&quotes),
+        Arc::clone(&clock),
+        config.accessible,
+        settings.ansi,
+        config.json
+    );
+
+    if !display.is_json() {
+        println!("Resolved addresses: {socket}");
+    }
+    let (mut receiver, conn, capabilities) = connect_to_server(&socket, &config.socket, config.wire_format, &label, &display)?;
+    let cancel = CancelToken::new();
+    receiver.get_mut().set_cancel_token(cancel.clone());
+
+    // send an initial message so the server can display who joined and keep track of name
+    conn.send(&ClientHello(settings.name.to_string()))?;
+
+    // timestamps are shown in server time, so find out how far off our clock is
+    if conn.send(&ClientSyncClock(unix_time_ms())).is_err() {
+        println!("[error] Unable to sync with the server's clock, times shown may be off");
+    }
+
+    if config.status.is_some() && conn.send(&ClientSetStatus(config.status.clone())).is_err() {
+        println!("[error] Unable to set your status");
+    }
+
+    let room = Arc::new(Room {
+        id: NEXT_ROOM_ID.fetch_add(1, Ordering::Relaxed),
+        draft: Draft::load(&label),
+        label,
+        is_host,
+        conn,
+        echoes: Arc::new(Echoes::new(display.clone())),
+        notifier: Arc::new(Notifier::new(config.notifications.clone(), settings.name)),
+        filters: Arc::clone(&settings.filters),
+        voice: Arc::clone(&settings.voice),
+        display,
+        clock,
+        spoilers,
+        quotes,
+        left: AtomicBool::new(false),
+        cancel,
+        receiving: Mutex::default(),
+        is_operator: AtomicBool::new(false),
+        history: Mutex::default(),
+        encryption: Mutex::default(),
+        limits: Mutex::default(),
+        capabilities: Mutex::new(capabilities),
+    });
+    rooms.add(Arc::clone(&room));
+
+    let receiving = Arc::clone(&room);
+    let rooms = Arc::clone(rooms);
+    let rejoin = Rejoin {
+        socket,
+        options: config.socket.clone(),
+        wire_format: config.wire_format,
+        status: config.status.clone(),
+    };
+    let thread = thread::Builder::new()
+        .name(String::from("client receive messages"))
+        .spawn(move || receive_messages(receiver, receiving, rooms, rejoin))
+        .unwrap();
+    *room.receiving.lock().unwrap() = Some(thread);
+
+    if let Some(mins) = config.away_after_mins {
+        let away_after = Duration::from_secs(mins * 60);
+        watch_presence(room.conn.clone(), Arc::clone(&settings.activity), away_after);
+    }
+
+    Ok(room)
+}
+
+/// Send a connection request to the specified server address. Returns the two halves of the
+/// connection: one for the thread receiving server messages, and one for sending. Both speak
+/// `wire_format` if the server agrees to it, or JSON otherwise. Also returns the capabilities the
+/// server agreed to.
+fn connect_to_server(
+    addr: &Address,
+    options: &TcpConnBuilder,
+    wire_format: WireFormat,
+    label: &str,
+    display: &Display
+) -> io::Result<(ServerReceiver, ServerSender, Capabilities)> {
+    let mut conn = options.build_socket(addr.connect_timeout(CONNECT_TIMEOUT)?)?;
+
+    if options.uses_noise() {
+        let key = conn.noise_connect(noise_keypair(display))?;
+        if KnownServers::verify(label, addr, &key)? && !display.is_json() {
+            display.print(&format!("{label} has the key {}, it will be checked from now on", fingerprint(&key)));
+        }
+    }
+
+    let mut conn = conn.into_codec(WireFormat::Json);
+    if wire_format != WireFormat::Json {
+        let offered = vec![wire_format.name().to_string(), WireFormat::Json.name().to_string()];
+        conn.send(&ClientWireFormats(offered))?;
+        let agreed = match conn.receive::<ServerMessage>()? {
+            ServerWireFormat(name) => WireFormat::from_name(&name).ok_or_else(|| io::Error::new(
+                io::ErrorKind::InvalidData,
+                format!("The server picked a wire format that wasn't offered ({name})")
+            ))?,
+            // turned away before getting that far, like for not using Noise
+            ServerText(_, reason) => return Err(io::Error::other(reason)),
+            other => return Err(io::Error::new(
+                io::ErrorKind::InvalidData,
+                format!("Expected the server to pick a wire format, got `{other:?}`")
+            )),
+        };
+        conn = conn.into_codec(agreed);
+    }
+
+    conn.send(&ClientCapabilities(Capabilities::all().names()))?;
+    let capabilities = match conn.receive::<ServerMessage>()? {
+        ServerHello(names) => Capabilities::from_names(&names),
+        ServerText(_, reason) => return Err(io::Error::other(reason)),
+        other => return Err(io::Error::new(
+            io::ErrorKind::InvalidData,
+            format!("Expected the server to agree to capabilities, got `{other:?}`")
+        )),
+    };
+
+    let (receiver, sender) = ClientConn::new(conn).split()?;
+    Ok((receiver, sender, capabilities))
+}
+
+/// The key this client makes Noise handshakes with, kept in the secret store so servers see the
+/// same one each time
+fn noise_keypair(display: &Display) -> &'static Keypair {
+    static KEYPAIR: OnceLock<Keypair> = OnceLock::new();
+    KEYPAIR.get_or_init(|| match Keypair::load_or_create(&secret_store(), NOISE_KEY_SECRET) {
+        Ok(keypair) => keypair,
+        Err(e) => {
+            let message = format!("Unable to load your Noise key, using a new one until you exit: {e}");
+            if display.is_json() {
+                emit(&JsonEvent::Error { message });
+            } else {
+                println!("[error] {message}");
+            }
+            Keypair::generate()
+        },
+    })
+}
+
+/// Receive messages from `room` and print them to the console window
+fn receive_messages(mut conn: ClientReader<WireFormat, Socket>, room: Arc<Room>, rooms: Arc<Rooms>, rejoin: Rejoin) {
+    let Room { conn: sender, display, clock, echoes, notifier, filters, voice, draft, history, is_operator, .. } = &*room;
+
+    // ephemeral messages that haven't expired yet, by id, so expiry can say whose message it was
+    let mut ephemerals = HashMap::<u64, String>::new();
+
+    let mut sequence = Sequence::default();
+
+    let mut last_heard = Instant::now();
+    let mut last_ping = Instant::now();
+
+    loop {
+        // looked at every time round, since reconnecting may have reached a different server
+        let capabilities = *room.capabilities.lock().unwrap();
+        let acks = capabilities.contains(Capability::Acks);
+        let heartbeats = capabilities.contains(Capability::Heartbeats);
+
+        if acks {
+            echoes.warn_overdue();
+        }
+
+        // the server drops anyone it doesn't hear from, so something goes out even while we're
+        // quiet. Failing to send shows up as the connection dropping soon enough.
+        if heartbeats && last_ping.elapsed() >= HEARTBEAT_INTERVAL {
+            let _ = sender.send(&ClientPing);
+            last_ping = Instant::now();
+        }
+
+        // nothing arriving for a while just means the room is quiet, unless not even the answers
+        // to our pings are coming back. Without heartbeats there's nothing to wake up for.
+        // big messages like history pages are deserialized as their chunks arrive
+        let received = if heartbeats {
+            conn.receive_streaming_timeout(HEARTBEAT_INTERVAL.saturating_sub(last_ping.elapsed()))
+        } else {
+            conn.receive_streaming()
+        };
+        let received = match received {
+            Err(RecvError::TimedOut) if !heartbeats || last_heard.elapsed() < HEARTBEAT_TIMEOUT => continue,
+            Err(RecvError::Cancelled) => return,
+            other => other,
+        };
+        if received.is_ok() {
+            last_heard = Instant::now();
+        }
+
+        // an attachment's bytes come on their own channel, sent right after it
+        let received = match received {
+            Ok(ServerAttachment(from, mut attachment)) => conn.receive_raw_on(ATTACHMENT_CHANNEL).map(|data| {
+                attachment.data = data;
+                ServerAttachment(from, attachment)
+            }),
+            other => other,
+        };
+
+        // a dropped connection is picked back up where it left off, unless the user is leaving
+        let received = match received {
+            Err(e) if connection_dropped(&e) && !room.left.load(Ordering::Relaxed) => {
+                match reconnect(&room, &rejoin, &mut sequence) {
+                    Some(receiver) => {
+                        conn = receiver;
+                        last_heard = Instant::now();
+                        continue;
+                    },
+                    None => Err(e),
+                }
+            },
+            other => other,
+        };
+
+        // broadcasts carry the id they can be quoted by
+        let (id, received) = match received {
+            Ok(ServerSequenced(seq, msg)) => match sequence.arrive(seq) {
+                Arrival::New => (Some(seq), Ok(*msg)),
+                Arrival::Skipped { first, last, lost } => {
+                    request_backfill(sender, first, last, lost);
+                    (Some(seq), Ok(*msg))
+                },
+                Arrival::Duplicate => continue,
+            },
+            // our own broadcasts aren't sent back, but still need to be counted
+            Ok(ServerSent(seq)) => {
+                history.lock().unwrap().saw(seq);
+                match sequence.arrive(seq) {
+                    Arrival::New => echoes.confirm(acks),
+                    Arrival::Skipped { first, last, lost } => {
+                        request_backfill(sender, first, last, lost);
+                        echoes.confirm(acks);
+                    },
+                    // the number isn't new, but it still acknowledges one of our messages
+                    Arrival::Duplicate => echoes.confirm(acks),
+                }
+                if display.is_json() {
+                    emit(&JsonEvent::Sent { id: seq });
+                }
+                continue;
+            },
+            Ok(ServerAck(id)) => {
+                echoes.acknowledge(id);
+                continue;
+            },
+            // only there to show the server is still around
+            Ok(ServerPong) => continue,
+            // asked for with !history, so it's shown however the rest of the room is
+            Ok(ServerHistoryPage(page)) => {
+                let complete = {
+                    let mut cursor = history.lock().unwrap();
+                    if let Some(first) = page.first() {
+                        cursor.saw(first.id);
+                    }
+                    // asking for more than a page's worth still only gets a page
+                    page.len() < cursor.requested.min(MAX_HISTORY_PAGE) as usize
+                };
+                if display.is_json() {
+                    emit(&JsonEvent::History { messages: page, complete });
+                } else {
+                    display.history(&page, complete);
+                }
+                continue;
+            },
+            other => (None, other),
+        };
+
+        if let Some(id) = id {
+            history.lock().unwrap().saw(id);
+        }
+
+        // checked before sending from now on, rather than finding out when it's refused
+        if let Ok(ServerLimits(limits)) = received {
+            *room.limits.lock().unwrap() = limits;
+            continue;
+        }
+
+        // the name we joined under, which the server may have tidied up from what was typed
+        if let Ok(ServerWelcome(name)) = &received {
+            notifier.set_name(name);
+            continue;
+        }
+
+        // a rename of our own only counts once the server has gone along with it, and as it
+        // tidied the name up, since that's the name what we seal has to be opened under
+        if let Ok(ServerRenamed(old_name, new_name)) = &received {
+            if *old_name == notifier.name() {
+                notifier.set_name(new_name);
+            }
+        }
+
+        if let Ok(ServerOperator(operator)) = received {
+            is_operator.store(operator, Ordering::Relaxed);
+            if display.is_json() {
+                emit(&JsonEvent::Operator { operator });
+            } else if operator {
+                display.print("You're the operator while the host is gone: !kick, !ids, !shadowban, and !connections work for you now");
+            } else {
+                display.print("You're no longer the operator");
+            }
+            continue;
+        }
+
+        // sealed messages are opened here, so from now on they're like any other
+        let received = match received {
+            Ok(ServerSealedText(name, sealed)) => {
+                let text = open_sealed(&room, &name, &sealed);
+                Ok(ServerText(name, text))
+            },
+            Ok(ServerEncrypted(salt)) => {
+                room_encrypted(&room, salt);
+                continue;
+            },
+            other => other,
+        };
+
+        if display.is_json() {
+            receive_json(id, received, clock, voice, draft);
+            continue;
+        }
+
+        // filtered messages are dealt with before they're shown or make any noise
+        let filtered = match &received {
+            Ok(ServerText(name, text) | ServerDirectText(name, text) | ServerEphemeralText(_, name, text, _)) => {
+                Some((name, text.as_str()))
+            },
+            Ok(ServerVoice(name, _)) => Some((name, "[voice clip]")),
+            Ok(ServerAttachment(name, attachment)) => Some((name, attachment.name.as_str())),
+            _ => None,
+        };
+        if let Some((name, text)) = filtered {
+            let action = (name != "[server]").then(|| filters.check(name, text)).flatten();
+            match action {
+                Some(FilterAction::Hide) => continue,
+                Some(FilterAction::Collapse) => {
+                    display.print(&format!("[a message from {name} was collapsed by a filter]"));
+                    display.break_group();
+                    echoes.interrupt();
+                    continue;
+                },
+                None => {},
+            }
+        }
+
+        match received {
+            Ok(ServerText(name, text)) => {
+                // server notices aren't worth quoting or making noise over
+                if name == "[server]" {
+                    display.print(&display.chat(None, &name, &text));
+                } else {
+                    display.print(&display.chat(id, &name, &text));
+                    notifier.message(&text);
+                }
+            },
+            Ok(ServerUrgentText(name, text)) => display.print(&display.urgent(id, &name, &text)),
+            Ok(ServerDirectText(name, text)) => {
+                display.print(&display.direct(&name, &text));
+                notifier.direct();
+            },
+            Ok(ServerEphemeralText(id, name, text, secs)) => {
+                display.print(&display.ephemeral(&name, &text, secs));
+                notifier.message(&text);
+                ephemerals.insert(id, name);
+            },
+            Ok(ServerVoice(name, clip)) => {
+                // clips recovered from the history come without their audio
+                let text = if clip.data.is_empty() {
+                    String::from("[sent a voice clip that's no longer available]")
+                } else {
+                    match voice.save(&name, &clip) {
+                        Ok((number, _)) => {
+                            format!("[voice clip {number}, {} KiB, !play {number} to listen]", clip.data.len().div_ceil(1024))
+                        },
+                        Err(e) => format!("[sent a voice clip that couldn't be saved: {e}]"),
+                    }
+                };
+                display.print(&display.chat(id, &name, &text));
+                notifier.message(&text);
+            },
+            Ok(ServerAttachment(name, attachment)) => {
+                let size = attachment.data.len().div_ceil(1024);
+                let text = match attachments::save(&name, &attachment) {
+                    Ok(path) => format!("[attached {} ({size} KiB), saved to {}]", attachment.name, path.display()),
+                    Err(e) => format!("[attached {} ({size} KiB), which couldn't be saved: {e}]", attachment.name),
+                };
+                display.print(&display.chat(None, &name, &text));
+                notifier.message(&text);
+            },
+            Ok(ServerRenamed(old_name, new_name)) => {
+                let text = format!("{old_name} is now known as {new_name}");
+                display.print(&display.chat(None, "[server]", &text));
+            },
+            Ok(ServerExpire(id)) => {
+                if let Some(name) = ephemerals.remove(&id) {
+                    display.print(&format!("[a message from {name} has expired]"));
+                    display.break_group();
+                }
+            },
+            Ok(ServerClock(sent, server_time)) => clock.sync(sent, server_time),
+            Ok(ServerRejected(reason)) => echoes.reject(&reason),
+            Ok(ServerShutdown) if room.is_host => {
+                return room_closed(&rooms, &room, "You closed the room", EXIT_LEFT);
+            },
+            Ok(ServerShutdown) => {
+                return room_closed(&rooms, &room, "The host has closed the room", EXIT_ROOM_CLOSED);
+            },
+            Ok(ServerResponseIDs(roster)) => {
+                display.break_group();
+                terminal::print_line("In the room:");
+                for entry in roster {
+                    let mut line = format!("  {} (id {})", entry.name, entry.id);
+
+                    if entry.presence == Presence::Away {
+                        line.push_str(" (away)");
+                    }
+                    if entry.idle_secs >= IDLE_DISPLAY_SECS {
+                        let idle = humanize_duration(Duration::from_secs(entry.idle_secs));
+                        line.push_str(&format!(" [idle {idle}]"));
+                    }
+                    if let Some(address) = entry.address {
+                        line.push_str(&format!(" from {}", describe_address(address, entry.hostname.as_deref())));
+                    }
+                    if let Some(status) = entry.status {
+                        line.push_str(&format!(" - {status}"));
+                    }
+
+                    terminal::print_line(&line);
+                }
+            },
+            Ok(ServerConnections(events)) => {
+                display.break_group();
+                if events.is_empty() {
+                    terminal::print_line("Nobody has connected yet");
+                }
+                for event in events {
+                    let time = Local.timestamp_opt(event.time as i64, 0)
+                        .single()
+                        .map(|time| time.format("%b %-d %H:%M:%S").to_string())
+                        .unwrap_or_default();
+                    let what = match event.change {
+                        ConnectionChange::Connected => "connected",
+                        ConnectionChange::Disconnected => "disconnected",
+                        ConnectionChange::Kicked => "was kicked",
+                        ConnectionChange::Reported => "was reported",
+                    };
+                    let mut line = format!(
+                        "  [{time}] {} (id {}) from {} {what}",
+                        event.name,
+                        event.id,
+                        describe_address(event.address, event.hostname.as_deref())
+                    );
+                    if let Some(detail) = event.detail {
+                        line.push_str(&format!(" {detail}"));
+                    }
+                    terminal::print_line(&line);
+                }
+            },
+            Ok(ServerReport(report)) => {
+                display.break_group();
+                terminal::print_line(&format!(
+                    "[report] {} reported {} (id {}): {}", report.reporter, report.name, report.id, report.reason
+                ));
+                if report.recent.is_empty() {
+                    terminal::print_line("  They haven't said anything recently");
+                } else {
+                    terminal::print_line("  Their latest messages:");
+                }
+                for entry in report.recent {
+                    let time = Local.timestamp_opt(entry.time as i64, 0)
+                        .single()
+                        .map(|time| time.format("%H:%M:%S").to_string())
+                        .unwrap_or_default();
+                    terminal::print_line(&format!("    [{time}] #{} {}", entry.id, entry.text));
+                }
+                notifier.direct();
+            },
+            Ok(ServerNotifyKick) => {
+                return room_closed(&rooms, &room, "The host has kicked you", EXIT_KICKED);
+            }
+            Ok(other) => {
+                terminal::print_line(&format!("Some other message was received: {:?}", other));
+                display.break_group();
+            },
+            Err(e) => {
+                let reason = format!("[error] Connection to server lost. Reason: {e}");
+                return room_closed(&rooms, &room, &reason, EXIT_ERROR);
+            }
+        }
+
+        echoes.interrupt();
+    }
+}
+
+/// Where someone is connecting from, with the address's name first if the server found one
+fn describe_address(address: SocketAddr, hostname: Option<&str>) -> String {
+    match hostname {
+        Some(hostname) => format!("{hostname} ({address})"),
+        None => address.to_string(),
+    }
+}
+
+/// Whether `e` means the connection to the server went away, as opposed to it sending something
+/// that didn't make sense. An attachment that stops arriving partway through counts, since the
+/// server never leaves one unfinished.
+fn connection_dropped(e: &RecvError) -> bool {
+    e.is_disconnect() || matches!(e, RecvError::TimedOut)
+}
+
+/// Try to get back into `room` after its connection dropped, waiting longer between each try.
+/// Once back in, the room sends on the new connection, and every broadcast from where `sequence`
+/// left off is asked for again. The ones that had already arrived are dropped as duplicates when
+/// they come back. Returns the new connection to receive on, or `None` after giving up.
+fn reconnect(room: &Room, rejoin: &Rejoin, sequence: &mut Sequence) -> Option<ClientReader<WireFormat, Socket>> {
+    let display = &room.display;
+    if display.is_json() {
+        emit(&JsonEvent::Reconnecting);
+    } else {
+        display.print("[error] Lost the connection to the server, trying to reconnect...");
+    }
+
+    let mut delay = RECONNECT_DELAY;
+    for attempt in 1..=RECONNECT_ATTEMPTS {
+        // leaving wakes this up early, see `stop_receiving`
+        let retry_at = Instant::now() + delay;
+        while !room.cancel.is_cancelled() && Instant::now() < retry_at {
+            thread::park_timeout(retry_at.saturating_duration_since(Instant::now()));
+        }
+        delay *= 2;
+
+        // the user may have given up and left meanwhile
+        if room.left.load(Ordering::Relaxed) || room.cancel.is_cancelled() {
+            return None;
+        }
+
+        let (receiver, sender) = match rejoin_room(room, rejoin) {
+            Ok(conns) => conns,
+            Err(e) => {
+                if !display.is_json() {
+                    display.print(&format!("[error] Reconnecting failed ({attempt}/{RECONNECT_ATTEMPTS}): {e}"));
+                }
+                continue;
+            },
+        };
+
+        if let Some(from) = sequence.resume() {
+            if sender.send(&ClientBackfill(from, u64::MAX)).is_err() && !display.is_json() {
+                display.print("[error] Unable to ask for the messages missed while disconnected");
+            }
+        }
+        room.conn.replace(&sender);
+
+        // the server sees a new client, which is only the operator if it says so again
+        room.is_operator.store(false, Ordering::Relaxed);
+
+        if display.is_json() {
+            emit(&JsonEvent::Reconnected);
+        } else {
+            display.print("Reconnected, catching up on anything missed");
+        }
+        return Some(receiver);
+    }
+    None
+}
+
+/// Join `room` again on a new connection, as whatever the user is called now, returning the
+/// halves to receive and send on once the server lets us in
+fn rejoin_room(room: &Room, rejoin: &Rejoin) -> io::Result<(ServerReceiver, ServerSender)> {
+    let (mut receiver, sender, capabilities) = connect_to_server(&rejoin.socket, &rejoin.options, rejoin.wire_format, &room.label, &room.display)?;
+    receiver.get_mut().set_cancel_token(room.cancel.clone());
+
+    // the server may have been updated while we were away
+    *room.capabilities.lock().unwrap() = capabilities;
+
+    // the server may have let us in before something went wrong, so it's told we've gone rather
+    // than keeping a copy of us around that the next attempt would clash with
+    match say_hello_again(room, rejoin, &mut receiver, &sender, capabilities) {
+        Ok(()) => Ok((receiver, sender)),
+        Err(e) => {
+            let _ = sender.send(&ClientGoodbye);
+            Err(e)
+        },
+    }
+}
+
+/// Say hello on a new connection to `room` and wait to be let in, picking up the name the server
+/// let us in under
+fn say_hello_again(
+    room: &Room,
+    rejoin: &Rejoin,
+    receiver: &mut ServerReceiver,
+    sender: &ServerSender,
+    capabilities: Capabilities
+) -> io::Result<()> {
+    sender.send(&ClientHello(room.notifier.name()))?;
+
+    // the server answers with our name, or why it turned us away (like the old connection still
+    // holding the name). Servers from before `ServerWelcome` only send the join notice.
+    match receiver.receive()? {
+        ServerWelcome(name) => {
+            room.notifier.set_name(&name);
+            // the join notice comes next, which the user doesn't need to see again
+            receiver.receive()?;
+        },
+        ServerText(_, text) if !capabilities.contains(Capability::Welcome) && text.ends_with(" has joined the room!") => {},
+        ServerText(_, reason) => return Err(io::Error::other(reason)),
+        other => return Err(io::Error::other(format!("Unexpected reply from the server: {other:?}"))),
+    }
+
+    sender.send(&ClientSyncClock(unix_time_ms()))?;
+    if rejoin.status.is_some() {
+        sender.send(&ClientSetStatus(rejoin.status.clone()))?;
+    }
+    Ok(())
+}
+
+/// Handle a message from the server in `--json` mode, where everything is printed as it arrived
+/// for scripts to make sense of. `id` is the message's sequence number if it was a broadcast.
+fn receive_json(
+    id: Option<u64>,
+    received: Result<ServerMessage, RecvError>,
+    clock: &Clock,
+    voice: &VoiceClips,
+    draft: &Draft
+) {
+    let time = clock.now().timestamp();
+
+    let event = match received {
+        Ok(ServerText(name, text)) if name == "[server]" => JsonEvent::Notice { time, text },
+        Ok(ServerText(from, text)) => JsonEvent::Message { id, time, from, text },
+        Ok(ServerUrgentText(from, text)) => JsonEvent::Urgent { id, time, from, text },
+        Ok(ServerDirectText(from, text)) => JsonEvent::Direct { time, from, text },
+        Ok(ServerVoice(from, clip)) => {
+            // clips recovered from the history come without their audio
+            let saved = (!clip.data.is_empty()).then(|| voice.save(&from, &clip));
+            let path = match saved {
+                Some(Ok((_, path))) => Some(path),
+                Some(Err(e)) => {
+                    emit(&JsonEvent::Error { message: format!("Unable to save a voice clip: {e}") });
+                    None
+                },
+                None => None,
+            };
+            JsonEvent::Voice { id, time, from, format: clip.format, path }
+        },
+        Ok(ServerEphemeralText(ephemeral_id, from, text, secs)) => {
+            JsonEvent::Ephemeral { ephemeral_id, time, from, text, secs }
+        },
+        Ok(ServerExpire(ephemeral_id)) => JsonEvent::Expired { ephemeral_id },
+        Ok(ServerAttachment(from, attachment)) => {
+            let path = match attachments::save(&from, &attachment) {
+                Ok(path) => Some(path),
+                Err(e) => {
+                    emit(&JsonEvent::Error { message: format!("Unable to save an attachment: {e}") });
+                    None
+                },
+            };
+            JsonEvent::Attachment { time, from, name: attachment.name, path }
+        },
+        Ok(ServerRenamed(from, to)) => JsonEvent::Renamed { time, from, to },
+        Ok(ServerRejected(reason)) => JsonEvent::Rejected { reason },
+        Ok(ServerResponseIDs(clients)) => JsonEvent::Roster { clients },
+        Ok(ServerConnections(events)) => JsonEvent::Connections { events },
+        Ok(ServerReport(report)) => JsonEvent::Report { report },
+        Ok(ServerClock(sent, server_time)) => return clock.sync(sent, server_time),
+        Ok(ServerShutdown) => {
+            emit(&JsonEvent::Closed);
+            leave(draft, EXIT_ROOM_CLOSED);
+        },
+        Ok(ServerNotifyKick) => {
+            emit(&JsonEvent::Kicked);
+            leave(draft, EXIT_KICKED);
+        },
+        // nothing else is sent to a client that's joined
+        Ok(_) => return,
+        Err(e) => {
+            emit(&JsonEvent::Error { message: format!("Connection to server lost: {e}") });
+            leave(draft, EXIT_ERROR);
+        },
+    };
+
+    emit(&event);
+}
+
+/// Ask for the `limit` messages before the earliest one seen in `room`, or the latest ones if none
+/// have been yet
+fn request_history(room: &Room, limit: u32) {
+    let before_id = {
+        let mut cursor = room.history.lock().unwrap();
+        cursor.requested = limit;
+        cursor.oldest
+    };
+    send_command(room, &ClientRequestHistory { before_id, limit });
+}
+
+/// Send a command's message to the server. Failing to is only reported, since the connection may
+/// just be down while it's picked back up.
+fn send_command(room: &Room, msg: &ClientMessage) {
+    let Err(e) = room.conn.send(msg) else {
+        return;
+    };
+    if room.display.is_json() {
+        emit(&JsonEvent::Error { message: format!("Unable to send command: {e}") });
+    } else {
+        terminal::print_line(&format!("[error] Could not send that to the server: {e}"));
+    }
+}
+
+/// Send `attachment` to `room`, its bytes in a raw frame on `ATTACHMENT_CHANNEL` right behind the
+/// message announcing it
+fn send_attachment(room: &Room, mut attachment: Attachment) {
+    // the bytes are never part of the message, so they're only needed for the raw frame
+    let data = mem::take(&mut attachment.data);
+    let name = attachment.name.clone();
+    match room.conn.send_with_raw(&ClientAttachment(attachment), ATTACHMENT_CHANNEL, &data) {
+        Ok(()) => println!("Sent {name} ({} KiB)", data.len().div_ceil(1024)),
+        Err(e) => terminal::print_line(&format!("[error] Could not send that to the server: {e}")),
+    }
+}
+
+/// Send `text` to `room` as a regular message, sealed if the room is end-to-end encrypted. Text
+/// longer than the room accepts goes as several messages.
+fn send_text(room: &Room, text: &str) {
+    let max_len = room.limits.lock().unwrap().max_message_len;
+    for piece in split_text(text, max_len) {
+        let msg = match &room.encryption.lock().unwrap().key {
+            Some(key) => ClientSealedText(key.seal(&room.notifier.name(), piece)),
+            // an encrypted room refuses this with a reason, rather than it going out in the clear
+            None if room.capabilities.lock().unwrap().contains(Capability::Acks) => {
+                ClientTrackedText(room.echoes.next_id(), piece.to_string())
+            },
+            None => ClientText(piece.to_string()),
+        };
+        room.echoes.send(&room.conn, &msg, piece);
+    }
+}
+
+/// Pass `clip` on if `room` accepts it, otherwise say why it wouldn't
+fn check_voice(room: &Room, clip: VoiceClip) -> Result<VoiceClip, String> {
+    let Limits { max_voice_len, .. } = *room.limits.lock().unwrap();
+    if max_voice_len == 0 {
+        return Err(String::from("Voice clips aren't allowed in this room"));
+    }
+    if clip.data.len() > max_voice_len {
+        return Err(format!(
+            "Voice clip is too big for this room ({} KiB, the limit is {} KiB). Try a shorter one.",
+            clip.data.len().div_ceil(1024),
+            max_voice_len / 1024
+        ));
+    }
+    Ok(clip)
+}
+
+/// Pass `attachment` on if `room` accepts it, otherwise say why it wouldn't
+fn check_attachment(room: &Room, attachment: Attachment) -> Result<Attachment, String> {
+    let Limits { max_attachment_len, .. } = *room.limits.lock().unwrap();
+    if max_attachment_len == 0 {
+        return Err(String::from("Attachments aren't allowed in this room"));
+    }
+    if attachment.data.len() > max_attachment_len {
+        return Err(format!(
+            "{} is too big to attach in this room ({} KiB, the limit is {} KiB)",
+            attachment.name,
+            attachment.data.len().div_ceil(1024),
+            max_attachment_len / 1024
+        ));
+    }
+    Ok(attachment)
+}
+
+/// The text of a message `sender` sealed, or why it can't be read
+fn open_sealed(room: &Room, sender: &str, sealed: &Sealed) -> String {
+    match &room.encryption.lock().unwrap().key {
+        Some(key) => key.open(sender, sealed)
+            .unwrap_or_else(|| String::from("[this message couldn't be decrypted, check the room's passphrase]")),
+        None => String::from("[encrypted message, enter the room's passphrase with !passphrase to read it]"),
+    }
+}
+
+/// The server said `room` is end-to-end encrypted with `salt`. The key is derived from the
+/// passphrase saved for the room, if there is one, otherwise the user has to give it.
+fn room_encrypted(room: &Room, salt: Vec<u8>) {
+    let mut encryption = room.encryption.lock().unwrap();
+    // reconnecting is told again, but what was already entered still works
+    if encryption.key.is_some() && encryption.salt.as_ref() == Some(&salt) {
+        return;
+    }
+
+    let secret = format!("{PASSPHRASE_SECRET_PREFIX}{}", room.label);
+    let key = match secret_store().get(&secret) {
+        Ok(passphrase) => passphrase.and_then(|passphrase| RoomKey::derive(&passphrase, &salt).ok()),
+        Err(e) => {
+            let message = format!("Unable to look up the room's passphrase: {e}");
+            if room.display.is_json() {
+                emit(&JsonEvent::Error { message });
+            } else {
+                room.display.print(&format!("[error] {message}"));
+            }
+            None
+        },
+    };
+    let unlocked = key.is_some();
+    *encryption = Encryption { salt: Some(salt), key };
+    drop(encryption);
+
+    if room.display.is_json() {
+        emit(&JsonEvent::Encrypted { unlocked });
+    } else if unlocked {
+        room.display.print("This room is end-to-end encrypted, using the passphrase saved for it");
+    } else {
+        room.display.print(&format!(
+            "This room is end-to-end encrypted. Enter its passphrase with !passphrase <passphrase>, or save it with `tcp_chat --set-secret {secret}`."
+        ));
+    }
+}
+
+/// Use `passphrase` for `room` from now on, for `!passphrase`
+fn unlock_room(room: &Room, passphrase: &str) {
+    let mut encryption = room.encryption.lock().unwrap();
+    let Some(salt) = &encryption.salt else {
+        println!("This room isn't encrypted, so it doesn't need a passphrase");
+        return;
+    };
+
+    match RoomKey::derive(passphrase, salt) {
+        Ok(key) => {
+            encryption.key = Some(key);
+            println!("Messages in this room are now sealed with that passphrase");
+        },
+        Err(e) => println!("[error] {e}"),
+    }
+}
+
+/// Ask the server to resend the broadcasts from `first` to `last`, after mentioning the `lost` ones
+/// before them that won't be asked for
+fn request_backfill(conn: &ClientWriter<WireFormat, Socket>, first: u64, last: u64, lost: u64) {
+    if lost > 0 {
+        terminal::print_line(&format!("[info] {lost} missed message(s) are too far back to recover"));
+    }
+    if conn.send(&ClientBackfill(first, last)).is_err() {
+        terminal::print_line("[error] Missed some messages and was unable to ask for them again");
+    }
+}
@@ -0,0 +1,33 @@
+// Keeps the client's idea of the time in line with the server's. Timestamps are shown using the
+// server's clock so everyone in a room sees the same times, however far off their own clocks are.
+
+use std::sync::atomic::{AtomicI64, Ordering};
+
+use chrono::{DateTime, Local, TimeDelta};
+
+use tcp_chat_proto::helpers::unix_time_ms;
+
+
+
+/// The difference between the server's clock and ours
+#[derive(Default)]
+pub struct Clock {
+    offset_ms: AtomicI64,
+}
+
+impl Clock {
+    /// Update the offset from the server's reply to a sync request sent at `sent_ms`. The server's
+    /// time is assumed to be from halfway through the round trip.
+    pub fn sync(&self, sent_ms: u64, server_ms: u64) {
+        let now = unix_time_ms();
+        let round_trip = now.saturating_sub(sent_ms);
+        let offset = (server_ms + round_trip / 2) as i64 - now as i64;
+
+        self.offset_ms.store(offset, Ordering::Relaxed);
+    }
+
+    /// The current time according to the server, in the local time zone
+    pub fn now(&self) -> DateTime<Local> {
+        Local::now() + TimeDelta::milliseconds(self.offset_ms.load(Ordering::Relaxed))
+    }
+}
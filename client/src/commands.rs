@@ -0,0 +1,211 @@
+use Command::*;
+
+use crate::filter::FilterAction;
+
+pub const CLIENT_COMMANDS: [&str; 23] = [
+    "!help", "!exit", "!rename <name>", "!who", "!status [text]", "!seen <name>", "!urgent <text>",
+    "!ephemeral <seconds> <text>", "!reveal <spoiler>", "!mute", "!more", "!history [count]",
+    "!clear", "!quote <message> <text>", "!join <address>", "!switch <room>", "!rooms",
+    "!filter [add|collapse <pattern> | remove <filter>]", "!report <who> <reason>",
+    "!voice [file]", "!play [clip]", "!attach <file>", "!passphrase <passphrase>"
+];
+pub const HOST_COMMANDS: [&str; 5] = [
+    "!kick <who>", "!ids", "!shadowban <who>", "!connections", "!shutdown"
+];
+
+/// Parse a line starting with `!`. The host's moderation commands are also available to an
+/// operator standing in for them, but only the host can close the room.
+pub fn parse_command(cmd: &str, is_host: bool, is_operator: bool) -> Option<Command> {
+
+    let cmd_args: Vec<&str> = cmd.split(' ').collect();
+    let &cmd = cmd_args.first()?;
+    let args = cmd_args.get(1..)?;
+
+    if is_host && cmd.starts_with("!shutdown") {
+        return Some(Shutdown);
+    }
+
+    if is_host || is_operator {
+        if cmd.starts_with("!help") {
+            return Some(HostHelp);
+        }
+        if cmd.starts_with("!kick") {
+            let &who = args.first()?;
+            return Some(Kick(who.parse().ok()?));
+            
+        }
+        if cmd.starts_with("!ids") {
+            return Some(RequestIDs)
+        }
+        if cmd.starts_with("!shadowban") {
+            let &who = args.first()?;
+            return Some(Shadowban(who.parse().ok()?));
+        }
+        if cmd.starts_with("!connections") {
+            return Some(Connections)
+        }
+    }
+
+    if cmd.starts_with("!help") {
+        return Some(Help)
+    }
+    if cmd.starts_with("!exit") {
+        return Some(Exit);
+    }
+    if cmd.starts_with("!rename") {
+        let &name = args.first()?;
+        return Some(Rename(name.to_string()))
+    }
+    if cmd.starts_with("!who") {
+        return Some(RequestIDs)
+    }
+    if cmd.starts_with("!seen") {
+        let &name = args.first()?;
+        return Some(Seen(name.to_string()))
+    }
+    if cmd.starts_with("!ephemeral") {
+        let &secs = args.first()?;
+        let text = args.get(1..)?.join(" ");
+        if text.trim().is_empty() {
+            return None;
+        }
+        return Some(Ephemeral(secs.parse().ok()?, text))
+    }
+    if cmd.starts_with("!mute") {
+        return Some(Mute)
+    }
+    if cmd.starts_with("!quote") {
+        let &id = args.first()?;
+        let text = args.get(1..)?.join(" ");
+        if text.trim().is_empty() {
+            return None;
+        }
+        // the id is shown as "#12", so that's accepted as well as "12"
+        return Some(Quote(id.trim_start_matches('#').parse().ok()?, text))
+    }
+    if cmd.starts_with("!more") {
+        return Some(More)
+    }
+    if cmd.starts_with("!history") {
+        let count = match args.first() {
+            Some(count) => Some(count.parse().ok()?),
+            None => None,
+        };
+        return Some(History(count))
+    }
+    if cmd.starts_with("!clear") {
+        return Some(Clear)
+    }
+    if cmd.starts_with("!join") {
+        let &address = args.first()?;
+        return Some(Join(address.to_string()))
+    }
+    if cmd.starts_with("!switch") {
+        let &number = args.first()?;
+        return Some(Switch(number.parse().ok()?))
+    }
+    if cmd.starts_with("!rooms") {
+        return Some(ListRooms)
+    }
+    if cmd.starts_with("!report") {
+        let &who = args.first()?;
+        let reason = args.get(1..)?.join(" ");
+        if reason.trim().is_empty() {
+            return None;
+        }
+        return Some(Report(who.parse().ok()?, reason))
+    }
+    if cmd.starts_with("!filter") {
+        // patterns may contain spaces
+        let pattern = args.get(1..).unwrap_or_default().join(" ");
+        return match args.first() {
+            None => Some(FilterList),
+            Some(&"add") if !pattern.is_empty() => Some(FilterAdd(pattern, FilterAction::Hide)),
+            Some(&"collapse") if !pattern.is_empty() => Some(FilterAdd(pattern, FilterAction::Collapse)),
+            Some(&"remove") => Some(FilterRemove(args.get(1)?.parse().ok()?)),
+            Some(_) => None,
+        };
+    }
+    if cmd.starts_with("!passphrase") {
+        // passphrases may contain spaces
+        let passphrase = args.join(" ");
+        if passphrase.is_empty() {
+            return None;
+        }
+        return Some(Passphrase(passphrase))
+    }
+    if cmd.starts_with("!voice") {
+        // paths may contain spaces
+        let path = args.join(" ");
+        let path = path.trim().trim_matches('"');
+        return Some(Voice((!path.is_empty()).then(|| path.to_string())))
+    }
+    if cmd.starts_with("!attach") {
+        // paths may contain spaces
+        let path = args.join(" ");
+        let path = path.trim().trim_matches('"');
+        if path.is_empty() {
+            return None;
+        }
+        return Some(Attach(path.to_string()))
+    }
+    if cmd.starts_with("!play") {
+        let number = match args.first() {
+            Some(number) => Some(number.parse().ok()?),
+            None => None,
+        };
+        return Some(Play(number))
+    }
+    if cmd.starts_with("!reveal") {
+        let &number = args.first()?;
+        return Some(Reveal(number.parse().ok()?))
+    }
+    if cmd.starts_with("!urgent") {
+        let text = args.join(" ");
+        if text.trim().is_empty() {
+            return None;
+        }
+        return Some(Urgent(text))
+    }
+    if cmd.starts_with("!status") {
+        // the status may contain spaces and is allowed to be quoted
+        let status = args.join(" ");
+        let status = status.trim().trim_matches('"').trim();
+        return Some(Status((!status.is_empty()).then(|| status.to_string())))
+    }
+    
+    None
+}
+
+pub enum Command {
+    Help,
+    HostHelp,
+    Exit,
+    Shutdown,
+    Rename(String),
+    Kick(u64),
+    Shadowban(u64),
+    RequestIDs,
+    Connections,
+    Status(Option<String>),
+    Seen(String),
+    Report(u64, String),
+    Urgent(String),
+    Ephemeral(u64, String),
+    Reveal(usize),
+    Mute,
+    More,
+    History(Option<u32>),
+    Clear,
+    Quote(u64, String),
+    Join(String),
+    Switch(usize),
+    ListRooms,
+    FilterList,
+    FilterAdd(String, FilterAction),
+    FilterRemove(usize),
+    Passphrase(String),
+    Voice(Option<String>),
+    Play(Option<usize>),
+    Attach(String),
+}
@@ -0,0 +1,161 @@
+use std::env;
+use std::fs;
+use std::path::PathBuf;
+use std::sync::OnceLock;
+
+use directories::ProjectDirs;
+use serde::Deserialize;
+use tcp_chat_proto::tcp_conn::TcpConnBuilder;
+use tcp_chat_proto::codec::WireFormat;
+use tcp_chat_proto::secrets::SecretStore;
+
+use crate::constants::{LEGACY_DATA_DIR_NAME, CONFIG_FILE_NAME, PROFILES_DIR_NAME, SECRETS_FILE_NAME};
+use crate::notify::NotificationConfig;
+use crate::filter::FilterConfig;
+use crate::voice::VoiceConfig;
+
+
+
+/// Settings for the chat client
+#[derive(Deserialize, Debug, Clone)]
+#[serde(default)]
+pub struct ClientConfig {
+    /// Username to join rooms with. The client asks for one every time it starts when this is
+    /// unset.
+    pub name: Option<String>,
+
+    /// Use colors and other terminal escape sequences. Turning this off gives plain text, the same
+    /// as on a terminal that doesn't support them.
+    pub color: bool,
+
+    /// Status line to set as soon as the client joins a room
+    pub status: Option<String>,
+
+    /// Sounds to play when messages arrive
+    pub notifications: NotificationConfig,
+
+    /// Messages to hide or collapse instead of showing, more of which can be added with `!filter`
+    pub filters: Vec<FilterConfig>,
+
+    /// How voice clips are recorded and played
+    pub voice: VoiceConfig,
+
+    /// Tracker to browse public rooms from when picking a server
+    pub tracker_url: Option<String>,
+
+    /// Minutes without typing before the client marks the user as away. `null` turns this off.
+    pub away_after_mins: Option<u64>,
+
+    /// Screen reader friendly output: no decoration, and every message announced with its sender
+    /// and time in the same sentence structure. Also enabled by `--accessible`.
+    pub accessible: bool,
+
+    /// Print every event as a line of JSON for scripts to read instead of text for people. Only
+    /// set by `--json`, since it also changes how the client is started.
+    #[serde(skip)]
+    pub json: bool,
+
+    /// Options for the socket connected to the server, like `nodelay` and `keepalive`
+    pub socket: TcpConnBuilder,
+
+    /// What messages are serialized as, if the server agrees. Bincode is much smaller than JSON.
+    pub wire_format: WireFormat,
+}
+
+impl Default for ClientConfig {
+    fn default() -> Self {
+        Self {
+            name: None,
+            color: true,
+            status: None,
+            notifications: NotificationConfig::default(),
+            filters: Vec::new(),
+            voice: VoiceConfig::default(),
+            tracker_url: None,
+            away_after_mins: Some(10),
+            accessible: false,
+            json: false,
+            socket: TcpConnBuilder::default(),
+            wire_format: WireFormat::default(),
+        }
+    }
+}
+
+/// The profile picked with `--profile`, if any. Each profile has its own config and data, so one
+/// machine can keep separate identities for different servers.
+static PROFILE: OnceLock<String> = OnceLock::new();
+
+/// Use `name`'s config and data from now on. Has to be called before anything reads either, and
+/// only once. Names are limited to letters, digits, `-`, and `_` since they become directory
+/// names.
+pub fn set_profile(name: &str) -> Result<(), String> {
+    let valid = !name.is_empty()
+        && name.chars().all(|c| c.is_ascii_alphanumeric() || c == '-' || c == '_');
+    if !valid {
+        return Err(format!("`{name}` isn't a valid profile name, use only letters, digits, - and _"));
+    }
+    PROFILE.set(name.to_string()).map_err(|_| String::from("A profile was already chosen"))
+}
+
+/// The profile in use, or `None` for the default one
+pub fn profile() -> Option<&'static str> {
+    PROFILE.get().map(String::as_str)
+}
+
+fn project_dirs() -> Option<ProjectDirs> {
+    ProjectDirs::from("", "", "tcp_chat")
+}
+
+/// Narrow one of the platform's directories down to the current profile's
+fn for_profile(dir: PathBuf) -> PathBuf {
+    match profile() {
+        Some(name) => dir.join(PROFILES_DIR_NAME).join(name),
+        None => dir,
+    }
+}
+
+/// Where the config file is read from, e.g. `~/.config/tcp_chat/tcp_chat.json` on Linux
+pub fn config_path() -> Option<PathBuf> {
+    project_dirs().map(|dirs| for_profile(dirs.config_dir().to_path_buf()).join(CONFIG_FILE_NAME))
+}
+
+/// The directory where the client keeps data between runs, like the servers it has connected to,
+/// e.g. `~/.local/share/tcp_chat` on Linux
+pub fn data_dir() -> Option<PathBuf> {
+    static DATA_DIR: OnceLock<Option<PathBuf>> = OnceLock::new();
+
+    DATA_DIR.get_or_init(|| {
+        let dir = for_profile(project_dirs()?.data_dir().to_path_buf());
+        match profile() {
+            Some(_) => Some(dir),
+            None => Some(move_legacy_data_dir(dir)),
+        }
+    }).clone()
+}
+
+/// Data used to be kept in `~/.tcp_chat`, so move it to `dir` if it's still there. Returns the
+/// directory to use, which stays the old one if it couldn't be moved.
+fn move_legacy_data_dir(dir: PathBuf) -> PathBuf {
+    let Some(home) = env::var_os("HOME").or_else(|| env::var_os("USERPROFILE")) else {
+        return dir;
+    };
+    let legacy = PathBuf::from(home).join(LEGACY_DATA_DIR_NAME);
+    if !legacy.is_dir() || dir.exists() {
+        return dir;
+    }
+
+    let moved = dir.parent().map_or(Ok(()), fs::create_dir_all).and_then(|_| fs::rename(&legacy, &dir));
+    match moved {
+        Ok(()) => dir,
+        Err(e) => {
+            println!("[error] Unable to move {} to {}: {e}", legacy.display(), dir.display());
+            legacy
+        },
+    }
+}
+
+/// Where the current profile's secrets like webhook tokens are kept, falling back to a file in the
+/// data directory
+pub fn secret_store() -> SecretStore {
+    SecretStore::new(profile(), data_dir().map(|dir| dir.join(SECRETS_FILE_NAME)))
+}
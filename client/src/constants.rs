@@ -0,0 +1,77 @@
+use std::time::Duration;
+
+
+
+/// Name of the directory in the user's home directory where client data used to be kept, which is
+/// moved to the platform's data directory the first time it's found
+pub const LEGACY_DATA_DIR_NAME: &str = ".tcp_chat";
+
+/// Name of the config file in the config directory
+pub const CONFIG_FILE_NAME: &str = "tcp_chat.json";
+
+/// Directory within the config and data directories that each profile's own directory goes in
+pub const PROFILES_DIR_NAME: &str = "profiles";
+
+/// Name of the encrypted file in the data directory secrets are kept in when there's no OS keyring
+pub const SECRETS_FILE_NAME: &str = "secrets.enc";
+
+/// Name of the directory in the data directory that voice clips are recorded and saved in
+pub const VOICE_DIR_NAME: &str = "voice";
+
+/// Name of the directory in the data directory that attachments are saved in
+pub const ATTACHMENT_DIR_NAME: &str = "attachments";
+
+/// What a room's passphrase is stored as in the secret store, followed by the room's address as
+/// it was typed when joining
+pub const PASSPHRASE_SECRET_PREFIX: &str = "passphrase/";
+
+/// What the client's Noise key is stored as in the secret store
+pub const NOISE_KEY_SECRET: &str = "noise/client";
+
+/// How many previously used servers the client remembers
+pub const MAX_SAVED_SERVERS: usize = 10;
+
+/// How many lines of the room the client keeps for `!more`
+pub const MAX_SCROLLBACK: usize = 1000;
+
+/// How far back skipped broadcasts are asked for again. The server doesn't keep more history than
+/// this, so anything older is gone anyway.
+pub const MAX_BACKFILL: u64 = 500;
+
+/// How many earlier messages `!history` asks the server for when it isn't given a count
+pub const HISTORY_PAGE: u32 = 20;
+
+/// How many recent messages the client remembers for `!quote`
+pub const MAX_QUOTABLE: usize = 200;
+
+/// Clients idle for less than this many seconds aren't shown as idle in the roster
+pub const IDLE_DISPLAY_SECS: u64 = 60;
+
+/// Exit codes for the client, so scripts can tell why it stopped
+pub const EXIT_LEFT: i32 = 0;
+/// Something went wrong partway through, like losing the connection
+pub const EXIT_ERROR: i32 = 1;
+/// The command line didn't make sense
+pub const EXIT_USAGE: i32 = 2;
+pub const EXIT_ROOM_CLOSED: i32 = 3;
+pub const EXIT_KICKED: i32 = 4;
+/// The server couldn't be reached in the first place
+pub const EXIT_UNREACHABLE: i32 = 5;
+
+/// How many times the client tries to get back into a room after the connection drops, waiting
+/// `RECONNECT_DELAY` before the first try and twice as long before each one after
+pub const RECONNECT_ATTEMPTS: u32 = 6;
+pub const RECONNECT_DELAY: Duration = Duration::from_secs(1);
+
+/// How long connecting to a server can take before giving up on it
+pub const CONNECT_TIMEOUT: Duration = Duration::from_secs(10);
+
+/// How long leaving waits for the threads receiving from each room to finish
+pub const STOP_RECEIVING_TIMEOUT: Duration = Duration::from_secs(2);
+
+/// How long the server can go without sending anything, even a `ServerPong`, before the connection
+/// is taken to have dropped
+pub const HEARTBEAT_TIMEOUT: Duration = Duration::from_secs(15);
+
+/// How long the client waits for a server to describe its room before giving up and joining anyway
+pub const INFO_QUERY_TIMEOUT: Duration = Duration::from_secs(3);
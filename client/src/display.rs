@@ -0,0 +1,546 @@
+// Formatting for text received from the server before it gets printed
+
+use std::io;
+use std::sync::{Arc, Mutex};
+
+use chrono::{DateTime, Local, NaiveDate};
+use tcp_chat_proto::packet::HistoryEntry;
+
+use crate::clock::Clock;
+use crate::constants::MAX_SCROLLBACK;
+use crate::scrollback::Scrollback;
+use crate::quote::{self, Quotes, split_quote};
+use crate::terminal;
+
+
+
+/// Spoilers hidden from received messages. Shared between the thread receiving messages, which
+/// hides them, and the input thread, which reveals them on request.
+pub type Spoilers = Arc<Mutex<Vec<String>>>;
+
+/// Replace every `||spoiler||` in `text` with a numbered placeholder, keeping the hidden content in
+/// `spoilers` so it can be revealed later. An unmatched `||` is left alone.
+pub fn hide_spoilers(text: &str, spoilers: &Spoilers) -> String {
+    let mut out = String::with_capacity(text.len());
+    let mut rest = text;
+
+    while let Some(start) = rest.find("||") {
+        let after = &rest[start + 2..];
+        let Some(len) = after.find("||") else { break };
+
+        let mut unlocked = spoilers.lock().unwrap();
+        unlocked.push(after[..len].to_string());
+
+        out.push_str(&rest[..start]);
+        out.push_str(&format!("[spoiler #{}, !reveal {0} to show]", unlocked.len()));
+
+        rest = &after[len + 2..];
+    }
+
+    out.push_str(rest);
+    out
+}
+
+/// Get the text of a spoiler by the number shown in its placeholder
+pub fn reveal_spoiler(spoilers: &Spoilers, number: usize) -> Option<String> {
+    let index = number.checked_sub(1)?;
+    spoilers.lock().unwrap().get(index).cloned()
+}
+
+/// What was shown last, so consecutive messages can be grouped and new days pointed out
+#[derive(Default)]
+struct Flow {
+    /// Who sent the last message, if the last thing shown was a regular message
+    sender: Option<String>,
+    /// The day of the last message
+    day: Option<NaiveDate>,
+}
+
+/// Turns messages into the text that gets printed
+#[derive(Clone)]
+pub struct Display {
+    spoilers: Spoilers,
+    quotes: Quotes,
+
+    /// Shared by every copy so both threads printing messages see the same flow
+    flow: Arc<Mutex<Flow>>,
+
+    /// Everything printed from the room, shared for the same reason
+    scrollback: Arc<Mutex<Scrollback>>,
+
+    /// What was printed while another room was on the screen, held back until this one is
+    /// switched to. `None` while this room is the one on the screen.
+    held: Arc<Mutex<Option<Vec<String>>>>,
+
+    /// Times are the server's so they match what everyone else sees
+    clock: Arc<Clock>,
+
+    /// Avoid decoration that confuses screen readers and announce every message in the same
+    /// sentence structure
+    accessible: bool,
+
+    /// Whether the terminal understands ANSI escape sequences for colors
+    ansi: bool,
+
+    /// Events are printed as JSON for scripts instead, see `json_output`
+    json: bool,
+}
+
+impl Display {
+    pub fn new(
+        spoilers: Spoilers,
+        quotes: Quotes,
+        clock: Arc<Clock>,
+        accessible: bool,
+        ansi: bool,
+        json: bool
+    ) -> Self {
+        Self {
+            spoilers,
+            quotes,
+            flow: Arc::default(),
+            scrollback: Arc::default(),
+            held: Arc::default(),
+            clock,
+            accessible,
+            ansi,
+            json,
+        }
+    }
+
+    /// Whether output is JSON for scripts rather than text for people
+    pub fn is_json(&self) -> bool {
+        self.json
+    }
+
+    /// Print something from the room, keeping it in the scrollback
+    pub fn print(&self, text: &str) {
+        // locked while printing so the scrollback is in the same order as the screen
+        let mut scrollback = self.scrollback.lock().unwrap();
+        match self.held.lock().unwrap().as_mut() {
+            Some(held) => {
+                held.push(text.to_string());
+                if held.len() > MAX_SCROLLBACK {
+                    held.remove(0);
+                }
+            },
+            None => terminal::print_line(text),
+        }
+        scrollback.record(text);
+    }
+
+    /// Replace the last line printed with `line`, e.g. once a pending message is confirmed
+    pub fn reprint_last(&self, line: &str) -> io::Result<()> {
+        let mut scrollback = self.scrollback.lock().unwrap();
+        match self.held.lock().unwrap().as_mut() {
+            Some(held) => {
+                if let Some(last) = held.last_mut() {
+                    *last = line.to_string();
+                }
+            },
+            None => terminal::rewrite_previous_line(line)?,
+        }
+        scrollback.replace_last(line);
+        Ok(())
+    }
+
+    /// Stop printing to the screen, holding on to everything until `catch_up`. Used when another
+    /// room is switched to.
+    pub fn hide(&self) {
+        self.held.lock().unwrap().get_or_insert_with(Vec::new);
+    }
+
+    /// Print everything held back while the room wasn't on the screen, and go back to printing
+    /// as things arrive
+    pub fn catch_up(&self) {
+        let _scrollback = self.scrollback.lock().unwrap();
+        let Some(held) = self.held.lock().unwrap().take() else { return };
+
+        for text in held {
+            terminal::print_line(&text);
+        }
+        self.break_group();
+    }
+
+    /// How many things were printed while the room wasn't on the screen
+    pub fn unseen(&self) -> usize {
+        self.held.lock().unwrap().as_ref().map_or(0, Vec::len)
+    }
+
+    /// Show the page of scrollback before the last one shown, for `!more`
+    pub fn show_earlier(&self) {
+        let page = self.scrollback.lock().unwrap().previous_page(terminal::height().saturating_sub(2).max(1));
+
+        match page {
+            Some(lines) => {
+                println!("--- earlier ---");
+                for line in lines {
+                    println!("{line}");
+                }
+                println!("--- !more for earlier still ---");
+            },
+            None => println!("--- nothing earlier ---"),
+        }
+    }
+
+    /// Show a page of the room's history fetched with `!history`. `complete` means the server has
+    /// nothing earlier. It isn't kept in the scrollback, since it's from before the client joined.
+    pub fn history(&self, page: &[HistoryEntry], complete: bool) {
+        let mut lines = vec![String::from("--- from the room's history ---")];
+        lines.extend(page.iter().map(|entry| self.history_line(entry)));
+        lines.push(String::from(match (page.is_empty(), complete) {
+            (true, _) => "--- nothing earlier in the room's history ---",
+            (false, true) => "--- start of the room's history ---",
+            (false, false) => "--- !history for earlier still ---",
+        }));
+        let text = lines.join("\n");
+
+        match self.held.lock().unwrap().as_mut() {
+            Some(held) => held.push(text),
+            None => terminal::print_line(&text),
+        }
+    }
+
+    /// One message from the room's history, with the date as well as the time unless it was today
+    fn history_line(&self, entry: &HistoryEntry) -> String {
+        let when = DateTime::from_timestamp(entry.time as i64, 0)
+            .map(|time| time.with_timezone(&Local))
+            .unwrap_or_default();
+        let when = if when.date_naive() == self.clock.now().date_naive() {
+            when.format("%H:%M").to_string()
+        } else {
+            when.format("%-d %b %H:%M").to_string()
+        };
+        let formatted = self.format_text(&entry.text);
+        let name = &entry.sender;
+
+        match (self.accessible, entry.urgent) {
+            (true, true) => format!("Urgent message at {when}, {name} said: {formatted}"),
+            (true, false) => format!("At {when}, {name} said: {formatted}"),
+            (false, true) => format!("[{when}] [URGENT] {name}: {formatted}"),
+            (false, false) => format!("[{when}] {name}: {formatted}"),
+        }
+    }
+
+    /// Clear the screen for `!clear`. Everything is still in the scrollback.
+    pub fn clear(&self) {
+        let mut scrollback = self.scrollback.lock().unwrap();
+        if self.ansi && terminal::clear().is_ok() {
+            scrollback.cleared();
+        }
+    }
+
+    /// A regular message from `name`. Consecutive messages from the same person are grouped under
+    /// their name, except in accessible mode where every message needs to say who it's from.
+    /// Messages with an id can be quoted, and show the id to quote them by.
+    pub fn chat(&self, id: Option<u64>, name: &str, text: &str) -> String {
+        let formatted = self.format_text(text);
+        let separator = self.day_separator();
+
+        let grouped = {
+            let mut flow = self.flow.lock().unwrap();
+            let grouped = flow.sender.as_deref() == Some(name) && separator.is_empty();
+            flow.sender = Some(name.to_string());
+            grouped
+        };
+
+        let line = if self.accessible {
+            format!("{separator}At {}, {name} said: {formatted}", self.now())
+        } else if grouped {
+            // lined up with the text of the first message in the group
+            format!("{separator}{:width$}{formatted}", "", width = name.chars().count() + 2)
+        } else {
+            format!("{separator}{name}: {formatted}")
+        };
+        self.numbered(line, id, name, text)
+    }
+
+    /// Add the id to quote a message by to the end of it, and remember it so it can be quoted
+    fn numbered(&self, line: String, id: Option<u64>, name: &str, text: &str) -> String {
+        let Some(id) = id else { return line };
+        quote::remember(&self.quotes, id, name, text);
+
+        if self.accessible {
+            format!("{line} (message {id})")
+        } else if self.ansi {
+            format!("{line} \x1b[2m#{id}\x1b[0m")
+        } else {
+            format!("{line} #{id}")
+        }
+    }
+
+    /// Stop grouping messages, since something else has been shown since the last one
+    pub fn break_group(&self) {
+        self.flow.lock().unwrap().sender = None;
+    }
+
+    /// A line announcing the date if it's changed since the last message (with a trailing newline),
+    /// otherwise an empty string. Nothing is announced for the first message.
+    fn day_separator(&self) -> String {
+        let today = self.clock.now().date_naive();
+        let previous = self.flow.lock().unwrap().day.replace(today);
+
+        if previous.is_none_or(|day| day == today) {
+            return String::new();
+        }
+
+        let date = today.format("%A, %-d %B");
+        if self.accessible {
+            format!("The date is now {date}\n")
+        } else if self.ansi {
+            format!("\x1b[2m— {date} —\x1b[0m\n")
+        } else {
+            format!("— {date} —\n")
+        }
+    }
+
+    /// A message that needs attention. A terminal bell is included either way.
+    pub fn urgent(&self, id: Option<u64>, name: &str, text: &str) -> String {
+        let formatted = self.format_text(text);
+        let separator = self.day_separator();
+        self.break_group();
+
+        let line = if self.accessible {
+            format!("{separator}\x07Urgent message at {}, {name} said: {formatted}", self.now())
+        } else if self.ansi {
+            // bold red so it can't be missed
+            format!("{separator}\x07\x1b[1;31m[URGENT] {name}: {formatted}\x1b[0m")
+        } else {
+            format!("{separator}\x07[URGENT] {name}: {formatted}")
+        };
+        self.numbered(line, id, name, text)
+    }
+
+    /// A message sent to us alone
+    pub fn direct(&self, name: &str, text: &str) -> String {
+        let text = self.format_text(text);
+        let separator = self.day_separator();
+        self.break_group();
+
+        if self.accessible {
+            format!("{separator}At {}, {name} said to you only: {text}", self.now())
+        } else if self.ansi {
+            format!("{separator}\x1b[1m{name} (to you):\x1b[0m {text}")
+        } else {
+            format!("{separator}{name} (to you): {text}")
+        }
+    }
+
+    /// A message that will expire after `secs` seconds
+    pub fn ephemeral(&self, name: &str, text: &str, secs: u64) -> String {
+        let text = self.format_text(text);
+        let separator = self.day_separator();
+        self.break_group();
+
+        if self.accessible {
+            format!("{separator}At {}, {name} said, disappearing in {secs} seconds: {text}", self.now())
+        } else {
+            format!("{separator}{name} (disappears in {secs}s): {text}")
+        }
+    }
+
+    /// Our own message as it's shown once the server has sent it to everyone
+    pub fn sent(&self, text: &str) -> String {
+        self.chat(None, "you", text)
+    }
+
+    /// How `sent`, the line for one of our messages, is shown until the server confirms it
+    pub fn pending(&self, sent: &str) -> String {
+        if self.ansi && !self.accessible {
+            // dimmed until it's confirmed
+            format!("\x1b[2m{sent} (sending...)\x1b[0m")
+        } else {
+            format!("{sent} (sending...)")
+        }
+    }
+
+    /// Confirmation for one of our messages that can't be marked as sent where it was shown
+    pub fn delivered(&self, text: &str) -> String {
+        if self.accessible {
+            format!("Delivered: {}", preview(text))
+        } else {
+            format!("[delivered: {}]", preview(text))
+        }
+    }
+
+    /// One of our messages that didn't make it to the room
+    pub fn failed(&self, text: &str, reason: &str) -> String {
+        if self.accessible {
+            format!("Not sent: {}. {reason}", preview(text))
+        } else if self.ansi {
+            format!("\x1b[31m[not sent: {}] {reason}\x1b[0m", preview(text))
+        } else {
+            format!("[not sent: {}] {reason}", preview(text))
+        }
+    }
+
+    /// One of our messages the server hasn't acknowledged in a while, which may not have made it
+    pub fn unacknowledged(&self, text: &str) -> String {
+        if self.accessible {
+            format!("Not acknowledged yet: {}", preview(text))
+        } else if self.ansi {
+            format!("\x1b[33m[not acknowledged yet: {}]\x1b[0m", preview(text))
+        } else {
+            format!("[not acknowledged yet: {}]", preview(text))
+        }
+    }
+
+    /// Whether a line that's already been printed can be changed in place. Screen readers would
+    /// read it out all over again, so it's never done in accessible mode.
+    pub fn can_rewrite(&self, line: &str) -> bool {
+        self.ansi && !self.accessible && !line.contains('\n') && line.chars().count() < terminal::width()
+    }
+
+    /// The current time, for announcing when messages arrived
+    fn now(&self) -> String {
+        self.clock.now().format("%H:%M").to_string()
+    }
+
+    /// Format the text of a message. A quoted line is shown above the rest. Spoilers are hidden,
+    /// and fenced code blocks are framed with their indentation preserved (and highlighted when
+    /// built with the `highlight` feature). Spoilers aren't looked for inside code blocks since
+    /// `||` is common in code.
+    fn format_text(&self, text: &str) -> String {
+        let mut out = String::with_capacity(text.len());
+        let mut prose = Vec::new();
+
+        let (quoted, text) = split_quote(text);
+        if let Some(quoted) = quoted {
+            self.push_quote(&mut out, quoted);
+        }
+
+        let mut code: Option<(&str, Vec<&str>)> = None;
+
+        for line in text.lines() {
+            match (&mut code, line.trim_start().strip_prefix("```")) {
+                // opening fence, whatever comes after it is the language
+                (None, Some(lang)) => {
+                    self.push_prose(&mut out, &prose);
+                    prose.clear();
+                    code = Some((lang.trim(), Vec::new()));
+                },
+                (None, None) => prose.push(line),
+                // closing fence
+                (Some((lang, lines)), Some(_)) => {
+                    self.push_code(&mut out, lang, lines);
+                    code = None;
+                },
+                (Some((_, lines)), None) => lines.push(line),
+            }
+        }
+
+        // an unclosed code block still gets shown as code
+        if let Some((lang, lines)) = &code {
+            self.push_code(&mut out, lang, lines);
+        }
+        self.push_prose(&mut out, &prose);
+
+        out
+    }
+
+    fn push_prose(&self, out: &mut String, lines: &[&str]) {
+        if lines.is_empty() {
+            return;
+        }
+        if !out.is_empty() {
+            out.push('\n');
+        }
+        out.push_str(&hide_spoilers(&lines.join("\n"), &self.spoilers));
+    }
+
+    /// Show what a reply is quoting, indented on its own line above the reply
+    fn push_quote(&self, out: &mut String, quoted: &str) {
+        let quoted = hide_spoilers(quoted, &self.spoilers);
+
+        if self.accessible {
+            out.push_str(&format!("\nQuoting {quoted}"));
+        } else if self.ansi {
+            out.push_str(&format!("\n\x1b[2m  │ {quoted}\x1b[0m"));
+        } else {
+            out.push_str(&format!("\n  │ {quoted}"));
+        }
+    }
+
+    /// Frame a code block so it stands out from the surrounding text. It always starts on its own
+    /// line so it doesn't end up next to the sender's name.
+    fn push_code(&self, out: &mut String, lang: &str, lines: &[&str]) {
+        out.push('\n');
+
+        if self.accessible {
+            match lang {
+                "" => out.push_str("Code block:\n"),
+                lang => out.push_str(&format!("Code block in {lang}:\n")),
+            }
+            for line in lines {
+                out.push_str(line);
+                out.push('\n');
+            }
+            out.push_str("End of code block.");
+            return;
+        }
+
+        let code = lines.join("\n");
+        let lines = highlight(lang, &code)
+            .filter(|_| self.ansi)
+            .unwrap_or_else(|| lines.iter().map(|l| l.to_string()).collect());
+
+        // the rules span the terminal so the block is easy to pick out
+        let width = terminal::width();
+        let label = format!("┌── {lang} ");
+        let rule_len = width.saturating_sub(label.chars().count());
+
+        out.push_str(&format!("{label}{}\n", "─".repeat(rule_len)));
+        for line in lines {
+            out.push_str(&format!("│ {line}\n"));
+        }
+        out.push_str(&format!("└{}", "─".repeat(width.saturating_sub(1))));
+    }
+}
+
+/// The start of a message's first line, for referring back to it. Quotes are left out since
+/// they're of someone else's message.
+fn preview(text: &str) -> String {
+    const PREVIEW_LEN: usize = 30;
+
+    let (_, text) = split_quote(text);
+    let line = text.lines().next().unwrap_or_default();
+    if line.chars().count() > PREVIEW_LEN || line.len() < text.len() {
+        format!("{}...", line.chars().take(PREVIEW_LEN).collect::<String>())
+    } else {
+        line.to_string()
+    }
+}
+
+/// Highlight `code` as the language `lang`, returning one string per line with ANSI colors. Returns
+/// `None` if the language isn't recognized.
+#[cfg(feature = "highlight")]
+fn highlight(lang: &str, code: &str) -> Option<Vec<String>> {
+    use std::sync::OnceLock;
+    use syntect::easy::HighlightLines;
+    use syntect::highlighting::ThemeSet;
+    use syntect::parsing::SyntaxSet;
+    use syntect::util::{as_24_bit_terminal_escaped, LinesWithEndings};
+
+    // loading these is slow, so only do it once
+    static SYNTAXES: OnceLock<SyntaxSet> = OnceLock::new();
+    static THEMES: OnceLock<ThemeSet> = OnceLock::new();
+
+    let syntaxes = SYNTAXES.get_or_init(SyntaxSet::load_defaults_newlines);
+    let themes = THEMES.get_or_init(ThemeSet::load_defaults);
+
+    let syntax = syntaxes.find_syntax_by_token(lang)?;
+    let mut highlighter = HighlightLines::new(syntax, &themes.themes["base16-ocean.dark"]);
+
+    LinesWithEndings::from(code)
+        .map(|line| {
+            let ranges = highlighter.highlight_line(line, syntaxes).ok()?;
+            let escaped = as_24_bit_terminal_escaped(&ranges, false);
+            Some(format!("{}\x1b[0m", escaped.trim_end_matches('\n')))
+        })
+        .collect()
+}
+
+#[cfg(not(feature = "highlight"))]
+fn highlight(_lang: &str, _code: &str) -> Option<Vec<String>> {
+    None
+}
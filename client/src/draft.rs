@@ -0,0 +1,69 @@
+use std::collections::HashMap;
+use std::fs;
+use std::path::PathBuf;
+use std::sync::{Arc, Mutex};
+
+use crate::config::data_dir;
+
+
+
+/// Whatever the user has typed but not yet sent. Cloning shares the same text, so it can be saved
+/// from whichever thread notices the client is about to exit.
+#[derive(Clone)]
+pub struct Draft {
+    /// Drafts are kept per server so switching servers doesn't mix them up
+    server: String,
+    text: Arc<Mutex<String>>,
+}
+
+impl Draft {
+    fn path() -> Option<PathBuf> {
+        data_dir().map(|dir| dir.join("drafts.json"))
+    }
+
+    fn load_all() -> HashMap<String, String> {
+        Self::path()
+            .and_then(|path| fs::read_to_string(path).ok())
+            .and_then(|contents| serde_json::from_str(&contents).ok())
+            .unwrap_or_default()
+    }
+
+    /// Restore the draft left over from the last time the user was connected to `server`
+    pub fn load(server: &str) -> Self {
+        let text = Self::load_all().remove(server).unwrap_or_default();
+        Self {
+            server: server.to_string(),
+            text: Arc::new(Mutex::new(text)),
+        }
+    }
+
+    pub fn text(&self) -> String {
+        self.text.lock().unwrap().clone()
+    }
+
+    pub fn set(&self, text: &str) {
+        text.clone_into(&mut self.text.lock().unwrap());
+    }
+
+    /// Write the draft to the data directory so it can be restored next time. An empty draft
+    /// removes the saved one instead.
+    pub fn save(&self) {
+        let Some(path) = Self::path() else { return };
+
+        let mut drafts = Self::load_all();
+        let text = self.text();
+        if text.is_empty() {
+            drafts.remove(&self.server);
+        } else {
+            drafts.insert(self.server.clone(), text);
+        }
+
+        let result = path.parent()
+            .map_or(Ok(()), fs::create_dir_all)
+            .and_then(|_| fs::write(&path, serde_json::to_string(&drafts)?));
+
+        if let Err(e) = result {
+            println!("[error] Unable to save your unsent message: {e}");
+        }
+    }
+}
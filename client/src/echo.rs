@@ -0,0 +1,196 @@
+// Local echo of our own messages. The server doesn't send our messages back to us, so they're
+// shown as soon as they're sent and marked once the server confirms they went out to the room.
+
+use std::collections::VecDeque;
+use std::mem;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::Mutex;
+use std::time::{Duration, Instant};
+
+use crate::display::Display;
+use crate::json_output::{emit, JsonEvent};
+use tcp_chat_proto::packet::ClientMessage;
+use tcp_chat_proto::codec::WireFormat;
+use tcp_chat_proto::typed_conn::ClientWriter;
+use tcp_chat_proto::transport::Socket;
+
+
+
+/// How long a message can go without the server acknowledging it before we say so
+const ACK_TIMEOUT: Duration = Duration::from_secs(10);
+
+/// A message shown before it was confirmed
+struct Pending {
+    /// The id the server acknowledges it by with `ServerAck`, for messages that have one. The
+    /// rest are confirmed by the `ServerSent` that comes back in their place.
+    id: Option<u64>,
+    text: String,
+    sent_at: Instant,
+    /// Whether its `ServerSent` has arrived, for one still waiting on its `ServerAck`
+    relayed: bool,
+    /// Whether we've already said the server hasn't acknowledged it
+    overdue: bool,
+    /// How it looks once it's sent, worked out when it was first shown so it's grouped the same
+    sent_line: String,
+    /// Whether it's still the last line on the screen, and so can be marked as sent in place
+    on_last_line: bool,
+}
+
+/// Our messages waiting on the server. The server handles messages in the order they're sent, so
+/// confirmations arrive in the same order, though text is matched to its `ServerAck` by id.
+pub struct Echoes {
+    display: Display,
+    pending: Mutex<VecDeque<Pending>>,
+    next_id: AtomicU64,
+}
+
+impl Echoes {
+    pub fn new(display: Display) -> Self {
+        Self { display, pending: Mutex::new(VecDeque::new()), next_id: AtomicU64::new(0) }
+    }
+
+    /// An id for the next message that's acknowledged by id, unique for as long as we're running
+    pub fn next_id(&self) -> u64 {
+        self.next_id.fetch_add(1, Ordering::Relaxed)
+    }
+
+    /// Send `msg` over `conn`, showing `text` as ours right away
+    pub fn send(&self, conn: &ClientWriter<WireFormat, Socket>, msg: &ClientMessage, text: &str) {
+        // scripts hear about it once it's sent or refused, there's nothing to show in the meantime
+        if self.display.is_json() {
+            if let Err(e) = conn.send(msg) {
+                emit(&JsonEvent::Error { message: format!("Unable to send message: {e}") });
+            }
+            return;
+        }
+
+        // locked throughout so the confirmation can't arrive before the message is pending
+        let mut pending = self.pending.lock().unwrap();
+
+        let sent_line = self.display.sent(text);
+        let line = self.display.pending(&sent_line);
+        self.display.print(&line);
+
+        if let Err(e) = conn.send(msg) {
+            self.display.print(&self.display.failed(text, &e.to_string()));
+            self.display.break_group();
+            return;
+        }
+
+        for earlier in pending.iter_mut() {
+            earlier.on_last_line = false;
+        }
+        let id = match msg {
+            ClientMessage::ClientTrackedText(id, _) => Some(*id),
+            _ => None,
+        };
+        pending.push_back(Pending {
+            id,
+            text: text.to_string(),
+            sent_at: Instant::now(),
+            relayed: false,
+            overdue: false,
+            sent_line,
+            on_last_line: self.display.can_rewrite(&line),
+        });
+    }
+
+    /// The server sent the oldest pending message to the room. Messages with an id are kept until
+    /// their `ServerAck` instead, if the server `acks` them, but later messages are confirmed past
+    /// them either way.
+    pub fn confirm(&self, acks: bool) {
+        let sent = {
+            let mut pending = self.pending.lock().unwrap();
+            match pending.iter().position(|message| !message.relayed) {
+                Some(index) if pending[index].id.is_some() && acks => {
+                    pending[index].relayed = true;
+                    None
+                },
+                Some(index) => pending.remove(index),
+                None => None,
+            }
+        };
+        if let Some(sent) = sent {
+            self.show_sent(sent);
+        }
+    }
+
+    /// The server acknowledged the message with this id, see `ServerAck`
+    pub fn acknowledge(&self, id: u64) {
+        let sent = {
+            let mut pending = self.pending.lock().unwrap();
+            pending.iter()
+                .position(|message| message.id == Some(id))
+                .and_then(|index| pending.remove(index))
+        };
+        if let Some(sent) = sent {
+            self.show_sent(sent);
+        }
+    }
+
+    /// Say which messages the server has taken too long to acknowledge, once each. They're still
+    /// marked as sent if the acknowledgement turns up later. Ones the server did send to the room
+    /// are marked as sent now instead, rather than waiting forever on an acknowledgement that got
+    /// lost. Only for servers that send `ServerAck`.
+    pub fn warn_overdue(&self) {
+        let mut pending = self.pending.lock().unwrap();
+
+        let (late, waiting): (VecDeque<_>, VecDeque<_>) = mem::take(&mut *pending).into_iter()
+            .partition(|message| message.relayed && message.sent_at.elapsed() >= ACK_TIMEOUT);
+        *pending = waiting;
+        for sent in late {
+            self.show_sent(sent);
+        }
+
+        let mut warned = false;
+        for message in pending.iter_mut() {
+            if message.id.is_some() && !message.relayed && !message.overdue && message.sent_at.elapsed() >= ACK_TIMEOUT {
+                message.overdue = true;
+                self.display.print(&self.display.unacknowledged(&message.text));
+                self.display.break_group();
+                warned = true;
+            }
+        }
+
+        // the warnings are below whatever was still on the last line
+        if warned {
+            for message in pending.iter_mut() {
+                message.on_last_line = false;
+            }
+        }
+    }
+
+    /// Mark `sent` as sent where it was shown, or on a line of its own if it can't be
+    fn show_sent(&self, sent: Pending) {
+        let rewritten = sent.on_last_line
+            && self.display.reprint_last(&sent.sent_line).is_ok();
+
+        if !rewritten {
+            self.display.print(&self.display.delivered(&sent.text));
+            self.display.break_group();
+        }
+    }
+
+    /// The server refused the oldest pending message it hasn't sent to the room
+    pub fn reject(&self, reason: &str) {
+        let rejected = {
+            let mut pending = self.pending.lock().unwrap();
+            pending.iter()
+                .position(|message| !message.relayed)
+                .and_then(|index| pending.remove(index))
+        };
+        if let Some(rejected) = rejected {
+            self.display.print(&self.display.failed(&rejected.text, reason));
+            self.display.break_group();
+        }
+    }
+
+    /// Something else was printed, so pending messages are no longer on the last line. This should
+    /// be called after printing rather than before, so a message shown in between is at worst
+    /// confirmed on a line of its own.
+    pub fn interrupt(&self) {
+        for pending in self.pending.lock().unwrap().iter_mut() {
+            pending.on_last_line = false;
+        }
+    }
+}
@@ -0,0 +1,100 @@
+// Filters the user sets up to keep messages they don't want to see, like a chatty bot or a topic
+// they're sick of, from being shown or making any noise
+
+use std::sync::Mutex;
+
+use regex::Regex;
+use serde::Deserialize;
+
+
+
+/// A filter as written in the config
+#[derive(Deserialize, Debug, Clone)]
+pub struct FilterConfig {
+    /// Regex matched against the text of each message. Matches everything when left out.
+    #[serde(default)]
+    pub pattern: String,
+    /// Regex matched against the sender's name, so only their messages are filtered. Matches
+    /// everyone when left out.
+    #[serde(default)]
+    pub from: String,
+    #[serde(default)]
+    pub action: FilterAction,
+}
+
+/// What happens to a message a filter matches
+#[derive(Deserialize, Debug, Clone, Copy, Default, PartialEq, Eq)]
+#[serde(rename_all = "lowercase")]
+pub enum FilterAction {
+    /// Not shown at all
+    #[default]
+    Hide,
+    /// Shown as a single line saying who it was from
+    Collapse,
+}
+
+struct Filter {
+    pattern: Regex,
+    from: Regex,
+    action: FilterAction,
+}
+
+/// Every filter in use, shared by all the rooms. Ones added with `!filter` only last until the
+/// client exits.
+#[derive(Default)]
+pub struct Filters {
+    filters: Mutex<Vec<Filter>>,
+}
+
+impl Filters {
+    /// The filters from the config. Any with an invalid regex are reported and left out.
+    pub fn new(configs: &[FilterConfig]) -> Self {
+        let filters = Self::default();
+        for config in configs {
+            if let Err(e) = filters.add(&config.pattern, &config.from, config.action) {
+                println!("[error] Ignoring the filter for `{}`: {e}", config.pattern);
+            }
+        }
+        filters
+    }
+
+    /// Filter messages matching `pattern` from senders matching `from`
+    pub fn add(&self, pattern: &str, from: &str, action: FilterAction) -> Result<(), regex::Error> {
+        let filter = Filter {
+            pattern: Regex::new(pattern)?,
+            from: Regex::new(from)?,
+            action,
+        };
+        self.filters.lock().unwrap().push(filter);
+        Ok(())
+    }
+
+    /// Stop using filter `number`, counting from 1 as listed by `list`. Returns whether there was
+    /// such a filter.
+    pub fn remove(&self, number: usize) -> bool {
+        let mut filters = self.filters.lock().unwrap();
+        match number.checked_sub(1).filter(|&index| index < filters.len()) {
+            Some(index) => {
+                filters.remove(index);
+                true
+            },
+            None => false,
+        }
+    }
+
+    /// Each filter's text pattern, sender pattern, and action, in the order they were added
+    pub fn list(&self) -> Vec<(String, String, FilterAction)> {
+        self.filters.lock().unwrap().iter()
+            .map(|filter| (filter.pattern.to_string(), filter.from.to_string(), filter.action))
+            .collect()
+    }
+
+    /// What to do with a message `from` sent, if any filter matches it. Hiding wins over
+    /// collapsing when several match.
+    pub fn check(&self, from: &str, text: &str) -> Option<FilterAction> {
+        self.filters.lock().unwrap().iter()
+            .filter(|filter| filter.from.is_match(from) && filter.pattern.is_match(text))
+            .map(|filter| filter.action)
+            .min_by_key(|&action| action != FilterAction::Hide)
+    }
+}
@@ -3,21 +3,6 @@
 
 use std::io::{self, Write};
 
-/// Simple input wrapper for my use case.
-pub fn input_msg() -> String {
-
-    let mut buf = String::new();
-
-    io::stdout()
-        .flush()
-        .expect("[error] Unable to write to buffer!");
-    io::stdin()
-        .read_line(&mut buf)
-        .expect("[error] Unable to read input!");
-
-    buf.trim().to_owned()
-}
-
 /// Simple input wrapper for my use case. Adds a little "> " prompt
 pub fn input() -> String {
 
@@ -50,6 +35,38 @@ impl CmdResponse for String {
     }
 }
 
+/// Split `text` into pieces of at most `max_len` bytes, breaking at whitespace where there's some
+/// so words stay whole. The whitespace broken at is dropped.
+pub fn split_text(text: &str, max_len: usize) -> Vec<&str> {
+    let mut pieces = Vec::new();
+    let mut rest = text;
+    while rest.len() > max_len {
+        let mut end = max_len;
+        while !rest.is_char_boundary(end) {
+            end -= 1;
+        }
+        // a limit shorter than one character can't be split to, so it's left for the server
+        if end == 0 {
+            break;
+        }
+        let cut = rest[..end].rfind(char::is_whitespace).filter(|&at| at > 0).unwrap_or(end);
+        pieces.push(&rest[..cut]);
+        rest = rest[cut..].trim_start();
+    }
+    if !rest.is_empty() || pieces.is_empty() {
+        pieces.push(rest);
+    }
+    pieces
+}
+
+/// `text` made safe to put in a file name on any platform: anything but letters, digits, `-` and
+/// `_` becomes `_`, so it can't add a directory or anything else the OS would read into it
+pub fn safe_file_component(text: &str) -> String {
+    text.chars()
+        .map(|c| if c.is_alphanumeric() || c == '-' || c == '_' { c } else { '_' })
+        .collect()
+}
+
 /// Validator for `UntilValid` to determine if a given string is like "yes" or "no". This is a
 /// helper to work with `input()`, which is why it takes a `&String` instead of a `&str`
 #[allow(clippy::ptr_arg)]
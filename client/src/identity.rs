@@ -0,0 +1,73 @@
+// The user's long-term identity: an Ed25519 keypair, which the first-run setup can generate. The
+// private half lives in the `SecretStore` rather than a file of its own, and only the public half
+// is ever shown.
+
+use std::io;
+
+use ed25519_dalek::SigningKey;
+use tcp_chat_proto::secrets::SecretStore;
+
+
+
+/// What the private key is stored under in the `SecretStore`
+pub const IDENTITY_SECRET_KEY: &str = "identity";
+
+pub struct Identity {
+    key: SigningKey,
+}
+
+impl Identity {
+    /// A brand new keypair from the OS's random number generator
+    pub fn generate() -> io::Result<Self> {
+        let mut seed = [0; 32];
+        getrandom::getrandom(&mut seed).map_err(|e| io::Error::other(e.to_string()))?;
+        Ok(Self { key: SigningKey::from_bytes(&seed) })
+    }
+
+    /// The identity saved in `secrets`, or `None` if one was never generated
+    pub fn load(secrets: &SecretStore) -> io::Result<Option<Self>> {
+        let Some(hex) = secrets.get(IDENTITY_SECRET_KEY)? else {
+            return Ok(None);
+        };
+        let seed = from_hex(&hex).ok_or_else(|| io::Error::new(
+            io::ErrorKind::InvalidData,
+            "The stored identity isn't a valid key"
+        ))?;
+        Ok(Some(Self { key: SigningKey::from_bytes(&seed) }))
+    }
+
+    /// Keep the private key in `secrets`, returning where it ended up
+    pub fn save(&self, secrets: &SecretStore) -> io::Result<&'static str> {
+        secrets.set(IDENTITY_SECRET_KEY, &to_hex(self.key.as_bytes()))
+    }
+
+    pub fn public_key(&self) -> [u8; 32] {
+        self.key.verifying_key().to_bytes()
+    }
+
+    /// The public key in groups of four hex digits, for people to compare by eye
+    pub fn fingerprint(&self) -> String {
+        let hex = to_hex(&self.public_key());
+        hex.as_bytes()
+            .chunks(4)
+            .map(|group| std::str::from_utf8(group).unwrap())
+            .collect::<Vec<_>>()
+            .join(" ")
+    }
+}
+
+
+fn to_hex(bytes: &[u8]) -> String {
+    bytes.iter().map(|b| format!("{b:02x}")).collect()
+}
+
+fn from_hex(hex: &str) -> Option<[u8; 32]> {
+    if hex.len() != 64 || !hex.is_ascii() {
+        return None;
+    }
+    let mut bytes = [0; 32];
+    for (byte, pair) in bytes.iter_mut().zip(hex.as_bytes().chunks(2)) {
+        *byte = u8::from_str_radix(std::str::from_utf8(pair).ok()?, 16).ok()?;
+    }
+    Some(bytes)
+}
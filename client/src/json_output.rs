@@ -0,0 +1,74 @@
+// Output for `--json`, where every event is printed as one line of JSON so scripts can follow the
+// room without picking apart the text meant for people
+
+use std::io::{self, Write};
+use std::path::PathBuf;
+
+use serde::Serialize;
+
+use tcp_chat_proto::packet::{ConnectionEvent, HistoryEntry, Report, RosterEntry};
+
+
+
+/// Something that happened, as printed in `--json` mode. Each line is an object with an `event`
+/// field naming the variant (e.g. `{"event":"message","id":3,...}`).
+#[derive(Serialize, Debug)]
+#[serde(tag = "event", rename_all = "snake_case")]
+pub enum JsonEvent {
+    /// A regular message. `id` is what `!quote` takes.
+    Message { id: Option<u64>, time: i64, from: String, text: String },
+    /// Something the server announced, like someone joining
+    Notice { time: i64, text: String },
+    Urgent { id: Option<u64>, time: i64, from: String, text: String },
+    /// A voice clip, saved to `path`. The path is missing when the clip couldn't be saved, or
+    /// the server no longer had it because it was sent a while ago.
+    Voice { id: Option<u64>, time: i64, from: String, format: String, path: Option<PathBuf> },
+    /// A file someone attached, saved to `path`, or missing if it couldn't be saved
+    Attachment { time: i64, from: String, name: String, path: Option<PathBuf> },
+    /// A message sent to us alone
+    Direct { time: i64, from: String, text: String },
+    /// A message that will be followed by an `expired` event with the same `ephemeral_id`
+    Ephemeral { ephemeral_id: u64, time: i64, from: String, text: String, secs: u64 },
+    Expired { ephemeral_id: u64 },
+    /// Someone changed their name, possibly us
+    Renamed { time: i64, from: String, to: String },
+    /// One of our messages went out to the room
+    Sent { id: u64 },
+    /// The server refused one of our messages
+    Rejected { reason: String },
+    Roster { clients: Vec<RosterEntry> },
+    Connections { events: Vec<ConnectionEvent> },
+    /// Someone reported someone else, passed on to us as a moderator
+    Report { report: Report },
+    /// Earlier messages asked for with `!history`, oldest first. `complete` means there's nothing
+    /// earlier.
+    History { messages: Vec<HistoryEntry>, complete: bool },
+    /// The room is end-to-end encrypted. `unlocked` says whether a passphrase for it was found,
+    /// otherwise messages can't be read or sent until one is given with `!passphrase`.
+    Encrypted { unlocked: bool },
+    /// The host closed the room. The client exits right after.
+    Closed,
+    /// The host kicked us. The client exits right after.
+    Kicked,
+    /// We became the room's operator while the host is gone, so the host's moderation commands
+    /// work, or stopped being it
+    Operator { operator: bool },
+    /// The connection dropped and the client is trying to get back in, which is followed by
+    /// `Reconnected`, or by an error once it gives up
+    Reconnecting,
+    /// Back in the room after the connection dropped. Anything missed meanwhile follows, without
+    /// repeating what had already arrived.
+    Reconnected,
+    Error { message: String },
+}
+
+/// Print `event` as a single line
+pub fn emit(event: &JsonEvent) {
+    // a value with no maps or floats in it can't fail to serialize
+    let line = serde_json::to_string(event).expect("[error] Unable to serialize an event");
+
+    // output is usually piped, so flush every line rather than leaving it in the buffer. If the
+    // pipe is gone there's nobody left to tell.
+    let mut stdout = io::stdout().lock();
+    let _ = writeln!(stdout, "{line}").and_then(|_| stdout.flush());
+}
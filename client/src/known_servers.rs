@@ -0,0 +1,97 @@
+use std::collections::HashMap;
+use std::fs;
+use std::io;
+use std::path::PathBuf;
+
+use serde::{Serialize, Deserialize};
+
+use tcp_chat_proto::noise::fingerprint;
+use tcp_chat_proto::transport::Address;
+
+use crate::config::data_dir;
+
+
+
+/// The Noise keys of servers connected to before, so a server that suddenly has a different key
+/// (which is what someone listening in between would look like) is noticed instead of trusted.
+/// Keys are remembered by the address exactly as the user typed it.
+#[derive(Serialize, Deserialize, Debug, Default)]
+pub struct KnownServers {
+    pub keys: HashMap<String, String>,
+}
+
+impl KnownServers {
+    fn path() -> Option<PathBuf> {
+        data_dir().map(|dir| dir.join("known_servers.json"))
+    }
+
+    /// Load the known keys from the data directory, or none if there aren't any yet
+    ///
+    /// # Errors
+    /// Fails if the file exists but can't be read or parsed. Starting over with no keys instead
+    /// would quietly forget every pinned key, and the next one saved would overwrite them for good.
+    pub fn load() -> io::Result<Self> {
+        let Some(path) = Self::path() else {
+            return Ok(Self::default());
+        };
+        let contents = match fs::read_to_string(&path) {
+            Ok(contents) => contents,
+            Err(e) if e.kind() == io::ErrorKind::NotFound => return Ok(Self::default()),
+            Err(e) => return Err(io::Error::new(
+                e.kind(),
+                format!("Unable to read {}: {e}", path.display())
+            )),
+        };
+        serde_json::from_str(&contents).map_err(|e| io::Error::new(
+            io::ErrorKind::InvalidData,
+            format!("{} is corrupted, fix or remove it and connect again: {e}", path.display())
+        ))
+    }
+
+    /// Write the known keys to the data directory
+    pub fn save(&self) -> io::Result<()> {
+        let path = Self::path().ok_or_else(|| io::Error::new(
+            io::ErrorKind::NotFound,
+            "Unable to determine the data directory"
+        ))?;
+        if let Some(dir) = path.parent() {
+            fs::create_dir_all(dir)?;
+        }
+        fs::write(path, serde_json::to_string_pretty(self)?)
+    }
+
+    /// Check the key the server at `label` made its handshake with, remembering it if it's the
+    /// first time connecting there. Servers on this machine are trusted as they are, since hosts
+    /// get a new key each time unless it's kept in the secret store.
+    ///
+    /// Returns whether the key was new.
+    ///
+    /// # Errors
+    /// Fails if the server's key isn't the one it had before, or the known keys can't be loaded.
+    pub fn verify(label: &str, addr: &Address, key: &[u8; 32]) -> io::Result<bool> {
+        if addr.is_local() {
+            return Ok(false);
+        }
+
+        let key = fingerprint(key);
+        let mut known = Self::load()?;
+        match known.keys.get(label) {
+            Some(pinned) if *pinned == key => Ok(false),
+            Some(pinned) => Err(io::Error::new(
+                io::ErrorKind::PermissionDenied,
+                format!(
+                    "{label} has a different key than last time (was {pinned}, now {key}). If the \
+                    host says they changed it, remove {label} from known_servers.json in the data \
+                    directory and connect again."
+                )
+            )),
+            None => {
+                known.keys.insert(label.to_string(), key);
+                // not being able to remember it isn't worth refusing to connect over, it just
+                // won't be checked next time
+                let _ = known.save();
+                Ok(true)
+            },
+        }
+    }
+}
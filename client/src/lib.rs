@@ -0,0 +1,35 @@
+//! The tcp_chat terminal client
+
+pub mod constants;
+pub mod helpers;
+pub mod result_repeat;
+
+mod client;
+mod config;
+mod commands;
+mod address_book;
+mod known_servers;
+mod display;
+mod notify;
+mod terminal;
+mod draft;
+mod presence;
+mod tracker;
+mod clock;
+mod sequence;
+mod echo;
+mod scrollback;
+mod quote;
+mod json_output;
+mod rooms;
+mod identity;
+mod filter;
+mod voice;
+mod attachments;
+
+pub use client::{client, interrupt};
+pub use config::{ClientConfig, secret_store, set_profile, profile, config_path, data_dir};
+pub use notify::NotificationConfig;
+pub use filter::{FilterConfig, FilterAction};
+pub use voice::VoiceConfig;
+pub use identity::Identity;
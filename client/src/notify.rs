@@ -0,0 +1,128 @@
+use std::io::{self, Write};
+use std::process::Command;
+use std::sync::Mutex;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::thread;
+use std::time::Duration;
+
+use serde::Deserialize;
+
+
+
+/// How long to wait between bells in a bell pattern so they're heard as separate rings
+const BELL_GAP: Duration = Duration::from_millis(150);
+
+/// A way of getting the user's attention
+#[derive(Deserialize, Debug, Clone)]
+#[serde(rename_all = "lowercase")]
+pub enum Sound {
+    /// Ring the terminal bell this many times
+    Bell(u32),
+    /// Run a shell command, e.g. to play an audio file
+    Command(String),
+}
+
+impl Sound {
+    /// Play the sound in the background so it doesn't hold up printing messages
+    fn play(&self) {
+        match self.clone() {
+            Sound::Bell(count) => {
+                thread::spawn(move || {
+                    for _ in 0..count {
+                        print!("\x07");
+                        let _ = io::stdout().flush();
+                        thread::sleep(BELL_GAP);
+                    }
+                });
+            },
+            Sound::Command(cmd) => {
+                let result = if cfg!(windows) {
+                    Command::new("cmd").args(["/C", &cmd]).spawn()
+                } else {
+                    Command::new("sh").args(["-c", &cmd]).spawn()
+                };
+                if let Err(e) = result {
+                    println!("[error] Unable to play notification sound: {e}");
+                }
+            },
+        }
+    }
+}
+
+/// Which sound to play for each kind of event. Events without a sound are silent.
+#[derive(Deserialize, Debug, Clone, Default)]
+#[serde(default)]
+pub struct NotificationConfig {
+    /// Someone said your name
+    pub on_mention: Option<Sound>,
+    /// Any message from another user
+    pub on_message: Option<Sound>,
+    /// A message sent to you alone. Falls back to `on_message` when unset.
+    pub on_dm: Option<Sound>,
+}
+
+/// Decides when to play notification sounds. Shared between the input thread, which can mute it
+/// and knows the user's name, and the thread receiving messages.
+pub struct Notifier {
+    config: NotificationConfig,
+    muted: AtomicBool,
+    name: Mutex<String>,
+}
+
+impl Notifier {
+    pub fn new(config: NotificationConfig, name: &str) -> Self {
+        Self {
+            config,
+            muted: AtomicBool::new(false),
+            name: Mutex::new(name.to_string()),
+        }
+    }
+
+    /// Keep track of the user's name so mentions still work after renaming
+    pub fn set_name(&self, name: &str) {
+        *self.name.lock().unwrap() = name.to_string();
+    }
+
+    /// The user's name as of their last rename
+    pub fn name(&self) -> String {
+        self.name.lock().unwrap().clone()
+    }
+
+    /// Flip between muted and unmuted, returning whether it's now muted
+    pub fn toggle_mute(&self) -> bool {
+        !self.muted.fetch_xor(true, Ordering::Relaxed)
+    }
+
+    /// Play whichever sound fits a message from another user. Mentions take priority over the
+    /// sound for every message so only one plays.
+    pub fn message(&self, text: &str) {
+        if self.muted.load(Ordering::Relaxed) {
+            return;
+        }
+
+        let mentioned = self.config.on_mention.as_ref()
+            .filter(|_| self.is_mention(text));
+
+        if let Some(sound) = mentioned.or(self.config.on_message.as_ref()) {
+            sound.play();
+        }
+    }
+
+    /// Play the sound for a message sent to the user alone
+    pub fn direct(&self) {
+        if self.muted.load(Ordering::Relaxed) {
+            return;
+        }
+
+        if let Some(sound) = self.config.on_dm.as_ref().or(self.config.on_message.as_ref()) {
+            sound.play();
+        }
+    }
+
+    /// Whether `text` contains the user's name as a whole word, with or without an '@'
+    fn is_mention(&self, text: &str) -> bool {
+        let name = self.name.lock().unwrap();
+        text.split(|c: char| !c.is_alphanumeric() && c != '_')
+            .any(|word| word.eq_ignore_ascii_case(&name))
+    }
+}
@@ -0,0 +1,62 @@
+use std::sync::{Arc, Mutex};
+use std::thread;
+use std::time::{Duration, Instant};
+
+use tcp_chat_proto::codec::WireFormat;
+use tcp_chat_proto::typed_conn::ClientWriter;
+use tcp_chat_proto::transport::Socket;
+use tcp_chat_proto::packet::{Presence, ClientMessage::ClientSetPresence};
+
+
+
+/// How often the presence watcher checks for (in)activity
+const CHECK_INTERVAL: Duration = Duration::from_secs(1);
+
+/// When the user last touched the keyboard
+pub struct Activity {
+    last_input: Mutex<Instant>,
+}
+
+impl Activity {
+    pub fn new() -> Self {
+        Self { last_input: Mutex::new(Instant::now()) }
+    }
+
+    /// Note that the user just pressed a key
+    pub fn touch(&self) {
+        *self.last_input.lock().unwrap() = Instant::now();
+    }
+
+    fn idle(&self) -> Duration {
+        self.last_input.lock().unwrap().elapsed()
+    }
+}
+
+/// Spawn a thread that marks the user as away after `away_after` without any input, and back
+/// online as soon as they type again
+pub fn watch_presence(conn: ClientWriter<WireFormat, Socket>, activity: Arc<Activity>, away_after: Duration) {
+    thread::Builder::new()
+        .name(String::from("client presence watcher"))
+        .spawn(move || {
+            let mut presence = Presence::Online;
+
+            loop {
+                thread::sleep(CHECK_INTERVAL);
+
+                let wanted = if activity.idle() >= away_after {
+                    Presence::Away
+                } else {
+                    Presence::Online
+                };
+
+                if wanted != presence {
+                    if conn.send(&ClientSetPresence(wanted)).is_err() {
+                        // the thread receiving messages handles losing the connection
+                        return;
+                    }
+                    presence = wanted;
+                }
+            }
+        })
+        .unwrap();
+}
@@ -0,0 +1,52 @@
+// Replying to earlier messages with a quote of them. A quote is sent as part of the reply's text,
+// as a line starting with "> " before the reply itself, so clients that don't know about quotes
+// still show something sensible.
+
+use std::collections::VecDeque;
+use std::sync::{Arc, Mutex};
+
+use crate::constants::MAX_QUOTABLE;
+
+
+
+/// The longest a quoted excerpt can be before it's cut short
+const EXCERPT_LEN: usize = 80;
+
+/// Recent messages that can be quoted, as (id, sender name, text). Shared between the thread
+/// receiving messages, which remembers them, and the input thread, which quotes them.
+pub type Quotes = Arc<Mutex<VecDeque<(u64, String, String)>>>;
+
+/// Keep a message around so it can be quoted by its id
+pub fn remember(quotes: &Quotes, id: u64, name: &str, text: &str) {
+    let mut unlocked = quotes.lock().unwrap();
+    unlocked.push_back((id, name.to_string(), text.to_string()));
+    if unlocked.len() > MAX_QUOTABLE {
+        unlocked.pop_front();
+    }
+}
+
+/// Build the text of a reply to message `id`, or `None` if there's no such message (or it's too
+/// old to remember)
+pub fn quote_reply(quotes: &Quotes, id: u64, reply: &str) -> Option<String> {
+    let unlocked = quotes.lock().unwrap();
+    let (_, name, text) = unlocked.iter().find(|(i, ..)| *i == id)?;
+
+    // quoting a reply quotes what was said rather than what it was replying to
+    let (_, said) = split_quote(text);
+    let first_line = said.lines().next().unwrap_or_default();
+
+    let mut excerpt: String = first_line.chars().take(EXCERPT_LEN).collect();
+    if excerpt.len() < said.trim_end().len() {
+        excerpt.push_str("...");
+    }
+
+    Some(format!("> {name}: {excerpt}\n{reply}"))
+}
+
+/// Split a message into the line it quotes (if any) and the rest of it
+pub fn split_quote(text: &str) -> (Option<&str>, &str) {
+    match text.strip_prefix("> ").and_then(|rest| rest.split_once('\n')) {
+        Some((quoted, rest)) => (Some(quoted), rest),
+        None => (None, text),
+    }
+}
@@ -13,7 +13,7 @@ where A: Fn() -> T
 {
     fn until_valid(&self, validator: Validator<T>) -> T {
         loop {
-            let x = self.call(());
+            let x = self();
     
             if validator(&x) {
                 return x;
@@ -33,7 +33,7 @@ where A: Fn() -> Result<T, E>
 {
     fn until_ok(&self) -> T {
         loop {
-            let x = self.call(());
+            let x = self();
 
             if let Ok(val) = x {
                 return val;
@@ -0,0 +1,188 @@
+// Every room the client is connected to at once. One of them is active: it's where whatever the
+// user types goes, and the only one printed as messages arrive. The rest keep receiving in the
+// background and catch up on the screen once they're switched to.
+
+use std::sync::{Arc, Mutex};
+use std::sync::atomic::AtomicBool;
+use std::thread::JoinHandle;
+
+use tcp_chat_proto::capability::Capabilities;
+use tcp_chat_proto::e2e::RoomKey;
+use tcp_chat_proto::codec::WireFormat;
+use tcp_chat_proto::packet::Limits;
+use tcp_chat_proto::tcp_conn::CancelToken;
+use tcp_chat_proto::typed_conn::ClientWriter;
+use tcp_chat_proto::transport::Socket;
+
+use crate::clock::Clock;
+use crate::display::{Display, Spoilers};
+use crate::draft::Draft;
+use crate::echo::Echoes;
+use crate::filter::Filters;
+use crate::notify::Notifier;
+use crate::quote::Quotes;
+use crate::voice::VoiceClips;
+
+
+
+/// Everything kept for one connection. Names, spoilers, and quotable messages are all per room, so
+/// nothing from one room can be mistaken for part of another.
+pub struct Room {
+    /// Stays the same while the room is joined, unlike its position in the list
+    pub id: u64,
+    /// What the user called the server when joining, to tell the rooms apart
+    pub label: String,
+    pub is_host: bool,
+    /// Whether the server made us the operator, standing in for the host while they're gone
+    pub is_operator: AtomicBool,
+    pub conn: ClientWriter<WireFormat, Socket>,
+    pub display: Display,
+    pub clock: Arc<Clock>,
+    pub echoes: Arc<Echoes>,
+    pub notifier: Arc<Notifier>,
+    /// Shared by every room, since they're the user's
+    pub filters: Arc<Filters>,
+    /// Also shared by every room, so clips are numbered the same whichever room they came from
+    pub voice: Arc<VoiceClips>,
+    pub spoilers: Spoilers,
+    pub quotes: Quotes,
+    pub draft: Draft,
+    /// Set once the user leaves, so the connection closing afterwards isn't mistaken for the room
+    /// closing on them
+    pub left: AtomicBool,
+    /// Wakes the thread receiving from the room once the user leaves, so it stops
+    pub cancel: CancelToken,
+    /// The thread receiving from the room, which exiting waits for
+    pub receiving: Mutex<Option<JoinHandle<()>>>,
+    pub history: Mutex<HistoryCursor>,
+    pub encryption: Mutex<Encryption>,
+    /// What the server said it accepts, which is checked before sending anything to it
+    pub limits: Mutex<Limits>,
+    /// What the server agreed to while connecting, see `capability`
+    pub capabilities: Mutex<Capabilities>,
+}
+
+/// A room's end-to-end encryption, for rooms the server says are encrypted
+#[derive(Default)]
+pub struct Encryption {
+    /// What the room's key is derived with, as given by the server
+    pub salt: Option<Vec<u8>>,
+    /// Set once the user's given the passphrase, after which everything sent is sealed with it
+    pub key: Option<RoomKey>,
+}
+
+/// How far back `!history` has paged in a room
+#[derive(Default)]
+pub struct HistoryCursor {
+    /// The earliest message id seen so far, from a broadcast or an earlier page. Paging continues
+    /// from just before it.
+    pub oldest: Option<u64>,
+    /// How many messages the last request asked for, to tell when a page came back short
+    pub requested: u32,
+}
+
+impl HistoryCursor {
+    /// Note that message `id` was seen
+    pub fn saw(&mut self, id: u64) {
+        self.oldest = Some(self.oldest.map_or(id, |oldest| oldest.min(id)));
+    }
+}
+
+#[derive(Default)]
+pub struct Rooms {
+    /// In the order they were joined, which is how they're numbered for `!switch`
+    joined: Mutex<Vec<Arc<Room>>>,
+    /// The id of the active room
+    active: Mutex<u64>,
+}
+
+impl Rooms {
+    /// Add a room to the end of the list. The first room added becomes the active one, any others
+    /// start out in the background.
+    pub fn add(&self, room: Arc<Room>) {
+        let mut joined = self.joined.lock().unwrap();
+        if joined.is_empty() {
+            *self.active.lock().unwrap() = room.id;
+        } else {
+            room.display.hide();
+        }
+        joined.push(room);
+    }
+
+    /// Take a room out of the list, returning whether it was still in it. If it was the active
+    /// room, the first remaining one takes over and is returned as well, still needing to catch
+    /// up (see `Display::catch_up`).
+    pub fn remove(&self, id: u64) -> (bool, Option<Arc<Room>>) {
+        let mut joined = self.joined.lock().unwrap();
+        let Some(index) = joined.iter().position(|room| room.id == id) else {
+            return (false, None);
+        };
+        joined.remove(index);
+
+        let mut active = self.active.lock().unwrap();
+        if *active != id {
+            return (true, None);
+        }
+
+        let next = joined.first().cloned();
+        if let Some(next) = &next {
+            *active = next.id;
+        }
+        (true, next)
+    }
+
+    /// The room whatever the user types goes to, or `None` once every room has closed
+    pub fn active(&self) -> Option<Arc<Room>> {
+        let active = *self.active.lock().unwrap();
+        self.joined.lock().unwrap().iter().find(|room| room.id == active).cloned()
+    }
+
+    /// Make room `number` (counting from 1, as listed by `list`) the active one. The room that was
+    /// active stops printing right away, while the new one is left to catch up on whatever it
+    /// received in the background (see `Display::catch_up`). Returns `None` if there's no such
+    /// room.
+    pub fn switch(&self, number: usize) -> Option<Arc<Room>> {
+        let joined = self.joined.lock().unwrap();
+        let room = joined.get(number.checked_sub(1)?)?;
+
+        let mut active = self.active.lock().unwrap();
+        if *active != room.id {
+            if let Some(previous) = joined.iter().find(|room| room.id == *active) {
+                previous.display.hide();
+            }
+            *active = room.id;
+        }
+        Some(Arc::clone(room))
+    }
+
+    /// Each room's label, whether it's the active one, and how many messages it has waiting
+    pub fn list(&self) -> Vec<(String, bool, usize)> {
+        let active = *self.active.lock().unwrap();
+        self.joined.lock().unwrap().iter()
+            .map(|room| (room.label.clone(), room.id == active, room.display.unseen()))
+            .collect()
+    }
+
+    /// Every room, for when they all need to be left at once
+    pub fn all(&self) -> Vec<Arc<Room>> {
+        self.joined.lock().unwrap().clone()
+    }
+
+    pub fn len(&self) -> usize {
+        self.joined.lock().unwrap().len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.joined.lock().unwrap().is_empty()
+    }
+
+    /// Save whatever hasn't been sent in every room. Skipped if the list is in use, since this is
+    /// also called when panicking, possibly while it's locked.
+    pub fn save_drafts(&self) {
+        if let Ok(joined) = self.joined.try_lock() {
+            for room in joined.iter() {
+                room.draft.save();
+            }
+        }
+    }
+}
@@ -0,0 +1,51 @@
+// Everything the client has shown from the room, kept so it can be paged back through once it's
+// scrolled off the screen or been cleared away
+
+use std::collections::VecDeque;
+
+use crate::constants::MAX_SCROLLBACK;
+
+
+
+#[derive(Default)]
+pub struct Scrollback {
+    lines: VecDeque<String>,
+    /// How many of the latest lines have already been paged through (or are still on the screen).
+    /// `None` means paging hasn't started, so the last screenful is assumed to be visible.
+    paged: Option<usize>,
+}
+
+impl Scrollback {
+    /// Keep `text` (which may be several lines), forgetting the oldest lines if there are too many.
+    /// Paging starts over from the bottom.
+    pub fn record(&mut self, text: &str) {
+        self.lines.extend(text.lines().map(String::from));
+        while self.lines.len() > MAX_SCROLLBACK {
+            self.lines.pop_front();
+        }
+        self.paged = None;
+    }
+
+    /// Change the most recent line, after it was changed on the screen
+    pub fn replace_last(&mut self, line: &str) {
+        if let Some(last) = self.lines.back_mut() {
+            *last = line.to_string();
+        }
+    }
+
+    /// The `size` lines before the ones paged through so far. Returns `None` once there's nothing
+    /// earlier.
+    pub fn previous_page(&mut self, size: usize) -> Option<Vec<String>> {
+        let skip = self.paged.unwrap_or(size);
+        let end = self.lines.len().checked_sub(skip).filter(|&end| end > 0)?;
+        let start = end.saturating_sub(size);
+
+        self.paged = Some(skip + end - start);
+        Some(self.lines.range(start..end).cloned().collect())
+    }
+
+    /// The screen was cleared, so paging starts from the very latest line
+    pub fn cleared(&mut self) {
+        self.paged = Some(0);
+    }
+}
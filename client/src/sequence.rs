@@ -0,0 +1,80 @@
+// Client side of numbered broadcasts. The server numbers everything it sends to the whole room, so
+// a jump in the numbers means something was missed and can be asked for again.
+
+use std::collections::HashSet;
+
+use crate::constants::MAX_BACKFILL;
+
+
+
+/// What a newly arrived broadcast means
+#[derive(Debug, PartialEq, Eq)]
+pub enum Arrival {
+    /// It hasn't been seen before
+    New,
+    /// It hasn't been seen before, but the broadcasts between `first` and `last` (inclusive) were
+    /// skipped to get to it. `lost` more before `first` were skipped too, but are too far back to
+    /// be worth asking for.
+    Skipped { first: u64, last: u64, lost: u64 },
+    /// It was already received, so it shouldn't be shown again
+    Duplicate,
+}
+
+/// Which broadcasts have arrived so far
+#[derive(Default)]
+pub struct Sequence {
+    /// The highest sequence number received. Anything before the first broadcast after joining
+    /// isn't considered missed.
+    latest: Option<u64>,
+    /// Skipped broadcasts that haven't been recovered yet, no more than `MAX_BACKFILL` behind the
+    /// latest
+    missing: HashSet<u64>,
+    /// Where broadcasts were asked for again from after reconnecting. Anything earlier arriving
+    /// afterwards means the server started numbering over.
+    resumed_from: Option<u64>,
+}
+
+impl Sequence {
+    /// Note that broadcast `seq` arrived
+    pub fn arrive(&mut self, seq: u64) -> Arrival {
+        // a server restarted without a snapshot counts from 0 again, and everything it sent would
+        // otherwise look like a duplicate until it caught up
+        if self.resumed_from.is_some_and(|from| seq < from) {
+            *self = Self::default();
+        }
+
+        let Some(latest) = self.latest else {
+            self.latest = Some(seq);
+            return Arrival::New;
+        };
+
+        if seq <= latest {
+            return if self.missing.remove(&seq) { Arrival::New } else { Arrival::Duplicate };
+        }
+
+        self.latest = Some(seq);
+
+        if seq == latest + 1 {
+            return Arrival::New;
+        }
+
+        // a server that jumped far ahead would otherwise have every number in between remembered
+        // and asked for, when it can't keep that many anyway
+        let oldest = seq.saturating_sub(MAX_BACKFILL);
+        self.missing.retain(|&missed| missed >= oldest);
+
+        let (first, last) = ((latest + 1).max(oldest), seq - 1);
+        self.missing.extend(first..=last);
+        Arrival::Skipped { first, last, lost: first - (latest + 1) }
+    }
+
+    /// Get ready to pick up where things left off after reconnecting. Returns the first broadcast
+    /// to ask for again, which is the earliest one still missing or else the one after the latest,
+    /// or `None` if none have arrived yet. Anything asked for that already arrived comes back as a
+    /// duplicate, so nothing is shown twice.
+    pub fn resume(&mut self) -> Option<u64> {
+        let from = self.missing.iter().min().copied().or(self.latest.map(|latest| latest + 1))?;
+        self.resumed_from = Some(from);
+        Some(from)
+    }
+}
@@ -0,0 +1,231 @@
+// Differences between terminals, mostly so the client behaves on Windows consoles as well as it
+// does on Unix-like terminals
+
+use std::io::{self, IsTerminal, Write};
+use std::sync::Mutex;
+
+use crossterm::{cursor, queue, terminal};
+use crossterm::event::{self, Event, KeyCode, KeyEventKind, KeyModifiers};
+use crossterm::style::Print;
+
+use crate::draft::Draft;
+use crate::presence::Activity;
+
+
+
+/// Width to assume when the terminal size can't be determined (e.g., output is redirected)
+const DEFAULT_WIDTH: usize = 80;
+
+/// Height to assume when the terminal size can't be determined
+const DEFAULT_HEIGHT: usize = 24;
+
+/// What's on the input line while the user is typing in a terminal, so output from other threads
+/// can go above it instead of over it
+static INPUT: Mutex<Option<String>> = Mutex::new(None);
+
+/// Get the terminal ready for the client's output, returning whether ANSI escape sequences (colors,
+/// bells, etc.) can be used. Windows consoles need virtual terminal processing switched on before
+/// they understand them, and older ones can't at all.
+pub fn init() -> bool {
+    #[cfg(windows)]
+    {
+        crossterm::ansi_support::supports_ansi()
+    }
+    #[cfg(not(windows))]
+    {
+        true
+    }
+}
+
+/// The current width of the terminal in columns. This is looked up every time rather than cached
+/// so output follows the terminal when it's resized.
+pub fn width() -> usize {
+    terminal::size()
+        .map(|(columns, _)| columns as usize)
+        .ok()
+        .filter(|&columns| columns > 0)
+        .unwrap_or(DEFAULT_WIDTH)
+}
+
+/// The current height of the terminal in rows
+pub fn height() -> usize {
+    terminal::size()
+        .map(|(_, rows)| rows as usize)
+        .ok()
+        .filter(|&rows| rows > 0)
+        .unwrap_or(DEFAULT_HEIGHT)
+}
+
+/// Print `text` on its own line. If the user is partway through typing, their line is cleared first
+/// and put back underneath, so incoming messages don't get mixed in with it.
+pub fn print_line(text: &str) {
+    let input = INPUT.lock().unwrap();
+    let Some(buf) = input.as_deref() else {
+        println!("{text}");
+        return;
+    };
+
+    let mut stdout = io::stdout();
+    let _ = queue!(
+        stdout,
+        cursor::MoveToColumn(0),
+        terminal::Clear(terminal::ClearType::CurrentLine),
+        Print(text),
+        Print("\n"),
+        Print(buf)
+    );
+    let _ = stdout.flush();
+}
+
+/// Wipe everything off the screen
+pub fn clear() -> io::Result<()> {
+    let mut stdout = io::stdout();
+    queue!(stdout, terminal::Clear(terminal::ClearType::All), cursor::MoveTo(0, 0))?;
+    stdout.flush()
+}
+
+/// Read a line typed by the user, keeping `draft` up to date with every keystroke so it can be
+/// saved if the client exits partway through, and noting each keystroke in `activity`. The returned
+/// line is not trimmed. In a terminal the line is cleared once enter is pressed so the caller can
+/// show it however it likes (see `show_typed`). Page up is a shortcut for `!more`, which leaves
+/// whatever was being typed in the draft to carry on with.
+/// 
+/// # Errors
+/// Ctrl-C, or Ctrl-D on an empty line, return an error of kind `io::ErrorKind::Interrupted`. The
+/// end of input returns `io::ErrorKind::UnexpectedEof`.
+pub fn read_line(draft: &Draft, activity: &Activity) -> io::Result<String> {
+    // the line can only be edited key by key in an actual terminal, so piped input is read whole
+    if !io::stdin().is_terminal() {
+        let line = read_line_plain();
+        activity.touch();
+        return line;
+    }
+
+    terminal::enable_raw_mode()?;
+    keep_output_processing();
+
+    let result = edit_line(draft, activity);
+    *INPUT.lock().unwrap() = None;
+
+    terminal::disable_raw_mode()?;
+    result
+}
+
+/// Put a line returned by `read_line` back on the screen as it was typed. Piped input never shows
+/// up on the screen in the first place, so it's left that way.
+pub fn show_typed(line: &str) {
+    if io::stdin().is_terminal() {
+        println!("{line}");
+    }
+}
+
+/// Replace the line above the cursor with `line`, leaving the cursor (and whatever is being typed)
+/// where it is. `line` needs to fit on one row.
+pub fn rewrite_previous_line(line: &str) -> io::Result<()> {
+    let _input = INPUT.lock().unwrap();
+    let mut stdout = io::stdout();
+    queue!(
+        stdout,
+        cursor::SavePosition,
+        cursor::MoveUp(1),
+        cursor::MoveToColumn(0),
+        terminal::Clear(terminal::ClearType::CurrentLine),
+        Print(line),
+        cursor::RestorePosition
+    )?;
+    stdout.flush()
+}
+
+/// Put the terminal back into its normal mode. This needs to happen before exiting if a thread
+/// might be in the middle of `read_line`, otherwise the user's shell is left in raw mode.
+pub fn restore() {
+    let _ = terminal::disable_raw_mode();
+}
+
+fn read_line_plain() -> io::Result<String> {
+    let mut buf = String::new();
+    if io::stdin().read_line(&mut buf)? == 0 {
+        return Err(io::Error::new(io::ErrorKind::UnexpectedEof, "No more input"));
+    }
+    Ok(buf.trim_end_matches(['\r', '\n']).to_string())
+}
+
+fn edit_line(draft: &Draft, activity: &Activity) -> io::Result<String> {
+    let mut buf = draft.text();
+    redraw(&buf)?;
+
+    loop {
+        let key = match event::read()? {
+            // Windows reports key releases as well as presses
+            Event::Key(key) if key.kind != KeyEventKind::Release => key,
+            Event::Resize(..) => {
+                redraw(&buf)?;
+                continue;
+            },
+            _ => continue,
+        };
+
+        activity.touch();
+        let ctrl = key.modifiers.contains(KeyModifiers::CONTROL);
+
+        match key.code {
+            KeyCode::Char('c') if ctrl => return Err(interrupted()),
+            KeyCode::Char('d') if ctrl && buf.is_empty() => return Err(interrupted()),
+            KeyCode::Char('u') if ctrl => buf.clear(),
+            KeyCode::Char(c) if !ctrl => buf.push(c),
+            KeyCode::PageUp => {
+                redraw("")?;
+                return Ok(String::from("!more"));
+            },
+            KeyCode::Backspace => {
+                buf.pop();
+            },
+            KeyCode::Enter => {
+                redraw("")?;
+                draft.set("");
+                return Ok(buf);
+            },
+            _ => continue,
+        }
+
+        draft.set(&buf);
+        redraw(&buf)?;
+    }
+}
+
+/// Replace the current line of the terminal with `buf`, remembering it as what's being typed
+fn redraw(buf: &str) -> io::Result<()> {
+    let mut input = INPUT.lock().unwrap();
+    *input = Some(buf.to_string());
+
+    let mut stdout = io::stdout();
+    queue!(
+        stdout,
+        cursor::MoveToColumn(0),
+        terminal::Clear(terminal::ClearType::CurrentLine),
+        Print(buf)
+    )?;
+    stdout.flush()
+}
+
+fn interrupted() -> io::Error {
+    io::Error::new(io::ErrorKind::Interrupted, "Input was interrupted")
+}
+
+/// Raw mode on Unix also turns off output processing, which would stop "\n" from returning the
+/// cursor to the start of the line for everything printed by other threads in the meantime. Only
+/// input needs to be raw, so output processing is turned back on.
+#[cfg(unix)]
+fn keep_output_processing() {
+    // SAFETY: `termios` is plain data that `tcgetattr` fills in before it's used
+    unsafe {
+        let mut termios: libc::termios = std::mem::zeroed();
+        if libc::tcgetattr(libc::STDOUT_FILENO, &mut termios) == 0 {
+            termios.c_oflag |= libc::OPOST | libc::ONLCR;
+            libc::tcsetattr(libc::STDOUT_FILENO, libc::TCSANOW, &termios);
+        }
+    }
+}
+
+#[cfg(not(unix))]
+fn keep_output_processing() {}
@@ -0,0 +1,43 @@
+// Browsing the public rooms listed by a tracker
+
+use std::net::{SocketAddr, TcpStream};
+use std::thread;
+use std::time::{Duration, Instant};
+
+use tcp_chat_proto::http;
+use tcp_chat_proto::packet::{ClientMessage::ClientQueryInfo, ServerMessage};
+use tcp_chat_proto::tcp_conn::TcpConn;
+use tcp_chat_proto::tracker::PublicRoom;
+
+
+
+/// How long to wait on each room when measuring latency
+const PING_TIMEOUT: Duration = Duration::from_secs(1);
+
+/// Get the public rooms from the tracker at `tracker_url`, each with how long it took to reach
+/// (`None` if it couldn't be reached)
+pub fn browse(tracker_url: &str) -> std::io::Result<Vec<(PublicRoom, Option<Duration>)>> {
+    let rooms: Vec<PublicRoom> = serde_json::from_str(&http::get(&format!("{tracker_url}/rooms"))?)?;
+
+    // ping every room at once so a few unreachable ones don't add up
+    let pings: Vec<_> = rooms.into_iter()
+        .map(|room| thread::spawn(move || {
+            let latency = ping(room.address).ok();
+            (room, latency)
+        }))
+        .collect();
+
+    Ok(pings.into_iter().filter_map(|ping| ping.join().ok()).collect())
+}
+
+/// Time a room info query. A bare connection isn't used since the server would sit waiting for a
+/// hello that never comes.
+fn ping(address: SocketAddr) -> std::io::Result<Duration> {
+    let start = Instant::now();
+
+    let mut conn = TcpConn::new(TcpStream::connect_timeout(&address, PING_TIMEOUT)?)?;
+    conn.send(&ClientQueryInfo)?;
+    conn.receive_timeout::<ServerMessage>(PING_TIMEOUT)?;
+
+    Ok(start.elapsed())
+}
@@ -0,0 +1,178 @@
+// Voice clips: recording or picking one to send, and keeping the ones that arrive so they can be
+// played back
+
+use std::fs;
+use std::io;
+use std::path::{Path, PathBuf};
+use std::process::{Command, Child};
+use std::sync::Mutex;
+
+use serde::Deserialize;
+
+use tcp_chat_proto::constants::MAX_VOICE_LEN;
+use tcp_chat_proto::helpers::unix_time_ms;
+use tcp_chat_proto::packet::VoiceClip;
+
+use crate::config::data_dir;
+use crate::helpers::safe_file_component;
+use crate::constants::VOICE_DIR_NAME;
+
+
+
+/// How clips are recorded and played. Both are shell commands with `{file}` standing in for the
+/// clip's path.
+#[derive(Deserialize, Debug, Clone)]
+#[serde(default)]
+pub struct VoiceConfig {
+    /// Records a clip to `{file}` and exits once it's done, e.g. `arecord -d 10 {file}`. Without
+    /// one, `!voice` has to be given a file to send.
+    pub record_command: Option<String>,
+    /// The format the record command records in, as a file extension
+    pub record_format: String,
+    /// Plays `{file}`, e.g. `mpv --no-video {file}`. Without one, `!play` just says where the clip
+    /// was saved.
+    pub player: Option<String>,
+}
+
+impl Default for VoiceConfig {
+    fn default() -> Self {
+        Self {
+            record_command: None,
+            record_format: String::from("wav"),
+            player: None,
+        }
+    }
+}
+
+/// The clips received so far, shared by all the rooms and numbered in the order they arrived, so
+/// `!play` can refer to them
+pub struct VoiceClips {
+    config: VoiceConfig,
+    saved: Mutex<Vec<PathBuf>>,
+}
+
+impl VoiceClips {
+    pub fn new(config: VoiceConfig) -> Self {
+        Self { config, saved: Mutex::default() }
+    }
+
+    /// Record a clip with the configured command, or read the one at `path`
+    pub fn prepare(&self, path: Option<&str>) -> Result<VoiceClip, String> {
+        let (path, format) = match path {
+            Some(path) => {
+                let format = Path::new(path).extension()
+                    .and_then(|extension| extension.to_str())
+                    .unwrap_or_default()
+                    .to_ascii_lowercase();
+                (PathBuf::from(path), format)
+            },
+            None => (self.record()?, self.config.record_format.clone()),
+        };
+
+        let data = fs::read(&path).map_err(|e| format!("Unable to read {}: {e}", path.display()))?;
+        let clip = VoiceClip { format, data };
+
+        if !clip.has_valid_format() {
+            return Err(format!("{} doesn't have an extension saying what format it's in", path.display()));
+        }
+        if clip.data.is_empty() {
+            return Err(format!("{} is empty", path.display()));
+        }
+        if clip.data.len() > MAX_VOICE_LEN {
+            return Err(format!(
+                "{} is too big to send ({} KiB, the most is {} KiB)",
+                path.display(),
+                clip.data.len().div_ceil(1024),
+                MAX_VOICE_LEN / 1024
+            ));
+        }
+        Ok(clip)
+    }
+
+    /// Run the record command, returning where it recorded to
+    fn record(&self) -> Result<PathBuf, String> {
+        let Some(command) = &self.config.record_command else {
+            return Err(String::from("Give a file to send, or set `voice.record_command` in the config to record one"));
+        };
+        let dir = voice_dir().ok_or("There's nowhere to record to")?;
+        fs::create_dir_all(&dir).map_err(|e| format!("Unable to create {}: {e}", dir.display()))?;
+
+        let path = dir.join(format!("recording.{}", self.config.record_format));
+        // an old recording left behind would otherwise be sent if this one fails
+        let _ = fs::remove_file(&path);
+
+        println!("Recording...");
+        let status = run(command, &path)
+            .and_then(|mut child| child.wait())
+            .map_err(|e| format!("Unable to run the record command: {e}"))?;
+        if !status.success() {
+            return Err(format!("The record command failed ({status})"));
+        }
+        Ok(path)
+    }
+
+    /// Save a clip `from` sent, returning its number for `!play` and where it was saved
+    pub fn save(&self, from: &str, clip: &VoiceClip) -> io::Result<(usize, PathBuf)> {
+        let dir = voice_dir().ok_or_else(|| io::Error::other("There's nowhere to save voice clips"))?;
+        fs::create_dir_all(&dir)?;
+
+        let from = safe_file_component(from);
+        let format = if clip.has_valid_format() { clip.format.as_str() } else { "bin" };
+        let path = dir.join(format!("{}-{from}.{format}", unix_time_ms()));
+        fs::write(&path, &clip.data)?;
+
+        let mut saved = self.saved.lock().unwrap();
+        saved.push(path.clone());
+        Ok((saved.len(), path))
+    }
+
+    /// Play clip `number`, or the latest one without a number. Returns what happened, to show the
+    /// user.
+    pub fn play(&self, number: Option<usize>) -> Result<String, String> {
+        let saved = self.saved.lock().unwrap();
+        let path = match number {
+            Some(number) => number.checked_sub(1).and_then(|index| saved.get(index)),
+            None => saved.last(),
+        };
+        let Some(path) = path else {
+            return Err(match number {
+                Some(number) => format!("There is no voice clip {number}"),
+                None => String::from("No voice clips have arrived yet"),
+            });
+        };
+
+        let Some(player) = &self.config.player else {
+            return Ok(format!("Saved at {}, set `voice.player` in the config to play clips from here", path.display()));
+        };
+        // played in the background so messages keep arriving meanwhile
+        match run(player, path) {
+            Ok(_) => Ok(format!("Playing {}", path.display())),
+            Err(e) => Err(format!("Unable to run the player: {e}")),
+        }
+    }
+}
+
+/// Where clips are recorded and saved
+fn voice_dir() -> Option<PathBuf> {
+    data_dir().map(|dir| dir.join(VOICE_DIR_NAME))
+}
+
+/// Start the shell command `command` with `{file}` replaced by `path`
+fn run(command: &str, path: &Path) -> io::Result<Child> {
+    let command = command.replace("{file}", &quote(path));
+    if cfg!(windows) {
+        Command::new("cmd").args(["/C", &command]).spawn()
+    } else {
+        Command::new("sh").args(["-c", &command]).spawn()
+    }
+}
+
+/// `path` quoted for the shell, since data directories can have spaces in them
+fn quote(path: &Path) -> String {
+    let path = path.display().to_string();
+    if cfg!(windows) {
+        format!("\"{path}\"")
+    } else {
+        format!("'{}'", path.replace('\'', r"'\''"))
+    }
+}
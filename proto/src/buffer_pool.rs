@@ -0,0 +1,75 @@
+// Scratch buffers for serializing messages into. Sending allocates nothing once the pool has warmed
+// up, which adds up when the server is sending the same broadcast to every client.
+
+use std::ops::{Deref, DerefMut};
+use std::sync::Mutex;
+
+
+
+/// Buffers that grew beyond this many bytes (e.g. for a huge message) aren't kept, so one big
+/// message doesn't hold on to memory forever
+const MAX_POOLED_CAPACITY: usize = 64 * 1024;
+
+/// The most buffers kept around at once
+const MAX_POOLED_BUFFERS: usize = 32;
+
+/// The pool used for sending messages
+pub static POOL: BufferPool = BufferPool::new();
+
+/// A stack of empty buffers ready to be reused
+pub struct BufferPool {
+    buffers: Mutex<Vec<Vec<u8>>>,
+}
+
+impl BufferPool {
+    pub const fn new() -> Self {
+        Self { buffers: Mutex::new(Vec::new()) }
+    }
+
+    /// Get an empty buffer, which goes back to the pool when it's dropped
+    pub fn take(&self) -> PooledBuffer<'_> {
+        let buffer = self.buffers.lock().unwrap().pop().unwrap_or_default();
+        PooledBuffer { buffer, pool: self }
+    }
+}
+
+impl Default for BufferPool {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+/// A buffer borrowed from a `BufferPool`. Derefs to the `Vec<u8>` itself.
+pub struct PooledBuffer<'a> {
+    buffer: Vec<u8>,
+    pool: &'a BufferPool,
+}
+
+impl Deref for PooledBuffer<'_> {
+    type Target = Vec<u8>;
+
+    fn deref(&self) -> &Self::Target {
+        &self.buffer
+    }
+}
+
+impl DerefMut for PooledBuffer<'_> {
+    fn deref_mut(&mut self) -> &mut Self::Target {
+        &mut self.buffer
+    }
+}
+
+impl Drop for PooledBuffer<'_> {
+    fn drop(&mut self) {
+        if self.buffer.capacity() > MAX_POOLED_CAPACITY {
+            return;
+        }
+
+        let mut buffers = self.pool.buffers.lock().unwrap();
+        if buffers.len() < MAX_POOLED_BUFFERS {
+            let mut buffer = std::mem::take(&mut self.buffer);
+            buffer.clear();
+            buffers.push(buffer);
+        }
+    }
+}
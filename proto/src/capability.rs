@@ -0,0 +1,84 @@
+// What each end of a connection can do beyond the basics, agreed on while connecting. Clients that
+// want any of it offer what they support with `ClientCapabilities` before their hello, and the
+// server answers with `ServerHello` and the ones it supports too. Clients that don't ask get none
+// of it, so they keep working as they always have.
+
+
+
+/// Something a client and server only do once both have said they can
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Capability {
+    /// The server says how much it accepts with `ServerLimits` when the client joins
+    Limits,
+    /// The client can send `ClientTrackedText`, which the server acknowledges with `ServerAck`
+    Acks,
+    /// The client sends `ClientPing` every `HEARTBEAT_INTERVAL`, and the server drops it if it
+    /// stops
+    Heartbeats,
+    /// The server says which name the client was let in under with `ServerWelcome`
+    Welcome,
+}
+
+impl Capability {
+    /// Every capability this end knows
+    pub const ALL: [Self; 4] = [Self::Limits, Self::Acks, Self::Heartbeats, Self::Welcome];
+
+    /// What the capability is called when offering it
+    pub fn name(self) -> &'static str {
+        match self {
+            Self::Limits => "limits",
+            Self::Acks => "acks",
+            Self::Heartbeats => "heartbeats",
+            Self::Welcome => "welcome",
+        }
+    }
+
+    /// The capability called `name`, or `None` if it isn't one this end knows
+    pub fn from_name(name: &str) -> Option<Self> {
+        Self::ALL.into_iter().find(|capability| capability.name() == name)
+    }
+
+    fn bit(self) -> u32 {
+        1 << self as u32
+    }
+}
+
+/// A set of capabilities, like the ones agreed on for a connection
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct Capabilities {
+    bits: u32,
+}
+
+impl Capabilities {
+    /// Every capability this end knows
+    pub fn all() -> Self {
+        let bits = Capability::ALL.into_iter().fold(0, |bits, capability| bits | capability.bit());
+        Self { bits }
+    }
+
+    /// The capabilities called `names`, skipping any this end doesn't know, which the other end
+    /// may well be newer than
+    pub fn from_names(names: &[String]) -> Self {
+        let bits = names.iter()
+            .filter_map(|name| Capability::from_name(name))
+            .fold(0, |bits, capability| bits | capability.bit());
+        Self { bits }
+    }
+
+    /// What the capabilities in the set are called, to offer or agree to them
+    pub fn names(self) -> Vec<String> {
+        Capability::ALL.into_iter()
+            .filter(|&capability| self.contains(capability))
+            .map(|capability| capability.name().to_string())
+            .collect()
+    }
+
+    pub fn contains(self, capability: Capability) -> bool {
+        self.bits & capability.bit() != 0
+    }
+
+    /// The capabilities in both sets, which is what two ends can agree on
+    pub fn intersection(self, other: Self) -> Self {
+        Self { bits: self.bits & other.bits }
+    }
+}
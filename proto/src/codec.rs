@@ -0,0 +1,396 @@
+// How messages are turned into the bytes inside a frame and back. `TcpConn` only deals with
+// framing, so a different wire format is just another `Codec`.
+
+use std::io::{self, Read};
+
+use bincode::Options;
+use serde::{Serialize, Deserialize};
+use serde::de::DeserializeOwned;
+
+use crate::tcp_conn::{limit_error, reconstruction_error};
+
+
+
+/// How deeply arrays and objects can be nested in a JSON payload. Nothing exchanged goes more than
+/// a handful of levels deep, so anything past this is refused rather than risking the stack.
+const MAX_NESTING_DEPTH: usize = 32;
+
+/// Serializes messages into the payload of a frame and deserializes them back out. Both ends of a
+/// connection have to use the same one.
+pub trait Codec {
+    /// Serialize `data`, appending it to `out`
+    ///
+    /// # Errors
+    /// Fails if `data` can't be represented in this format.
+    fn encode<T>(&self, data: &T, out: &mut Vec<u8>) -> io::Result<()>
+    where T: Serialize;
+
+    /// Deserialize a whole payload into `T`
+    ///
+    /// # Errors
+    /// Fails with `io::ErrorKind::InvalidData` if the payload isn't a valid `T`. It stays in the
+    /// buffer afterwards, so it can still be tried as some other type.
+    fn decode<T>(&self, payload: &[u8]) -> io::Result<T>
+    where T: DeserializeOwned;
+
+    /// Deserialize a payload into `T` as it's read from `reader`, for payloads too big to want
+    /// all in memory at once. The payload is no more than `max` bytes, which codecs that size
+    /// what they allocate by lengths in the payload hold those lengths to. Unless a codec says
+    /// otherwise, this reads the whole payload first and hands it to `decode`.
+    ///
+    /// # Errors
+    /// The same as `decode`, along with whatever `reader` fails with. Unlike with `decode`, what
+    /// was read is gone either way.
+    fn decode_from<T, R>(&self, reader: R, max: usize) -> io::Result<T>
+    where T: DeserializeOwned, R: Read {
+        let mut payload = Vec::new();
+        reader.take(max as u64).read_to_end(&mut payload)?;
+        self.decode(&payload)
+    }
+}
+
+/// JSON, which is what everything spoke before there was a choice, and so the default
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+pub struct Json;
+
+impl Codec for Json {
+    fn encode<T>(&self, data: &T, out: &mut Vec<u8>) -> io::Result<()>
+    where T: Serialize {
+        serde_json::to_writer(out, data)?;
+        Ok(())
+    }
+
+    fn decode<T>(&self, payload: &[u8]) -> io::Result<T>
+    where T: DeserializeOwned {
+        if too_deep(payload) {
+            return Err(limit_error(format!(
+                "Message is nested more than {MAX_NESTING_DEPTH} levels deep"
+            )));
+        }
+        serde_json::from_slice(payload).map_err(|_| reconstruction_error::<T>())
+    }
+
+    fn decode_from<T, R>(&self, reader: R, _max: usize) -> io::Result<T>
+    where T: DeserializeOwned, R: Read {
+        let reader = NestingChecked { reader, nesting: Nesting::default() };
+        serde_json::from_reader(reader).map_err(|e| match e.is_io() {
+            // the reader's own errors, like the nesting limit, say more than ours would
+            true => e.into(),
+            false => reconstruction_error::<T>(),
+        })
+    }
+}
+
+/// bincode, which is far more compact than JSON and quicker to get in and out of. Unlike JSON, how
+/// deeply a payload nests isn't limited. Nothing a client sends can nest, so this is only a concern
+/// for clients of a server that's out to crash them.
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+pub struct Bincode;
+
+impl Codec for Bincode {
+    fn encode<T>(&self, data: &T, out: &mut Vec<u8>) -> io::Result<()>
+    where T: Serialize {
+        bincode::serialize_into(out, data).map_err(io::Error::other)
+    }
+
+    fn decode<T>(&self, payload: &[u8]) -> io::Result<T>
+    where T: DeserializeOwned {
+        bincode::deserialize(payload).map_err(|_| reconstruction_error::<T>())
+    }
+
+    /// Lengths in the payload are held to `max`, so one that claims to be gigabytes long is refused
+    /// rather than trusted
+    fn decode_from<T, R>(&self, reader: R, max: usize) -> io::Result<T>
+    where T: DeserializeOwned, R: Read {
+        // the same options `bincode::serialize_into` uses, with the limit on top
+        let options = bincode::DefaultOptions::new()
+            .with_fixint_encoding()
+            .allow_trailing_bytes()
+            .with_limit(max as u64);
+        options.deserialize_from(reader).map_err(|e| match *e {
+            bincode::ErrorKind::Io(e) => e,
+            bincode::ErrorKind::SizeLimit => limit_error(format!("Message is more than {max} bytes")),
+            _ => reconstruction_error::<T>(),
+        })
+    }
+}
+
+/// MessagePack, for clients written in languages with better support for it than for bincode.
+/// Structs are written as maps with their field names, so messages have the same shape as in JSON.
+#[cfg(feature = "msgpack")]
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+pub struct MessagePack;
+
+#[cfg(feature = "msgpack")]
+impl Codec for MessagePack {
+    fn encode<T>(&self, data: &T, out: &mut Vec<u8>) -> io::Result<()>
+    where T: Serialize {
+        rmp_serde::encode::write_named(out, data).map_err(io::Error::other)
+    }
+
+    fn decode<T>(&self, payload: &[u8]) -> io::Result<T>
+    where T: DeserializeOwned {
+        let mut deserializer = rmp_serde::Deserializer::from_read_ref(payload);
+        deserializer.set_max_depth(MAX_NESTING_DEPTH);
+        T::deserialize(&mut deserializer).map_err(|_| reconstruction_error::<T>())
+    }
+
+    fn decode_from<T, R>(&self, reader: R, _max: usize) -> io::Result<T>
+    where T: DeserializeOwned, R: Read {
+        let mut deserializer = rmp_serde::Deserializer::new(reader);
+        deserializer.set_max_depth(MAX_NESTING_DEPTH);
+        T::deserialize(&mut deserializer).map_err(|e| match e {
+            rmp_serde::decode::Error::InvalidMarkerRead(e) | rmp_serde::decode::Error::InvalidDataRead(e) => e,
+            _ => reconstruction_error::<T>(),
+        })
+    }
+}
+
+/// One of the codecs above, picked while connecting. Clients that want something other than JSON
+/// offer it with `ClientWireFormats` before their hello, and the server answers with
+/// `ServerWireFormat` before both ends switch. Everything until then is JSON, so clients that
+/// don't ask keep working as they always have.
+#[derive(Serialize, Deserialize, Debug, Default, Clone, Copy, PartialEq, Eq)]
+#[serde(rename_all = "lowercase")]
+pub enum WireFormat {
+    #[default]
+    Json,
+    Bincode,
+    /// Only with the `msgpack` feature
+    #[cfg(feature = "msgpack")]
+    MessagePack,
+}
+
+impl WireFormat {
+    /// What the format is called when offering it, which is also how it's written in the config
+    pub fn name(self) -> &'static str {
+        match self {
+            Self::Json => "json",
+            Self::Bincode => "bincode",
+            #[cfg(feature = "msgpack")]
+            Self::MessagePack => "messagepack",
+        }
+    }
+
+    /// The format called `name`, or `None` if it isn't one this end knows
+    pub fn from_name(name: &str) -> Option<Self> {
+        match name {
+            "json" => Some(Self::Json),
+            "bincode" => Some(Self::Bincode),
+            #[cfg(feature = "msgpack")]
+            "messagepack" => Some(Self::MessagePack),
+            _ => None,
+        }
+    }
+}
+
+impl Codec for WireFormat {
+    fn encode<T>(&self, data: &T, out: &mut Vec<u8>) -> io::Result<()>
+    where T: Serialize {
+        match self {
+            Self::Json => Json.encode(data, out),
+            Self::Bincode => Bincode.encode(data, out),
+            #[cfg(feature = "msgpack")]
+            Self::MessagePack => MessagePack.encode(data, out),
+        }
+    }
+
+    fn decode<T>(&self, payload: &[u8]) -> io::Result<T>
+    where T: DeserializeOwned {
+        match self {
+            Self::Json => Json.decode(payload),
+            Self::Bincode => Bincode.decode(payload),
+            #[cfg(feature = "msgpack")]
+            Self::MessagePack => MessagePack.decode(payload),
+        }
+    }
+
+    fn decode_from<T, R>(&self, reader: R, max: usize) -> io::Result<T>
+    where T: DeserializeOwned, R: Read {
+        match self {
+            Self::Json => Json.decode_from(reader, max),
+            Self::Bincode => Bincode.decode_from(reader, max),
+            #[cfg(feature = "msgpack")]
+            Self::MessagePack => MessagePack.decode_from(reader, max),
+        }
+    }
+}
+
+/// Whether `payload` nests arrays and objects deeper than `MAX_NESTING_DEPTH`. This is checked
+/// before deserializing since serde_json only stops at a depth far beyond anything legitimate.
+/// Brackets inside strings don't count.
+fn too_deep(payload: &[u8]) -> bool {
+    Nesting::default().exceeded_by(payload)
+}
+
+/// How deeply nested a JSON payload is at the point it's been read up to, so it can be checked a
+/// piece at a time as it arrives
+#[derive(Default)]
+struct Nesting {
+    depth: usize,
+    in_string: bool,
+    escaped: bool,
+}
+
+impl Nesting {
+    /// Follow the payload through `bytes`, which come after whatever this was given before,
+    /// returning whether it's now nested deeper than `MAX_NESTING_DEPTH`
+    fn exceeded_by(&mut self, bytes: &[u8]) -> bool {
+        for &byte in bytes {
+            if self.in_string {
+                match byte {
+                    _ if self.escaped => self.escaped = false,
+                    b'\\' => self.escaped = true,
+                    b'"' => self.in_string = false,
+                    _ => {},
+                }
+                continue;
+            }
+
+            match byte {
+                b'"' => self.in_string = true,
+                b'[' | b'{' => {
+                    self.depth += 1;
+                    if self.depth > MAX_NESTING_DEPTH {
+                        return true;
+                    }
+                },
+                b']' | b'}' => self.depth = self.depth.saturating_sub(1),
+                _ => {},
+            }
+        }
+        false
+    }
+}
+
+/// Passes a JSON payload through from `reader`, failing as soon as it nests too deeply, since
+/// there's no whole payload to check beforehand when deserializing as it arrives
+struct NestingChecked<R> {
+    reader: R,
+    nesting: Nesting,
+}
+
+impl<R: Read> Read for NestingChecked<R> {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        let read = self.reader.read(buf)?;
+        if self.nesting.exceeded_by(&buf[..read]) {
+            return Err(limit_error(format!(
+                "Message is nested more than {MAX_NESTING_DEPTH} levels deep"
+            )));
+        }
+        Ok(read)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::thread;
+
+    use super::*;
+    use crate::local::LocalStream;
+    use crate::packet::{ClientMessage, ServerMessage};
+    use crate::tcp_conn::{TcpConn, CHUNK_SIZE};
+
+    /// Every format this build knows
+    fn formats() -> Vec<WireFormat> {
+        ["json", "bincode", "messagepack"].into_iter().filter_map(WireFormat::from_name).collect()
+    }
+
+    #[derive(Serialize, Deserialize, Debug, PartialEq)]
+    enum Kind {
+        Plain,
+        Tagged(u8, String),
+    }
+
+    #[derive(Serialize, Deserialize, Debug, PartialEq)]
+    struct Sample {
+        name: String,
+        numbers: Vec<u64>,
+        kind: Kind,
+        inner: Option<Box<Sample>>,
+    }
+
+    fn sample(name: &str) -> Sample {
+        Sample {
+            name: name.to_string(),
+            numbers: vec![0, 1, u64::MAX],
+            kind: Kind::Tagged(7, String::from("tag \"quoted\" ]}")),
+            inner: Some(Box::new(Sample { name: String::new(), numbers: Vec::new(), kind: Kind::Plain, inner: None })),
+        }
+    }
+
+    #[test]
+    fn payloads_round_trip() {
+        for format in formats() {
+            let mut payload = Vec::new();
+            format.encode(&sample("payload"), &mut payload).unwrap();
+            assert_eq!(format.decode::<Sample>(&payload).unwrap(), sample("payload"), "{format:?}");
+            assert_eq!(format.decode_from::<Sample, _>(&payload[..], payload.len()).unwrap(), sample("payload"), "{format:?}");
+        }
+    }
+
+    #[test]
+    fn messages_round_trip_over_a_connection() {
+        for format in formats() {
+            let (ours, theirs) = LocalStream::pair();
+            let mut client = TcpConn::with_codec(ours, format).unwrap();
+            let mut server = TcpConn::with_codec(theirs, format).unwrap();
+
+            client.send(&ClientMessage::ClientTrackedText(3, String::from("hi"))).unwrap();
+            let received = server.receive::<ClientMessage>().unwrap();
+            assert!(matches!(&received, ClientMessage::ClientTrackedText(3, text) if text == "hi"), "{format:?}: {received:?}");
+
+            server.send(&ServerMessage::ServerText(String::from("alice"), String::from("hello"))).unwrap();
+            let received = client.receive::<ServerMessage>().unwrap();
+            assert!(matches!(&received, ServerMessage::ServerText(name, text) if name == "alice" && text == "hello"), "{format:?}: {received:?}");
+        }
+    }
+
+    #[test]
+    fn chunked_messages_stream_in() {
+        for format in formats() {
+            let (ours, theirs) = LocalStream::pair();
+            let mut sender = TcpConn::with_codec(ours, format).unwrap();
+            let mut receiver = TcpConn::with_codec(theirs, format).unwrap();
+
+            // several chunks' worth, so it's deserialized while it's still being sent
+            let mut big = sample("big");
+            big.numbers = (0..CHUNK_SIZE as u64).map(|n| n.wrapping_mul(0x9E37_79B9_7F4A_7C15)).collect();
+            let sending = thread::spawn(move || {
+                sender.send(&big).unwrap();
+                big
+            });
+
+            let received = receiver.receive_streaming::<Sample>().unwrap();
+            assert_eq!(received, sending.join().unwrap(), "{format:?}");
+        }
+    }
+
+    #[test]
+    fn the_wrong_type_is_invalid() {
+        for format in formats() {
+            let mut payload = Vec::new();
+            format.encode(&Kind::Plain, &mut payload).unwrap();
+            assert!(format.decode::<Sample>(&payload).is_err(), "{format:?}");
+        }
+    }
+
+    #[test]
+    fn deep_json_is_refused() {
+        let deep = "[".repeat(MAX_NESTING_DEPTH + 1) + &"]".repeat(MAX_NESTING_DEPTH + 1);
+        assert!(Json.decode::<serde_json::Value>(deep.as_bytes()).is_err());
+        assert!(Json.decode_from::<serde_json::Value, _>(deep.as_bytes(), deep.len()).is_err());
+
+        // brackets in strings don't count
+        let quoted = format!("\"{}\"", "[".repeat(MAX_NESTING_DEPTH + 1));
+        assert!(Json.decode::<String>(quoted.as_bytes()).is_ok());
+    }
+
+    #[test]
+    fn bincode_lengths_are_held_to_the_payload() {
+        // a string claiming to be far longer than what's there
+        let payload = u64::MAX.to_le_bytes();
+        let e = Bincode.decode_from::<String, _>(&payload[..], payload.len()).unwrap_err();
+        assert_eq!(e.to_string(), "Message is more than 8 bytes");
+    }
+}
@@ -0,0 +1,62 @@
+use std::net::{IpAddr, Ipv4Addr, SocketAddr};
+use std::time::Duration;
+
+
+
+pub const PORT: u16 = 42069;
+pub const LOOPBACK: IpAddr = IpAddr::V4(Ipv4Addr::new(127, 0, 0, 1));
+pub const LOOPBACK_SOCKET: SocketAddr = SocketAddr::new(LOOPBACK, PORT);
+pub const BIND_ADDR: IpAddr = IpAddr::V4(Ipv4Addr::new(0, 0, 0, 0));
+pub const BIND_SOCKET: SocketAddr = SocketAddr::new(BIND_ADDR, PORT);
+
+/// Version of the messages exchanged between client and server. Bump this whenever `ClientMessage`
+/// or `ServerMessage` changes in a way older clients or servers won't understand.
+pub const PROTOCOL_VERSION: u32 = 15;
+
+/// What every frame starts with, so a connection from something that isn't speaking tcp_chat is
+/// noticed straight away
+pub const FRAME_MAGIC: [u8; 2] = *b"TC";
+
+/// Version of the framing around messages, which comes right after `FRAME_MAGIC`. Unlike
+/// `PROTOCOL_VERSION`, a mismatch here means nothing the other end sends can even be read, so the
+/// connection is given up on.
+pub const FRAME_VERSION: u8 = 6;
+
+/// The longest any one string in a message from a client can be, in bytes. Text messages are held
+/// to a (usually much lower) limit by the server's config as well, this just stops anything
+/// enormous from getting any further than the connection.
+pub const MAX_FIELD_LEN: usize = 16 * 1024;
+
+/// The biggest voice clip a client can send, in bytes, for the same reason. Servers usually allow
+/// less, see their config. Encoded, this still fits comfortably in one frame.
+pub const MAX_VOICE_LEN: usize = 512 * 1024;
+
+/// The biggest attachment a client can send, in bytes. Servers usually allow less, see their
+/// config. Its bytes arrive in chunks, so this only has to fit under the maximum message size.
+pub const MAX_ATTACHMENT_LEN: usize = 8 * 1024 * 1024;
+
+/// The channel the bytes of attachments are sent on, so chat on the main channel carries on while
+/// they arrive
+pub const ATTACHMENT_CHANNEL: u8 = 1;
+
+/// The most wire formats a client can offer at once, far more than there are
+pub const MAX_WIRE_FORMATS: usize = 8;
+
+/// The most capabilities a client can offer at once, leaving plenty of room for new ones
+pub const MAX_CAPABILITIES: usize = 32;
+
+/// How often clients that agreed to `Capability::Heartbeats` send `ClientPing`, so the server hears
+/// from them even while they're quiet. Servers drop them if they haven't heard from them in a few
+/// of these.
+pub const HEARTBEAT_INTERVAL: Duration = Duration::from_secs(5);
+
+/// The most messages a server sends back for one `ClientRequestHistory`, however many were asked
+/// for, so a shorter page than that is the start of the room's history
+pub const MAX_HISTORY_PAGE: u32 = 100;
+
+/// The id the host gets when they join their own server, through the connector it hands them.
+/// Everyone else is numbered from the one after it.
+pub const HOST_ID: u64 = 0;
+
+/// The port a tracker listens on when run with `--tracker`
+pub const TRACKER_PORT: u16 = 42071;
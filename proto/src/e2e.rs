@@ -0,0 +1,137 @@
+// End-to-end encryption for rooms. Everyone in the room derives the same key from a passphrase
+// they were given some other way, so messages are sealed before they leave one client and only
+// opened once they reach another. The server, and anything between it and the clients, only ever
+// relays ciphertext. Each message is bound to who sent it and which room it was sent in, so the
+// server can't pass one off as someone else's or replay it into another room with the same
+// passphrase.
+
+use std::io;
+
+use argon2::Argon2;
+use chacha20poly1305::{XChaCha20Poly1305, XNonce, KeyInit};
+use chacha20poly1305::aead::{Aead, AeadCore, OsRng, Payload, rand_core::RngCore};
+
+use crate::packet::Sealed;
+
+/// How long the salt a room's key is derived with is, in bytes
+pub const SALT_LEN: usize = 16;
+
+/// How much longer sealing makes a message, for the tag that proves it wasn't tampered with
+pub const TAG_LEN: usize = 16;
+
+const NONCE_LEN: usize = 24;
+
+
+
+/// A fresh salt for a room that's starting to be encrypted
+pub fn new_salt() -> Vec<u8> {
+    let mut salt = vec![0; SALT_LEN];
+    OsRng.fill_bytes(&mut salt);
+    salt
+}
+
+/// The key a room's messages are sealed with
+pub struct RoomKey {
+    cipher: XChaCha20Poly1305,
+    /// The room's salt, which is unique to the room, so it also tells rooms apart
+    salt: Vec<u8>,
+}
+
+impl RoomKey {
+    /// The key for the room with `salt`, from its passphrase. This is deliberately slow, so only
+    /// do it once per room.
+    pub fn derive(passphrase: &str, salt: &[u8]) -> io::Result<Self> {
+        let mut key = [0; 32];
+        Argon2::default().hash_password_into(passphrase.as_bytes(), salt, &mut key)
+            .map_err(|e| io::Error::other(format!("Unable to derive the room's key: {e}")))?;
+        Ok(Self { cipher: XChaCha20Poly1305::new(&key.into()), salt: salt.to_vec() })
+    }
+
+    /// Encrypt `text` for the room, as said by `sender`
+    pub fn seal(&self, sender: &str, text: &str) -> Sealed {
+        let nonce = XChaCha20Poly1305::generate_nonce(&mut OsRng);
+        let payload = Payload { msg: text.as_bytes(), aad: &self.associated_data(sender) };
+        // encrypting into memory only fails for messages far bigger than a frame
+        let ciphertext = self.cipher.encrypt(&nonce, payload)
+            .expect("[error] Unable to encrypt a message");
+        Sealed { nonce: nonce.to_vec(), ciphertext }
+    }
+
+    /// Decrypt a message the server says `sender` sealed for the room. Returns `None` if it was
+    /// sealed with a different key, which usually means someone has the wrong passphrase, or was
+    /// tampered with or reattributed on the way.
+    pub fn open(&self, sender: &str, sealed: &Sealed) -> Option<String> {
+        if sealed.nonce.len() != NONCE_LEN {
+            return None;
+        }
+        let payload = Payload { msg: &sealed.ciphertext, aad: &self.associated_data(sender) };
+        let plaintext = self.cipher.decrypt(XNonce::from_slice(&sealed.nonce), payload).ok()?;
+        String::from_utf8(plaintext).ok()
+    }
+
+    /// What a message is bound to besides its text: the room's salt, length first so it can't run
+    /// into the name, then the sender's name
+    fn associated_data(&self, sender: &str) -> Vec<u8> {
+        let mut aad = Vec::with_capacity(4 + self.salt.len() + sender.len());
+        aad.extend((self.salt.len() as u32).to_be_bytes());
+        aad.extend(&self.salt);
+        aad.extend(sender.as_bytes());
+        aad
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn sealed_messages_open() {
+        let key = RoomKey::derive("correct horse", &[1; SALT_LEN]).unwrap();
+        let sealed = key.seal("alice", "hi everyone");
+        assert_eq!(sealed.ciphertext.len(), "hi everyone".len() + TAG_LEN);
+        assert_eq!(key.open("alice", &sealed).as_deref(), Some("hi everyone"));
+
+        // every message gets its own nonce
+        assert_ne!(key.seal("alice", "hi everyone").nonce, sealed.nonce);
+    }
+
+    #[test]
+    fn only_the_sender_opens() {
+        let key = RoomKey::derive("correct horse", &[1; SALT_LEN]).unwrap();
+        let sealed = key.seal("alice", "it's me, alice");
+        assert_eq!(key.open("mallory", &sealed), None);
+        assert_eq!(key.open("Alice", &sealed), None);
+        assert_eq!(key.open("", &sealed), None);
+    }
+
+    #[test]
+    fn only_the_room_opens() {
+        let key = RoomKey::derive("correct horse", &[1; SALT_LEN]).unwrap();
+        let other_room = RoomKey::derive("correct horse", &[2; SALT_LEN]).unwrap();
+        let wrong_passphrase = RoomKey::derive("battery staple", &[1; SALT_LEN]).unwrap();
+
+        let sealed = key.seal("alice", "for this room only");
+        assert_eq!(other_room.open("alice", &sealed), None);
+        assert_eq!(wrong_passphrase.open("alice", &sealed), None);
+    }
+
+    #[test]
+    fn tampered_messages_dont_open() {
+        let key = RoomKey::derive("correct horse", &[1; SALT_LEN]).unwrap();
+        let sealed = key.seal("alice", "untouched");
+
+        for i in 0..sealed.ciphertext.len() {
+            let mut tampered = sealed.clone();
+            tampered.ciphertext[i] ^= 0x01;
+            assert_eq!(key.open("alice", &tampered), None, "byte {i} was changed without it being noticed");
+        }
+
+        let mut tampered = sealed.clone();
+        tampered.nonce[0] ^= 0x01;
+        assert_eq!(key.open("alice", &tampered), None);
+
+        let mut short = sealed;
+        short.nonce.pop();
+        assert_eq!(key.open("alice", &short), None);
+    }
+}
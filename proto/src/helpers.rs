@@ -0,0 +1,39 @@
+// Helpers shared by the server and client
+
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+
+
+/// Seconds since the unix epoch
+pub fn unix_time() -> u64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map(|d| d.as_secs())
+        .unwrap_or_default()
+}
+
+/// Milliseconds since the unix epoch
+pub fn unix_time_ms() -> u64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map(|d| d.as_millis() as u64)
+        .unwrap_or_default()
+}
+
+/// Describe a duration roughly, using only its largest unit (e.g., "2 hours")
+pub fn humanize_duration(d: Duration) -> String {
+    let secs = d.as_secs();
+
+    let (amount, unit) = match secs {
+        0..=59 => (secs, "second"),
+        60..=3599 => (secs / 60, "minute"),
+        3600..=86399 => (secs / 3600, "hour"),
+        _ => (secs / 86400, "day"),
+    };
+
+    if amount == 1 {
+        format!("{amount} {unit}")
+    } else {
+        format!("{amount} {unit}s")
+    }
+}
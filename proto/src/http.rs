@@ -0,0 +1,205 @@
+// Just enough HTTP/1.1 to talk to webhook endpoints without pulling in an entire HTTP stack. Only
+// plain `http://` URLs are supported.
+
+use std::io::{self, Read, Write};
+use std::net::TcpStream;
+use std::time::{Duration, Instant};
+
+/// How long to wait on a remote HTTP server before giving up
+const HTTP_TIMEOUT: Duration = Duration::from_secs(5);
+
+/// The pieces of a `http://host[:port]/path` URL needed to make a request
+struct Url<'a> {
+    host: &'a str,
+    port: u16,
+    path: &'a str,
+}
+
+/// Split a URL into host, port, and path. The port defaults to 80 and the path defaults to "/".
+fn parse_url(url: &str) -> io::Result<Url<'_>> {
+    let rest = url.strip_prefix("http://").ok_or_else(|| io::Error::new(
+        io::ErrorKind::InvalidInput,
+        format!("Only http:// URLs are supported, got `{url}`")
+    ))?;
+
+    let (authority, path) = match rest.find('/') {
+        Some(i) => rest.split_at(i),
+        None => (rest, "/"),
+    };
+
+    let (host, port) = match authority.split_once(':') {
+        Some((host, port)) => {
+            let port = port.parse().map_err(|_| io::Error::new(
+                io::ErrorKind::InvalidInput,
+                format!("Invalid port in URL `{url}`")
+            ))?;
+            (host, port)
+        },
+        None => (authority, 80),
+    };
+
+    Ok(Url { host, port, path })
+}
+
+/// POST a JSON body to `url` and wait for the response status.
+/// 
+/// # Errors
+/// Fails if the URL is not a plain `http://` URL, the server can't be reached, or the server
+/// responds with anything other than a 2xx status.
+pub fn post_json(url: &str, body: &str) -> io::Result<()> {
+    request("POST", url, Some(body)).map(|_| ())
+}
+
+/// GET `url`, returning the body of the response.
+/// 
+/// # Errors
+/// Same as `post_json`.
+pub fn get(url: &str) -> io::Result<String> {
+    request("GET", url, None)
+}
+
+/// Make a request and return the body of the response if it has a 2xx status
+fn request(method: &str, url: &str, body: Option<&str>) -> io::Result<String> {
+    let url = parse_url(url)?;
+
+    let mut stream = TcpStream::connect((url.host, url.port))?;
+    stream.set_read_timeout(Some(HTTP_TIMEOUT))?;
+    stream.set_write_timeout(Some(HTTP_TIMEOUT))?;
+
+    write!(stream, "{method} {} HTTP/1.1\r\nHost: {}\r\nConnection: close\r\n", url.path, url.host)?;
+    match body {
+        Some(body) => write!(
+            stream,
+            "Content-Type: application/json\r\nContent-Length: {}\r\n\r\n{body}",
+            body.len()
+        )?,
+        None => write!(stream, "\r\n")?,
+    }
+    stream.flush()?;
+
+    // the connection is closed by the server once the whole response is sent
+    let mut response = Vec::new();
+    stream.take(MAX_BODY_SIZE as u64).read_to_end(&mut response)?;
+    let response = String::from_utf8_lossy(&response);
+
+    let malformed = || io::Error::new(io::ErrorKind::InvalidData, "Server sent a malformed response");
+
+    let (head, body) = response.split_once("\r\n\r\n").ok_or_else(malformed)?;
+    let status = head.split(' ').nth(1).ok_or_else(malformed)?;
+
+    if !status.starts_with('2') {
+        return Err(io::Error::other(format!("Server responded with status {status}")));
+    }
+
+    Ok(body.to_string())
+}
+
+/// The largest request body `read_request` will accept
+const MAX_BODY_SIZE: usize = 64 * 1024;
+
+/// An incoming HTTP request
+pub struct Request {
+    pub method: String,
+    pub path: String,
+    /// Header names are lowercased so lookups don't need to worry about case
+    headers: Vec<(String, String)>,
+    pub body: Vec<u8>,
+}
+
+impl Request {
+    /// Get the value of a header by its (case-insensitive) name
+    pub fn header(&self, name: &str) -> Option<&str> {
+        let name = name.to_lowercase();
+        self.headers.iter()
+            .find(|(n, _)| n == &name)
+            .map(|(_, v)| v.as_str())
+    }
+}
+
+/// Read a single HTTP request from `stream`, which has to arrive in full within `HTTP_TIMEOUT`.
+/// 
+/// # Errors
+/// Fails if the stream can't be read, the request is malformed or takes too long to arrive, or the
+/// body is larger than `MAX_BODY_SIZE`.
+pub fn read_request(stream: &mut TcpStream) -> io::Result<Request> {
+    // a per-read timeout alone would let a peer trickle in a byte at a time for as long as it likes
+    let deadline = Instant::now() + HTTP_TIMEOUT;
+    let read = |stream: &mut TcpStream, buf: &mut [u8]| {
+        let remaining = deadline.saturating_duration_since(Instant::now());
+        if remaining.is_zero() {
+            return Err(io::Error::new(io::ErrorKind::TimedOut, "HTTP request took too long"));
+        }
+        stream.set_read_timeout(Some(remaining))?;
+        stream.read(buf)
+    };
+
+    let malformed = || io::Error::new(io::ErrorKind::InvalidData, "Malformed HTTP request");
+
+    // read until the end of the headers
+    let mut data = Vec::new();
+    let mut readbuf = [0u8; 1024];
+    let header_end = loop {
+        if let Some(i) = data.windows(4).position(|w| w == b"\r\n\r\n") {
+            break i;
+        }
+        if data.len() > MAX_BODY_SIZE {
+            return Err(malformed());
+        }
+        let bytes_read = read(stream, &mut readbuf)?;
+        if bytes_read == 0 {
+            return Err(malformed());
+        }
+        data.extend(&readbuf[..bytes_read]);
+    };
+
+    let head = std::str::from_utf8(&data[..header_end]).map_err(|_| malformed())?;
+    let mut lines = head.split("\r\n");
+
+    let mut request_line = lines.next().ok_or_else(malformed)?.split(' ');
+    let method = request_line.next().ok_or_else(malformed)?.to_string();
+    let path = request_line.next().ok_or_else(malformed)?.to_string();
+
+    let headers: Vec<(String, String)> = lines
+        .filter_map(|line| line.split_once(':'))
+        .map(|(n, v)| (n.trim().to_lowercase(), v.trim().to_string()))
+        .collect();
+
+    let content_length: usize = headers.iter()
+        .find(|(n, _)| n == "content-length")
+        .map(|(_, v)| v.parse().map_err(|_| malformed()))
+        .transpose()?
+        .unwrap_or(0);
+
+    if content_length > MAX_BODY_SIZE {
+        return Err(io::Error::new(io::ErrorKind::InvalidData, "HTTP request body is too large"));
+    }
+
+    // whatever came after the headers is the start of the body
+    let mut body = data.split_off(header_end + 4);
+    while body.len() < content_length {
+        let bytes_read = read(stream, &mut readbuf)?;
+        if bytes_read == 0 {
+            return Err(malformed());
+        }
+        body.extend(&readbuf[..bytes_read]);
+    }
+    body.truncate(content_length);
+
+    Ok(Request { method, path, headers, body })
+}
+
+/// Send an empty-bodied response with the given status, e.g. `"204 No Content"`
+pub fn respond(stream: &mut TcpStream, status: &str) -> io::Result<()> {
+    write!(stream, "HTTP/1.1 {status}\r\nContent-Length: 0\r\nConnection: close\r\n\r\n")?;
+    stream.flush()
+}
+
+/// Send a "200 OK" response with a JSON body
+pub fn respond_json(stream: &mut TcpStream, body: &str) -> io::Result<()> {
+    write!(
+        stream,
+        "HTTP/1.1 200 OK\r\nContent-Type: application/json\r\nContent-Length: {}\r\nConnection: close\r\n\r\n{body}",
+        body.len()
+    )?;
+    stream.flush()
+}
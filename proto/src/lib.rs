@@ -0,0 +1,26 @@
+//! The protocol spoken between tcp_chat servers and clients: the `ClientMessage`s and
+//! `ServerMessage`s they exchange, the `TcpConn` framing they're sent with (`ClientConn` and
+//! `ServerConn` keep the two the right way round), and what's exchanged with a tracker. This is
+//! all a bot or an alternative client needs to take part in a room.
+
+pub mod constants;
+pub mod packet;
+pub mod tcp_conn;
+pub mod transport;
+pub mod local;
+#[cfg(windows)]
+pub mod pipe;
+#[cfg(feature = "quic")]
+pub mod quic;
+pub mod typed_conn;
+pub mod codec;
+pub mod capability;
+pub mod buffer_pool;
+mod read_buffer;
+pub mod http;
+pub mod tracker;
+pub mod helpers;
+#[cfg(feature = "secrets")]
+pub mod secrets;
+pub mod e2e;
+pub mod noise;
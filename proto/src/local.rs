@@ -0,0 +1,256 @@
+// Connections that never leave the process, for the host's own client to talk to the server it's
+// running. Each direction is a buffer that one end writes into and the other reads out of, which
+// behaves like a socket as far as `TcpConn` can tell, without a loopback socket to fail. Like a
+// socket's, the buffer only holds so much, so a reader that falls behind holds up the writer
+// instead of the writer filling up memory.
+
+use std::collections::VecDeque;
+use std::io::{self, IoSlice, Read, Write};
+use std::net::SocketAddr;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::mpsc::Sender;
+use std::sync::{Arc, Condvar, Mutex};
+use std::time::{Duration, Instant};
+
+use crate::transport::{Socket, Transport};
+
+
+
+/// The most bytes that can be on their way in one direction, about what a socket's buffers hold
+const PIPE_CAPACITY: usize = 256 * 1024;
+
+/// One end of an in-process connection. Clones from `try_clone` share the end, the same way clones
+/// of a `TcpStream` share the socket, and the other end reads 0 bytes once they're all dropped.
+pub struct LocalStream {
+    end: Arc<End>,
+    peer: Option<SocketAddr>,
+    host: bool,
+}
+
+struct End {
+    incoming: Arc<Pipe>,
+    outgoing: Arc<Pipe>,
+    nonblocking: AtomicBool,
+    read_timeout: Mutex<Option<Duration>>,
+}
+
+impl Drop for End {
+    fn drop(&mut self) {
+        self.incoming.close();
+        self.outgoing.close();
+    }
+}
+
+/// Bytes on their way from one end to the other
+#[derive(Default)]
+struct Pipe {
+    state: Mutex<PipeState>,
+    ready: Condvar,
+}
+
+#[derive(Default)]
+struct PipeState {
+    bytes: VecDeque<u8>,
+    /// Whether either end has gone away
+    closed: bool,
+}
+
+impl Pipe {
+    fn close(&self) {
+        self.state.lock().unwrap().closed = true;
+        self.ready.notify_all();
+    }
+}
+
+impl LocalStream {
+    /// Both ends of a new connection
+    pub fn pair() -> (Self, Self) {
+        let there = Arc::new(Pipe::default());
+        let back = Arc::new(Pipe::default());
+        (Self::end(Arc::clone(&back), Arc::clone(&there)), Self::end(there, back))
+    }
+
+    fn end(incoming: Arc<Pipe>, outgoing: Arc<Pipe>) -> Self {
+        Self {
+            end: Arc::new(End {
+                incoming,
+                outgoing,
+                nonblocking: AtomicBool::new(false),
+                read_timeout: Mutex::new(None),
+            }),
+            peer: None,
+            host: false,
+        }
+    }
+
+    /// Where the other end is connecting on behalf of, if it said, see
+    /// `LocalConnector::connect_from`
+    pub fn peer_addr(&self) -> Option<SocketAddr> {
+        self.peer
+    }
+
+    /// Whether the other end was opened by a connector from `LocalConnector::host`, which only the
+    /// program running the server hands out
+    pub fn is_host(&self) -> bool {
+        self.host
+    }
+
+    /// Hang up both ways now, as if every handle to this end had been dropped. What was already
+    /// sent can still be read at the other end.
+    pub fn shutdown(&self) {
+        self.end.incoming.close();
+        self.end.outgoing.close();
+    }
+}
+
+impl Read for LocalStream {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        if buf.is_empty() {
+            return Ok(0);
+        }
+
+        let pipe = &self.end.incoming;
+        let deadline = self.end.read_timeout.lock().unwrap().map(|timeout| Instant::now() + timeout);
+        let mut state = pipe.state.lock().unwrap();
+        loop {
+            // what was sent before the other end went away can still be read
+            if !state.bytes.is_empty() {
+                let read = state.bytes.read(buf);
+                // there's room for a writer that was waiting on it now
+                pipe.ready.notify_all();
+                return read;
+            }
+            if state.closed {
+                return Ok(0);
+            }
+            if self.end.nonblocking.load(Ordering::Relaxed) {
+                return Err(io::ErrorKind::WouldBlock.into());
+            }
+
+            state = match deadline {
+                Some(deadline) => {
+                    let left = deadline.saturating_duration_since(Instant::now());
+                    if left.is_zero() {
+                        return Err(io::ErrorKind::WouldBlock.into());
+                    }
+                    pipe.ready.wait_timeout(state, left).unwrap().0
+                },
+                None => pipe.ready.wait(state).unwrap(),
+            };
+        }
+    }
+}
+
+impl Write for LocalStream {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        self.write_vectored(&[IoSlice::new(buf)])
+    }
+
+    /// Moves as much as fits into the other end's buffer. When it's full, this waits for the other
+    /// end to read some, or fails with `io::ErrorKind::WouldBlock` if the stream is non-blocking.
+    fn write_vectored(&mut self, bufs: &[IoSlice<'_>]) -> io::Result<usize> {
+        if bufs.iter().all(|buf| buf.is_empty()) {
+            return Ok(0);
+        }
+
+        let pipe = &self.end.outgoing;
+        let mut state = pipe.state.lock().unwrap();
+        let room = loop {
+            if state.closed {
+                return Err(io::ErrorKind::BrokenPipe.into());
+            }
+            let room = PIPE_CAPACITY.saturating_sub(state.bytes.len());
+            if room > 0 {
+                break room;
+            }
+            if self.end.nonblocking.load(Ordering::Relaxed) {
+                return Err(io::ErrorKind::WouldBlock.into());
+            }
+            state = pipe.ready.wait(state).unwrap();
+        };
+
+        let mut written = 0;
+        for buf in bufs {
+            let take = buf.len().min(room - written);
+            state.bytes.extend(&buf[..take]);
+            written += take;
+            if written == room {
+                break;
+            }
+        }
+        pipe.ready.notify_all();
+        Ok(written)
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        Ok(())
+    }
+}
+
+impl Transport for LocalStream {
+    fn set_nonblocking(&self, nonblocking: bool) -> io::Result<()> {
+        self.end.nonblocking.store(nonblocking, Ordering::Relaxed);
+        Ok(())
+    }
+
+    fn set_read_timeout(&self, timeout: Option<Duration>) -> io::Result<()> {
+        // refused the same way a `TcpStream` refuses it
+        if timeout == Some(Duration::ZERO) {
+            return Err(io::Error::new(io::ErrorKind::InvalidInput, "Cannot set a 0 duration timeout"));
+        }
+        *self.end.read_timeout.lock().unwrap() = timeout;
+        Ok(())
+    }
+
+    fn try_clone(&self) -> io::Result<Self> {
+        Ok(Self { end: Arc::clone(&self.end), peer: self.peer, host: self.host })
+    }
+}
+
+/// Opens in-process connections to a server, which gets the other end of each one from the
+/// `Sender` it handed out
+#[derive(Debug, Clone)]
+pub struct LocalConnector {
+    server: Sender<Socket>,
+    host: bool,
+}
+
+impl LocalConnector {
+    pub fn new(server: Sender<Socket>) -> Self {
+        Self { server, host: false }
+    }
+
+    /// Same as `new`, for the host. The server can tell the connections it opens apart from
+    /// everyone else's with `LocalStream::is_host`.
+    pub fn host(server: Sender<Socket>) -> Self {
+        Self { server, host: true }
+    }
+
+    /// Connect to the server
+    ///
+    /// # Errors
+    /// Fails with `io::ErrorKind::ConnectionRefused` if the server has stopped accepting.
+    pub fn connect(&self) -> io::Result<Socket> {
+        self.open(None)
+    }
+
+    /// Same as `connect`, for a gateway bringing in someone from `peer`. The server sees `peer`
+    /// as the address the connection came from.
+    ///
+    /// # Errors
+    /// The same as `connect`.
+    pub fn connect_from(&self, peer: SocketAddr) -> io::Result<Socket> {
+        self.open(Some(peer))
+    }
+
+    fn open(&self, peer: Option<SocketAddr>) -> io::Result<Socket> {
+        let (ours, mut theirs) = LocalStream::pair();
+        theirs.peer = peer;
+        theirs.host = self.host;
+        self.server.send(Socket::Local(theirs)).map_err(|_| io::Error::new(
+            io::ErrorKind::ConnectionRefused,
+            "The server isn't accepting connections anymore"
+        ))?;
+        Ok(Socket::Local(ours))
+    }
+}
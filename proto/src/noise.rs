@@ -0,0 +1,544 @@
+// The Noise handshake `TcpConn` can run before anything else is sent, so the connection is
+// encrypted and each end knows the other's long-term key, without certificates or anyone to vouch
+// for either side. This is Noise_XX_25519_ChaChaPoly_SHA256 (see noiseprotocol.org): both ends
+// send their static key, encrypted, so each can recognize the other the next time they connect.
+
+use std::fmt;
+use std::io;
+
+use chacha20poly1305::{ChaCha20Poly1305, Nonce, KeyInit};
+use chacha20poly1305::aead::{Aead, Payload, OsRng, rand_core::RngCore};
+use curve25519_dalek::montgomery::MontgomeryPoint;
+use hmac::{Hmac, Mac};
+use sha2::{Digest, Sha256};
+
+#[cfg(feature = "secrets")]
+use crate::secrets::SecretStore;
+
+/// What a client sends before its first handshake message, so a server can tell a Noise
+/// connection apart from a plain one. Read as a plain frame's length, it's far beyond any limit.
+pub const NOISE_PREAMBLE: [u8; 8] = *b"TCNOISE1";
+
+/// The longest a single Noise message can be, including its tag
+pub const MAX_NOISE_MESSAGE: usize = 65535;
+
+/// What each encrypted message has added to it, proving it wasn't tampered with
+pub const NOISE_TAG_LEN: usize = 16;
+
+const PROTOCOL_NAME: &[u8; 32] = b"Noise_XX_25519_ChaChaPoly_SHA256";
+const KEY_LEN: usize = 32;
+
+
+
+/// A long-term X25519 keypair, which the other end of a connection sees as who it's talking to
+#[derive(Clone)]
+pub struct Keypair {
+    secret: [u8; KEY_LEN],
+    public: [u8; KEY_LEN],
+}
+
+impl fmt::Debug for Keypair {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        // the secret half stays out of logs and debug output
+        f.debug_struct("Keypair").field("public", &fingerprint(&self.public)).finish_non_exhaustive()
+    }
+}
+
+impl Keypair {
+    /// A brand new keypair from the OS's random number generator
+    pub fn generate() -> Self {
+        let mut secret = [0; KEY_LEN];
+        OsRng.fill_bytes(&mut secret);
+        Self::from_secret(secret)
+    }
+
+    fn from_secret(secret: [u8; KEY_LEN]) -> Self {
+        Self { secret, public: MontgomeryPoint::mul_base_clamped(secret).to_bytes() }
+    }
+
+    /// The keypair kept in `secrets` under `key`, generating and storing one if there isn't one
+    /// yet
+    #[cfg(feature = "secrets")]
+    pub fn load_or_create(secrets: &SecretStore, key: &str) -> io::Result<Self> {
+        if let Some(hex) = secrets.get(key)? {
+            let secret = from_hex(&hex).ok_or_else(|| io::Error::new(
+                io::ErrorKind::InvalidData,
+                format!("The key stored as {key} isn't a valid key")
+            ))?;
+            return Ok(Self::from_secret(secret));
+        }
+
+        let keypair = Self::generate();
+        secrets.set(key, &to_hex(&keypair.secret))?;
+        Ok(keypair)
+    }
+
+    pub fn public(&self) -> [u8; KEY_LEN] {
+        self.public
+    }
+
+    fn dh(&self, public: &[u8; KEY_LEN]) -> io::Result<[u8; KEY_LEN]> {
+        let shared = MontgomeryPoint(*public).mul_clamped(self.secret).to_bytes();
+        // a key that gives all zeros would let whoever sent it know the result in advance
+        if shared == [0; KEY_LEN] {
+            return Err(handshake_error("the other end sent an invalid key"));
+        }
+        Ok(shared)
+    }
+}
+
+/// A public key in groups of four hex digits, for people to compare by eye
+pub fn fingerprint(public: &[u8; KEY_LEN]) -> String {
+    let hex = to_hex(public);
+    hex.as_bytes()
+        .chunks(4)
+        .map(|group| std::str::from_utf8(group).unwrap())
+        .collect::<Vec<_>>()
+        .join(" ")
+}
+
+
+/// Encrypts or decrypts messages in one direction, each with the next nonce
+pub(crate) struct CipherState {
+    cipher: Option<ChaCha20Poly1305>,
+    nonce: u64,
+}
+
+impl CipherState {
+    fn new(key: Option<[u8; KEY_LEN]>) -> Self {
+        Self { cipher: key.map(|key| ChaCha20Poly1305::new(&key.into())), nonce: 0 }
+    }
+
+    /// The nonce for the next message. Noise keeps the last possible one back, so running out
+    /// is an error rather than a nonce being used twice.
+    fn nonce(&self) -> io::Result<Nonce> {
+        if self.nonce == u64::MAX {
+            return Err(io::Error::other("The connection has sent all the messages its keys allow"));
+        }
+        let mut nonce = [0; 12];
+        nonce[4..].copy_from_slice(&self.nonce.to_le_bytes());
+        Ok(nonce.into())
+    }
+
+    /// Encrypt `plaintext`, or pass it through as it is before there's a key
+    /// 
+    /// # Errors
+    /// Fails once every nonce has been used
+    pub(crate) fn encrypt(&mut self, ad: &[u8], plaintext: &[u8]) -> io::Result<Vec<u8>> {
+        let Some(cipher) = &self.cipher else {
+            return Ok(plaintext.to_vec());
+        };
+        // encrypting into memory can't fail for anything as short as a Noise message
+        let ciphertext = cipher.encrypt(&self.nonce()?, Payload { msg: plaintext, aad: ad })
+            .expect("[error] Unable to encrypt a Noise message");
+        self.nonce += 1;
+        Ok(ciphertext)
+    }
+
+    /// Decrypt `ciphertext`, which fails if it was tampered with, not meant for this connection,
+    /// or not the next message the other end sent (like one played back a second time). A
+    /// message that fails doesn't use up a nonce.
+    pub(crate) fn decrypt(&mut self, ad: &[u8], ciphertext: &[u8]) -> io::Result<Vec<u8>> {
+        let Some(cipher) = &self.cipher else {
+            return Ok(ciphertext.to_vec());
+        };
+        let plaintext = cipher.decrypt(&self.nonce()?, Payload { msg: ciphertext, aad: ad })
+            .map_err(|_| io::Error::new(io::ErrorKind::InvalidData, "Unable to decrypt a message, it may have been tampered with"))?;
+        self.nonce += 1;
+        Ok(plaintext)
+    }
+}
+
+/// The running hash and chaining key both ends build up over the handshake
+struct SymmetricState {
+    cipher: CipherState,
+    chaining_key: [u8; KEY_LEN],
+    hash: [u8; KEY_LEN],
+}
+
+impl SymmetricState {
+    fn new(prologue: &[u8]) -> Self {
+        // the protocol name is exactly as long as a hash, so it's used as it is
+        let mut state = Self { cipher: CipherState::new(None), chaining_key: *PROTOCOL_NAME, hash: *PROTOCOL_NAME };
+        state.mix_hash(prologue);
+        state
+    }
+
+    fn mix_hash(&mut self, data: &[u8]) {
+        self.hash = Sha256::new().chain_update(self.hash).chain_update(data).finalize().into();
+    }
+
+    fn mix_key(&mut self, input: &[u8]) {
+        let [chaining_key, key] = hkdf(&self.chaining_key, input);
+        self.chaining_key = chaining_key;
+        self.cipher = CipherState::new(Some(key));
+    }
+
+    fn encrypt_and_hash(&mut self, plaintext: &[u8]) -> io::Result<Vec<u8>> {
+        let ciphertext = self.cipher.encrypt(&self.hash, plaintext)?;
+        self.mix_hash(&ciphertext);
+        Ok(ciphertext)
+    }
+
+    fn decrypt_and_hash(&mut self, ciphertext: &[u8]) -> io::Result<Vec<u8>> {
+        let plaintext = self.cipher.decrypt(&self.hash, ciphertext)?;
+        self.mix_hash(ciphertext);
+        Ok(plaintext)
+    }
+
+    /// The ciphers for each direction once the handshake is done: the initiator sends with the
+    /// first and the responder with the second
+    fn split(&self) -> (CipherState, CipherState) {
+        let [first, second] = hkdf(&self.chaining_key, &[]);
+        (CipherState::new(Some(first)), CipherState::new(Some(second)))
+    }
+}
+
+/// HKDF with SHA-256 as Noise uses it, producing two keys
+fn hkdf(chaining_key: &[u8; KEY_LEN], input: &[u8]) -> [[u8; KEY_LEN]; 2] {
+    let temp = hmac(chaining_key, &[input]);
+    let first = hmac(&temp, &[&[1]]);
+    let second = hmac(&temp, &[&first, &[2]]);
+    [first, second]
+}
+
+fn hmac(key: &[u8], parts: &[&[u8]]) -> [u8; KEY_LEN] {
+    let mut mac = <Hmac<Sha256> as Mac>::new_from_slice(key).expect("HMAC takes keys of any length");
+    for part in parts {
+        mac.update(part);
+    }
+    mac.finalize().into_bytes().into()
+}
+
+
+/// The encryption for each direction of a connection after the handshake. Either half is missing
+/// once the connection's been split and the other half went elsewhere.
+pub(crate) struct Transport {
+    pub send: Option<CipherState>,
+    pub recv: Option<CipherState>,
+    /// The other end's static key
+    pub remote: [u8; KEY_LEN],
+}
+
+/// One side's part in the handshake, which goes:
+/// ```text
+/// -> e
+/// <- e, ee, s, es
+/// -> s, se
+/// ```
+/// Each message can carry a payload after its keys, which connections leave empty.
+pub(crate) struct Handshake {
+    symmetric: SymmetricState,
+    keypair: Keypair,
+    ephemeral: Keypair,
+    remote_ephemeral: [u8; KEY_LEN],
+    remote_static: [u8; KEY_LEN],
+    initiator: bool,
+}
+
+impl Handshake {
+    pub(crate) fn new(keypair: &Keypair, initiator: bool) -> Self {
+        Self::with_ephemeral(keypair, Keypair::generate(), &[], initiator)
+    }
+
+    fn with_ephemeral(keypair: &Keypair, ephemeral: Keypair, prologue: &[u8], initiator: bool) -> Self {
+        Self {
+            symmetric: SymmetricState::new(prologue),
+            keypair: keypair.clone(),
+            ephemeral,
+            remote_ephemeral: [0; KEY_LEN],
+            remote_static: [0; KEY_LEN],
+            initiator,
+        }
+    }
+
+    /// The initiator's first message: its ephemeral key
+    pub(crate) fn write_first(&mut self, payload: &[u8]) -> io::Result<Vec<u8>> {
+        let mut message = self.ephemeral.public.to_vec();
+        self.symmetric.mix_hash(&self.ephemeral.public);
+        message.extend(self.symmetric.encrypt_and_hash(payload)?);
+        Ok(message)
+    }
+
+    /// Read the initiator's first message, returning its payload
+    pub(crate) fn read_first(&mut self, message: &[u8]) -> io::Result<Vec<u8>> {
+        let (remote_ephemeral, payload) = split_key(message)?;
+        self.remote_ephemeral = remote_ephemeral;
+        self.symmetric.mix_hash(&remote_ephemeral);
+        self.symmetric.decrypt_and_hash(payload)
+    }
+
+    /// The responder's message: its ephemeral key, then its static key encrypted
+    pub(crate) fn write_second(&mut self, payload: &[u8]) -> io::Result<Vec<u8>> {
+        let mut message = self.ephemeral.public.to_vec();
+        self.symmetric.mix_hash(&self.ephemeral.public);
+        self.symmetric.mix_key(&self.ephemeral.dh(&self.remote_ephemeral)?);
+        message.extend(self.symmetric.encrypt_and_hash(&self.keypair.public)?);
+        self.symmetric.mix_key(&self.keypair.dh(&self.remote_ephemeral)?);
+        message.extend(self.symmetric.encrypt_and_hash(payload)?);
+        Ok(message)
+    }
+
+    /// Read the responder's message, returning its payload
+    pub(crate) fn read_second(&mut self, message: &[u8]) -> io::Result<Vec<u8>> {
+        let (remote_ephemeral, rest) = split_key(message)?;
+        self.remote_ephemeral = remote_ephemeral;
+        self.symmetric.mix_hash(&remote_ephemeral);
+        self.symmetric.mix_key(&self.ephemeral.dh(&self.remote_ephemeral)?);
+        self.read_static(rest)?;
+        self.symmetric.mix_key(&self.ephemeral.dh(&self.remote_static)?);
+        self.symmetric.decrypt_and_hash(&rest[KEY_LEN + NOISE_TAG_LEN..])
+    }
+
+    /// The initiator's last message: its static key encrypted
+    pub(crate) fn write_third(&mut self, payload: &[u8]) -> io::Result<Vec<u8>> {
+        let mut message = self.symmetric.encrypt_and_hash(&self.keypair.public)?;
+        self.symmetric.mix_key(&self.keypair.dh(&self.remote_ephemeral)?);
+        message.extend(self.symmetric.encrypt_and_hash(payload)?);
+        Ok(message)
+    }
+
+    /// Read the initiator's last message, returning its payload
+    pub(crate) fn read_third(&mut self, message: &[u8]) -> io::Result<Vec<u8>> {
+        self.read_static(message)?;
+        self.symmetric.mix_key(&self.ephemeral.dh(&self.remote_static)?);
+        self.symmetric.decrypt_and_hash(&message[KEY_LEN + NOISE_TAG_LEN..])
+    }
+
+    /// Decrypt the other end's static key from the start of `message`
+    fn read_static(&mut self, message: &[u8]) -> io::Result<()> {
+        let encrypted = message.get(..KEY_LEN + NOISE_TAG_LEN)
+            .ok_or_else(|| handshake_error("a handshake message was cut short"))?;
+        let remote_static = self.symmetric.decrypt_and_hash(encrypted)?;
+        self.remote_static = remote_static.try_into().unwrap();
+        Ok(())
+    }
+
+    /// The handshake is done, so everything from here on is encrypted with the keys it agreed on
+    pub(crate) fn finish(self) -> Transport {
+        let (initiator_sends, responder_sends) = self.symmetric.split();
+        let (send, recv) = if self.initiator {
+            (initiator_sends, responder_sends)
+        } else {
+            (responder_sends, initiator_sends)
+        };
+        Transport { send: Some(send), recv: Some(recv), remote: self.remote_static }
+    }
+}
+
+/// A key from the start of a handshake message, and the rest of the message after it
+fn split_key(message: &[u8]) -> io::Result<([u8; KEY_LEN], &[u8])> {
+    if message.len() < KEY_LEN {
+        return Err(handshake_error("a handshake message was cut short"));
+    }
+    let (key, rest) = message.split_at(KEY_LEN);
+    Ok((key.try_into().unwrap(), rest))
+}
+
+pub(crate) fn handshake_error(reason: &str) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidData, format!("The Noise handshake failed, {reason}"))
+}
+
+fn to_hex(bytes: &[u8]) -> String {
+    bytes.iter().map(|b| format!("{b:02x}")).collect()
+}
+
+#[cfg(feature = "secrets")]
+fn from_hex(hex: &str) -> Option<[u8; KEY_LEN]> {
+    if hex.len() != KEY_LEN * 2 || !hex.is_ascii() {
+        return None;
+    }
+    let mut bytes = [0; KEY_LEN];
+    for (byte, pair) in bytes.iter_mut().zip(hex.as_bytes().chunks(2)) {
+        *byte = u8::from_str_radix(std::str::from_utf8(pair).ok()?, 16).ok()?;
+    }
+    Some(bytes)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn hex(hex: &str) -> Vec<u8> {
+        (0..hex.len()).step_by(2).map(|i| u8::from_str_radix(&hex[i..i + 2], 16).unwrap()).collect()
+    }
+
+    fn keypair(secret: &str) -> Keypair {
+        Keypair::from_secret(hex(secret).try_into().unwrap())
+    }
+
+    /// An initiator and responder that make the same handshake every time, so a message can be
+    /// tampered with and tried again from the start
+    fn pair() -> (Handshake, Handshake) {
+        let initiator = Handshake::with_ephemeral(&keypair(&"11".repeat(32)), keypair(&"22".repeat(32)), &[], true);
+        let responder = Handshake::with_ephemeral(&keypair(&"33".repeat(32)), keypair(&"44".repeat(32)), &[], false);
+        (initiator, responder)
+    }
+
+    /// Run the handshake between a new pair as far as the second message, which the initiator
+    /// hasn't read yet
+    fn up_to_second() -> (Handshake, Handshake, Vec<u8>) {
+        let (mut initiator, mut responder) = pair();
+        responder.read_first(&initiator.write_first(&[]).unwrap()).unwrap();
+        let second = responder.write_second(b"second").unwrap();
+        (initiator, responder, second)
+    }
+
+    /// The same, as far as the third message, which the responder hasn't read yet
+    fn up_to_third() -> (Handshake, Handshake, Vec<u8>) {
+        let (mut initiator, responder, second) = up_to_second();
+        initiator.read_second(&second).unwrap();
+        let third = initiator.write_third(b"third").unwrap();
+        (initiator, responder, third)
+    }
+
+    #[test]
+    fn handshake_round_trip() {
+        let (initiator_key, responder_key) = (Keypair::generate(), Keypair::generate());
+        let mut initiator = Handshake::new(&initiator_key, true);
+        let mut responder = Handshake::new(&responder_key, false);
+
+        assert_eq!(responder.read_first(&initiator.write_first(b"one").unwrap()).unwrap(), b"one");
+        assert_eq!(initiator.read_second(&responder.write_second(b"two").unwrap()).unwrap(), b"two");
+        assert_eq!(responder.read_third(&initiator.write_third(b"three").unwrap()).unwrap(), b"three");
+        assert_eq!(initiator.symmetric.hash, responder.symmetric.hash);
+
+        let mut initiator = initiator.finish();
+        let mut responder = responder.finish();
+        assert_eq!(initiator.remote, responder_key.public());
+        assert_eq!(responder.remote, initiator_key.public());
+
+        for i in 0..3 {
+            let message = format!("message {i}");
+            let sealed = initiator.send.as_mut().unwrap().encrypt(&[], message.as_bytes()).unwrap();
+            assert_ne!(sealed, message.as_bytes());
+            assert_eq!(responder.recv.as_mut().unwrap().decrypt(&[], &sealed).unwrap(), message.as_bytes());
+
+            let sealed = responder.send.as_mut().unwrap().encrypt(&[], message.as_bytes()).unwrap();
+            assert_eq!(initiator.recv.as_mut().unwrap().decrypt(&[], &sealed).unwrap(), message.as_bytes());
+        }
+    }
+
+    #[test]
+    fn tampered_second_message_is_rejected() {
+        let (_, _, second) = up_to_second();
+        for i in 0..second.len() {
+            let (mut initiator, _, mut tampered) = up_to_second();
+            tampered[i] ^= 0x01;
+            assert!(initiator.read_second(&tampered).is_err(), "byte {i} was changed without it being noticed");
+        }
+
+        let (mut initiator, _, second) = up_to_second();
+        assert!(initiator.read_second(&second[..KEY_LEN + NOISE_TAG_LEN]).is_err());
+    }
+
+    #[test]
+    fn tampered_third_message_is_rejected() {
+        let (_, _, third) = up_to_third();
+        for i in 0..third.len() {
+            let (_, mut responder, mut tampered) = up_to_third();
+            tampered[i] ^= 0x01;
+            assert!(responder.read_third(&tampered).is_err(), "byte {i} was changed without it being noticed");
+        }
+
+        let (_, mut responder, third) = up_to_third();
+        assert!(responder.read_third(&third[..KEY_LEN]).is_err());
+    }
+
+    #[test]
+    fn messages_only_open_once_and_in_order() {
+        let (initiator, mut responder, third) = up_to_third();
+        responder.read_third(&third).unwrap();
+        let (mut initiator, mut responder) = (initiator.finish(), responder.finish());
+        let send = initiator.send.as_mut().unwrap();
+        let recv = responder.recv.as_mut().unwrap();
+
+        let first = send.encrypt(&[], b"first").unwrap();
+        let second = send.encrypt(&[], b"second").unwrap();
+
+        // played back out of order
+        assert!(recv.decrypt(&[], &second).is_err());
+        assert_eq!(recv.decrypt(&[], &first).unwrap(), b"first");
+        // or a second time
+        assert!(recv.decrypt(&[], &first).is_err());
+        assert_eq!(recv.decrypt(&[], &second).unwrap(), b"second");
+    }
+
+    #[test]
+    fn running_out_of_nonces_is_an_error() {
+        let key = Some([7; KEY_LEN]);
+        let mut send = CipherState::new(key);
+        let mut recv = CipherState::new(key);
+        send.nonce = u64::MAX - 1;
+        recv.nonce = u64::MAX - 1;
+
+        let last = send.encrypt(&[], b"last").unwrap();
+        assert_eq!(recv.decrypt(&[], &last).unwrap(), b"last");
+
+        assert!(send.encrypt(&[], b"one too many").is_err());
+        assert!(recv.decrypt(&[], &last).is_err());
+    }
+
+    /// The keys, prologue, and payloads of cacophony's Noise_XX_25519_ChaChaPoly_SHA256 vector,
+    /// run through both ends, with the handshake messages, transport messages after it, and the
+    /// final handshake hash checked byte for byte
+    #[test]
+    fn cacophony_vector() {
+        let prologue = hex("4a6f686e2047616c74");
+        let initiator_static = keypair("e61ef9919cde45dd5f82166404bd08e38bceb5dfdfded0a34c8df7ed542214d1");
+        let initiator_ephemeral = keypair("893e28b9dc6ca8d611ab664754b8ceb7bac5117349a4439a6b0569da977c464a");
+        let responder_static = keypair("4a3acbfdb163dec651dfa3194dece676d437029c62a408b4c5ea9114246e4893");
+        let responder_ephemeral = keypair("bbdb4cdbd309f1a1f2e1456967fe288cadd6f712d65dc7b7793d5e63da6b375b");
+
+        // (payload, ciphertext), alternating from the initiator
+        let messages = [
+            (
+                "4c756477696720766f6e204d69736573",
+                "ca35def5ae56cec33dc2036731ab14896bc4c75dbb07a61f879f8e3afa4c79444c756477696720766f6e204d69736573",
+            ),
+            (
+                "4d757272617920526f746862617264",
+                "95ebc60d2b1fa672c1f46a8aa265ef51bfe38e7ccb39ec5be34069f14480884381cbad1f276e038c48378ffce2b65285e08d6b68aaa3629a5a8639392490e5b9bd5269c2f1e4f488ed8831161f19b7815528f8982ffe09be9b5c412f8a0db50f8814c7194e83f23dbd8d162c9326ad",
+            ),
+            (
+                "462e20412e20486179656b",
+                "c7195ffacac1307ff99046f219750fc47693e23c3cb08b89c2af808b444850a80ae475b9df0f169ae80a89be0865b57f58c9fea0d4ec82a286427402f113e4b6ae769a1d95941d49b25030",
+            ),
+            ("4361726c204d656e676572", "96763ed773f8e47bb3712f0e29b3060ffc956ffc146cee53d5e1df"),
+            ("4a65616e2d426170746973746520536179", "3e40f15f6f3a46ae446b253bf8b1d9ffb6ed9b174d272328ff91a7e2e5c79c07f5"),
+            ("457567656e2042f6686d20766f6e2042617765726b", "eb3f3515110702e047a6c9da4478b6ead94873c11c0f2d710ddb3f09fce024b3a58502ae3f"),
+        ];
+        let messages = messages.map(|(payload, ciphertext)| (hex(payload), hex(ciphertext)));
+
+        let mut initiator = Handshake::with_ephemeral(&initiator_static, initiator_ephemeral, &prologue, true);
+        let mut responder = Handshake::with_ephemeral(&responder_static, responder_ephemeral, &prologue, false);
+
+        let [first, second, third, fourth, fifth, sixth] = &messages;
+
+        assert_eq!(initiator.write_first(&first.0).unwrap(), first.1);
+        assert_eq!(responder.read_first(&first.1).unwrap(), first.0);
+        assert_eq!(responder.write_second(&second.0).unwrap(), second.1);
+        assert_eq!(initiator.read_second(&second.1).unwrap(), second.0);
+        assert_eq!(initiator.write_third(&third.0).unwrap(), third.1);
+        assert_eq!(responder.read_third(&third.1).unwrap(), third.0);
+
+        let hash = hex("c8e5f64e846193be2a834104c2a009868d6c9f3bd3c186299888b488b2f1f58e");
+        assert_eq!(initiator.symmetric.hash.as_slice(), hash);
+        assert_eq!(responder.symmetric.hash.as_slice(), hash);
+
+        let mut initiator = initiator.finish();
+        let mut responder = responder.finish();
+        assert_eq!(initiator.remote, responder_static.public());
+        assert_eq!(responder.remote, initiator_static.public());
+
+        for (i, (payload, ciphertext)) in [fourth, fifth, sixth].into_iter().enumerate() {
+            let (from, to) = if i % 2 == 0 {
+                (&mut responder, &mut initiator)
+            } else {
+                (&mut initiator, &mut responder)
+            };
+            assert_eq!(&from.send.as_mut().unwrap().encrypt(&[], payload).unwrap(), ciphertext);
+            assert_eq!(&to.recv.as_mut().unwrap().decrypt(&[], ciphertext).unwrap(), payload);
+        }
+    }
+}
@@ -0,0 +1,414 @@
+use std::net::SocketAddr;
+
+use serde::{self, Serialize, Deserialize};
+
+use crate::constants::{MAX_ATTACHMENT_LEN, MAX_CAPABILITIES, MAX_FIELD_LEN, MAX_VOICE_LEN, MAX_WIRE_FORMATS};
+use crate::e2e::TAG_LEN;
+
+
+
+/// Everything a client can send to the server
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub enum ClientMessage {
+    /// A generic message to the server
+    ClientText(String),
+
+    /// A message that should disappear after the given number of seconds
+    ClientEphemeralText(u64, String), // seconds to live, text
+
+    /// A message that should get everyone's attention, even if they've silenced notifications
+    ClientUrgentText(String),   // text
+
+    /// A message sealed with the room's key, for rooms that are end-to-end encrypted. The
+    /// server can't read it, only pass it on.
+    ClientSealedText(Sealed),
+
+    /// A short audio clip for the room
+    ClientVoice(VoiceClip),
+
+    /// A file for the room. Its bytes follow in a raw frame of their own, see `Attachment`.
+    ClientAttachment(Attachment),
+
+    /// Client's first message to server
+    ClientHello(String),        // name
+
+    /// Client asking to speak something other than JSON, in order of preference, before its
+    /// `ClientHello`. The server answers with `ServerWireFormat`. Always sent as JSON.
+    ClientWireFormats(Vec<String>), // format names, see `WireFormat::name`
+
+    /// Client offering what it can do beyond the basics, after any `ClientWireFormats` and before
+    /// its `ClientHello`. The server answers with `ServerHello`. See `capability`.
+    ClientCapabilities(Vec<String>), // capability names, see `Capability::name`
+
+    /// Client asking about the room without joining it. This can be sent instead of `ClientHello`,
+    /// after which the server replies with `ServerInfo` and closes the connection.
+    ClientQueryInfo,
+
+    /// Client letting the server know that it is leaving the room
+    ClientGoodbye,
+
+    /// Client requesting to change their name
+    ClientRename(String),
+
+    /// Host Client requesting to kick someone by id
+    ClientKick(u64),
+
+    /// Host Client hiding someone's messages from everyone but them, by id. Sending it again for
+    /// the same person lifts the shadowban.
+    ClientShadowban(u64),
+
+    /// Client requesting the roster (list of client ids, names, and statuses)
+    ClientRequestIDs,
+
+    /// Host Client requesting the recent connects, disconnects, and kicks
+    ClientRequestConnections,
+
+    /// Client setting their status line, or clearing it with `None`
+    ClientSetStatus(Option<String>),
+
+    /// Client letting the server know whether they're at the keyboard
+    ClientSetPresence(Presence),
+
+    /// Client asking when someone was last seen
+    ClientSeen(String),             // name
+
+    /// Client reporting someone to the host and operators, by id
+    ClientReport(u64, String),      // id, reason
+
+    /// Client asking the server to resend broadcasts it missed, by sequence number
+    ClientBackfill(u64, u64),       // first, last (inclusive)
+
+    /// Client asking for up to `limit` earlier broadcasts, the ones just before `before_id` or the
+    /// very latest ones if it's `None`. Answered with `ServerHistoryPage`, so a client can page
+    /// back through the room as far as it likes.
+    ClientRequestHistory { before_id: Option<u64>, limit: u32 },
+
+    /// Client letting the server know it's still there, every `HEARTBEAT_INTERVAL`. Answered with
+    /// `ServerPong`, so the client can tell the server is still there too.
+    ClientPing,
+
+    /// Client asking for the server's time so it can correct for its own clock being off
+    ClientSyncClock(u64),           // client's unix time in ms when sent
+
+    /// Host Client closing the room for everyone
+    ClientShutdown,
+
+    /// The same as `ClientText`, but acknowledged with `ServerAck` once it's gone out to the room.
+    /// The id is the client's own, and only has to differ from its other messages'. Only for
+    /// servers that agreed to `Capability::Acks`.
+    ClientTrackedText(u64, String), // message id, text
+}
+
+impl ClientMessage {
+    /// Whether every string in the message is within `MAX_FIELD_LEN`. Deserializing only limits
+    /// the size of a message as a whole, so servers should check this before acting on one.
+    pub fn within_limits(&self) -> bool {
+        match self {
+            Self::ClientText(text)
+            | Self::ClientTrackedText(_, text)
+            | Self::ClientEphemeralText(_, text)
+            | Self::ClientUrgentText(text)
+            | Self::ClientHello(text)
+            | Self::ClientRename(text)
+            | Self::ClientSetStatus(Some(text))
+            | Self::ClientSeen(text)
+            | Self::ClientReport(_, text) => text.len() <= MAX_FIELD_LEN,
+            Self::ClientSealedText(sealed) => sealed.ciphertext.len() <= MAX_FIELD_LEN + TAG_LEN,
+            Self::ClientVoice(clip) => clip.format.len() <= MAX_FIELD_LEN && clip.data.len() <= MAX_VOICE_LEN,
+            Self::ClientAttachment(attachment) => attachment.name.len() <= MAX_FIELD_LEN && attachment.len <= MAX_ATTACHMENT_LEN as u64,
+            Self::ClientWireFormats(names) => names.len() <= MAX_WIRE_FORMATS && names.iter().all(|name| name.len() <= MAX_FIELD_LEN),
+            Self::ClientCapabilities(names) => names.len() <= MAX_CAPABILITIES && names.iter().all(|name| name.len() <= MAX_FIELD_LEN),
+            _ => true,
+        }
+    }
+}
+
+/// Everything the server can send to a client
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub enum ServerMessage {
+    /// The server sending a message to client B by distributing a message from client A
+    /// Use cases: distribution of client message or server update (e.g., someone leaving)
+    ServerText(String, String), // sender name, text
+
+    /// The server distributing an urgent message from a client
+    ServerUrgentText(String, String), // sender name, text
+
+    /// The server distributing a sealed message from a client, for everyone with the room's key
+    /// to open
+    ServerSealedText(String, Sealed), // sender name, sealed text
+
+    /// Server telling a client that just joined that the room is end-to-end encrypted, and the
+    /// salt to derive its key with. Messages can't be sent or read without the room's passphrase.
+    ServerEncrypted(#[serde(with = "base64_bytes")] Vec<u8>), // salt
+
+    /// The server distributing a voice clip from a client
+    ServerVoice(String, VoiceClip), // sender name, clip
+
+    /// The server distributing a file from a client, whose bytes follow in a raw frame. These
+    /// aren't numbered or kept in the history, since the bytes couldn't be sent again with them.
+    ServerAttachment(String, Attachment), // sender name, attachment
+
+    /// A message for one client alone, rather than the whole room
+    ServerDirectText(String, String), // sender name, text
+
+    /// The server distributing an ephemeral message. The id is used to refer to it when it expires.
+    ServerEphemeralText(u64, String, String, u64), // id, sender name, text, seconds to live
+
+    /// Server notifying clients that an ephemeral message expired and should no longer be shown
+    ServerExpire(u64),          // id
+
+    /// Server letting everyone know someone changed their name
+    ServerRenamed(String, String), // old name, new name
+
+    /// Server notifying all the clients that the room is closing
+    ServerShutdown,
+
+    /// Server notifying the person being kicked
+    ServerNotifyKick,
+
+    /// Server telling a client it's now the room's operator, with the host's moderation commands
+    /// while the host is gone, or that it no longer is
+    ServerOperator(bool),       // whether they're the operator
+
+    /// Server describing the room in response to `ClientQueryInfo`
+    ServerInfo(RoomInfo),
+
+    /// Server picking which of the formats offered with `ClientWireFormats` the rest of the
+    /// connection uses, or JSON if it knows none of them. Always sent as JSON.
+    ServerWireFormat(String),   // format name
+
+    /// Server agreeing to the capabilities offered with `ClientCapabilities` that it has too, which
+    /// are the ones used for the rest of the connection
+    ServerHello(Vec<String>),   // capability names
+
+    /// Server responding to a client with the roster
+    ServerResponseIDs(Vec<RosterEntry>),
+
+    /// Server responding to the host with the recent connects, disconnects, and kicks, oldest
+    /// first
+    ServerConnections(Vec<ConnectionEvent>),
+
+    /// A broadcast to the whole room, numbered so clients can tell when they've missed one
+    ServerSequenced(u64, Box<ServerMessage>), // sequence number, broadcast
+
+    /// Server letting the sender of a broadcast know its sequence number, since the broadcast
+    /// itself isn't sent back to them
+    ServerSent(u64),            // sequence number
+
+    /// Server letting a client know its `ClientTrackedText` was accepted and sent to the room, by the id
+    /// it was sent with. Comes after the `ServerSent` for it, for clients that agreed to
+    /// `Capability::Acks`.
+    ServerAck(u64),             // message id
+
+    /// Server refusing to send a client's message to the room
+    ServerRejected(String),     // reason
+
+    /// Server replying to `ClientSyncClock`, echoing the client's time back so it can tell how
+    /// long the round trip took
+    ServerClock(u64, u64),      // client's unix time in ms from the request, server's unix time in ms
+
+    /// Server replying to `ClientRequestHistory`, oldest first. A page shorter than the limit
+    /// that was asked for means there's nothing earlier.
+    ServerHistoryPage(Vec<HistoryEntry>),
+
+    /// Server answering `ClientPing`
+    ServerPong,
+
+    /// Server passing a `ClientReport` on to the host and operators
+    ServerReport(Report),
+
+    /// Server telling a client that just joined how much it accepts, so the client can split up or
+    /// refuse what's too big before sending it rather than having it refused. Only sent to clients
+    /// that agreed to `Capability::Limits`.
+    ServerLimits(Limits),
+
+    /// Server letting a client in, under the name it'll be known by. That's the one from its
+    /// `ClientHello`, tidied up the same way every name is (see `check_name` in the server). Comes
+    /// before the join notice, for clients that agreed to `Capability::Welcome`.
+    ServerWelcome(String),      // name
+}
+
+/// The most a server accepts in each kind of message, see `ServerLimits`
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Limits {
+    /// The longest a text message can be, in bytes
+    pub max_message_len: usize,
+    /// The biggest voice clip, in bytes. Voice clips aren't allowed at all when this is 0.
+    pub max_voice_len: usize,
+    /// The biggest attachment, in bytes. Attachments aren't allowed at all when this is 0.
+    pub max_attachment_len: usize,
+}
+
+impl Default for Limits {
+    /// The most any server accepts, for servers that haven't said
+    fn default() -> Self {
+        Self {
+            max_message_len: MAX_FIELD_LEN,
+            max_voice_len: MAX_VOICE_LEN,
+            max_attachment_len: MAX_ATTACHMENT_LEN,
+        }
+    }
+}
+
+/// Text encrypted with a room's key, see `e2e::RoomKey`
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct Sealed {
+    #[serde(with = "base64_bytes")]
+    pub nonce: Vec<u8>,
+    #[serde(with = "base64_bytes")]
+    pub ciphertext: Vec<u8>,
+}
+
+/// A short audio clip, like a voice message
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct VoiceClip {
+    /// The audio's file extension, like `ogg` or `wav`, so it can be saved somewhere a player will
+    /// recognize it
+    pub format: String,
+    /// The audio itself, sent as base64. Empty when the server no longer has it, which is the case
+    /// for clips recovered from the history.
+    #[serde(with = "base64_bytes")]
+    pub data: Vec<u8>,
+}
+
+impl VoiceClip {
+    /// Whether the format is a plausible file extension: a few letters and digits, and nothing
+    /// that could change where the clip is saved
+    pub fn has_valid_format(&self) -> bool {
+        (1..=8).contains(&self.format.len()) && self.format.chars().all(|c| c.is_ascii_alphanumeric())
+    }
+}
+
+/// A file sent to the room. Its bytes don't go in the message, they follow it in a raw frame (see
+/// `TcpConn::send_raw`), so they aren't made a third bigger by base64 or slowed down by
+/// deserializing.
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct Attachment {
+    /// The file's name, without the directories it was in
+    pub name: String,
+    /// How many bytes follow in the raw frame
+    pub len: u64,
+    /// The bytes themselves, once they've been received. Never part of the message.
+    #[serde(skip)]
+    pub data: Vec<u8>,
+}
+
+impl Attachment {
+    /// Whether the name is one the file can be saved under as it is on any platform: not empty,
+    /// nothing that could change where it's saved, and none of the characters Windows doesn't
+    /// allow in file names (`:` there would write to a hidden stream of another file)
+    pub fn has_valid_name(&self) -> bool {
+        (1..=255).contains(&self.name.len())
+            && self.name != "."
+            && self.name != ".."
+            && !self.name.chars().any(|c| matches!(c, '/' | '\\' | ':' | '<' | '>' | '"' | '|' | '?' | '*') || c.is_control())
+    }
+}
+
+/// Bytes as a base64 string, which is about a third bigger rather than the several times bigger
+/// they'd be as a JSON array of numbers
+mod base64_bytes {
+    use base64::Engine;
+    use base64::engine::general_purpose::STANDARD;
+    use serde::{Deserialize, Deserializer, Serializer};
+
+    pub fn serialize<S: Serializer>(bytes: &[u8], serializer: S) -> Result<S::Ok, S::Error> {
+        serializer.serialize_str(&STANDARD.encode(bytes))
+    }
+
+    pub fn deserialize<'de, D: Deserializer<'de>>(deserializer: D) -> Result<Vec<u8>, D::Error> {
+        let encoded = String::deserialize(deserializer)?;
+        STANDARD.decode(encoded).map_err(serde::de::Error::custom)
+    }
+}
+
+/// What someone can find out about a room before joining it
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct RoomInfo {
+    pub room_name: String,
+    pub user_count: usize,
+    /// Rooms can't have passwords yet, so this is always false for now. It's part of the info so
+    /// clients won't need to change once they can.
+    pub password_required: bool,
+    /// Whether the room is end-to-end encrypted, so joining it is no use without its passphrase
+    #[serde(default)]
+    pub encrypted: bool,
+    pub protocol_version: u32,
+}
+
+/// Everything the server shares about a client in the roster
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct RosterEntry {
+    pub id: u64,
+    pub name: String,
+    pub status: Option<String>,
+    /// How long it's been since the client last sent anything
+    pub idle_secs: u64,
+    pub presence: Presence,
+    /// Where they're connecting from. Only the host is told this, so it can be matched up with
+    /// firewall rules or bans.
+    pub address: Option<SocketAddr>,
+    /// The address's reverse DNS name, if the server looks them up and found one
+    #[serde(default)]
+    pub hostname: Option<String>,
+}
+
+/// A message from earlier in the room, as sent in a `ServerHistoryPage`
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct HistoryEntry {
+    /// The sequence number it was broadcast with
+    pub id: u64,
+    /// Seconds since the unix epoch
+    pub time: u64,
+    pub sender: String,
+    pub text: String,
+    pub urgent: bool,
+}
+
+/// Someone reporting someone else, as passed on to the moderators
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct Report {
+    /// Who sent the report
+    pub reporter: String,
+    /// The id and name of who they reported
+    pub id: u64,
+    pub name: String,
+    pub reason: String,
+    /// What the reported person said most recently, oldest first, so the moderators can see
+    /// what the report is about
+    pub recent: Vec<HistoryEntry>,
+}
+
+/// Something that happened to a connection, kept so the host can look back on it
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct ConnectionEvent {
+    /// Seconds since the unix epoch
+    pub time: u64,
+    pub change: ConnectionChange,
+    pub id: u64,
+    pub name: String,
+    pub address: SocketAddr,
+    /// The address's reverse DNS name, if the server looks them up and found one
+    #[serde(default)]
+    pub hostname: Option<String>,
+    /// More about what happened, like who made a report and why
+    #[serde(default)]
+    pub detail: Option<String>,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, Copy)]
+pub enum ConnectionChange {
+    Connected,
+    Disconnected,
+    Kicked,
+    /// Someone reported them to the moderators
+    Reported,
+}
+
+/// Whether someone is at the keyboard
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum Presence {
+    #[default]
+    Online,
+    Away,
+}
@@ -0,0 +1,378 @@
+// Named pipes, which stand in for Unix sockets on Windows. A `unix:<name>` address is the pipe
+// `\\.\pipe\<name>` there, or the name itself if it's already a full pipe path, so rooms and bots
+// that stay on one machine are set up the same way everywhere. Only on Windows.
+//
+// A pipe handle can't be read on one thread while it's written on another unless it's opened for
+// overlapped I/O, and then it can't be given timeouts or made non-blocking the way sockets can. So
+// like QUIC, each connection is passed through an in-process pipe (see `local`) by a pair of
+// threads, and `TcpConn` only sees a `PipeStream`.
+
+use std::ffi::OsString;
+use std::io::{self, IoSlice, Read, Write};
+use std::iter;
+use std::mem;
+use std::os::windows::ffi::OsStrExt;
+use std::os::windows::io::{AsRawHandle, FromRawHandle, OwnedHandle};
+use std::path::Path;
+use std::ptr;
+use std::sync::{Arc, Mutex};
+use std::thread;
+use std::time::Duration;
+
+use windows_sys::Win32::Foundation::{
+    ERROR_ACCESS_DENIED, ERROR_IO_PENDING, ERROR_PIPE_BUSY, ERROR_PIPE_CONNECTED, GENERIC_READ,
+    GENERIC_WRITE, INVALID_HANDLE_VALUE,
+};
+use windows_sys::Win32::Storage::FileSystem::{
+    CreateFileW, ReadFile, WriteFile, FILE_FLAG_FIRST_PIPE_INSTANCE, FILE_FLAG_OVERLAPPED,
+    OPEN_EXISTING, PIPE_ACCESS_DUPLEX, SECURITY_IDENTIFICATION, SECURITY_SQOS_PRESENT,
+};
+use windows_sys::Win32::System::IO::{CancelIoEx, GetOverlappedResult, OVERLAPPED};
+use windows_sys::Win32::System::Pipes::{
+    ConnectNamedPipe, CreateNamedPipeW, WaitNamedPipeW, PIPE_READMODE_BYTE,
+    PIPE_REJECT_REMOTE_CLIENTS, PIPE_TYPE_BYTE, PIPE_UNLIMITED_INSTANCES, PIPE_WAIT,
+};
+use windows_sys::Win32::System::Threading::CreateEventW;
+
+use crate::local::LocalStream;
+use crate::transport::Transport;
+
+
+
+/// What every named pipe's path starts with
+const PIPE_PREFIX: &str = r"\\.\pipe\";
+
+/// How much the pipe holds in each direction before writes wait for the other end to read
+const BUFFER_SIZE: u32 = 64 * 1024;
+
+/// How much is passed through the in-process pipe at a time
+const PUMP_SIZE: usize = 16 * 1024;
+
+/// How long to wait for the room to free up when every instance of its pipe is busy, which only
+/// happens for a moment while it's accepting someone else
+const BUSY_WAIT: Duration = Duration::from_secs(5);
+
+/// How often the sending thread cancels the receiving thread's read when it's done, until the
+/// receiving thread notices
+const CANCEL_INTERVAL: Duration = Duration::from_millis(10);
+
+/// One end of a named pipe connection, which reads and writes it. Clones from `try_clone` share
+/// it, and the pipe is closed once they're all dropped.
+pub struct PipeStream {
+    pipe: LocalStream,
+}
+
+impl Read for PipeStream {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        self.pipe.read(buf)
+    }
+}
+
+impl Write for PipeStream {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        self.pipe.write(buf)
+    }
+
+    fn write_vectored(&mut self, bufs: &[IoSlice<'_>]) -> io::Result<usize> {
+        self.pipe.write_vectored(bufs)
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        self.pipe.flush()
+    }
+}
+
+impl Transport for PipeStream {
+    fn set_nonblocking(&self, nonblocking: bool) -> io::Result<()> {
+        self.pipe.set_nonblocking(nonblocking)
+    }
+
+    fn set_read_timeout(&self, timeout: Option<Duration>) -> io::Result<()> {
+        self.pipe.set_read_timeout(timeout)
+    }
+
+    fn try_clone(&self) -> io::Result<Self> {
+        Ok(Self { pipe: self.pipe.try_clone()? })
+    }
+}
+
+/// Connect to a room hosted on the named pipe `path` names
+///
+/// # Errors
+/// Fails with `io::ErrorKind::NotFound` if no room is hosted there, or if the room was still busy
+/// after `BUSY_WAIT`.
+pub fn connect(path: &Path) -> io::Result<PipeStream> {
+    let name = pipe_name(path);
+    let mut waited = false;
+    loop {
+        // SAFETY: `name` is nul-terminated, and nothing else is passed by pointer. Identification
+        // only, so whatever is hosting the pipe can't act as us.
+        let handle = unsafe {
+            CreateFileW(
+                name.as_ptr(),
+                GENERIC_READ | GENERIC_WRITE,
+                0,
+                ptr::null(),
+                OPEN_EXISTING,
+                FILE_FLAG_OVERLAPPED | SECURITY_SQOS_PRESENT | SECURITY_IDENTIFICATION,
+                ptr::null_mut(),
+            )
+        };
+        if handle != INVALID_HANDLE_VALUE {
+            // SAFETY: `handle` was just opened and nothing else owns it
+            return bridge(unsafe { OwnedHandle::from_raw_handle(handle) });
+        }
+
+        let e = io::Error::last_os_error();
+        if waited || e.raw_os_error() != Some(ERROR_PIPE_BUSY as i32) {
+            return Err(e);
+        }
+        // SAFETY: `name` is nul-terminated. If this gives up, opening the pipe again says why.
+        unsafe { WaitNamedPipeW(name.as_ptr(), BUSY_WAIT.as_millis() as u32) };
+        waited = true;
+    }
+}
+
+/// Accepts connections to a room on a named pipe
+pub struct PipeListener {
+    name: Vec<u16>,
+    /// The instance of the pipe the next client connects to. There's always one waiting, so
+    /// clients don't find the pipe missing in between accepts.
+    next: Mutex<OwnedHandle>,
+}
+
+impl PipeListener {
+    /// Host on the named pipe `path` names
+    ///
+    /// # Errors
+    /// Fails with `io::ErrorKind::AddrInUse` if something else is already hosting on it, or if the
+    /// pipe can't be made.
+    pub fn bind(path: &Path) -> io::Result<Self> {
+        let name = pipe_name(path);
+        let next = match create_instance(&name, true) {
+            Err(e) if e.raw_os_error() == Some(ERROR_ACCESS_DENIED as i32) => return Err(io::Error::new(
+                io::ErrorKind::AddrInUse,
+                format!("Something is already hosting on {}", path.display())
+            )),
+            created => created?,
+        };
+        Ok(Self { name, next: Mutex::new(next) })
+    }
+
+    /// Wait for the next connection
+    ///
+    /// # Errors
+    /// Fails if waiting for a client does, or if another instance of the pipe can't be made for
+    /// the client after it.
+    pub fn accept(&self) -> io::Result<PipeStream> {
+        let mut next = self.next.lock().unwrap();
+        let pipe = next.as_raw_handle();
+        // SAFETY: the instance stays open while it's waited on, `next` holds it
+        match overlapped(&next, |overlapped| unsafe { ConnectNamedPipe(pipe, overlapped) }) {
+            Ok(_) => {},
+            // the client got there between making the instance and waiting on it
+            Err(e) if e.raw_os_error() == Some(ERROR_PIPE_CONNECTED as i32) => {},
+            Err(e) => return Err(e),
+        }
+        let connected = mem::replace(&mut *next, create_instance(&self.name, false)?);
+        bridge(connected)
+    }
+}
+
+/// The nul-terminated name of the pipe `path` names, which is under `PIPE_PREFIX` unless it's
+/// there already
+fn pipe_name(path: &Path) -> Vec<u16> {
+    let mut name = OsString::new();
+    if !path.to_string_lossy().to_lowercase().starts_with(PIPE_PREFIX) {
+        name.push(PIPE_PREFIX);
+    }
+    name.push(path);
+    name.encode_wide().chain(iter::once(0)).collect()
+}
+
+/// Make a new instance of the pipe `name`, for the next client to connect to. Only this machine
+/// can connect to it. The `first` one fails if something else already has the pipe.
+fn create_instance(name: &[u16], first: bool) -> io::Result<OwnedHandle> {
+    let mut mode = PIPE_ACCESS_DUPLEX | FILE_FLAG_OVERLAPPED;
+    if first {
+        mode |= FILE_FLAG_FIRST_PIPE_INSTANCE;
+    }
+    // SAFETY: `name` is nul-terminated, and the default security is used
+    let handle = unsafe {
+        CreateNamedPipeW(
+            name.as_ptr(),
+            mode,
+            PIPE_TYPE_BYTE | PIPE_READMODE_BYTE | PIPE_WAIT | PIPE_REJECT_REMOTE_CLIENTS,
+            PIPE_UNLIMITED_INSTANCES,
+            BUFFER_SIZE,
+            BUFFER_SIZE,
+            0,
+            ptr::null(),
+        )
+    };
+    if handle == INVALID_HANDLE_VALUE {
+        return Err(io::Error::last_os_error());
+    }
+    // SAFETY: `handle` was just made and nothing else owns it
+    Ok(unsafe { OwnedHandle::from_raw_handle(handle) })
+}
+
+/// Start an overlapped operation on `handle` with `start` and wait for it to finish, returning how
+/// many bytes it moved. Each call waits on an event of its own, so a read and a write can be under
+/// way on the same handle at once.
+fn overlapped(handle: &OwnedHandle, start: impl FnOnce(*mut OVERLAPPED) -> i32) -> io::Result<u32> {
+    // SAFETY: an unnamed manual-reset event with the default security
+    let event = unsafe { CreateEventW(ptr::null(), 1, 0, ptr::null()) };
+    if event.is_null() {
+        return Err(io::Error::last_os_error());
+    }
+    // SAFETY: `event` was just made and nothing else owns it
+    let event = unsafe { OwnedHandle::from_raw_handle(event) };
+
+    // SAFETY: `OVERLAPPED` is plain data, and all zeroes (apart from the event) is how it starts
+    let mut overlapped: OVERLAPPED = unsafe { mem::zeroed() };
+    overlapped.hEvent = event.as_raw_handle();
+    if start(&mut overlapped) == 0 {
+        let e = io::Error::last_os_error();
+        if e.raw_os_error() != Some(ERROR_IO_PENDING as i32) {
+            return Err(e);
+        }
+    }
+
+    let mut moved = 0;
+    // SAFETY: `overlapped` is the one the operation was started with, and it's waited on here, so
+    // it outlives the operation
+    if unsafe { GetOverlappedResult(handle.as_raw_handle(), &overlapped, &mut moved, 1) } == 0 {
+        return Err(io::Error::last_os_error());
+    }
+    Ok(moved)
+}
+
+/// Pass the pipe through an in-process pipe, one thread for each direction, and return the end of
+/// it to read and write with. Either thread stopping, because the other end hung up or every
+/// handle to the returned end was dropped, stops the other one too.
+fn bridge(handle: OwnedHandle) -> io::Result<PipeStream> {
+    let (ours, theirs) = LocalStream::pair();
+    let mut incoming = theirs.try_clone()?;
+    let mut outgoing = theirs;
+    let receiving = Arc::new(handle);
+    let sending = Arc::clone(&receiving);
+
+    thread::Builder::new()
+        .name(String::from("pipe receiving thread"))
+        .spawn(move || {
+            let mut buf = vec![0; PUMP_SIZE];
+            let pipe = receiving.as_raw_handle();
+            loop {
+                // SAFETY: `buf` and `overlapped` outlive the read, which is waited on
+                let read = overlapped(&receiving, |overlapped| unsafe {
+                    ReadFile(pipe, buf.as_mut_ptr(), PUMP_SIZE as u32, ptr::null_mut(), overlapped)
+                });
+                match read {
+                    Ok(read) if read > 0 => if incoming.write_all(&buf[..read as usize]).is_err() {
+                        break;
+                    },
+                    // the other end hung up, or the sending thread cancelled the read
+                    _ => break,
+                }
+            }
+            // nothing more is coming, which whoever's reading finds out once they've read the rest
+            incoming.shutdown();
+        })?;
+
+    thread::Builder::new()
+        .name(String::from("pipe sending thread"))
+        .spawn(move || {
+            let mut buf = vec![0; PUMP_SIZE];
+            let pipe = sending.as_raw_handle();
+            'pumping: loop {
+                let mut read = match outgoing.read(&mut buf) {
+                    Ok(0) | Err(_) => break,
+                    Ok(read) => &buf[..read],
+                };
+                while !read.is_empty() {
+                    // SAFETY: `read` and `overlapped` outlive the write, which is waited on
+                    let written = overlapped(&sending, |overlapped| unsafe {
+                        WriteFile(pipe, read.as_ptr(), read.len() as u32, ptr::null_mut(), overlapped)
+                    });
+                    match written {
+                        Ok(written) => read = &read[written as usize..],
+                        Err(_) => break 'pumping,
+                    }
+                }
+            }
+            // the receiving thread may be waiting on a read that never finishes, or about to start
+            // one, so it's cancelled until the thread's gone. What's been written stays in the
+            // pipe for the other end once the handle is closed.
+            while Arc::strong_count(&sending) > 1 {
+                // SAFETY: the handle is still open, `sending` holds it
+                unsafe { CancelIoEx(pipe, ptr::null()) };
+                thread::sleep(CANCEL_INTERVAL);
+            }
+        })?;
+
+    Ok(PipeStream { pipe: ours })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::path::PathBuf;
+    use crate::tcp_conn::TcpConn;
+
+    /// A pipe name nothing else is using, so tests can run side by side
+    fn unused(test: &str) -> PathBuf {
+        PathBuf::from(format!("tcp_chat-{}-{test}", std::process::id()))
+    }
+
+    #[test]
+    fn messages_go_both_ways() {
+        let path = unused("both_ways");
+        let listener = PipeListener::bind(&path).unwrap();
+
+        let mut client = TcpConn::new(connect(&path).unwrap()).unwrap();
+        client.send(&"hello").unwrap();
+
+        let mut server = TcpConn::new(listener.accept().unwrap()).unwrap();
+        assert_eq!(server.receive::<String>().unwrap(), "hello");
+
+        // more than the pipe holds at once, even compressed
+        let mut state = 0x2545_f491_u32;
+        let big: String = (0..1024 * 1024).map(|_| {
+            state ^= state << 13;
+            state ^= state >> 17;
+            state ^= state << 5;
+            char::from_digit(state % 16, 16).unwrap()
+        }).collect();
+        let sending = thread::spawn(move || {
+            server.send(&big).unwrap();
+            big
+        });
+        assert_eq!(client.receive::<String>().unwrap(), sending.join().unwrap());
+    }
+
+    #[test]
+    fn hanging_up_is_noticed() {
+        let path = unused("hanging_up");
+        let listener = PipeListener::bind(&path).unwrap();
+
+        let mut client = TcpConn::new(connect(&path).unwrap()).unwrap();
+        client.send(&"bye").unwrap();
+        let mut server = TcpConn::new(listener.accept().unwrap()).unwrap();
+        drop(client);
+
+        assert_eq!(server.receive::<String>().unwrap(), "bye");
+        assert!(server.receive::<String>().is_err());
+    }
+
+    #[test]
+    fn only_one_room_per_pipe() {
+        let path = unused("one_room");
+        let _listener = PipeListener::bind(&path).unwrap();
+        assert_eq!(PipeListener::bind(&path).err().unwrap().kind(), io::ErrorKind::AddrInUse);
+
+        // the full path names the same pipe
+        let full = PathBuf::from(format!("{PIPE_PREFIX}{}", path.display()));
+        assert_eq!(PipeListener::bind(&full).err().unwrap().kind(), io::ErrorKind::AddrInUse);
+    }
+}
@@ -0,0 +1,399 @@
+// QUIC as another way to reach a room. It's encrypted with TLS 1.3 out of the box, and a lost
+// packet only holds up what it was carrying, so it copes better with lossy links than TCP. Only
+// with the `quic` feature.
+//
+// Everything else in tcp_chat blocks a thread per connection, while QUIC runs on an async runtime,
+// so each connection's one stream is passed through an in-process pipe (see `local`) by a pair of
+// threads. `TcpConn` only sees a `QuicStream`, which behaves like any other socket.
+//
+// The server's certificate is made up each time it starts and clients don't check it, so QUIC
+// keeps what's said private from anyone listening in, but doesn't prove who's at the other end.
+// Turn on `noise` as well for that, whose keys are checked against the servers joined before.
+
+use std::future;
+use std::io::{self, IoSlice, Read, Write};
+use std::net::{Ipv4Addr, Ipv6Addr, SocketAddr};
+use std::sync::mpsc::{self, Receiver, Sender};
+use std::sync::{Arc, OnceLock};
+use std::thread;
+use std::time::Duration;
+
+use quinn::crypto::rustls::{QuicClientConfig, QuicServerConfig};
+use quinn::{ClientConfig, Connection, Endpoint, Incoming, RecvStream, SendStream, ServerConfig, TransportConfig};
+use rustls::client::danger::{HandshakeSignatureValid, ServerCertVerified, ServerCertVerifier};
+use rustls::crypto::{ring, verify_tls12_signature, verify_tls13_signature, CryptoProvider};
+use rustls::pki_types::{CertificateDer, PrivatePkcs8KeyDer, ServerName, UnixTime};
+use rustls::{DigitallySignedStruct, SignatureScheme};
+use tokio::runtime::{Builder, Handle};
+use tokio::time::timeout;
+
+use crate::local::LocalStream;
+use crate::transport::Transport;
+
+
+
+/// What both ends say they speak during the TLS handshake, so a QUIC server for something else
+/// turns clients away rather than talking past them
+const ALPN: &[u8] = b"tcp_chat";
+
+/// The name the server's certificate is made out to. Clients don't check it, but it has to be
+/// something.
+const SERVER_NAME: &str = "tcp_chat";
+
+/// How long a connection gets to finish its handshake and open its stream
+pub const HANDSHAKE_TIMEOUT: Duration = Duration::from_secs(10);
+
+/// QUIC drops connections that go quiet for half a minute, which TCP doesn't, so clients keep
+/// theirs from looking that way even when they don't agree to heartbeats
+const KEEP_ALIVE_INTERVAL: Duration = Duration::from_secs(10);
+
+/// How long to wait, once a connection is done with, for the other end to get what was last sent
+/// before hanging up
+const LINGER: Duration = Duration::from_secs(5);
+
+/// How much is passed through the pipe at a time
+const PUMP_SIZE: usize = 16 * 1024;
+
+/// The runtime QUIC endpoints run on, started the first time one's needed and kept for the rest of
+/// the process by a thread of its own
+fn runtime() -> &'static Handle {
+    static RUNTIME: OnceLock<Handle> = OnceLock::new();
+    RUNTIME.get_or_init(|| {
+        let runtime = Builder::new_current_thread()
+            .enable_all()
+            .build()
+            .expect("Unable to start the QUIC runtime");
+        let handle = runtime.handle().clone();
+        thread::Builder::new()
+            .name(String::from("quic runtime thread"))
+            .spawn(move || runtime.block_on(future::pending::<()>()))
+            .unwrap();
+        handle
+    })
+}
+
+fn provider() -> Arc<CryptoProvider> {
+    Arc::new(ring::default_provider())
+}
+
+/// One end of a QUIC connection, which reads and writes its stream. Clones from `try_clone` share
+/// it, and the connection is closed once they're all dropped.
+pub struct QuicStream {
+    pipe: LocalStream,
+    peer: SocketAddr,
+}
+
+impl QuicStream {
+    /// Where the other end is
+    pub fn peer_addr(&self) -> SocketAddr {
+        self.peer
+    }
+}
+
+impl Read for QuicStream {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        self.pipe.read(buf)
+    }
+}
+
+impl Write for QuicStream {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        self.pipe.write(buf)
+    }
+
+    fn write_vectored(&mut self, bufs: &[IoSlice<'_>]) -> io::Result<usize> {
+        self.pipe.write_vectored(bufs)
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        self.pipe.flush()
+    }
+}
+
+impl Transport for QuicStream {
+    fn set_nonblocking(&self, nonblocking: bool) -> io::Result<()> {
+        self.pipe.set_nonblocking(nonblocking)
+    }
+
+    fn set_read_timeout(&self, timeout: Option<Duration>) -> io::Result<()> {
+        self.pipe.set_read_timeout(timeout)
+    }
+
+    fn try_clone(&self) -> io::Result<Self> {
+        Ok(Self { pipe: self.pipe.try_clone()?, peer: self.peer })
+    }
+}
+
+/// Connect to a room over QUIC at the first of `addrs` that answers, giving up on each one that
+/// takes longer than `wait`
+///
+/// # Errors
+/// Fails with whatever went wrong with the last address, or with `io::ErrorKind::TimedOut` if it
+/// didn't answer in time.
+pub fn connect(addrs: &[SocketAddr], wait: Duration) -> io::Result<QuicStream> {
+    let mut last_error = io::Error::new(io::ErrorKind::InvalidInput, "No addresses to connect to");
+    for &addr in addrs {
+        // timers only start inside the runtime
+        match runtime().block_on(async { timeout(wait, connect_to(addr)).await }) {
+            Ok(Ok(stream)) => return Ok(stream),
+            Ok(Err(e)) => last_error = e,
+            Err(_) => last_error = io::Error::new(
+                io::ErrorKind::TimedOut,
+                format!("{addr} didn't answer over QUIC in time")
+            ),
+        }
+    }
+    Err(last_error)
+}
+
+async fn connect_to(addr: SocketAddr) -> io::Result<QuicStream> {
+    let any = match addr {
+        SocketAddr::V4(_) => SocketAddr::new(Ipv4Addr::UNSPECIFIED.into(), 0),
+        SocketAddr::V6(_) => SocketAddr::new(Ipv6Addr::UNSPECIFIED.into(), 0),
+    };
+    let mut endpoint = Endpoint::client(any)?;
+    endpoint.set_default_client_config(client_config()?);
+
+    let connection = endpoint.connect(addr, SERVER_NAME).map_err(io::Error::other)?.await?;
+    let streams = connection.open_bi().await?;
+    bridge(endpoint, connection, streams)
+}
+
+/// Accepts connections to a room over QUIC
+pub struct QuicListener {
+    endpoint: Endpoint,
+    connections: Receiver<QuicStream>,
+}
+
+impl QuicListener {
+    /// Listen for QUIC connections on `addr`, with a certificate made up on the spot
+    ///
+    /// # Errors
+    /// Fails if `addr` can't be bound, usually because something else is using the port.
+    pub fn bind(addr: SocketAddr) -> io::Result<Self> {
+        let handle = runtime();
+        let _entered = handle.enter();
+        let endpoint = Endpoint::server(server_config()?, addr)?;
+
+        let (sender, connections) = mpsc::channel();
+        handle.spawn(accept_connections(endpoint.clone(), sender));
+        Ok(Self { endpoint, connections })
+    }
+
+    /// The address the listener is bound to, which has the port picked if it was bound to port 0
+    ///
+    /// # Errors
+    /// Fails if the socket can't say.
+    pub fn local_addr(&self) -> io::Result<SocketAddr> {
+        self.endpoint.local_addr()
+    }
+
+    /// Wait for the next connection
+    ///
+    /// # Errors
+    /// Fails if the listener has stopped.
+    pub fn accept(&self) -> io::Result<QuicStream> {
+        self.connections.recv().map_err(|_| io::Error::other("The QUIC listener stopped"))
+    }
+}
+
+impl Drop for QuicListener {
+    fn drop(&mut self) {
+        // turns away anyone connecting from now on, and stops `accept_connections`
+        self.endpoint.close(0u32.into(), b"");
+    }
+}
+
+/// Hand every connection made to `endpoint` to `connections` once its stream is open, until the
+/// endpoint is closed. Each one is waited on separately, so one that's slow to finish its
+/// handshake doesn't hold up the rest.
+async fn accept_connections(endpoint: Endpoint, connections: Sender<QuicStream>) {
+    while let Some(incoming) = endpoint.accept().await {
+        let endpoint = endpoint.clone();
+        let connections = connections.clone();
+        tokio::spawn(async move {
+            let Ok(Ok((connection, streams))) = timeout(HANDSHAKE_TIMEOUT, accept_stream(incoming)).await else {
+                return;
+            };
+            if let Ok(stream) = bridge(endpoint, connection, streams) {
+                // nobody's accepting anymore if this fails, and dropping it hangs up
+                let _ = connections.send(stream);
+            }
+        });
+    }
+}
+
+/// The connection `incoming` makes, once it's opened its stream, which happens when the client
+/// first sends something on it
+async fn accept_stream(incoming: Incoming) -> io::Result<(Connection, (SendStream, RecvStream))> {
+    let connection = incoming.await?;
+    let streams = connection.accept_bi().await?;
+    Ok((connection, streams))
+}
+
+/// Pass the connection's stream through a pipe, one thread for each direction, and return the end
+/// of the pipe to read and write it with. Either thread stopping, because the connection closed or
+/// every handle to the returned end was dropped, stops the other one too.
+fn bridge(endpoint: Endpoint, connection: Connection, (mut send, mut recv): (SendStream, RecvStream)) -> io::Result<QuicStream> {
+    let peer = connection.remote_address();
+    let (ours, theirs) = LocalStream::pair();
+    let mut incoming = theirs.try_clone()?;
+    let mut outgoing = theirs;
+
+    thread::Builder::new()
+        .name(String::from("quic receiving thread"))
+        .spawn(move || {
+            let mut buf = vec![0; PUMP_SIZE];
+            while let Ok(Some(read)) = runtime().block_on(recv.read(&mut buf)) {
+                if incoming.write_all(&buf[..read]).is_err() {
+                    break;
+                }
+            }
+            // nothing more is coming, which whoever's reading finds out once they've read the rest
+            incoming.shutdown();
+        })?;
+
+    thread::Builder::new()
+        .name(String::from("quic sending thread"))
+        .spawn(move || {
+            let mut buf = vec![0; PUMP_SIZE];
+            loop {
+                match outgoing.read(&mut buf) {
+                    Ok(0) | Err(_) => break,
+                    Ok(read) => if runtime().block_on(send.write_all(&buf[..read])).is_err() {
+                        break;
+                    },
+                }
+            }
+
+            // closing the connection straight away could lose what hasn't been acknowledged yet
+            if send.finish().is_ok() {
+                let _ = runtime().block_on(async { timeout(LINGER, send.stopped()).await });
+            }
+            connection.close(0u32.into(), b"");
+            // the endpoint has to outlive its connections
+            drop(endpoint);
+        })?;
+
+    Ok(QuicStream { pipe: ours, peer })
+}
+
+fn server_config() -> io::Result<ServerConfig> {
+    let certified = rcgen::generate_simple_self_signed(vec![SERVER_NAME.to_string()]).map_err(io::Error::other)?;
+    let key = PrivatePkcs8KeyDer::from(certified.key_pair.serialize_der());
+
+    let mut crypto = rustls::ServerConfig::builder_with_provider(provider())
+        .with_protocol_versions(&[&rustls::version::TLS13])
+        .map_err(io::Error::other)?
+        .with_no_client_auth()
+        .with_single_cert(vec![certified.cert.der().clone()], key.into())
+        .map_err(io::Error::other)?;
+    crypto.alpn_protocols = vec![ALPN.to_vec()];
+
+    let crypto = QuicServerConfig::try_from(crypto).map_err(io::Error::other)?;
+    Ok(ServerConfig::with_crypto(Arc::new(crypto)))
+}
+
+fn client_config() -> io::Result<ClientConfig> {
+    let provider = provider();
+    let mut crypto = rustls::ClientConfig::builder_with_provider(Arc::clone(&provider))
+        .with_protocol_versions(&[&rustls::version::TLS13])
+        .map_err(io::Error::other)?
+        .dangerous()
+        .with_custom_certificate_verifier(Arc::new(AnyCertificate(provider)))
+        .with_no_client_auth();
+    crypto.alpn_protocols = vec![ALPN.to_vec()];
+
+    let crypto = QuicClientConfig::try_from(crypto).map_err(io::Error::other)?;
+    let mut transport = TransportConfig::default();
+    transport.keep_alive_interval(Some(KEEP_ALIVE_INTERVAL));
+    let mut config = ClientConfig::new(Arc::new(crypto));
+    config.transport_config(Arc::new(transport));
+    Ok(config)
+}
+
+/// Takes whatever certificate the server has, see the top of this file. The handshake is still
+/// signed with the certificate's key, so the connection is private to whoever holds it.
+#[derive(Debug)]
+struct AnyCertificate(Arc<CryptoProvider>);
+
+impl ServerCertVerifier for AnyCertificate {
+    fn verify_server_cert(
+        &self,
+        _end_entity: &CertificateDer<'_>,
+        _intermediates: &[CertificateDer<'_>],
+        _server_name: &ServerName<'_>,
+        _ocsp_response: &[u8],
+        _now: UnixTime
+    ) -> Result<ServerCertVerified, rustls::Error> {
+        Ok(ServerCertVerified::assertion())
+    }
+
+    fn verify_tls12_signature(
+        &self,
+        message: &[u8],
+        cert: &CertificateDer<'_>,
+        dss: &DigitallySignedStruct
+    ) -> Result<HandshakeSignatureValid, rustls::Error> {
+        verify_tls12_signature(message, cert, dss, &self.0.signature_verification_algorithms)
+    }
+
+    fn verify_tls13_signature(
+        &self,
+        message: &[u8],
+        cert: &CertificateDer<'_>,
+        dss: &DigitallySignedStruct
+    ) -> Result<HandshakeSignatureValid, rustls::Error> {
+        verify_tls13_signature(message, cert, dss, &self.0.signature_verification_algorithms)
+    }
+
+    fn supported_verify_schemes(&self) -> Vec<SignatureScheme> {
+        self.0.signature_verification_algorithms.supported_schemes()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::tcp_conn::TcpConn;
+
+    #[test]
+    fn messages_go_both_ways() {
+        let listener = QuicListener::bind(SocketAddr::new(Ipv4Addr::LOCALHOST.into(), 0)).unwrap();
+        let addr = listener.local_addr().unwrap();
+
+        let mut client = TcpConn::new(connect(&[addr], HANDSHAKE_TIMEOUT).unwrap()).unwrap();
+        client.send(&"hello").unwrap();
+
+        let mut server = TcpConn::new(listener.accept().unwrap()).unwrap();
+        assert_eq!(server.receive::<String>().unwrap(), "hello");
+
+        // more than the pipe between the threads holds at once, even compressed
+        let mut state = 0x2545_f491_u32;
+        let big: String = (0..1024 * 1024).map(|_| {
+            state ^= state << 13;
+            state ^= state >> 17;
+            state ^= state << 5;
+            char::from_digit(state % 16, 16).unwrap()
+        }).collect();
+        let sending = thread::spawn(move || {
+            server.send(&big).unwrap();
+            big
+        });
+        assert_eq!(client.receive::<String>().unwrap(), sending.join().unwrap());
+    }
+
+    #[test]
+    fn hanging_up_is_noticed() {
+        let listener = QuicListener::bind(SocketAddr::new(Ipv4Addr::LOCALHOST.into(), 0)).unwrap();
+        let addr = listener.local_addr().unwrap();
+
+        let mut client = TcpConn::new(connect(&[addr], HANDSHAKE_TIMEOUT).unwrap()).unwrap();
+        client.send(&"bye").unwrap();
+        let mut server = TcpConn::new(listener.accept().unwrap()).unwrap();
+        drop(client);
+
+        assert_eq!(server.receive::<String>().unwrap(), "bye");
+        assert!(server.receive::<String>().is_err());
+    }
+}
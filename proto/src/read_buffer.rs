@@ -0,0 +1,62 @@
+// Bytes that are added at the end and taken off the front, like what's arrived on a connection.
+// Taking bytes off the front only moves where the buffer starts, and what's left is moved back to
+// the front of the allocation once at least as much has been taken as is left, so taking frames
+// out one at a time doesn't shift everything behind them each time.
+
+use std::io::{self, Read};
+use std::ops::Deref;
+
+
+
+#[derive(Debug, Default)]
+pub struct ReadBuffer {
+    bytes: Vec<u8>,
+    /// Where the bytes that haven't been taken yet start
+    start: usize,
+}
+
+impl ReadBuffer {
+    /// Take the first `len` bytes off the front
+    ///
+    /// # Panics
+    /// Panics if there aren't that many.
+    pub fn consume(&mut self, len: usize) {
+        assert!(len <= self.len(), "[error] Tried to take more bytes than were buffered");
+        self.start += len;
+        if self.start == self.bytes.len() {
+            self.clear();
+        } else if self.start >= self.bytes.len() - self.start {
+            self.bytes.drain(..self.start);
+            self.start = 0;
+        }
+    }
+
+    pub fn extend_from_slice(&mut self, bytes: &[u8]) {
+        self.bytes.extend_from_slice(bytes);
+    }
+
+    pub fn clear(&mut self) {
+        self.bytes.clear();
+        self.start = 0;
+    }
+
+    /// Read once from `reader` straight into the end of the buffer, taking at most `len` bytes
+    ///
+    /// # Errors
+    /// Whatever `reader` fails with, in which case nothing is added.
+    pub fn read_from(&mut self, reader: &mut impl Read, len: usize) -> io::Result<usize> {
+        let end = self.bytes.len();
+        self.bytes.resize(end + len, 0);
+        let read = reader.read(&mut self.bytes[end..]);
+        self.bytes.truncate(end + *read.as_ref().unwrap_or(&0));
+        read
+    }
+}
+
+impl Deref for ReadBuffer {
+    type Target = [u8];
+
+    fn deref(&self) -> &[u8] {
+        &self.bytes[self.start..]
+    }
+}
@@ -0,0 +1,186 @@
+// Secrets like webhook tokens are kept out of the config file. They go in the OS keyring where
+// there is one, and otherwise in a file encrypted with a passphrase from the environment, which is
+// what headless machines without a keyring daemon end up using.
+
+use std::collections::BTreeMap;
+use std::env;
+use std::fs;
+use std::io;
+use std::path::PathBuf;
+
+use argon2::Argon2;
+use chacha20poly1305::{XChaCha20Poly1305, XNonce, KeyInit};
+use chacha20poly1305::aead::{Aead, AeadCore, OsRng, rand_core::RngCore};
+
+/// Service name secrets are filed under in the OS keyring. Profiles other than the default one add
+/// their name, e.g. `tcp_chat/work`.
+pub const KEYRING_SERVICE: &str = "tcp_chat";
+
+/// Environment variable holding the passphrase for the fallback file
+pub const PASSPHRASE_VAR: &str = "TCP_CHAT_SECRETS_PASSPHRASE";
+
+const SALT_LEN: usize = 16;
+const NONCE_LEN: usize = 24;
+
+
+
+/// Where secrets are looked up and stored. The keyring is always tried first, and the fallback
+/// file is only used for secrets the keyring doesn't have or when there's no keyring to use.
+#[derive(Debug, Clone)]
+pub struct SecretStore {
+    /// What the secrets are filed under in the keyring, so each profile has its own
+    service: String,
+    /// The encrypted file to use without a keyring. Without one, only the keyring is used.
+    fallback: Option<PathBuf>,
+}
+
+impl SecretStore {
+    /// The store for `profile`, or the default profile if there isn't one
+    pub fn new(profile: Option<&str>, fallback: Option<PathBuf>) -> Self {
+        let service = match profile {
+            Some(profile) => format!("{KEYRING_SERVICE}/{profile}"),
+            None => KEYRING_SERVICE.to_string(),
+        };
+        Self { service, fallback }
+    }
+
+    /// Look up the secret stored under `key`, e.g. `webhook/ci`
+    pub fn get(&self, key: &str) -> io::Result<Option<String>> {
+        match self.keyring_entry(key)?.get_password() {
+            Ok(secret) => return Ok(Some(secret)),
+            Err(keyring::Error::NoEntry) => (),
+            Err(e) if keyring_unavailable(&e) => (),
+            Err(e) => return Err(keyring_error(e)),
+        }
+
+        match &self.fallback {
+            Some(path) if path.exists() => Ok(self.read_file()?.remove(key)),
+            _ => Ok(None),
+        }
+    }
+
+    /// Store `secret` under `key`, replacing whatever was there. Returns where it ended up, to
+    /// tell the user.
+    pub fn set(&self, key: &str, secret: &str) -> io::Result<&'static str> {
+        match self.keyring_entry(key)?.set_password(secret) {
+            Ok(()) => return Ok("the OS keyring"),
+            Err(e) if keyring_unavailable(&e) => (),
+            Err(e) => return Err(keyring_error(e)),
+        }
+
+        let mut secrets = self.read_file()?;
+        secrets.insert(key.to_owned(), secret.to_owned());
+        self.write_file(&secrets)?;
+        Ok("the encrypted secrets file")
+    }
+
+    /// Forget the secret stored under `key` wherever it is. Returns whether there was one.
+    pub fn delete(&self, key: &str) -> io::Result<bool> {
+        let in_keyring = match self.keyring_entry(key)?.delete_credential() {
+            Ok(()) => true,
+            Err(keyring::Error::NoEntry) => false,
+            Err(e) if keyring_unavailable(&e) => false,
+            Err(e) => return Err(keyring_error(e)),
+        };
+
+        let in_file = match &self.fallback {
+            Some(path) if path.exists() => {
+                let mut secrets = self.read_file()?;
+                let removed = secrets.remove(key).is_some();
+                if removed {
+                    self.write_file(&secrets)?;
+                }
+                removed
+            },
+            _ => false,
+        };
+
+        Ok(in_keyring || in_file)
+    }
+
+    /// Decrypt every secret in the fallback file. A missing file holds no secrets.
+    fn read_file(&self) -> io::Result<BTreeMap<String, String>> {
+        let path = self.fallback_path()?;
+        let contents = match fs::read(path) {
+            Ok(contents) => contents,
+            Err(e) if e.kind() == io::ErrorKind::NotFound => return Ok(BTreeMap::new()),
+            Err(e) => return Err(e),
+        };
+
+        if contents.len() < SALT_LEN + NONCE_LEN {
+            return Err(invalid_file("it's too short"));
+        }
+        let (salt, rest) = contents.split_at(SALT_LEN);
+        let (nonce, ciphertext) = rest.split_at(NONCE_LEN);
+
+        let cipher = cipher(salt)?;
+        let plaintext = cipher.decrypt(XNonce::from_slice(nonce), ciphertext)
+            .map_err(|_| invalid_file("the passphrase is wrong or the file is damaged"))?;
+
+        serde_json::from_slice(&plaintext).map_err(|e| invalid_file(&e.to_string()))
+    }
+
+    /// Encrypt `secrets` into the fallback file with a fresh salt and nonce
+    fn write_file(&self, secrets: &BTreeMap<String, String>) -> io::Result<()> {
+        let path = self.fallback_path()?;
+
+        let mut salt = [0; SALT_LEN];
+        OsRng.fill_bytes(&mut salt);
+        let nonce = XChaCha20Poly1305::generate_nonce(&mut OsRng);
+
+        let plaintext = serde_json::to_vec(secrets)?;
+        let ciphertext = cipher(&salt)?.encrypt(&nonce, plaintext.as_slice())
+            .map_err(|_| io::Error::other("Unable to encrypt secrets"))?;
+
+        if let Some(dir) = path.parent() {
+            fs::create_dir_all(dir)?;
+        }
+        fs::write(path, [&salt[..], &nonce[..], &ciphertext[..]].concat())?;
+
+        // encrypted or not, nobody else has any business reading it
+        #[cfg(unix)]
+        {
+            use std::os::unix::fs::PermissionsExt;
+            fs::set_permissions(path, fs::Permissions::from_mode(0o600))?;
+        }
+        Ok(())
+    }
+
+    fn keyring_entry(&self, key: &str) -> io::Result<keyring::Entry> {
+        keyring::Entry::new(&self.service, key).map_err(keyring_error)
+    }
+
+    fn fallback_path(&self) -> io::Result<&PathBuf> {
+        self.fallback.as_ref().ok_or_else(|| io::Error::new(
+            io::ErrorKind::Unsupported,
+            "There's no OS keyring to keep secrets in, and nowhere to put a secrets file"
+        ))
+    }
+}
+
+
+/// Whether `e` means there's no usable keyring at all, as opposed to a problem with one secret
+fn keyring_unavailable(e: &keyring::Error) -> bool {
+    matches!(e, keyring::Error::PlatformFailure(_) | keyring::Error::NoStorageAccess(_))
+}
+
+fn keyring_error(e: keyring::Error) -> io::Error {
+    io::Error::other(format!("OS keyring: {e}"))
+}
+
+fn invalid_file(reason: &str) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidData, format!("Unable to read the secrets file, {reason}"))
+}
+
+/// The cipher for the fallback file, keyed by the passphrase in `PASSPHRASE_VAR` and `salt`
+fn cipher(salt: &[u8]) -> io::Result<XChaCha20Poly1305> {
+    let passphrase = env::var(PASSPHRASE_VAR).map_err(|_| io::Error::new(
+        io::ErrorKind::PermissionDenied,
+        format!("There's no OS keyring available, so set {PASSPHRASE_VAR} to use the encrypted secrets file instead")
+    ))?;
+
+    let mut key = [0; 32];
+    Argon2::default().hash_password_into(passphrase.as_bytes(), salt, &mut key)
+        .map_err(|e| io::Error::other(format!("Unable to derive the secrets key: {e}")))?;
+    Ok(XChaCha20Poly1305::new(&key.into()))
+}
@@ -0,0 +1,3121 @@
+use std::any::type_name;
+use std::collections::{BTreeMap, HashMap, VecDeque};
+use std::io::{self, BufReader, IoSlice, Write, Read};
+use std::mem;
+use std::num::NonZeroUsize;
+use std::ops::Range;
+use std::net::TcpStream;
+use std::sync::{Arc, Mutex, MutexGuard};
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::thread;
+use std::time::{Duration, Instant};
+use flate2::Compression;
+use flate2::read::DeflateDecoder;
+use flate2::write::DeflateEncoder;
+use serde::{Serialize, Deserialize, Deserializer};
+use serde::de::DeserializeOwned;
+use socket2::{SockRef, TcpKeepalive};
+use thiserror::Error;
+
+use crate::buffer_pool::POOL;
+use crate::read_buffer::ReadBuffer;
+use crate::codec::{Codec, Json};
+use crate::constants::{FRAME_MAGIC, FRAME_VERSION};
+use crate::noise::{self, handshake_error, Handshake, Keypair, MAX_NOISE_MESSAGE, NOISE_PREAMBLE, NOISE_TAG_LEN};
+use crate::transport::{Socket, Transport};
+
+
+
+/// The number of bytes pulled from the TcpStream at a time unless told otherwise, see
+/// `TcpConn::set_poll_size`. Smaller means more system calls, larger means more memory sitting
+/// around for each connection.
+pub const DEFAULT_POLL_SIZE: NonZeroUsize = NonZeroUsize::new(4096).unwrap();
+
+/// The largest payload a connection will receive unless told otherwise, see
+/// `TcpConn::set_max_frame_size`
+pub const DEFAULT_MAX_FRAME_SIZE: usize = 1024 * 1024;
+
+/// The largest message a connection will put back together from chunks unless told otherwise, see
+/// `TcpConn::set_max_message_size`
+pub const DEFAULT_MAX_MESSAGE_SIZE: usize = 16 * 1024 * 1024;
+
+/// How much can be waiting to go out on a connection unless told otherwise before it's considered
+/// too far behind, see `TcpConn::set_max_backlog`
+pub const DEFAULT_MAX_BACKLOG: usize = 64 * 1024 * 1024;
+
+/// How much that's arrived on a connection can be held waiting to be received unless told
+/// otherwise, see `TcpConn::set_max_buffered`. Comfortably more than a whole message of the
+/// default maximum size with its frame headers.
+pub const DEFAULT_MAX_BUFFERED: usize = 32 * 1024 * 1024;
+
+/// Payloads longer than this many bytes are split over several frames of at most this much each,
+/// so one huge message doesn't need a frame size limit to match. The maximum frame size shouldn't
+/// be set any lower than this.
+pub const CHUNK_SIZE: usize = 64 * 1024;
+
+/// The most separate buffers handed to the socket in one vectored write
+const MAX_WRITE_SLICES: usize = 64;
+
+/// How long the header in front of every payload is: `FRAME_MAGIC`, `FRAME_VERSION`, a byte of
+/// flags, the channel, the frame's sequence number, then the payload's length as a big-endian u32.
+/// A checksum comes after that if the flags say so.
+const HEADER_LEN: usize = FRAME_MAGIC.len() + 1 + 1 + 1 + 4 + 4;
+
+/// Where the flags are in the header
+const FLAGS_AT: usize = FRAME_MAGIC.len() + 1;
+
+/// Where the channel is in the header
+const CHANNEL_AT: usize = FLAGS_AT + 1;
+
+/// Where the frame's sequence number is in the header, as a big-endian u32. Frames are numbered
+/// one after another as they're sent on a connection, whatever channel they're on, see
+/// `SequenceStats`.
+const SEQUENCE_AT: usize = CHANNEL_AT + 1;
+
+/// Where the payload's length is in the header
+const LENGTH_AT: usize = SEQUENCE_AT + 4;
+
+/// The channel everything is sent and received on unless another is asked for. Frames are always
+/// encoded for it, and moved to another channel as they're queued.
+pub const MAIN_CHANNEL: u8 = 0;
+
+/// Set in a frame's flags when a CRC32 of its payload follows the length, as a big-endian u32
+const FLAG_CHECKSUM: u8 = 1;
+
+/// Set in a frame's flags when its payload is compressed with deflate. For a message split into
+/// chunks, this is about the whole message once it's put back together.
+const FLAG_COMPRESSED: u8 = 2;
+
+/// Set in a frame's flags when its payload is one chunk of a message split over several frames.
+/// Frames that aren't chunks can arrive in between and are received as usual.
+const FLAG_CHUNK: u8 = 4;
+
+/// Set along with `FLAG_CHUNK` on the last chunk of a message
+const FLAG_LAST_CHUNK: u8 = 8;
+
+/// Set in a frame's flags when its payload is raw bytes sent with `TcpConn::send_raw`, rather than
+/// a message serialized with the connection's codec
+const FLAG_RAW: u8 = 16;
+
+/// Every flag this end understands. Frames with any others set are refused, since they'd need
+/// handling this end doesn't know about.
+const KNOWN_FLAGS: u8 = FLAG_CHECKSUM | FLAG_COMPRESSED | FLAG_CHUNK | FLAG_LAST_CHUNK | FLAG_RAW;
+
+const CHECKSUM_LEN: usize = 4;
+
+/// Payloads longer than this many bytes are compressed before they're sent. Shorter ones, which is
+/// most chat messages, don't shrink by enough to be worth the time.
+const COMPRESS_ABOVE: usize = 512;
+
+/// How long `receive` waits before timing out in the case of blocking unless told otherwise, see
+/// `TcpConn::set_receive_timeout`
+pub const DEFAULT_RECEIVE_TIMEOUT: Duration = Duration::from_secs(10);
+/// How long each end of a Noise handshake waits on the other before giving up
+const HANDSHAKE_TIMEOUT: Duration = Duration::from_secs(5);
+
+/// The longest a receive waits on the socket at a time when it can be cancelled, see
+/// `TcpConn::set_cancel_token`. This is how long cancelling can take to be noticed.
+const CANCEL_CHECK_INTERVAL: Duration = Duration::from_millis(100);
+
+/// The read timeout for taking only what's already arrived on a blocking connection, see
+/// `TcpConn::fill_buffer_now`. A read timeout can't be zero, so this is as close as it gets.
+const NOW_READ_TIMEOUT: Duration = Duration::from_millis(1);
+
+/// How long a receive that waits pauses between reads that find nothing on a non-blocking
+/// connection, whose reads never wait themselves
+const NONBLOCKING_POLL_DELAY: Duration = Duration::from_millis(5);
+
+/// Sets up the socket underneath a `TcpConn` before wrapping it. Can be read from a config file,
+/// where any option left out keeps its default.
+#[derive(Deserialize, Debug, Clone)]
+#[serde(default)]
+pub struct TcpConnBuilder {
+    /// Send small writes right away instead of waiting to batch them up (Nagle's algorithm). Chat
+    /// messages are small and someone is waiting on each one, so this is on by default.
+    nodelay: bool,
+
+    /// Size of the socket's send buffer in bytes. The OS default is used when unset.
+    send_buffer_size: Option<usize>,
+
+    /// Size of the socket's receive buffer in bytes. The OS default is used when unset.
+    recv_buffer_size: Option<usize>,
+
+    /// Have the OS check on idle connections, so one that silently went away (e.g. dropped by a
+    /// NAT) is noticed. Off when set to `None`.
+    keepalive: Option<Keepalive>,
+
+    /// Encrypt the connection with a Noise handshake. Clients make the handshake as soon as they
+    /// connect. Servers accept it from any client that offers it, and with this set turn away
+    /// the ones that don't.
+    noise: bool,
+
+    /// The biggest frame that will be received, in bytes
+    max_frame_size: usize,
+
+    /// The biggest message that will be put back together from chunks, in bytes
+    max_message_size: usize,
+
+    /// The most bytes that can be waiting to be sent, see `TcpConn::set_max_backlog`
+    max_backlog: usize,
+
+    /// The most bytes that can have arrived without being received, see
+    /// `TcpConn::set_max_buffered`
+    max_buffered: usize,
+
+    /// Send a checksum with every message, see `TcpConn::set_checksums`
+    checksums: bool,
+
+    /// How many bytes are read from the socket at a time, see `TcpConn::set_poll_size`. Can't be
+    /// 0, which a config file is refused for.
+    poll_size: NonZeroUsize,
+
+    /// How long `receive` waits on a blocking connection, given in seconds, which can be
+    /// fractional, as `receive_timeout_secs`
+    #[serde(rename = "receive_timeout_secs", deserialize_with = "secs")]
+    receive_timeout: Duration,
+}
+
+/// Read a `Duration` from a number of seconds, refusing ones that are negative or too big
+fn secs<'de, D: Deserializer<'de>>(deserializer: D) -> Result<Duration, D::Error> {
+    let secs = f64::deserialize(deserializer)?;
+    Duration::try_from_secs_f64(secs).map_err(|_| serde::de::Error::custom(format!(
+        "{secs} isn't a number of seconds that can be waited"
+    )))
+}
+
+impl Default for TcpConnBuilder {
+    fn default() -> Self {
+        Self {
+            nodelay: true,
+            send_buffer_size: None,
+            recv_buffer_size: None,
+            keepalive: Some(Keepalive::default()),
+            noise: false,
+            max_frame_size: DEFAULT_MAX_FRAME_SIZE,
+            max_message_size: DEFAULT_MAX_MESSAGE_SIZE,
+            max_backlog: DEFAULT_MAX_BACKLOG,
+            max_buffered: DEFAULT_MAX_BUFFERED,
+            checksums: false,
+            poll_size: DEFAULT_POLL_SIZE,
+            receive_timeout: DEFAULT_RECEIVE_TIMEOUT,
+        }
+    }
+}
+
+/// When the OS probes an idle connection to check the other end is still there (`SO_KEEPALIVE`)
+#[derive(Deserialize, Debug, Clone, Copy)]
+#[serde(default)]
+pub struct Keepalive {
+    /// Seconds a connection has to be idle before it's probed
+    pub idle_secs: u64,
+    /// Seconds between probes once they start
+    pub interval_secs: u64,
+    /// How many probes can go unanswered before the connection is dropped
+    pub count: u32,
+}
+
+impl Default for Keepalive {
+    fn default() -> Self {
+        Self {
+            idle_secs: 120,
+            interval_secs: 15,
+            count: 4,
+        }
+    }
+}
+
+impl Keepalive {
+    /// The interval and count can't be set everywhere, in which case the OS defaults are used
+    #[cfg(any(
+        target_os = "linux",
+        target_os = "android",
+        target_os = "macos",
+        target_os = "ios",
+        target_os = "freebsd",
+        target_os = "netbsd",
+        target_os = "windows",
+    ))]
+    fn to_socket(self) -> TcpKeepalive {
+        TcpKeepalive::new()
+            .with_time(Duration::from_secs(self.idle_secs))
+            .with_interval(Duration::from_secs(self.interval_secs))
+            .with_retries(self.count)
+    }
+
+    #[cfg(not(any(
+        target_os = "linux",
+        target_os = "android",
+        target_os = "macos",
+        target_os = "ios",
+        target_os = "freebsd",
+        target_os = "netbsd",
+        target_os = "windows",
+    )))]
+    fn to_socket(self) -> TcpKeepalive {
+        TcpKeepalive::new().with_time(Duration::from_secs(self.idle_secs))
+    }
+}
+
+impl TcpConnBuilder {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Whether to disable Nagle's algorithm (set `TCP_NODELAY`)
+    pub fn nodelay(mut self, nodelay: bool) -> Self {
+        self.nodelay = nodelay;
+        self
+    }
+
+    /// Set the size of the socket's send buffer (`SO_SNDBUF`)
+    pub fn send_buffer_size(mut self, size: usize) -> Self {
+        self.send_buffer_size = Some(size);
+        self
+    }
+
+    /// Set the size of the socket's receive buffer (`SO_RCVBUF`)
+    pub fn recv_buffer_size(mut self, size: usize) -> Self {
+        self.recv_buffer_size = Some(size);
+        self
+    }
+
+    /// Probe idle connections to find ones that silently went away, or `None` to leave them be
+    pub fn keepalive(mut self, keepalive: Option<Keepalive>) -> Self {
+        self.keepalive = keepalive;
+        self
+    }
+
+    /// Whether connections should be encrypted with a Noise handshake, see `TcpConn::noise_connect`
+    /// and `TcpConn::noise_accept`
+    pub fn noise(mut self, noise: bool) -> Self {
+        self.noise = noise;
+        self
+    }
+
+    pub fn uses_noise(&self) -> bool {
+        self.noise
+    }
+
+    /// The biggest frame connections will receive, see `TcpConn::set_max_frame_size`
+    pub fn max_frame_size(mut self, size: usize) -> Self {
+        self.max_frame_size = size;
+        self
+    }
+
+    /// The biggest message connections will receive, see `TcpConn::set_max_message_size`
+    pub fn max_message_size(mut self, size: usize) -> Self {
+        self.max_message_size = size;
+        self
+    }
+
+    /// How much can be waiting to be sent to the other end, see `TcpConn::set_max_backlog`
+    pub fn max_backlog(mut self, size: usize) -> Self {
+        self.max_backlog = size;
+        self
+    }
+
+    /// How much that's arrived can be waiting to be received, see `TcpConn::set_max_buffered`
+    pub fn max_buffered(mut self, size: usize) -> Self {
+        self.max_buffered = size;
+        self
+    }
+
+    /// Whether to send a checksum with every message, see `TcpConn::set_checksums`
+    pub fn checksums(mut self, checksums: bool) -> Self {
+        self.checksums = checksums;
+        self
+    }
+
+    /// How many bytes connections read from the socket at a time, see `TcpConn::set_poll_size`
+    pub fn poll_size(mut self, size: NonZeroUsize) -> Self {
+        self.poll_size = size;
+        self
+    }
+
+    /// How long `receive` waits on a blocking connection, see `TcpConn::set_receive_timeout`
+    pub fn receive_timeout(mut self, timeout: Duration) -> Self {
+        self.receive_timeout = timeout;
+        self
+    }
+
+    /// Apply the options to `stream` and wrap it in a `TcpConn`. The Noise handshake is left to
+    /// the caller, since only it knows which end it is and what its key is.
+    /// 
+    /// # Errors
+    /// Fails if any of the options can't be set on the socket.
+    pub fn build(&self, stream: TcpStream) -> io::Result<TcpConn> {
+        self.build_with_codec(stream, Json)
+    }
+
+    /// Same as `build`, for a connection that speaks `codec` instead of JSON
+    /// 
+    /// # Errors
+    /// The same as `build`.
+    pub fn build_with_codec<C: Codec>(&self, stream: TcpStream, codec: C) -> io::Result<TcpConn<C>> {
+        self.configure_tcp(&stream)?;
+        Ok(self.configure(TcpConn::with_codec(stream, codec)?))
+    }
+
+    /// Same as `build`, for a connection over either kind of `Socket`. The TCP options are only
+    /// applied to TCP sockets.
+    /// 
+    /// # Errors
+    /// The same as `build`.
+    pub fn build_socket(&self, socket: Socket) -> io::Result<TcpConn<Json, Socket>> {
+        if let Socket::Tcp(stream) = &socket {
+            self.configure_tcp(stream)?;
+        }
+        Ok(self.configure(TcpConn::with_codec(socket, Json)?))
+    }
+
+    /// Apply the TCP options to `stream` without wrapping it, for sockets that carry something
+    /// other than frames, like a WebSocket
+    /// 
+    /// # Errors
+    /// The same as `build`.
+    pub fn configure_tcp(&self, stream: &TcpStream) -> io::Result<()> {
+        stream.set_nodelay(self.nodelay)?;
+
+        let socket = SockRef::from(stream);
+        if let Some(size) = self.send_buffer_size {
+            socket.set_send_buffer_size(size)?;
+        }
+        if let Some(size) = self.recv_buffer_size {
+            socket.set_recv_buffer_size(size)?;
+        }
+        match self.keepalive {
+            Some(keepalive) => socket.set_tcp_keepalive(&keepalive.to_socket()),
+            None => socket.set_keepalive(false),
+        }
+    }
+
+    fn configure<C: Codec, S: Transport>(&self, mut conn: TcpConn<C, S>) -> TcpConn<C, S> {
+        conn.set_max_frame_size(self.max_frame_size);
+        conn.set_max_message_size(self.max_message_size);
+        conn.set_max_backlog(self.max_backlog);
+        conn.set_max_buffered(self.max_buffered);
+        conn.set_checksums(self.checksums);
+        conn.set_poll_size(self.poll_size);
+        conn.set_receive_timeout(self.receive_timeout);
+        conn
+    }
+}
+
+/// A stretch of bytes queued to be sent. The buffer can be shared with other connections that
+/// queued the same frames, see `TcpConn::queue_shared`.
+struct Segment {
+    bytes: Arc<[u8]>,
+    range: Range<usize>,
+}
+
+impl Segment {
+    fn new(bytes: Arc<[u8]>) -> Self {
+        let range = 0..bytes.len();
+        Self { bytes, range }
+    }
+
+    fn as_slice(&self) -> &[u8] {
+        &self.bytes[self.range.clone()]
+    }
+
+    /// Split off the first `len` bytes, leaving the rest
+    fn split_to(&mut self, len: usize) -> Self {
+        let front = Self { bytes: Arc::clone(&self.bytes), range: self.range.start..self.range.start + len };
+        self.range.start += len;
+        front
+    }
+}
+
+/// The frames that have arrived on one channel, waiting to be received
+#[derive(Default)]
+struct Inbox {
+    /// Whole frames one after another, with their headers already checked
+    frames: ReadBuffer,
+
+    /// The chunks of a split message that have arrived so far, see `FLAG_CHUNK`
+    chunks: Vec<u8>,
+
+    /// Whether the rest of a message that stopped being streamed partway through (see
+    /// `TcpConn::receive_streaming_on`) is still to come, and should be thrown away as it arrives
+    abandoned: bool,
+}
+
+impl Inbox {
+    /// Throw away whatever's arrived of an abandoned message
+    fn skip_abandoned(&mut self) {
+        while self.abandoned {
+            let Some((_, frame_size)) = next_frame(&self.frames) else {
+                return;
+            };
+            self.abandoned = self.frames[FLAGS_AT] & (FLAG_CHUNK | FLAG_LAST_CHUNK) == FLAG_CHUNK;
+            self.frames.consume(frame_size);
+        }
+    }
+}
+
+/// The payload of the message at the front of a channel's inbox, read as its chunks arrive rather
+/// than once they all have, see `TcpConn::receive_streaming_on`. Each chunk is taken out of the
+/// inbox once it's been read.
+struct PayloadStream<'a, C, S> {
+    conn: &'a mut TcpConn<C, S>,
+    channel: u8,
+
+    /// Chunks that were gathered before streaming started, which come first
+    gathered: io::Cursor<Vec<u8>>,
+
+    /// The chunk being read, once it's arrived
+    current: Option<StreamedChunk>,
+
+    /// How much of the payload has arrived so far, before decompressing
+    size: usize,
+
+    /// Whether the last chunk has been read
+    done: bool,
+}
+
+/// Where the rest of a chunk being streamed is in its inbox
+struct StreamedChunk {
+    /// Where the part of the payload that hasn't been read yet starts
+    start: usize,
+    /// Where the frame ends
+    end: usize,
+    last: bool,
+}
+
+impl<C: Codec, S: Transport> PayloadStream<'_, C, S> {
+    /// Take the chunk that was being read out of the inbox, and if the message was given up on
+    /// before its last chunk, have the rest thrown away as it arrives
+    fn finish(self) {
+        let inbox = self.conn.inboxes.entry(self.channel).or_default();
+        let mut done = self.done;
+        if let Some(chunk) = self.current {
+            inbox.frames.consume(chunk.end);
+            done = chunk.last;
+        }
+        inbox.abandoned = !done;
+    }
+}
+
+impl<C: Codec, S: Transport> Read for PayloadStream<'_, C, S> {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        let read = self.gathered.read(buf)?;
+        if read > 0 {
+            return Ok(read);
+        }
+
+        while !self.done && !buf.is_empty() {
+            let Some(chunk) = &mut self.current else {
+                self.current = Some(self.conn.next_chunk(self.channel, &mut self.size)?);
+                continue;
+            };
+
+            let frames = &self.conn.inboxes[&self.channel].frames;
+            let left = &frames[chunk.start..chunk.end];
+            if !left.is_empty() {
+                let read = left.len().min(buf.len());
+                buf[..read].copy_from_slice(&left[..read]);
+                chunk.start += read;
+                return Ok(read);
+            }
+
+            self.conn.inboxes.entry(self.channel).or_default().frames.consume(chunk.end);
+            self.done = chunk.last;
+            self.current = None;
+        }
+        Ok(0)
+    }
+}
+
+/// Passes on what `reader` reads, failing once it's gone past `max` bytes
+struct Limited<R> {
+    reader: R,
+    max: usize,
+    read: usize,
+}
+
+impl<R: Read> Read for Limited<R> {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        let read = self.reader.read(buf)?;
+        self.read += read;
+        if self.read > self.max {
+            return Err(limit_error(format!("Message decompresses to more than {} bytes", self.max)));
+        }
+        Ok(read)
+    }
+}
+
+/// Wraps a TcpStream to provide an interface for sending arbitrary data over the network. Any
+/// other `Transport` works in its place.
+/// 
+/// # Security
+/// This should not be used in professional settings. Unless the connection starts with a Noise
+/// handshake (see `noise_connect`), nothing is encrypted or authenticated, and anyone can construct
+/// a custom message that will be deserialized into a type used by the application.
+/// 
+/// Messages are serialized with the connection's `Codec`, JSON unless another is given with
+/// `with_codec` or `into_codec`.
+pub struct TcpConn<C = Json, S = TcpStream> {
+    stream: S,
+
+    /// What's arrived on the stream that hasn't been sorted into an inbox yet. Frames are taken off
+    /// the front without moving what's behind them, and payloads are handed to the codec as slices
+    /// of wherever they are, so nothing is copied on the way to being deserialized.
+    buffer: ReadBuffer,
+
+    /// Frames queued on each channel, waiting to be moved into `outgoing` a stretch at a time so
+    /// none of them holds up the others, see `queue_on`
+    pending: BTreeMap<u8, VecDeque<Segment>>,
+
+    /// Bytes on their way out, sealed already if the connection is encrypted. They go out in one
+    /// vectored write rather than being copied together first.
+    outgoing: VecDeque<Segment>,
+
+    nonblocking: bool,
+
+    /// The encryption agreed on by a Noise handshake, if there was one
+    noise: Option<noise::Transport>,
+
+    /// Encrypted bytes that have arrived but don't make up a whole Noise message yet
+    sealed: ReadBuffer,
+
+    /// Frames claiming to be bigger than this are refused before any of it is parsed
+    max_frame_size: usize,
+
+    /// Frames that have arrived in full, sorted by the channel they're on, see `send_on`
+    inboxes: HashMap<u8, Inbox>,
+
+    /// Messages bigger than this are refused, whether they arrive in chunks or compressed
+    max_message_size: usize,
+
+    /// Flushing fails once more than this is left waiting to be sent
+    max_backlog: usize,
+
+    /// Receiving fails once this much has arrived without being received, see `buffered`
+    max_buffered: usize,
+
+    /// Whether frames sent are given a checksum. Ones that arrive with one are checked either way.
+    checksums: bool,
+
+    /// Whether a whole frame has arrived yet. After one has, a frame that doesn't start with
+    /// `FRAME_MAGIC` means the stream got garbled, rather than that the other end isn't tcp_chat.
+    received_any: bool,
+
+    /// How many bytes are read from the socket at a time
+    poll_size: usize,
+
+    /// How long `receive` waits on a blocking connection
+    receive_timeout: Duration,
+
+    /// Cancels whatever receive is waiting on this connection, see `set_cancel_token`
+    cancel: Option<CancelToken>,
+
+    /// The sequence numbers of frames sent and expected next, and what's been noticed about the
+    /// ones that arrived
+    sequence: SequenceStats,
+
+    codec: C,
+}
+
+impl<S: Transport> TcpConn<Json, S> {
+    /// Construct a `TcpConn` by wrapping a `TcpStream`. The `TcpStream` should be configured
+    /// beforehand (see `TcpConnBuilder`), with the exception of blocking. Blocking is enforced by default regardless of
+    /// how the `TcpStream` was set before. This can be changed with `set_nonblocking`.
+    pub fn new(stream: S) -> io::Result<Self> {
+        Self::with_codec(stream, Json)
+    }
+}
+
+impl<C: Codec, S: Transport> TcpConn<C, S> {
+    /// Same as `new`, for a connection that speaks `codec` instead of JSON
+    pub fn with_codec(stream: S, codec: C) -> io::Result<Self> {
+        stream.set_nonblocking(false)?;
+        Ok(Self {
+            stream,
+            buffer: ReadBuffer::default(),
+            pending: BTreeMap::new(),
+            outgoing: VecDeque::new(),
+            nonblocking: false,
+            noise: None,
+            sealed: ReadBuffer::default(),
+            max_frame_size: DEFAULT_MAX_FRAME_SIZE,
+            inboxes: HashMap::new(),
+            max_message_size: DEFAULT_MAX_MESSAGE_SIZE,
+            max_backlog: DEFAULT_MAX_BACKLOG,
+            max_buffered: DEFAULT_MAX_BUFFERED,
+            checksums: false,
+            received_any: false,
+            poll_size: DEFAULT_POLL_SIZE.get(),
+            receive_timeout: DEFAULT_RECEIVE_TIMEOUT,
+            cancel: None,
+            sequence: SequenceStats::default(),
+            codec,
+        })
+    }
+
+    /// Switch to speaking `codec` from the next message on, keeping everything else about the
+    /// connection, including anything that's already arrived
+    pub fn into_codec<D: Codec>(self, codec: D) -> TcpConn<D, S> {
+        TcpConn {
+            stream: self.stream,
+            buffer: self.buffer,
+            pending: self.pending,
+            outgoing: self.outgoing,
+            nonblocking: self.nonblocking,
+            noise: self.noise,
+            sealed: self.sealed,
+            max_frame_size: self.max_frame_size,
+            inboxes: self.inboxes,
+            max_message_size: self.max_message_size,
+            max_backlog: self.max_backlog,
+            max_buffered: self.max_buffered,
+            checksums: self.checksums,
+            received_any: self.received_any,
+            poll_size: self.poll_size,
+            receive_timeout: self.receive_timeout,
+            cancel: self.cancel,
+            sequence: self.sequence,
+            codec,
+        }
+    }
+
+    pub fn codec(&self) -> &C {
+        &self.codec
+    }
+
+    /// Make a Noise handshake as the end that connected, after which everything sent either way
+    /// is encrypted. Returns the other end's static key, which tells whether it's the same server
+    /// as last time.
+    /// 
+    /// # Errors
+    /// Fails if the other end doesn't complete the handshake in time, or what it sends doesn't
+    /// check out, in which case the connection is no use.
+    pub fn noise_connect(&mut self, keypair: &Keypair) -> io::Result<[u8; 32]> {
+        let mut handshake = Handshake::new(keypair, true);
+
+        let mut first = NOISE_PREAMBLE.to_vec();
+        push_record(&mut first, &handshake.write_first(&[])?);
+        self.handshake_step(|conn, deadline| {
+            conn.write_raw(&first)?;
+            handshake.read_second(&conn.read_record(deadline)?)?;
+
+            let mut third = Vec::new();
+            push_record(&mut third, &handshake.write_third(&[])?);
+            conn.write_raw(&third)
+        })?;
+
+        self.start_transport(handshake)
+    }
+
+    /// Make a Noise handshake as the end that was connected to, if the other end opens with one.
+    /// Returns the other end's static key, or `None` if it didn't offer a handshake, in which case
+    /// whatever it did send is left to be received as usual.
+    /// 
+    /// # Errors
+    /// The same as `noise_connect`, as well as timing out if the other end doesn't send anything.
+    pub fn noise_accept(&mut self, keypair: &Keypair) -> io::Result<Option<[u8; 32]>> {
+        let mut handshake = Handshake::new(keypair, false);
+
+        let offered = self.handshake_step(|conn, deadline| {
+            // only waits as long as it takes to tell, since a plain frame can be shorter than the
+            // preamble
+            let mut seen = 0;
+            while seen < NOISE_PREAMBLE.len() {
+                conn.buffer_at_least(seen + 1, deadline)?;
+                seen = conn.buffer.len().min(NOISE_PREAMBLE.len());
+                if conn.buffer[..seen] != NOISE_PREAMBLE[..seen] {
+                    return Ok(false);
+                }
+            }
+            conn.buffer.consume(NOISE_PREAMBLE.len());
+
+            handshake.read_first(&conn.read_record(deadline)?)?;
+            let mut second = Vec::new();
+            push_record(&mut second, &handshake.write_second(&[])?);
+            conn.write_raw(&second)?;
+            handshake.read_third(&conn.read_record(deadline)?)?;
+            Ok(true)
+        })?;
+
+        offered.then(|| self.start_transport(handshake)).transpose()
+    }
+
+    /// The other end's static key, if the connection started with a Noise handshake
+    pub fn remote_key(&self) -> Option<[u8; 32]> {
+        self.noise.as_ref().map(|noise| noise.remote)
+    }
+
+    /// Split the connection into a half to receive on and a half to send on, so each can be used
+    /// from its own thread. Each half only has the methods for its direction, and with Noise only
+    /// the encryption for it. Anything already received or queued goes with the half it belongs
+    /// to. Both halves keep blocking or not as the connection did, since they share a socket.
+    /// 
+    /// # Errors
+    /// Fails if the socket can't be cloned.
+    pub fn split(mut self) -> io::Result<(ConnReader<C, S>, ConnWriter<C, S>)>
+    where C: Clone {
+        let (send, recv) = match self.noise.take() {
+            Some(noise::Transport { send, recv, remote }) => (
+                Some(noise::Transport { send, recv: None, remote }),
+                Some(noise::Transport { send: None, recv, remote }),
+            ),
+            None => (None, None),
+        };
+
+        let receiver = TcpConn {
+            stream: self.stream.try_clone()?,
+            buffer: mem::take(&mut self.buffer),
+            pending: BTreeMap::new(),
+            outgoing: VecDeque::new(),
+            nonblocking: self.nonblocking,
+            noise: recv,
+            sealed: mem::take(&mut self.sealed),
+            max_frame_size: self.max_frame_size,
+            inboxes: mem::take(&mut self.inboxes),
+            max_message_size: self.max_message_size,
+            max_backlog: self.max_backlog,
+            max_buffered: self.max_buffered,
+            checksums: self.checksums,
+            received_any: self.received_any,
+            poll_size: self.poll_size,
+            receive_timeout: self.receive_timeout,
+            cancel: self.cancel.take(),
+            sequence: self.sequence,
+            codec: self.codec.clone(),
+        };
+        self.noise = send;
+        Ok((ConnReader { conn: receiver }, ConnWriter { conn: self }))
+    }
+
+    /// Run part of a handshake on a blocking socket that gives up on reads at the deadline, then
+    /// put the socket back how it was
+    fn handshake_step<F, R>(&mut self, step: F) -> io::Result<R>
+    where F: FnOnce(&mut Self, Instant) -> io::Result<R> {
+        let nonblocking = self.nonblocking;
+        self.set_nonblocking(false)?;
+        self.stream.set_read_timeout(Some(HANDSHAKE_TIMEOUT))?;
+
+        let result = step(self, Instant::now() + HANDSHAKE_TIMEOUT);
+
+        self.stream.set_read_timeout(None)?;
+        self.set_nonblocking(nonblocking)?;
+        result
+    }
+
+    /// Switch to encrypting everything with what `handshake` agreed on, returning the other end's
+    /// static key. Anything that arrived after the handshake is encrypted already.
+    ///
+    /// # Errors
+    /// Fails if what the other end sent right behind the handshake doesn't decrypt.
+    fn start_transport(&mut self, handshake: Handshake) -> io::Result<[u8; 32]> {
+        let transport = handshake.finish();
+        let remote = transport.remote;
+        self.noise = Some(transport);
+
+        // the other end may not have waited to send its first messages, and if they've already
+        // arrived nothing else will come along to get them decrypted
+        self.sealed = mem::take(&mut self.buffer);
+        self.open_sealed()?;
+        Ok(remote)
+    }
+
+    /// Write `bytes` as they are, blocking until they're all sent
+    fn write_raw(&mut self, bytes: &[u8]) -> io::Result<()> {
+        self.stream.write_all(bytes)?;
+        self.stream.flush()
+    }
+
+    /// Wait until the buffer holds at least `len` bytes, failing once `deadline` passes
+    fn buffer_at_least(&mut self, len: usize, deadline: Instant) -> io::Result<()> {
+        while self.buffer.len() < len {
+            if Instant::now() >= deadline {
+                return Err(handshake_error("the other end took too long"));
+            }
+            self.fill_buffer()?;
+        }
+        Ok(())
+    }
+
+    /// Take the next length-prefixed handshake message out of the buffer, waiting for it to arrive
+    fn read_record(&mut self, deadline: Instant) -> io::Result<Vec<u8>> {
+        self.buffer_at_least(2, deadline)?;
+        let len = u16::from_be_bytes([self.buffer[0], self.buffer[1]]) as usize;
+        self.buffer_at_least(2 + len, deadline)?;
+        let record = self.buffer[2..2 + len].to_vec();
+        self.buffer.consume(2 + len);
+        Ok(record)
+    }
+
+    /// Set the connection's blocking state. This affects both the underlying `TcpStream` and the
+    /// way `receive` behaves. Non-blocking will allow `receive` to return early if a message has
+    /// only partially arrived.
+    pub fn set_nonblocking(&mut self, nonblocking: bool) -> io::Result<()> {
+        self.nonblocking = nonblocking;
+        self.stream.set_nonblocking(nonblocking)
+    }
+
+    /// Refuse frames claiming to be bigger than `size` bytes, which `DEFAULT_MAX_FRAME_SIZE` is
+    /// unless set. Frames can't be bigger than `u32::MAX` bytes whatever this is, and shouldn't be
+    /// set smaller than `CHUNK_SIZE`, since any message longer than that is sent in chunks of it.
+    pub fn set_max_frame_size(&mut self, size: usize) {
+        self.max_frame_size = size;
+    }
+
+    /// Refuse messages bigger than `size` bytes, which `DEFAULT_MAX_MESSAGE_SIZE` is unless set.
+    /// This is what limits messages that arrive in chunks, or compressed, since each of their
+    /// frames can be well under the maximum frame size.
+    pub fn set_max_message_size(&mut self, size: usize) {
+        self.max_message_size = size;
+    }
+
+    /// Have `flush_queued` fail with `SendError::Backlog` once more than `size` bytes are left waiting to
+    /// be sent, which `DEFAULT_MAX_BACKLOG` is unless set. Only a non-blocking connection can fall
+    /// behind, when the other end stops taking what it's sent.
+    pub fn set_max_backlog(&mut self, size: usize) {
+        self.max_backlog = size;
+    }
+
+    /// Have receiving fail with `RecvError::Backlog` once `size` bytes have arrived that haven't
+    /// been received yet, which `DEFAULT_MAX_BUFFERED` is unless set. Messages waiting on channels
+    /// nobody is receiving from count too, so the other end can't get around this by sending
+    /// somewhere that isn't read. Should be more than the maximum message size, since a message
+    /// has to arrive in full before it can be received.
+    pub fn set_max_buffered(&mut self, size: usize) {
+        self.max_buffered = size;
+    }
+
+    /// How many bytes have arrived that haven't been received yet, whole frames or not
+    pub fn buffered(&self) -> usize {
+        self.inboxes.values()
+            .map(|inbox| inbox.frames.len() + inbox.chunks.len())
+            .sum::<usize>()
+            + self.buffer.len()
+            + self.sealed.len()
+    }
+
+    /// The sequence numbers of the next frame sent and the next one expected, along with how many
+    /// frames went missing or arrived twice. After `split`, each half only keeps track of its own
+    /// direction.
+    pub fn sequence_stats(&self) -> SequenceStats {
+        self.sequence
+    }
+
+    /// How many bytes are queued that the socket hasn't taken yet, see `flush_queued`
+    pub fn backlog(&self) -> usize {
+        self.pending.values().flatten()
+            .chain(&self.outgoing)
+            .map(|segment| segment.range.len())
+            .sum()
+    }
+
+    /// Send a CRC32 of each message along with it, so the other end can tell if it was corrupted on
+    /// the way (see `FrameCorrupt`). TCP already checks for corruption, and Noise for tampering, so
+    /// this is mostly for catching buggy peers and middleboxes. Checksums that arrive are checked
+    /// whether or not this is on.
+    pub fn set_checksums(&mut self, checksums: bool) {
+        self.checksums = checksums;
+    }
+
+    /// Set how many bytes are read from the socket at a time. A connection moving files gets
+    /// through them with fewer system calls if this is bigger, at the cost of memory.
+    pub fn set_poll_size(&mut self, size: NonZeroUsize) {
+        self.poll_size = size.get();
+    }
+
+    /// Set how long `receive` waits on a blocking connection before timing out. Connections over
+    /// slow links, or receiving big messages, may need longer than the 10 second default.
+    pub fn set_receive_timeout(&mut self, timeout: Duration) {
+        self.receive_timeout = timeout;
+    }
+
+    /// Have anything waiting to receive on this connection give up with `RecvError::Cancelled`
+    /// once `token` is cancelled, and anything after that give up straight away. Meant for a
+    /// thread that spends its life receiving, so it can be woken up and stopped. The token goes to
+    /// the receiving half when the connection is split.
+    pub fn set_cancel_token(&mut self, token: CancelToken) {
+        self.cancel = Some(token);
+    }
+
+    /// Throw away the first message in the buffer if it's entirely arrived, leaving anything after
+    /// it. Meant for skipping a frame that arrived corrupted (see `FrameCorrupt`) or couldn't be
+    /// deserialized, rather than emptying the buffer of good ones along with it. If the frame's
+    /// header is garbled too, this skips to wherever the next frame seems to start (see `resync`).
+    pub fn discard_frame(&mut self) {
+        self.discard_frame_on(MAIN_CHANNEL)
+    }
+
+    /// Same as `discard_frame`, for the next frame on `channel`
+    pub fn discard_frame_on(&mut self, channel: u8) {
+        let inbox = self.inboxes.entry(channel).or_default();
+        if let Some((_, frame_size)) = next_frame(&inbox.frames) {
+            // the chunks before a last one can't be made sense of without it
+            if inbox.frames[FLAGS_AT] & FLAG_LAST_CHUNK != 0 {
+                inbox.chunks.clear();
+            }
+            inbox.frames.consume(frame_size);
+            return;
+        }
+
+        // nothing whole has arrived on the channel, so it's the stream itself that can't be read
+        if let Err(RecvError::Garbled) = self.frame_bounds() {
+            self.resync();
+        }
+    }
+
+    /// Throw away the start of the buffer up to the next place a frame could begin, which is
+    /// wherever `FRAME_MAGIC` and `FRAME_VERSION` next show up. For recovering from a frame whose
+    /// header got garbled, since its length can't be trusted to say where the next one starts.
+    /// A message that happens to contain those bytes can be mistaken for a frame, but each call
+    /// skips at least one byte, so repeated ones always get past it. Returns how many bytes were
+    /// skipped.
+    pub fn resync(&mut self) -> usize {
+        let marker: Vec<u8> = FRAME_MAGIC.iter().copied().chain([FRAME_VERSION]).collect();
+
+        // a marker cut off by the end of the buffer may be finished by what arrives next
+        let start = (1..self.buffer.len())
+            .find(|&at| {
+                let seen = &self.buffer[at..self.buffer.len().min(at + marker.len())];
+                *seen == marker[..seen.len()]
+            })
+            .unwrap_or(self.buffer.len());
+        self.buffer.consume(start);
+        start
+    }
+
+    /// Empty the internal buffer of the connection. This may be necessary when recovering from an
+    /// error returned by `receive`. For example, if `receive` returns `RecvError::Invalid`, that
+    /// probably means there is something wrong about the type sent across the network, but the
+    /// buffer is still filled. The server may wish to discard the buffer so it can receive other
+    /// messages. Every channel's messages are thrown away.
+    pub fn empty_buffer(&mut self) {
+        self.buffer.clear();
+        self.inboxes.clear();
+    }
+
+    /// Send an arbitrary message across the network, along with anything queued before it.
+    /// 
+    /// # Errors
+    /// Fails with `SendError::Encode` if serialization of the message fails, or with whatever
+    /// `flush_queued` fails with.
+    pub fn send<T>(&mut self, data: &T) -> Result<(), SendError>
+    where T: Serialize {
+        self.send_on(MAIN_CHANNEL, data)
+    }
+
+    /// Same as `send`, on `channel` instead of the main one. Each channel is received separately
+    /// (see `try_receive_on`), so one that's busy with something big doesn't hold up the messages
+    /// on another that arrive after it.
+    /// 
+    /// # Errors
+    /// The same as `send`.
+    pub fn send_on<T>(&mut self, channel: u8, data: &T) -> Result<(), SendError>
+    where T: Serialize {
+        self.queue_on(channel, data)?;
+        self.flush_queued()
+    }
+
+    /// Send `bytes` as they are, without serializing them, for things like files that would only
+    /// get bigger as part of a message. The other end takes them with `receive_raw`, so it has to
+    /// know they're coming, usually from a message sent just before.
+    /// 
+    /// # Errors
+    /// Fails with `SendError::TooLarge` if `bytes` is too big to be sent at all, or with whatever
+    /// `flush_queued` fails with.
+    pub fn send_raw(&mut self, bytes: &[u8]) -> Result<(), SendError> {
+        self.send_raw_on(MAIN_CHANNEL, bytes)
+    }
+
+    /// Same as `send_raw`, on `channel` instead of the main one
+    /// 
+    /// # Errors
+    /// The same as `send_raw`.
+    pub fn send_raw_on(&mut self, channel: u8, bytes: &[u8]) -> Result<(), SendError> {
+        self.queue_raw_on(channel, bytes)?;
+        self.flush_queued()
+    }
+
+    /// Send a message that was already framed with `encode_frame` (or `encode_frame_with`, for the
+    /// codec this connection speaks), along with anything queued before it. Useful for sending the
+    /// same message to several connections without serializing it for each one.
+    /// 
+    /// # Errors
+    /// The same as `flush_queued`.
+    pub fn send_frame(&mut self, frame: &[u8]) -> Result<(), SendError> {
+        self.queue_frame(frame);
+        self.flush_queued()
+    }
+
+    /// Hold on to a message to be sent by the next `flush_queued` (or `send`), so several
+    /// messages for the same connection go out in a single write.
+    /// 
+    /// # Errors
+    /// Fails with `SendError::Encode` if serialization of the message fails.
+    pub fn queue<T>(&mut self, data: &T) -> Result<(), SendError>
+    where T: Serialize {
+        self.queue_on(MAIN_CHANNEL, data)
+    }
+
+    /// Same as `queue`, on `channel` instead of the main one
+    /// 
+    /// # Errors
+    /// The same as `queue`.
+    pub fn queue_on<T>(&mut self, channel: u8, data: &T) -> Result<(), SendError>
+    where T: Serialize {
+        let mut packet = POOL.take();
+        encode_frame_with(&self.codec, data, &mut packet)?;
+        self.queue_frame_on(channel, &packet);
+        Ok(())
+    }
+
+    /// Same as `queue`, for raw bytes (see `send_raw`)
+    /// 
+    /// # Errors
+    /// Fails with `SendError::TooLarge` if `bytes` is too big to be sent at all.
+    pub fn queue_raw(&mut self, bytes: &[u8]) -> Result<(), SendError> {
+        self.queue_raw_on(MAIN_CHANNEL, bytes)
+    }
+
+    /// Same as `queue_raw`, on `channel` instead of the main one
+    /// 
+    /// # Errors
+    /// The same as `queue_raw`.
+    pub fn queue_raw_on(&mut self, channel: u8, bytes: &[u8]) -> Result<(), SendError> {
+        let mut packet = POOL.take();
+        encode_raw_frame(bytes, &mut packet)?;
+        self.queue_frame_on(channel, &packet);
+        Ok(())
+    }
+
+    /// Same as `queue`, for a message that was already framed with `encode_frame`
+    /// 
+    /// # Panics
+    /// Panics on the receiving half of a split Noise connection, rather than letting anything go
+    /// out unencrypted.
+    pub fn queue_frame(&mut self, frame: &[u8]) {
+        self.queue_frame_on(MAIN_CHANNEL, frame)
+    }
+
+    /// Same as `queue_frame`, on `channel` instead of the main one
+    /// 
+    /// # Panics
+    /// The same as `queue_frame`.
+    pub fn queue_frame_on(&mut self, channel: u8, frame: &[u8]) {
+        // frames are encoded for the main channel without a checksum, since they can be shared
+        // between connections (and channels) that don't all want one
+        let frame = if stamped_for(frame, channel, self.checksums) {
+            Arc::from(frame)
+        } else {
+            let mut out = POOL.take();
+            restamp_frames(frame, channel, self.checksums, &mut out);
+            Arc::from(&out[..])
+        };
+        self.queue_segment(channel, frame);
+    }
+
+    /// Same as `queue_frame`, for a frame that's being queued on many connections at once, like a
+    /// broadcast. Connections that can send it as it is share it instead of each keeping a copy.
+    /// 
+    /// # Panics
+    /// The same as `queue_frame`.
+    pub fn queue_shared(&mut self, frame: &Arc<[u8]>) {
+        self.queue_shared_on(MAIN_CHANNEL, frame)
+    }
+
+    /// Same as `queue_shared`, on `channel` instead of the main one. Frames already stamped for
+    /// `channel` with `restamp_frames` can be shared on it too.
+    /// 
+    /// # Panics
+    /// The same as `queue_frame`.
+    pub fn queue_shared_on(&mut self, channel: u8, frame: &Arc<[u8]>) {
+        if stamped_for(frame, channel, self.checksums) {
+            self.queue_segment(channel, Arc::clone(frame));
+        } else {
+            self.queue_frame_on(channel, frame);
+        }
+    }
+
+    fn queue_segment(&mut self, channel: u8, bytes: Arc<[u8]>) {
+        if let Some(noise) = &self.noise {
+            assert!(noise.send.is_some(), "[error] Tried to send on the receiving half of an encrypted connection");
+        }
+        self.pending.entry(channel).or_default().push_back(Segment::new(bytes));
+    }
+
+    /// Send everything queued so far. If the connection is non-blocking and can't take all of it
+    /// right now, the rest stays queued for next time rather than being cut off partway through a
+    /// message. Channels take turns, so something small queued on one goes out after at most a
+    /// chunk of something big queued on another.
+    /// 
+    /// # Errors
+    /// Fails with `SendError::Io` if writing to the stream does. If more is left queued than the
+    /// maximum backlog (see `set_max_backlog`), this fails with `SendError::Backlog`, since the
+    /// other end isn't keeping up with what it's sent. It stays queued either way.
+    pub fn flush_queued(&mut self) -> Result<(), SendError> {
+        while !self.outgoing.is_empty() || self.take_pending()? {
+            let mut slices = [IoSlice::new(&[]); MAX_WRITE_SLICES];
+            for (slice, segment) in slices.iter_mut().zip(&self.outgoing) {
+                *slice = IoSlice::new(segment.as_slice());
+            }
+            let count = self.outgoing.len().min(MAX_WRITE_SLICES);
+
+            match self.stream.write_vectored(&slices[..count]) {
+                Ok(0) => return Err(io::Error::from(io::ErrorKind::WriteZero).into()),
+                Ok(written) => self.advance_outgoing(written),
+                Err(e) if e.kind() == io::ErrorKind::WouldBlock => break,
+                Err(e) if e.kind() == io::ErrorKind::Interrupted => {},
+                Err(e) => return Err(e.into()),
+            }
+        }
+
+        let queued = self.backlog();
+        if queued > self.max_backlog {
+            return Err(SendBacklog { queued, max: self.max_backlog }.into());
+        }
+        Ok(self.stream.flush()?)
+    }
+
+    /// Drop the first `written` bytes of `outgoing`, which the socket has taken
+    fn advance_outgoing(&mut self, mut written: usize) {
+        while let Some(segment) = self.outgoing.front_mut() {
+            let len = segment.range.len();
+            if written < len {
+                segment.range.start += written;
+                return;
+            }
+            written -= len;
+            self.outgoing.pop_front();
+        }
+    }
+
+    /// Move up to about a chunk's worth of whole frames from each channel's queue into `outgoing`,
+    /// sealing them if the connection is encrypted. Returns whether there was anything to move.
+    /// 
+    /// # Errors
+    /// Fails if sealing does, once an encrypted connection has used up its nonces
+    fn take_pending(&mut self) -> io::Result<bool> {
+        let mut moved = false;
+        for queue in self.pending.values_mut() {
+            let mut taken = 0;
+            let mut sealed = Vec::new();
+            while taken < CHUNK_SIZE {
+                let Some(segment) = queue.front_mut() else { break };
+                let frames = segment.as_slice();
+
+                let mut len = 0;
+                while let Some((_, frame_size)) = next_frame(&frames[len..]) {
+                    len += frame_size;
+                    if taken + len >= CHUNK_SIZE {
+                        break;
+                    }
+                }
+                // whatever was queued that isn't a frame goes out as it is
+                if len == 0 {
+                    len = frames.len();
+                }
+                taken += len;
+
+                let mut front = if len == frames.len() {
+                    queue.pop_front().unwrap()
+                } else {
+                    segment.split_to(len)
+                };
+                let next_sent = &mut self.sequence.next_sent;
+                match &mut self.noise {
+                    Some(noise) => {
+                        let cipher = noise.send.as_mut()
+                            .expect("[error] Tried to send on the receiving half of an encrypted connection");
+
+                        // the frames are copied into the records anyway, so they're numbered on
+                        // the way
+                        let mut frames = front.as_slice().to_vec();
+                        number_frames(&mut frames, next_sent);
+
+                        // Noise messages are limited in size, so big frames are spread over several
+                        for chunk in frames.chunks(MAX_NOISE_MESSAGE - NOISE_TAG_LEN) {
+                            push_record(&mut sealed, &cipher.encrypt(&[], chunk)?);
+                        }
+                    },
+                    None => {
+                        let range = front.range.clone();
+                        match Arc::get_mut(&mut front.bytes) {
+                            Some(bytes) => {
+                                number_frames(&mut bytes[range], next_sent);
+                                self.outgoing.push_back(front);
+                            },
+                            // a frame queued on other connections too gets a header of its own,
+                            // so its payload can still be shared
+                            None => {
+                                while let Some((payload_start, frame_size)) = next_frame(front.as_slice()) {
+                                    let mut header = front.as_slice()[..payload_start].to_vec();
+                                    header[SEQUENCE_AT..LENGTH_AT].copy_from_slice(&next_sent.to_be_bytes());
+                                    *next_sent = next_sent.wrapping_add(1);
+                                    self.outgoing.push_back(Segment::new(Arc::from(header)));
+
+                                    let mut payload = front.split_to(frame_size);
+                                    payload.range.start += payload_start;
+                                    self.outgoing.push_back(payload);
+                                }
+                                if !front.range.is_empty() {
+                                    self.outgoing.push_back(front);
+                                }
+                            },
+                        }
+                    },
+                }
+            }
+            if !sealed.is_empty() {
+                self.outgoing.push_back(Segment::new(Arc::from(sealed)));
+            }
+            moved |= taken > 0;
+        }
+        self.pending.retain(|_, queue| !queue.is_empty());
+        Ok(moved)
+    }
+
+    /// Receive the next incoming message and attempt to deserialize it into some type.
+    /// 
+    /// # Errors
+    /// This function may return errors for several reasons, some are perfectly normal and expected
+    /// while others are more significant problems like failure to interact with the TCP socket.
+    /// 
+    /// The "normal" error is `RecvError::Incomplete`, when only part of the message has arrived.
+    /// Note that this only happens when the connection is set to non-blocking.
+    /// 
+    /// The "unexpected" errors include failure to deserialize supposedly complete data into the
+    /// wrong type (`RecvError::Invalid`), failure to receive entire message in time
+    /// (`RecvError::TimedOut`, only in the case of "blocking"), the other end closing the
+    /// connection (`RecvError::Closed`), and failure to read from the `TcpStream`
+    /// (`RecvError::Io`). See `RecvError` for the rest, and which ones the connection survives.
+    pub fn receive<T>(&mut self) -> Result<T, RecvError>
+    where T: DeserializeOwned {
+        if self.nonblocking {
+            self.receive_partial()
+        } else {
+            self.receive_full(self.receive_timeout, |conn| conn.poll_frame(MAIN_CHANNEL, false, true, decode))
+        }
+    }
+
+    /// Receive the raw bytes sent with `send_raw`, the same way `receive` receives a message
+    /// 
+    /// # Errors
+    /// The same as `receive`. If the next frame is a message instead, this fails with
+    /// `RecvError::Invalid` and leaves it where it is.
+    pub fn receive_raw(&mut self) -> Result<Vec<u8>, RecvError> {
+        self.receive_raw_on(MAIN_CHANNEL)
+    }
+
+    /// Same as `receive_raw`, for raw bytes sent on `channel`
+    /// 
+    /// # Errors
+    /// The same as `receive_raw`.
+    pub fn receive_raw_on(&mut self, channel: u8) -> Result<Vec<u8>, RecvError> {
+        if self.nonblocking {
+            self.try_receive_raw_on(channel)?.ok_or(RecvError::Incomplete)
+        } else {
+            self.receive_full(self.receive_timeout, |conn| conn.poll_frame(channel, true, true, copy_raw))
+        }
+    }
+
+    /// Receive the next incoming message with a timeout. This waits for it the way `receive` does
+    /// on a blocking connection, even if this one is non-blocking.
+    /// 
+    /// # Errors
+    /// The same as `receive` on a blocking connection, so anything but `RecvError::Incomplete`.
+    pub fn receive_timeout<T>(&mut self, timeout: Duration) -> Result<T, RecvError>
+    where T: DeserializeOwned {
+        self.receive_full(timeout, |conn| conn.poll_frame(MAIN_CHANNEL, false, true, decode))
+    }
+
+    /// Receive the next incoming message if it has entirely arrived, or `None` if it hasn't yet.
+    /// This never waits, whether or not the connection is set to non-blocking, so it can be polled
+    /// without changing how the connection is set up.
+    /// 
+    /// # Errors
+    /// The same as `receive`, except for `RecvError::Incomplete` and `RecvError::TimedOut`, which
+    /// are `None` here instead. Raw bytes sent with `send_raw` are `RecvError::Invalid` here, and
+    /// are left for `try_receive_raw`.
+    pub fn try_receive<T>(&mut self) -> Result<Option<T>, RecvError>
+    where T: DeserializeOwned {
+        self.try_receive_on(MAIN_CHANNEL)
+    }
+
+    /// Same as `try_receive`, for the next message sent on `channel`. Messages on other channels
+    /// are kept until they're asked for, however many arrive in the meantime.
+    /// 
+    /// # Errors
+    /// The same as `try_receive`.
+    pub fn try_receive_on<T>(&mut self, channel: u8) -> Result<Option<T>, RecvError>
+    where T: DeserializeOwned {
+        self.poll_frame(channel, false, false, decode)
+    }
+
+    /// Same as `try_receive`, for raw bytes sent with `send_raw`
+    /// 
+    /// # Errors
+    /// The same as `try_receive`. If the next frame is a message instead, this fails with
+    /// `RecvError::Invalid` and leaves it where it is.
+    pub fn try_receive_raw(&mut self) -> Result<Option<Vec<u8>>, RecvError> {
+        self.try_receive_raw_on(MAIN_CHANNEL)
+    }
+
+    /// Same as `try_receive_raw`, for raw bytes sent on `channel`
+    /// 
+    /// # Errors
+    /// The same as `try_receive_raw`.
+    pub fn try_receive_raw_on(&mut self, channel: u8) -> Result<Option<Vec<u8>>, RecvError> {
+        self.poll_frame(channel, true, false, copy_raw)
+    }
+
+    /// Look at the next incoming message without taking it, so the next `peek` or `receive` sees
+    /// it again. Useful for deciding how to handle a connection based on what it opens with. Like
+    /// `try_receive`, returns `None` if the message hasn't entirely arrived yet.
+    /// 
+    /// # Errors
+    /// The same as `try_receive`. A message that can't be deserialized into `T` is left where it
+    /// is, so it can still be received as some other type.
+    pub fn peek<T>(&mut self) -> Result<Option<T>, RecvError>
+    where T: DeserializeOwned {
+        if let Some((data, _)) = self.open_next(MAIN_CHANNEL, false, decode)? {
+            return Ok(Some(data));
+        }
+
+        self.fill_buffer_now()?;
+        Ok(self.open_next(MAIN_CHANNEL, false, decode)?.map(|(data, _)| data))
+    }
+
+    /// Same as `receive`, except that a message sent in chunks (see `CHUNK_SIZE`) is deserialized
+    /// as they arrive instead of once they all have. Only a chunk at a time is held onto, so a big
+    /// message takes no more memory than what it deserializes into, and is mostly deserialized by
+    /// the time the last of it arrives. Messages that fit in one frame are received the same way
+    /// `receive` receives them.
+    ///
+    /// On a non-blocking connection this only returns early if none of the message has arrived
+    /// yet. Once some of it has, this waits for the rest, up to the receive timeout (see
+    /// `set_receive_timeout`) for each chunk.
+    ///
+    /// # Errors
+    /// The same as `receive`, with one difference: once a chunked message has started to be
+    /// deserialized, it's gone whether or not it could be, along with the rest of it when it
+    /// arrives. It can't be tried as another type, or skipped with `discard_frame`.
+    pub fn receive_streaming<T>(&mut self) -> Result<T, RecvError>
+    where T: DeserializeOwned, C: Clone {
+        self.receive_streaming_on(MAIN_CHANNEL)
+    }
+
+    /// Same as `receive_streaming`, for the next message sent on `channel`
+    ///
+    /// # Errors
+    /// The same as `receive_streaming`.
+    pub fn receive_streaming_on<T>(&mut self, channel: u8) -> Result<T, RecvError>
+    where T: DeserializeOwned, C: Clone {
+        let timeout = self.receive_timeout;
+        self.stream_next(channel, timeout, !self.nonblocking)
+    }
+
+    /// Same as `receive_streaming`, waiting up to `timeout` for the message to start arriving the
+    /// way `receive_timeout` does. Each chunk after the first still gets the receive timeout.
+    ///
+    /// # Errors
+    /// The same as `receive_streaming` on a blocking connection.
+    pub fn receive_streaming_timeout<T>(&mut self, timeout: Duration) -> Result<T, RecvError>
+    where T: DeserializeOwned, C: Clone {
+        self.stream_next(MAIN_CHANNEL, timeout, true)
+    }
+
+    /// Receive the next incoming message, returning early with an error if the entire message has
+    /// not yet arrived.
+    /// 
+    /// # Errors
+    /// `RecvError::Incomplete` if it hasn't, otherwise the same as `try_receive`.
+    fn receive_partial<T>(&mut self) -> Result<T, RecvError>
+    where T: DeserializeOwned {
+        self.try_receive()?.ok_or(RecvError::Incomplete)
+    }
+
+    /// Move everything that has arrived on the `TcpStream` so far into the buffer, polling it until
+    /// it's empty, and decrypting it if the connection started with a Noise handshake. No more is
+    /// read than fits under the maximum buffered (see `set_max_buffered`).
+    /// 
+    /// # Errors
+    /// Fails with `RecvError::Backlog` if nothing more fits, with `RecvError::Closed` if the other
+    /// end closed the connection, with `RecvError::Undecryptable` if it doesn't decrypt, or with
+    /// whatever error the `TcpStream` returned.
+    fn fill_buffer(&mut self) -> Result<(), RecvError> {
+        self.read_into_buffer(!self.nonblocking)
+    }
+
+    /// Same as `fill_buffer`, waiting up to `timeout` for something to arrive, even on a
+    /// non-blocking connection. Switching the socket to blocking for this would switch the other
+    /// half of a split connection too, which could be relying on its writes not blocking, so a
+    /// non-blocking connection is polled every `NONBLOCKING_POLL_DELAY` instead.
+    /// 
+    /// # Errors
+    /// The same as `fill_buffer`, or whatever error the `TcpStream` returned when setting the read
+    /// timeout.
+    fn fill_buffer_within(&mut self, timeout: Duration) -> Result<(), RecvError> {
+        if !self.nonblocking {
+            self.stream.set_read_timeout(Some(timeout))?;
+            let filled = self.fill_buffer();
+            self.stream.set_read_timeout(None)?;
+            return filled;
+        }
+
+        let deadline = Instant::now() + timeout;
+        let buffered = self.buffered();
+        loop {
+            self.fill_buffer()?;
+            let now = Instant::now();
+            if self.buffered() > buffered || now >= deadline {
+                return Ok(());
+            }
+            thread::sleep(NONBLOCKING_POLL_DELAY.min(deadline - now));
+        }
+    }
+
+    /// Same as `fill_buffer`, without waiting for anything to arrive on a blocking connection.
+    /// Switching the socket to non-blocking for this would switch the other half of a split
+    /// connection too, which could be in the middle of a blocking write, so the reads are given a
+    /// timeout too short to wait for anything instead.
+    /// 
+    /// # Errors
+    /// The same as `fill_buffer`, or whatever error the `TcpStream` returned when setting the read
+    /// timeout.
+    fn fill_buffer_now(&mut self) -> Result<(), RecvError> {
+        if self.nonblocking {
+            return self.fill_buffer();
+        }
+
+        self.stream.set_read_timeout(Some(NOW_READ_TIMEOUT))?;
+        let filled = self.read_into_buffer(false);
+        self.stream.set_read_timeout(None)?;
+        filled
+    }
+
+    /// Read into the buffer for `fill_buffer`. `blocking` is whether to stop after the first read,
+    /// since the one after it could wait, rather than keep reading until nothing's left.
+    fn read_into_buffer(&mut self, blocking: bool) -> Result<(), RecvError> {
+        let buffered = self.buffered();
+        if buffered >= self.max_buffered {
+            return Err(RecvBacklog { buffered, max: self.max_buffered }.into());
+        }
+
+        let limit = self.max_buffered - buffered;
+        if self.noise.is_none() {
+            return read_available(&mut self.stream, &mut self.buffer, self.poll_size, limit, blocking);
+        }
+
+        read_available(&mut self.stream, &mut self.sealed, self.poll_size, limit, blocking)?;
+        self.open_sealed()
+    }
+
+    /// Decrypt every whole Noise message that's arrived into the buffer
+    /// 
+    /// # Errors
+    /// Fails with `RecvError::Undecryptable` if a message doesn't decrypt, after which nothing
+    /// more can be received, or with `RecvError::Io` if this is the sending half of a split
+    /// connection.
+    fn open_sealed(&mut self) -> Result<(), RecvError> {
+        let Some(cipher) = self.noise.as_mut().and_then(|noise| noise.recv.as_mut()) else {
+            return Err(RecvError::Io(io::Error::new(
+                io::ErrorKind::Unsupported,
+                "Tried to receive on the sending half of an encrypted connection"
+            )));
+        };
+
+        let mut start = 0;
+        while let Some(len_bytes) = self.sealed.get(start..start + 2) {
+            let len = u16::from_be_bytes([len_bytes[0], len_bytes[1]]) as usize;
+            let Some(message) = self.sealed.get(start + 2..start + 2 + len) else {
+                break;
+            };
+            let opened = cipher.decrypt(&[], message).map_err(|_| RecvError::Undecryptable)?;
+            self.buffer.extend_from_slice(&opened);
+            start += 2 + len;
+        }
+        self.sealed.consume(start);
+        Ok(())
+    }
+
+    /// Make the next frame on `channel` into a `T` with `open` and remove it, reading whatever's
+    /// arrived first if it isn't already buffered, or return `None` if it hasn't entirely arrived
+    /// yet. `wait` is whether that read waits for data on a blocking connection, the way `receive`
+    /// does, or returns straight away, the way `try_receive` does.
+    /// 
+    /// # Errors
+    /// The same as `take_frame`, along with anything reading fails with, see `fill_buffer`.
+    fn poll_frame<T, F>(&mut self, channel: u8, raw: bool, wait: bool, open: F) -> Result<Option<T>, RecvError>
+    where F: Fn(&C, &[u8]) -> Result<T, RecvError> {
+
+        // a previous read may have pulled in more than one message, and those shouldn't have to
+        // wait for more data to arrive
+        if let Some(data) = self.take_frame(channel, raw, &open)? {
+            return Ok(Some(data));
+        }
+
+        match wait {
+            true => self.fill_buffer()?,
+            false => self.fill_buffer_now()?,
+        }
+        self.take_frame(channel, raw, open)
+    }
+
+    /// Make the next frame on `channel` into a `T` with `open` and remove it, or return `None` if
+    /// it hasn't entirely arrived yet. `raw` is whether it should be raw bytes rather than a
+    /// message.
+    /// 
+    /// # Errors
+    /// The same as `open_next`.
+    fn take_frame<T, F>(&mut self, channel: u8, raw: bool, open: F) -> Result<Option<T>, RecvError>
+    where F: FnOnce(&C, &[u8]) -> Result<T, RecvError> {
+        let Some((data, frame_size)) = self.open_next(channel, raw, open)? else {
+            return Ok(None);
+        };
+
+        // this is last because we don't want to drain if decoding fails
+        let inbox = self.inboxes.entry(channel).or_default();
+        if inbox.frames[FLAGS_AT] & FLAG_LAST_CHUNK != 0 {
+            inbox.chunks.clear();
+        }
+        inbox.frames.consume(frame_size);
+
+        Ok(Some(data))
+    }
+
+    /// Make the next frame on `channel` into a `T` with `open` without removing it, along with how
+    /// many bytes of its inbox it takes up. Returns `None` if it hasn't entirely arrived yet.
+    /// 
+    /// # Errors
+    /// The same as `sort_frames`, `gather_chunks` and `open_buffered`. A problem with the stream
+    /// further along than anything on `channel` is only returned once the channel has nothing
+    /// left that arrived before it.
+    fn open_next<T, F>(&mut self, channel: u8, raw: bool, open: F) -> Result<Option<(T, usize)>, RecvError>
+    where F: FnOnce(&C, &[u8]) -> Result<T, RecvError> {
+        let sorted = self.sort_frames();
+        self.gather_chunks(channel)?;
+        match self.open_buffered(channel, raw, open)? {
+            Some(opened) => Ok(Some(opened)),
+            None => sorted.map(|()| None),
+        }
+    }
+
+    /// Move every whole frame at the front of the buffer into the inbox of the channel it's on.
+    /// A frame numbered before the one expected next has already arrived, so it's thrown away.
+    /// 
+    /// # Errors
+    /// The same as `frame_bounds`, after moving the frames before the one it fails on.
+    fn sort_frames(&mut self) -> Result<(), RecvError> {
+        while let Some((_, frame_size)) = self.frame_bounds()? {
+            let sequence = u32::from_be_bytes(self.buffer[SEQUENCE_AT..LENGTH_AT].try_into().unwrap());
+            if self.sequence.arrived(sequence) {
+                let channel = self.buffer[CHANNEL_AT];
+                self.inboxes.entry(channel).or_default().frames.extend_from_slice(&self.buffer[..frame_size]);
+            }
+            self.buffer.consume(frame_size);
+            self.received_any = true;
+        }
+        Ok(())
+    }
+
+    /// Move the chunks at the front of `channel`'s inbox into its `chunks`, up to the last chunk
+    /// of their message, which is left for `open_buffered` to put the message together from. That
+    /// way a message that can't be deserialized as one type can still be tried as another.
+    /// 
+    /// # Errors
+    /// Fails with `RecvError::Corrupt` if a chunk doesn't match its checksum, or with
+    /// `RecvError::TooLarge` if the message gets too big.
+    fn gather_chunks(&mut self, channel: u8) -> Result<(), RecvError> {
+        let Some(inbox) = self.inboxes.get_mut(&channel) else {
+            return Ok(());
+        };
+        inbox.skip_abandoned();
+        while let Some((payload_start, frame_size)) = next_frame(&inbox.frames) {
+            if inbox.frames[FLAGS_AT] & (FLAG_CHUNK | FLAG_LAST_CHUNK) != FLAG_CHUNK {
+                break;
+            }
+            let payload = checked_payload(&inbox.frames[..frame_size], payload_start)?;
+
+            let size = inbox.chunks.len() + payload.len();
+            if size > self.max_message_size {
+                return Err(FrameTooLarge { size, max: self.max_message_size }.into());
+            }
+            inbox.chunks.extend_from_slice(payload);
+            inbox.frames.consume(frame_size);
+        }
+        Ok(())
+    }
+
+    /// Make the next frame in `channel`'s inbox into a `T` with `open` without removing it, along
+    /// with how many bytes of the inbox it takes up. `open` is given the payload once it's put
+    /// back together and decompressed. Returns `None` if there's no whole frame there yet.
+    /// 
+    /// # Errors
+    /// Fails with whatever `open` does, with `RecvError::Invalid` if the frame is raw bytes when
+    /// `raw` isn't set (or the other way around) or doesn't decompress, or with
+    /// `RecvError::Corrupt` if it doesn't match its checksum.
+    fn open_buffered<T, F>(&self, channel: u8, raw: bool, open: F) -> Result<Option<(T, usize)>, RecvError>
+    where F: FnOnce(&C, &[u8]) -> Result<T, RecvError> {
+        let Some(inbox) = self.inboxes.get(&channel) else {
+            return Ok(None);
+        };
+        let Some((payload_start, frame_size)) = next_frame(&inbox.frames) else {
+            return Ok(None);
+        };
+        let payload = checked_payload(&inbox.frames[..frame_size], payload_start)?;
+        let flags = inbox.frames[FLAGS_AT];
+
+        match (raw, flags & FLAG_RAW != 0) {
+            (false, true) => return Err(RecvError::Invalid(String::from(
+                "Expected a message, but raw bytes arrived instead (see `TcpConn::receive_raw`)"
+            ))),
+            (true, false) => return Err(RecvError::Invalid(String::from(
+                "Expected raw bytes, but a message arrived instead"
+            ))),
+            _ => {},
+        }
+
+        let reassembled;
+        let payload = if flags & FLAG_LAST_CHUNK != 0 {
+            let size = inbox.chunks.len() + payload.len();
+            if size > self.max_message_size {
+                return Err(FrameTooLarge { size, max: self.max_message_size }.into());
+            }
+            let mut buffer = POOL.take();
+            buffer.extend_from_slice(&inbox.chunks);
+            buffer.extend_from_slice(payload);
+            reassembled = buffer;
+            &reassembled
+        } else {
+            payload
+        };
+
+        let decompressed;
+        let payload = if flags & FLAG_COMPRESSED != 0 {
+            let mut buffer = POOL.take();
+            decompress(payload, self.max_message_size, &mut buffer)?;
+            decompressed = buffer;
+            &decompressed
+        } else {
+            payload
+        };
+
+        let data = open(&self.codec, payload)?;
+
+        Ok(Some((data, frame_size)))
+    }
+
+    /// Where the payload of the first frame in the buffer starts, and how long the whole frame is.
+    /// Returns `None` if it hasn't entirely arrived yet.
+    /// 
+    /// # Errors
+    /// Fails with `RecvError::Unsupported` if the frame doesn't have a header this end
+    /// understands, with `RecvError::TooLarge` if it's too big, or with `RecvError::Garbled` if it
+    /// doesn't start where a frame should (after others have arrived fine).
+    fn frame_bounds(&self) -> Result<Option<(usize, usize)>, RecvError> {
+
+        // checked as soon as it arrives, so something that isn't tcp_chat at all is turned away
+        // without waiting on the rest
+        let magic_len = FRAME_MAGIC.len();
+        if let Some(magic) = self.buffer.get(..magic_len) {
+            if magic != FRAME_MAGIC {
+                // the other end has spoken tcp_chat before, so something got mangled on the way
+                if self.received_any {
+                    return Err(RecvError::Garbled);
+                }
+                return Err(header_error(String::from("The other end isn't speaking tcp_chat")));
+            }
+        }
+        if let Some(&version) = self.buffer.get(magic_len) {
+            if version != FRAME_VERSION {
+                return Err(header_error(format!(
+                    "The other end frames messages with version {version}, this end only understands version {FRAME_VERSION}"
+                )));
+            }
+        }
+        let Some(&flags) = self.buffer.get(FLAGS_AT) else {
+            return Ok(None);
+        };
+        if flags & !KNOWN_FLAGS != 0 {
+            return Err(header_error(format!("The other end sent a message with flags this end doesn't know ({flags:#010b})")));
+        }
+
+        // attempt to read the 4 bytes representing the payload size
+        let Some(size_bytes) = self.buffer.get(LENGTH_AT..HEADER_LEN) else {
+            return Ok(None);
+        };
+        let payload_size = u32::from_be_bytes(size_bytes.try_into().unwrap()) as usize;
+
+        // refused up front so nobody can make us wait on (and buffer) an enormous message
+        if payload_size > self.max_frame_size {
+            return Err(FrameTooLarge { size: payload_size, max: self.max_frame_size }.into());
+        }
+
+        let payload_start = if flags & FLAG_CHECKSUM != 0 { HEADER_LEN + CHECKSUM_LEN } else { HEADER_LEN };
+
+        // make sure theres enough bytes to reconstruct the original data type
+        if self.buffer.len() < payload_start + payload_size {
+            return Ok(None);
+        }
+
+        Ok(Some((payload_start, payload_start + payload_size)))
+    }
+
+    /// Same as `receive_partial` except it repeats `attempt` (a `poll_frame` that waits) until it
+    /// receives the entire message. Each attempt's read waits on the socket until more arrives, so
+    /// the message is returned as soon as the last of it does. A non-blocking connection's reads
+    /// don't wait, so it's polled every `NONBLOCKING_POLL_DELAY` instead.
+    /// 
+    /// # Errors
+    /// This function has the potential to return all of the same errors as `receive_partial` except
+    /// for `RecvError::Incomplete`, since that's the incomplete data this function waits for.
+    /// 
+    /// In addition, this function may return `RecvError::TimedOut` if it took too long to receive
+    /// the entire message. If this happens, recovery will most likely involve
+    /// re-establishing a connection with the other end as the internal buffer is not flushed. On
+    /// the other hand, calling any form of `receive` again will not result in a corrupted buffer.
+    fn receive_full<T, F>(&mut self, timeout: Duration, mut attempt: F) -> Result<T, RecvError>
+    where F: FnMut(&mut Self) -> Result<Option<T>, RecvError> {
+        let deadline = Instant::now() + timeout;
+        let result = loop {
+            // the OS gives up on the read at the deadline, rather than it being polled for
+            let read_timeout = match self.read_timeout_until(deadline) {
+                Ok(read_timeout) => read_timeout,
+                Err(e) => break Err(e),
+            };
+            if let Err(e) = self.stream.set_read_timeout(Some(read_timeout)) {
+                break Err(e.into());
+            }
+            match attempt(self) {
+                Ok(Some(msg)) => break Ok(msg),
+                Ok(None) if Instant::now() < deadline => if self.nonblocking {
+                    thread::sleep(NONBLOCKING_POLL_DELAY.min(read_timeout));
+                },
+                Ok(None) => break Err(RecvError::TimedOut),
+                Err(e) => break Err(e),
+            }
+        };
+        self.stream.set_read_timeout(None)?;
+        result
+    }
+
+    /// How long the next read can wait to be done by `deadline`, which is no longer than
+    /// `CANCEL_CHECK_INTERVAL` if the connection can be cancelled
+    ///
+    /// # Errors
+    /// Fails with `RecvError::Cancelled` if it has been.
+    fn read_timeout_until(&self, deadline: Instant) -> Result<Duration, RecvError> {
+        let timeout = read_timeout_until(deadline);
+        match &self.cancel {
+            Some(token) if token.is_cancelled() => Err(RecvError::Cancelled),
+            Some(_) => Ok(timeout.min(CANCEL_CHECK_INTERVAL)),
+            None => Ok(timeout),
+        }
+    }
+
+    /// Deserialize the next message on `channel`, streaming it if it was sent in chunks, see
+    /// `receive_streaming_on`. If `wait` is set, this waits up to `timeout` for it to start.
+    ///
+    /// # Errors
+    /// The same as `receive_streaming_on`.
+    fn stream_next<T>(&mut self, channel: u8, timeout: Duration, wait: bool) -> Result<T, RecvError>
+    where T: DeserializeOwned, C: Clone {
+        let flags = match wait {
+            true => self.receive_full(timeout, |conn| conn.first_frame_on(channel))?,
+            false => self.first_frame_on(channel)?.ok_or(RecvError::Incomplete)?,
+        };
+
+        let codec = self.codec.clone();
+        let max = self.max_message_size;
+        let read = |payload: &mut dyn Read| codec.decode_from(payload, max).map_err(decode_error);
+
+        // everything that's left of it has arrived, so there's nothing to stream
+        if flags & (FLAG_CHUNK | FLAG_LAST_CHUNK) != FLAG_CHUNK {
+            let data = self.take_frame(channel, false, |_, mut payload| read(&mut payload))?;
+            return data.ok_or(RecvError::Incomplete);
+        }
+        if flags & FLAG_RAW != 0 {
+            return Err(RecvError::Invalid(String::from(
+                "Expected a message, but raw bytes arrived instead"
+            )));
+        }
+
+        // once some of it has arrived, the rest is waited for even on a non-blocking connection,
+        // see `next_chunk`
+        let inbox = self.inboxes.entry(channel).or_default();
+        let gathered = mem::take(&mut inbox.chunks);
+        let mut stream = PayloadStream {
+            size: gathered.len(),
+            gathered: io::Cursor::new(gathered),
+            conn: self,
+            channel,
+            current: None,
+            done: false,
+        };
+
+        // serde reads a byte at a time, so it's given a buffer to read them out of
+        let data = if flags & FLAG_COMPRESSED != 0 {
+            let decompressed = Limited { reader: DeflateDecoder::new(&mut stream), max, read: 0 };
+            read(&mut BufReader::new(decompressed))
+        } else {
+            read(&mut BufReader::new(&mut stream))
+        };
+        stream.finish();
+        data
+    }
+
+    /// The flags of the next whole frame on `channel`, or `None` if there isn't one yet, reading
+    /// whatever's arrived if there isn't one already
+    ///
+    /// # Errors
+    /// The same as `open_next`.
+    fn first_frame_on(&mut self, channel: u8) -> Result<Option<u8>, RecvError> {
+        for attempt in 0..2 {
+            if attempt > 0 {
+                self.fill_buffer()?;
+            }
+            let sorted = self.sort_frames();
+            let inbox = self.inboxes.entry(channel).or_default();
+            inbox.skip_abandoned();
+            if next_frame(&inbox.frames).is_some() {
+                return Ok(Some(inbox.frames[FLAGS_AT]));
+            }
+            sorted?;
+        }
+        Ok(None)
+    }
+
+    /// Wait for the next chunk of a message being streamed on `channel`, up to the receive timeout,
+    /// adding its size to `size`. This waits on a non-blocking connection too, see
+    /// `fill_buffer_within`.
+    ///
+    /// # Errors
+    /// Fails with `RecvError::TimedOut` if it doesn't arrive in time, with `RecvError::Invalid` if
+    /// something other than the rest of the message arrives, with `RecvError::Corrupt` if it
+    /// doesn't match its checksum, with `RecvError::TooLarge` if the message gets too big, or if
+    /// reading does.
+    fn next_chunk(&mut self, channel: u8, size: &mut usize) -> Result<StreamedChunk, RecvError> {
+        let deadline = Instant::now() + self.receive_timeout;
+        loop {
+            self.sort_frames()?;
+            let inbox = self.inboxes.entry(channel).or_default();
+            if let Some((payload_start, frame_size)) = next_frame(&inbox.frames) {
+                let flags = inbox.frames[FLAGS_AT];
+                if flags & FLAG_CHUNK == 0 {
+                    return Err(RecvError::Invalid(String::from(
+                        "Expected the rest of a message that was split into chunks"
+                    )));
+                }
+                let payload = checked_payload(&inbox.frames[..frame_size], payload_start)?;
+
+                *size += payload.len();
+                if *size > self.max_message_size {
+                    return Err(FrameTooLarge { size: *size, max: self.max_message_size }.into());
+                }
+                return Ok(StreamedChunk { start: payload_start, end: frame_size, last: flags & FLAG_LAST_CHUNK != 0 });
+            }
+
+            if Instant::now() >= deadline {
+                return Err(RecvError::TimedOut);
+            }
+            let read_timeout = self.read_timeout_until(deadline)?;
+            self.fill_buffer_within(read_timeout)?;
+        }
+    }
+}
+
+/// The receiving half of a connection, see `TcpConn::split`
+pub struct ConnReader<C = Json, S = TcpStream> {
+    conn: TcpConn<C, S>,
+}
+
+impl<C: Codec, S: Transport> ConnReader<C, S> {
+    pub fn codec(&self) -> &C {
+        self.conn.codec()
+    }
+
+    /// See `TcpConn::remote_key`
+    pub fn remote_key(&self) -> Option<[u8; 32]> {
+        self.conn.remote_key()
+    }
+
+    /// See `TcpConn::set_max_frame_size`
+    pub fn set_max_frame_size(&mut self, size: usize) {
+        self.conn.set_max_frame_size(size)
+    }
+
+    /// See `TcpConn::set_max_message_size`
+    pub fn set_max_message_size(&mut self, size: usize) {
+        self.conn.set_max_message_size(size)
+    }
+
+    /// See `TcpConn::set_max_buffered`
+    pub fn set_max_buffered(&mut self, size: usize) {
+        self.conn.set_max_buffered(size)
+    }
+
+    /// See `TcpConn::set_poll_size`
+    pub fn set_poll_size(&mut self, size: NonZeroUsize) {
+        self.conn.set_poll_size(size)
+    }
+
+    /// See `TcpConn::set_receive_timeout`
+    pub fn set_receive_timeout(&mut self, timeout: Duration) {
+        self.conn.set_receive_timeout(timeout)
+    }
+
+    /// See `TcpConn::set_cancel_token`
+    pub fn set_cancel_token(&mut self, token: CancelToken) {
+        self.conn.set_cancel_token(token)
+    }
+
+    /// See `TcpConn::receive`
+    /// 
+    /// # Errors
+    /// The same as `TcpConn::receive`.
+    pub fn receive<T>(&mut self) -> Result<T, RecvError>
+    where T: DeserializeOwned {
+        self.conn.receive()
+    }
+
+    /// See `TcpConn::receive_timeout`
+    /// 
+    /// # Errors
+    /// The same as `TcpConn::receive_timeout`.
+    pub fn receive_timeout<T>(&mut self, timeout: Duration) -> Result<T, RecvError>
+    where T: DeserializeOwned {
+        self.conn.receive_timeout(timeout)
+    }
+
+    /// See `TcpConn::try_receive`
+    /// 
+    /// # Errors
+    /// The same as `TcpConn::try_receive`.
+    pub fn try_receive<T>(&mut self) -> Result<Option<T>, RecvError>
+    where T: DeserializeOwned {
+        self.conn.try_receive()
+    }
+
+    /// See `TcpConn::try_receive_on`
+    /// 
+    /// # Errors
+    /// The same as `TcpConn::try_receive_on`.
+    pub fn try_receive_on<T>(&mut self, channel: u8) -> Result<Option<T>, RecvError>
+    where T: DeserializeOwned {
+        self.conn.try_receive_on(channel)
+    }
+
+    /// See `TcpConn::peek`
+    /// 
+    /// # Errors
+    /// The same as `TcpConn::peek`.
+    pub fn peek<T>(&mut self) -> Result<Option<T>, RecvError>
+    where T: DeserializeOwned {
+        self.conn.peek()
+    }
+
+    /// See `TcpConn::receive_streaming`
+    /// 
+    /// # Errors
+    /// The same as `TcpConn::receive_streaming`.
+    pub fn receive_streaming<T>(&mut self) -> Result<T, RecvError>
+    where T: DeserializeOwned, C: Clone {
+        self.conn.receive_streaming()
+    }
+
+    /// See `TcpConn::receive_streaming_on`
+    /// 
+    /// # Errors
+    /// The same as `TcpConn::receive_streaming_on`.
+    pub fn receive_streaming_on<T>(&mut self, channel: u8) -> Result<T, RecvError>
+    where T: DeserializeOwned, C: Clone {
+        self.conn.receive_streaming_on(channel)
+    }
+
+    /// See `TcpConn::receive_streaming_timeout`
+    /// 
+    /// # Errors
+    /// The same as `TcpConn::receive_streaming_timeout`.
+    pub fn receive_streaming_timeout<T>(&mut self, timeout: Duration) -> Result<T, RecvError>
+    where T: DeserializeOwned, C: Clone {
+        self.conn.receive_streaming_timeout(timeout)
+    }
+
+    /// See `TcpConn::receive_raw`
+    /// 
+    /// # Errors
+    /// The same as `TcpConn::receive_raw`.
+    pub fn receive_raw(&mut self) -> Result<Vec<u8>, RecvError> {
+        self.conn.receive_raw()
+    }
+
+    /// See `TcpConn::receive_raw_on`
+    /// 
+    /// # Errors
+    /// The same as `TcpConn::receive_raw_on`.
+    pub fn receive_raw_on(&mut self, channel: u8) -> Result<Vec<u8>, RecvError> {
+        self.conn.receive_raw_on(channel)
+    }
+
+    /// See `TcpConn::try_receive_raw`
+    /// 
+    /// # Errors
+    /// The same as `TcpConn::try_receive_raw`.
+    pub fn try_receive_raw(&mut self) -> Result<Option<Vec<u8>>, RecvError> {
+        self.conn.try_receive_raw()
+    }
+
+    /// See `TcpConn::try_receive_raw_on`
+    /// 
+    /// # Errors
+    /// The same as `TcpConn::try_receive_raw_on`.
+    pub fn try_receive_raw_on(&mut self, channel: u8) -> Result<Option<Vec<u8>>, RecvError> {
+        self.conn.try_receive_raw_on(channel)
+    }
+
+    /// See `TcpConn::discard_frame`
+    pub fn discard_frame(&mut self) {
+        self.conn.discard_frame()
+    }
+
+    /// See `TcpConn::discard_frame_on`
+    pub fn discard_frame_on(&mut self, channel: u8) {
+        self.conn.discard_frame_on(channel)
+    }
+
+    /// See `TcpConn::resync`
+    pub fn resync(&mut self) -> usize {
+        self.conn.resync()
+    }
+
+    /// See `TcpConn::sequence_stats`
+    pub fn sequence_stats(&self) -> SequenceStats {
+        self.conn.sequence_stats()
+    }
+
+    /// See `TcpConn::empty_buffer`
+    pub fn empty_buffer(&mut self) {
+        self.conn.empty_buffer()
+    }
+}
+
+/// The sending half of a connection, see `TcpConn::split`
+pub struct ConnWriter<C = Json, S = TcpStream> {
+    conn: TcpConn<C, S>,
+}
+
+impl<C: Codec, S: Transport> ConnWriter<C, S> {
+    /// Turn this into a handle that can be cloned for each thread that sends
+    pub fn into_shared(self) -> SharedWriter<C, S> {
+        SharedWriter { writer: Arc::new(Mutex::new(self)) }
+    }
+
+    pub fn codec(&self) -> &C {
+        self.conn.codec()
+    }
+
+    /// See `TcpConn::set_checksums`
+    pub fn set_checksums(&mut self, checksums: bool) {
+        self.conn.set_checksums(checksums)
+    }
+
+    /// See `TcpConn::set_max_backlog`
+    pub fn set_max_backlog(&mut self, size: usize) {
+        self.conn.set_max_backlog(size)
+    }
+
+    /// See `TcpConn::backlog`
+    pub fn backlog(&self) -> usize {
+        self.conn.backlog()
+    }
+
+    /// See `TcpConn::sequence_stats`
+    pub fn sequence_stats(&self) -> SequenceStats {
+        self.conn.sequence_stats()
+    }
+
+    /// See `TcpConn::send`
+    /// 
+    /// # Errors
+    /// The same as `TcpConn::send`.
+    pub fn send<T>(&mut self, data: &T) -> Result<(), SendError>
+    where T: Serialize {
+        self.conn.send(data)
+    }
+
+    /// See `TcpConn::send_on`
+    /// 
+    /// # Errors
+    /// The same as `TcpConn::send_on`.
+    pub fn send_on<T>(&mut self, channel: u8, data: &T) -> Result<(), SendError>
+    where T: Serialize {
+        self.conn.send_on(channel, data)
+    }
+
+    /// See `TcpConn::send_raw`
+    /// 
+    /// # Errors
+    /// The same as `TcpConn::send_raw`.
+    pub fn send_raw(&mut self, bytes: &[u8]) -> Result<(), SendError> {
+        self.conn.send_raw(bytes)
+    }
+
+    /// See `TcpConn::send_raw_on`
+    /// 
+    /// # Errors
+    /// The same as `TcpConn::send_raw_on`.
+    pub fn send_raw_on(&mut self, channel: u8, bytes: &[u8]) -> Result<(), SendError> {
+        self.conn.send_raw_on(channel, bytes)
+    }
+
+    /// See `TcpConn::send_frame`
+    /// 
+    /// # Errors
+    /// The same as `TcpConn::send_frame`.
+    pub fn send_frame(&mut self, frame: &[u8]) -> Result<(), SendError> {
+        self.conn.send_frame(frame)
+    }
+
+    /// See `TcpConn::queue`
+    /// 
+    /// # Errors
+    /// The same as `TcpConn::queue`.
+    pub fn queue<T>(&mut self, data: &T) -> Result<(), SendError>
+    where T: Serialize {
+        self.conn.queue(data)
+    }
+
+    /// See `TcpConn::queue_on`
+    /// 
+    /// # Errors
+    /// The same as `TcpConn::queue_on`.
+    pub fn queue_on<T>(&mut self, channel: u8, data: &T) -> Result<(), SendError>
+    where T: Serialize {
+        self.conn.queue_on(channel, data)
+    }
+
+    /// See `TcpConn::queue_raw`
+    /// 
+    /// # Errors
+    /// The same as `TcpConn::queue_raw`.
+    pub fn queue_raw(&mut self, bytes: &[u8]) -> Result<(), SendError> {
+        self.conn.queue_raw(bytes)
+    }
+
+    /// See `TcpConn::queue_raw_on`
+    /// 
+    /// # Errors
+    /// The same as `TcpConn::queue_raw_on`.
+    pub fn queue_raw_on(&mut self, channel: u8, bytes: &[u8]) -> Result<(), SendError> {
+        self.conn.queue_raw_on(channel, bytes)
+    }
+
+    /// See `TcpConn::queue_frame`
+    pub fn queue_frame(&mut self, frame: &[u8]) {
+        self.conn.queue_frame(frame)
+    }
+
+    /// See `TcpConn::queue_frame_on`
+    pub fn queue_frame_on(&mut self, channel: u8, frame: &[u8]) {
+        self.conn.queue_frame_on(channel, frame)
+    }
+
+    /// See `TcpConn::queue_shared`
+    pub fn queue_shared(&mut self, frame: &Arc<[u8]>) {
+        self.conn.queue_shared(frame)
+    }
+
+    /// See `TcpConn::queue_shared_on`
+    pub fn queue_shared_on(&mut self, channel: u8, frame: &Arc<[u8]>) {
+        self.conn.queue_shared_on(channel, frame)
+    }
+
+    /// See `TcpConn::flush_queued`
+    /// 
+    /// # Errors
+    /// The same as `TcpConn::flush_queued`.
+    pub fn flush_queued(&mut self) -> Result<(), SendError> {
+        self.conn.flush_queued()
+    }
+}
+
+/// The sending half of a connection, shared between every thread that sends on it. Clones are
+/// handles to the same connection, and each send holds it for as long as writing takes, so
+/// messages from different threads never end up interleaved.
+pub struct SharedWriter<C = Json, S = TcpStream> {
+    writer: Arc<Mutex<ConnWriter<C, S>>>,
+}
+
+impl<C, S> Clone for SharedWriter<C, S> {
+    fn clone(&self) -> Self {
+        Self { writer: Arc::clone(&self.writer) }
+    }
+}
+
+impl<C: Codec, S: Transport> SharedWriter<C, S> {
+    /// Hold the connection, for queueing several things that have to go out together without
+    /// another thread's messages in between
+    pub fn lock(&self) -> MutexGuard<'_, ConnWriter<C, S>> {
+        self.writer.lock().unwrap()
+    }
+
+    /// Swap in `new`'s connection, so every handle sends on it from now on. `new` is left with the
+    /// old one, which closes once it's dropped.
+    pub fn replace(&self, new: &Self) {
+        // the same connection twice would deadlock, and swapping it with itself does nothing anyway
+        if Arc::ptr_eq(&self.writer, &new.writer) {
+            return;
+        }
+        mem::swap(&mut *self.lock(), &mut *new.lock());
+    }
+
+    /// See `TcpConn::send`
+    /// 
+    /// # Errors
+    /// The same as `TcpConn::send`.
+    pub fn send<T>(&self, data: &T) -> Result<(), SendError>
+    where T: Serialize {
+        self.lock().send(data)
+    }
+
+    /// See `TcpConn::send_on`
+    /// 
+    /// # Errors
+    /// The same as `TcpConn::send_on`.
+    pub fn send_on<T>(&self, channel: u8, data: &T) -> Result<(), SendError>
+    where T: Serialize {
+        self.lock().send_on(channel, data)
+    }
+
+    /// See `TcpConn::send_raw_on`
+    /// 
+    /// # Errors
+    /// The same as `TcpConn::send_raw_on`.
+    pub fn send_raw_on(&self, channel: u8, bytes: &[u8]) -> Result<(), SendError> {
+        self.lock().send_raw_on(channel, bytes)
+    }
+}
+
+/// Serialize `data` as JSON into `packet` along with the header `receive` needs to find where it
+/// ends, replacing whatever `packet` held before. Send it with `TcpConn::send_frame`.
+/// 
+/// # Errors
+/// Fails if `data` can't be serialized.
+pub fn encode_frame<T>(data: &T, packet: &mut Vec<u8>) -> Result<(), SendError>
+where T: Serialize {
+    encode_frame_with(&Json, data, packet)
+}
+
+/// Same as `encode_frame`, serializing with `codec`. Long payloads are compressed, and ones longer
+/// than `CHUNK_SIZE` after that are split into several frames, both of which `receive` undoes
+/// without being asked.
+/// 
+/// # Errors
+/// Fails if `data` can't be serialized.
+pub fn encode_frame_with<C, T>(codec: &C, data: &T, packet: &mut Vec<u8>) -> Result<(), SendError>
+where C: Codec, T: Serialize {
+
+    // the header is filled in once the length of the payload is known
+    packet.clear();
+    packet.resize(HEADER_LEN, 0);
+
+    codec.encode(data, packet).map_err(SendError::Encode)?;
+
+    finish_frame(packet, 0)
+}
+
+/// Frame raw bytes to be received with `TcpConn::receive_raw`, replacing whatever `packet` held
+/// before. Like messages, they're compressed and split into chunks when they're long enough.
+/// 
+/// # Errors
+/// Fails if `bytes` is more than `u32::MAX` bytes long.
+pub fn encode_raw_frame(bytes: &[u8], packet: &mut Vec<u8>) -> Result<(), SendError> {
+    packet.clear();
+    packet.resize(HEADER_LEN, 0);
+    packet.extend_from_slice(bytes);
+    finish_frame(packet, FLAG_RAW)
+}
+
+/// Compress the payload after the space left for the header in `packet` and split it into chunks
+/// as needed, then fill in the header with `flags` along with whatever those added
+/// 
+/// # Errors
+/// Fails if the payload is too big to be framed at all.
+fn finish_frame(packet: &mut Vec<u8>, flags: u8) -> Result<(), SendError> {
+    let flags = flags | compress_payload(packet)?;
+    if packet.len() - HEADER_LEN > CHUNK_SIZE {
+        return Ok(split_payload(packet, flags)?);
+    }
+    Ok(write_header(packet, flags)?)
+}
+
+/// Frame a payload that was already serialized, replacing whatever `packet` held before. Only
+/// needed for sending something no `Codec` would produce, like a deliberately malformed message
+/// to test how the other end copes.
+/// 
+/// # Errors
+/// Fails if the payload is too big to fit in a frame.
+pub fn frame_payload(payload: &[u8], packet: &mut Vec<u8>) -> Result<(), SendError> {
+    packet.clear();
+    packet.resize(HEADER_LEN, 0);
+    packet.extend_from_slice(payload);
+    Ok(write_header(packet, 0)?)
+}
+
+/// Fill in the header at the start of `packet`, for the payload after it
+/// 
+/// # Errors
+/// Fails if the payload is more than `u32::MAX` bytes, which the header can't describe.
+fn write_header(packet: &mut [u8], flags: u8) -> Result<(), FrameTooLarge> {
+    let magic_len = FRAME_MAGIC.len();
+    let payload_size = packet.len() - HEADER_LEN;
+    let payload_size = u32::try_from(payload_size)
+        .map_err(|_| FrameTooLarge { size: payload_size, max: u32::MAX as usize })?;
+    packet[..magic_len].copy_from_slice(&FRAME_MAGIC);
+    packet[magic_len] = FRAME_VERSION;
+    packet[FLAGS_AT] = flags;
+    packet[CHANNEL_AT] = MAIN_CHANNEL;
+    packet[LENGTH_AT..HEADER_LEN].copy_from_slice(&payload_size.to_be_bytes());
+    Ok(())
+}
+
+/// Compress the payload after the header in `packet` if it's long enough, returning the flags
+/// that say whether it was. It's left alone if compressing it wouldn't make it any shorter, like
+/// a paste of something that was already compressed.
+/// 
+/// # Errors
+/// Fails if the compressor does, which it only can by running out of memory.
+fn compress_payload(packet: &mut Vec<u8>) -> io::Result<u8> {
+    if packet.len() - HEADER_LEN <= COMPRESS_ABOVE {
+        return Ok(0);
+    }
+
+    let mut compressed = POOL.take();
+    compressed.resize(HEADER_LEN, 0);
+    let mut encoder = DeflateEncoder::new(&mut *compressed, Compression::fast());
+    encoder.write_all(&packet[HEADER_LEN..])?;
+    encoder.finish()?;
+
+    if compressed.len() >= packet.len() {
+        return Ok(0);
+    }
+    mem::swap(packet, &mut compressed);
+    Ok(FLAG_COMPRESSED)
+}
+
+/// Decompress `payload` into `out`, giving up past `max` bytes so a small message can't be made to
+/// take up an enormous amount of memory
+/// 
+/// # Errors
+/// Fails with `RecvError::Invalid` if `payload` isn't valid deflate or decompresses to more than
+/// `max` bytes.
+fn decompress(payload: &[u8], max: usize, out: &mut Vec<u8>) -> Result<(), RecvError> {
+    DeflateDecoder::new(payload)
+        .take(max as u64 + 1)
+        .read_to_end(out)
+        .map_err(|_| RecvError::Invalid(String::from("Message couldn't be decompressed")))?;
+    if out.len() > max {
+        return Err(RecvError::Invalid(format!("Message decompresses to more than {max} bytes")));
+    }
+    Ok(())
+}
+
+/// Replace the payload after the header in `packet` with frames of at most `CHUNK_SIZE` bytes of
+/// it each, all with `flags` as well as the ones marking them as chunks
+/// 
+/// # Errors
+/// Fails if the payload is more than `u32::MAX` bytes, which a chunk's header can't describe
+/// either. Past that the other end would refuse it anyway.
+fn split_payload(packet: &mut Vec<u8>, flags: u8) -> Result<(), FrameTooLarge> {
+    let payload_size = packet.len() - HEADER_LEN;
+    if u32::try_from(payload_size).is_err() {
+        return Err(FrameTooLarge { size: payload_size, max: u32::MAX as usize });
+    }
+
+    let mut chunked = POOL.take();
+    let mut chunks = packet[HEADER_LEN..].chunks(CHUNK_SIZE).peekable();
+    while let Some(chunk) = chunks.next() {
+        let start = chunked.len();
+        chunked.resize(start + HEADER_LEN, 0);
+        chunked.extend_from_slice(chunk);
+
+        let last = if chunks.peek().is_none() { FLAG_LAST_CHUNK } else { 0 };
+        write_header(&mut chunked[start..], flags | FLAG_CHUNK | last)?;
+    }
+    mem::swap(packet, &mut chunked);
+    Ok(())
+}
+
+/// Whether the frames in `frames` can go out on `channel` as they are, to a connection that does
+/// or doesn't want `checksums`. Only the first frame is looked at, since frames encoded or
+/// restamped together are stamped alike.
+fn stamped_for(frames: &[u8], channel: u8, checksums: bool) -> bool {
+    frames.len() >= HEADER_LEN
+        && frames[CHANNEL_AT] == channel
+        && (!checksums || frames[FLAGS_AT] & FLAG_CHECKSUM != 0)
+}
+
+/// Copy the frames in `frames` into `out`, moving each one to `channel` and, if `checksums` is
+/// set, adding a checksum of its payload to each one that doesn't have one already
+pub fn restamp_frames(mut frames: &[u8], channel: u8, checksums: bool, out: &mut Vec<u8>) {
+    out.clear();
+    while let Some((payload_start, frame_size)) = next_frame(frames) {
+        let (frame, rest) = frames.split_at(frame_size);
+        frames = rest;
+
+        let header_at = out.len();
+        if !checksums || frame[FLAGS_AT] & FLAG_CHECKSUM != 0 {
+            out.extend_from_slice(frame);
+        } else {
+            let payload = &frame[payload_start..];
+            out.extend_from_slice(&frame[..HEADER_LEN]);
+            out[header_at + FLAGS_AT] |= FLAG_CHECKSUM;
+            out.extend(crc32fast::hash(payload).to_be_bytes());
+            out.extend_from_slice(payload);
+        }
+        out[header_at + CHANNEL_AT] = channel;
+    }
+    // anything too short to be a frame is passed along as it is
+    out.extend_from_slice(frames);
+}
+
+/// The payload of `frame`, which starts at `payload_start`, once it's been checked against its
+/// checksum if it came with one
+/// 
+/// # Errors
+/// Fails if the payload doesn't match its checksum.
+fn checked_payload(frame: &[u8], payload_start: usize) -> Result<&[u8], FrameCorrupt> {
+    let payload = &frame[payload_start..];
+
+    // checked before decoding, since a corrupted payload could still happen to decode
+    if frame[FLAGS_AT] & FLAG_CHECKSUM != 0 {
+        let sent = u32::from_be_bytes(frame[HEADER_LEN..payload_start].try_into().unwrap());
+        let actual = crc32fast::hash(payload);
+        if sent != actual {
+            return Err(FrameCorrupt { sent, actual });
+        }
+    }
+    Ok(payload)
+}
+
+/// Where the payload of the first frame in `frames` starts, and how long the frame is, going by
+/// its header alone. Returns `None` if there isn't a whole frame there.
+fn next_frame(frames: &[u8]) -> Option<(usize, usize)> {
+    let flags = *frames.get(FLAGS_AT)?;
+    let payload_size = u32::from_be_bytes(frames.get(LENGTH_AT..HEADER_LEN)?.try_into().unwrap()) as usize;
+    let payload_start = if flags & FLAG_CHECKSUM != 0 { HEADER_LEN + CHECKSUM_LEN } else { HEADER_LEN };
+    let frame_size = payload_start + payload_size;
+    (frames.len() >= frame_size).then_some((payload_start, frame_size))
+}
+
+/// Why receiving on a `TcpConn` failed. Some of these only lose the one message, others mean
+/// nothing more the other end sends can be read, see each one.
+#[derive(Debug, Error)]
+pub enum RecvError {
+    /// Only part of the next message has arrived, on a non-blocking connection. The rest is kept
+    /// for when it's asked for again.
+    #[error("Only part of the next message has arrived so far")]
+    Incomplete,
+
+    /// The whole message didn't arrive before the receive timeout, see
+    /// `TcpConn::set_receive_timeout`. What did arrive is kept, so receiving again carries on from
+    /// where this left off.
+    #[error("The message didn't arrive in time")]
+    TimedOut,
+
+    /// The other end closed the connection. Everything it sent before that has been received.
+    #[error("The other end closed the connection")]
+    Closed,
+
+    /// A message arrived whole but wasn't what was asked for: the wrong type, raw bytes instead of
+    /// a message (or the other way around), or a payload that doesn't decompress. It's left where
+    /// it is, so it can be received as something else or skipped with `TcpConn::discard_frame`.
+    #[error("{0}")]
+    Invalid(String),
+
+    #[error(transparent)]
+    Corrupt(#[from] FrameCorrupt),
+
+    #[error(transparent)]
+    TooLarge(#[from] FrameTooLarge),
+
+    #[error(transparent)]
+    Backlog(#[from] RecvBacklog),
+
+    /// A frame didn't start where one should, after others had arrived fine, so the stream got
+    /// garbled on the way. `TcpConn::discard_frame` skips ahead to where the next one seems to
+    /// start.
+    #[error("Lost track of where messages start")]
+    Garbled,
+
+    /// The other end isn't speaking the same framing, or a version of it this end understands, so
+    /// nothing it sends can be read
+    #[error("{0}")]
+    Unsupported(String),
+
+    /// A Noise message didn't decrypt, so it was tampered with or the stream got garbled. Nothing
+    /// after it can be decrypted either.
+    #[error("Unable to decrypt a message, it may have been tampered with")]
+    Undecryptable,
+
+    /// The receive was given up on because the connection's `CancelToken` was cancelled, see
+    /// `TcpConn::set_cancel_token`. Whatever had arrived is kept.
+    #[error("Receiving was cancelled")]
+    Cancelled,
+
+    /// Reading from the stream underneath failed
+    #[error(transparent)]
+    Io(io::Error),
+}
+
+impl RecvError {
+    /// Whether the connection itself is gone, as opposed to something that arrived on it not
+    /// making sense
+    pub fn is_disconnect(&self) -> bool {
+        match self {
+            Self::Closed => true,
+            Self::Io(e) => matches!(
+                e.kind(),
+                io::ErrorKind::ConnectionReset
+                    | io::ErrorKind::ConnectionAborted
+                    | io::ErrorKind::BrokenPipe
+                    | io::ErrorKind::UnexpectedEof
+                    | io::ErrorKind::TimedOut
+            ),
+            _ => false,
+        }
+    }
+}
+
+impl From<io::Error> for RecvError {
+    fn from(error: io::Error) -> Self {
+        // one that had to be turned into an `io::Error` to get through a `Read` comes back out as
+        // it was
+        if error.get_ref().is_some_and(|inner| inner.is::<Self>()) {
+            return *error.into_inner().unwrap().downcast::<Self>().unwrap();
+        }
+        Self::Io(error)
+    }
+}
+
+impl From<RecvError> for io::Error {
+    fn from(error: RecvError) -> Self {
+        let kind = match error {
+            RecvError::Io(e) => return e,
+            RecvError::Incomplete => io::ErrorKind::WouldBlock,
+            RecvError::TimedOut => io::ErrorKind::TimedOut,
+            RecvError::Closed => io::ErrorKind::UnexpectedEof,
+            RecvError::Unsupported(_) => io::ErrorKind::Unsupported,
+            RecvError::Cancelled => io::ErrorKind::Other,
+            _ => io::ErrorKind::InvalidData,
+        };
+        io::Error::new(kind, error)
+    }
+}
+
+/// Wakes up a receive that's waiting on a connection so it gives up, see
+/// `TcpConn::set_cancel_token`. Clones are the same token, so one can be kept to cancel with while
+/// another is handed to the connection.
+#[derive(Debug, Clone, Default)]
+pub struct CancelToken {
+    cancelled: Arc<AtomicBool>,
+}
+
+impl CancelToken {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Have every connection holding this token stop receiving, within `CANCEL_CHECK_INTERVAL`
+    pub fn cancel(&self) {
+        self.cancelled.store(true, Ordering::Relaxed);
+    }
+
+    pub fn is_cancelled(&self) -> bool {
+        self.cancelled.load(Ordering::Relaxed)
+    }
+}
+
+/// Why sending (or queueing, or framing) a message failed
+#[derive(Debug, Error)]
+pub enum SendError {
+    /// The message couldn't be serialized with the connection's codec
+    #[error("Unable to serialize the message: {0}")]
+    Encode(#[source] io::Error),
+
+    #[error(transparent)]
+    TooLarge(#[from] FrameTooLarge),
+
+    /// Everything is still queued, but the other end isn't keeping up
+    #[error(transparent)]
+    Backlog(#[from] SendBacklog),
+
+    /// Writing to the stream underneath failed
+    #[error(transparent)]
+    Io(#[from] io::Error),
+}
+
+impl From<SendError> for io::Error {
+    fn from(error: SendError) -> Self {
+        let kind = match error {
+            SendError::Io(e) => return e,
+            SendError::Encode(_) | SendError::TooLarge(_) => io::ErrorKind::InvalidInput,
+            SendError::Backlog(_) => io::ErrorKind::Other,
+        };
+        io::Error::new(kind, error)
+    }
+}
+
+/// A frame bigger than is allowed, either one that arrived claiming to be bigger than the
+/// connection's maximum frame size or message size (`RecvError::TooLarge`), or one too big to send
+/// at all (`SendError::TooLarge`). Nothing after a frame that's too big to receive can be trusted
+/// to line up, so the connection is best closed.
+#[derive(Debug, Error, Clone, Copy, PartialEq, Eq)]
+#[error("Message claims to be {size} bytes, the limit is {max}")]
+pub struct FrameTooLarge {
+    /// How big the frame's payload is, in bytes
+    pub size: usize,
+    /// The most that was allowed
+    pub max: usize,
+}
+
+/// A frame whose payload doesn't match the checksum sent with it (`RecvError::Corrupt`). The
+/// frame's header was fine, so only it needs to be thrown away with `TcpConn::discard_frame`, and
+/// anything after it can still be received.
+#[derive(Debug, Error, Clone, Copy, PartialEq, Eq)]
+#[error("Message was corrupted on the way (checksum {actual:08x}, expected {sent:08x})")]
+pub struct FrameCorrupt {
+    /// The checksum the frame arrived with
+    pub sent: u32,
+    /// The checksum of the payload that arrived
+    pub actual: u32,
+}
+
+/// More waiting to be sent on a connection than its maximum backlog (`SendError::Backlog`), see
+/// `TcpConn::set_max_backlog`. The other end isn't taking what it's sent, usually because it's
+/// stopped reading or its network can't keep up. Nothing queued is lost, but it'll only keep
+/// growing while that lasts.
+#[derive(Debug, Error, Clone, Copy, PartialEq, Eq)]
+#[error("{queued} bytes are waiting to be sent, the limit is {max}")]
+pub struct SendBacklog {
+    /// How many bytes are waiting to be sent
+    pub queued: usize,
+    /// The most that was allowed
+    pub max: usize,
+}
+
+/// More has arrived on a connection than its maximum buffered (`RecvError::Backlog`), see
+/// `TcpConn::set_max_buffered`. The other end is sending faster than what it sends is received,
+/// or sending somewhere nobody receives from. Nothing more can be read until some of it is.
+#[derive(Debug, Error, Clone, Copy, PartialEq, Eq)]
+#[error("{buffered} bytes have arrived without being received, the limit is {max}")]
+pub struct RecvBacklog {
+    /// How many bytes are waiting to be received
+    pub buffered: usize,
+    /// The most that was allowed
+    pub max: usize,
+}
+
+/// Where a connection is up to in numbering the frames it sends and receives, see
+/// `TcpConn::sequence_stats`. Every frame is numbered one after the last sent on the connection,
+/// wrapping around after `u32::MAX`, so the receiving end can tell when some went missing (say,
+/// thrown away after being garbled) or were sent twice.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct SequenceStats {
+    /// The number the next frame sent is given
+    pub next_sent: u32,
+    /// The number the next frame to arrive should have
+    pub next_received: u32,
+    /// How many frames were skipped over by ones that arrived after them
+    pub missed: u64,
+    /// How many frames arrived numbered before one that already had, which were thrown away
+    pub duplicates: u64,
+}
+
+impl SequenceStats {
+    /// Note that a frame numbered `sequence` arrived. Returns whether it's new, rather than a
+    /// duplicate to throw away.
+    fn arrived(&mut self, sequence: u32) -> bool {
+        // how far ahead of what was expected it is, where being halfway round or more means behind
+        let ahead = sequence.wrapping_sub(self.next_received);
+        if ahead > u32::MAX / 2 {
+            self.duplicates += 1;
+            return false;
+        }
+        self.missed += u64::from(ahead);
+        self.next_received = sequence.wrapping_add(1);
+        true
+    }
+}
+
+/// Number every frame in `frames` from `next_sent` on, in order
+fn number_frames(frames: &mut [u8], next_sent: &mut u32) {
+    let mut at = 0;
+    while let Some((_, frame_size)) = next_frame(&frames[at..]) {
+        frames[at + SEQUENCE_AT..at + LENGTH_AT].copy_from_slice(&next_sent.to_be_bytes());
+        *next_sent = next_sent.wrapping_add(1);
+        at += frame_size;
+    }
+}
+
+/// Move everything that has arrived on `stream` so far into `buffer`, polling it `poll_size`
+/// bytes at a time until it's empty or `limit` bytes have been read. A `blocking` stream is only
+/// read once, since another read would wait for more to arrive even if what did makes up a whole
+/// message.
+/// 
+/// # Errors
+/// Fails with `RecvError::Closed` if the other end closed the connection, or with whatever error
+/// the `TcpStream` returned.
+fn read_available(stream: &mut impl Read, buffer: &mut ReadBuffer, poll_size: usize, limit: usize, blocking: bool) -> Result<(), RecvError> {
+    let mut read = 0;
+    loop {
+        let len = poll_size.min(limit - read);
+        match buffer.read_from(stream, len) {
+            // whatever did arrive is still worth handing out before reporting the close
+            Ok(0) if read > 0 => return Ok(()),
+            Ok(0) => return Err(RecvError::Closed),
+            Ok(bytes_read) => {
+                read += bytes_read;
+
+                // check if there are no more bytes to read (even if we don't have enough bytes
+                // to deserialize a message)
+                if blocking || bytes_read < len || read == limit {
+                    return Ok(());
+                }
+            },
+            // a blocking read that hit its timeout says so in one of these, depending on the OS
+            Err(e) if matches!(e.kind(), io::ErrorKind::WouldBlock | io::ErrorKind::TimedOut) => return Ok(()),
+            Err(e) if e.kind() == io::ErrorKind::Interrupted => {},
+            Err(e) => return Err(RecvError::Io(e)),
+        }
+    }
+}
+
+/// How long a read can wait to be done by `deadline`. A read timeout can't be zero, so one that's
+/// already passed still waits a moment.
+fn read_timeout_until(deadline: Instant) -> Duration {
+    deadline.saturating_duration_since(Instant::now()).max(Duration::from_millis(1))
+}
+
+/// Append `message` to `out` behind the two byte length Noise messages are sent with
+fn push_record(out: &mut Vec<u8>, message: &[u8]) {
+    out.extend((message.len() as u16).to_be_bytes());
+    out.extend_from_slice(message);
+}
+
+/// An error for a frame whose header shows the other end isn't speaking the same framing, after
+/// which nothing more it sends can be read
+fn header_error(reason: String) -> RecvError {
+    RecvError::Unsupported(reason)
+}
+
+/// Deserialize a whole message with `codec`, see `decode_error`
+fn decode<C: Codec, T: DeserializeOwned>(codec: &C, payload: &[u8]) -> Result<T, RecvError> {
+    codec.decode(payload).map_err(decode_error)
+}
+
+/// Copy out raw bytes that arrived whole
+fn copy_raw<C>(_: &C, payload: &[u8]) -> Result<Vec<u8>, RecvError> {
+    Ok(payload.to_vec())
+}
+
+/// The `RecvError` for a message that arrived whole but that `Codec::decode` couldn't make sense
+/// of, as opposed to reading it failing partway through
+fn decode_error(error: io::Error) -> RecvError {
+    match RecvError::from(error) {
+        RecvError::Io(e) if e.kind() == io::ErrorKind::InvalidData => RecvError::Invalid(e.to_string()),
+        other => other,
+    }
+}
+
+/// An error for a message that goes over one of the limits on what will be deserialized
+pub(crate) fn limit_error(reason: String) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidData, reason)
+}
+
+/// A helper function to return an error which is used frequently
+pub(crate) fn reconstruction_error<T>() -> io::Error {
+    io::Error::new(
+        io::ErrorKind::InvalidData,
+        format!(
+            "Unable to reconstruct a value of type `{}` due to invalid type. This error indicates that all the data from the message has arrived, but it cannot be deserialized into the given type.",
+            type_name::<T>()
+        )
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::local::LocalStream;
+
+    /// A connection that reads what's written to the returned stream
+    fn receiver() -> (LocalStream, TcpConn<Json, LocalStream>) {
+        let (ours, theirs) = LocalStream::pair();
+        (ours, TcpConn::new(theirs).unwrap())
+    }
+
+    /// The frames a connection sends for `messages`, as they'd arrive, one after another
+    fn frames_for(messages: &[&str], checksums: bool) -> Vec<Vec<u8>> {
+        let (ours, mut theirs) = LocalStream::pair();
+        let mut sender = TcpConn::new(ours).unwrap();
+        sender.set_checksums(checksums);
+        for message in messages {
+            sender.send(message).unwrap();
+        }
+        drop(sender);
+
+        let mut bytes = Vec::new();
+        theirs.read_to_end(&mut bytes).unwrap();
+
+        let mut frames = Vec::new();
+        let mut rest = &bytes[..];
+        while let Some((_, frame_size)) = next_frame(rest) {
+            frames.push(rest[..frame_size].to_vec());
+            rest = &rest[frame_size..];
+        }
+        assert!(rest.is_empty());
+        frames
+    }
+
+    /// Text that barely compresses, so a payload of it stays long
+    fn noise(len: usize) -> String {
+        let mut state = 0x2545_f491_u32;
+        (0..len).map(|_| {
+            state ^= state << 13;
+            state ^= state >> 17;
+            state ^= state << 5;
+            char::from_digit(state % 36, 36).unwrap()
+        }).collect()
+    }
+
+    #[test]
+    fn messages_arrive_in_order() {
+        let (stream, mut conn) = receiver();
+        let mut sender = TcpConn::new(stream).unwrap();
+
+        let long = "long ".repeat(1000);
+        sender.send(&"short").unwrap();
+        sender.send(&long).unwrap();
+
+        assert_eq!(conn.receive::<String>().unwrap(), "short");
+        assert_eq!(conn.receive::<String>().unwrap(), long);
+    }
+
+    #[test]
+    fn encoded_frames_are_received() {
+        let (mut stream, mut conn) = receiver();
+
+        let mut packet = Vec::new();
+        encode_frame(&vec![1, 2, 3], &mut packet).unwrap();
+        stream.write_all(&packet).unwrap();
+
+        assert_eq!(conn.receive::<Vec<i32>>().unwrap(), [1, 2, 3]);
+    }
+
+    #[test]
+    fn frames_arriving_a_byte_at_a_time_are_received() {
+        let (mut stream, mut conn) = receiver();
+
+        let mut frame = Vec::new();
+        encode_frame(&"trickled", &mut frame).unwrap();
+        let (last, rest) = frame.split_last().unwrap();
+        for byte in rest {
+            stream.write_all(&[*byte]).unwrap();
+            assert!(conn.try_receive::<String>().unwrap().is_none());
+        }
+        stream.write_all(&[*last]).unwrap();
+
+        assert_eq!(conn.receive::<String>().unwrap(), "trickled");
+    }
+
+    #[test]
+    fn other_protocols_are_refused() {
+        let (mut stream, mut conn) = receiver();
+
+        stream.write_all(b"GET / HTTP/1.1\r\n").unwrap();
+
+        assert!(matches!(conn.receive::<String>(), Err(RecvError::Unsupported(_))));
+    }
+
+    #[test]
+    fn other_framing_versions_are_refused() {
+        let (mut stream, mut conn) = receiver();
+
+        let mut frame = Vec::new();
+        encode_frame(&"from the future", &mut frame).unwrap();
+        frame[FRAME_MAGIC.len()] = FRAME_VERSION + 1;
+        stream.write_all(&frame).unwrap();
+
+        assert!(matches!(conn.receive::<String>(), Err(RecvError::Unsupported(_))));
+    }
+
+    #[test]
+    fn lengths_are_big_endian_u32s() {
+        let mut frame = Vec::new();
+        encode_frame(&"four", &mut frame).unwrap();
+
+        let payload_len = frame.len() - HEADER_LEN;
+        assert_eq!(frame[HEADER_LEN - 4..HEADER_LEN], (payload_len as u32).to_be_bytes());
+    }
+
+    #[test]
+    fn oversized_frames_are_refused() {
+        let (stream, mut conn) = receiver();
+        let mut sender = TcpConn::new(stream).unwrap();
+        conn.set_max_frame_size(16);
+
+        sender.send(&"x".repeat(64)).unwrap();
+
+        assert!(matches!(conn.receive::<String>(), Err(RecvError::TooLarge(FrameTooLarge { size: 66, max: 16 }))));
+    }
+
+    #[test]
+    fn corrupted_payloads_fail_their_checksum() {
+        let (mut stream, mut conn) = receiver();
+
+        let mut frames = frames_for(&["first", "second"], true);
+        let (payload_start, _) = next_frame(&frames[0]).unwrap();
+        frames[0][payload_start + 2] ^= 0x20;
+        stream.write_all(&frames.concat()).unwrap();
+
+        assert!(matches!(conn.receive::<String>(), Err(RecvError::Corrupt(_))));
+        conn.discard_frame();
+        assert_eq!(conn.receive::<String>().unwrap(), "second");
+    }
+
+    #[test]
+    fn unknown_flags_are_refused() {
+        let (mut stream, mut conn) = receiver();
+
+        let mut frame = Vec::new();
+        encode_frame(&"flagged", &mut frame).unwrap();
+        frame[FLAGS_AT] |= 0x80;
+        stream.write_all(&frame).unwrap();
+
+        assert!(matches!(conn.receive::<String>(), Err(RecvError::Unsupported(_))));
+    }
+
+    #[test]
+    fn only_long_payloads_are_compressed() {
+        let mut short = Vec::new();
+        encode_frame(&"short", &mut short).unwrap();
+        assert_eq!(short[FLAGS_AT] & FLAG_COMPRESSED, 0);
+
+        let long = "long ".repeat(1000);
+        let mut frame = Vec::new();
+        encode_frame(&long, &mut frame).unwrap();
+        assert_ne!(frame[FLAGS_AT] & FLAG_COMPRESSED, 0);
+        assert!(frame.len() < long.len());
+    }
+
+    #[test]
+    fn payloads_that_decompress_too_big_are_refused() {
+        let (stream, mut conn) = receiver();
+        let mut sender = TcpConn::new(stream).unwrap();
+        conn.set_max_message_size(1000);
+
+        sender.send(&"x".repeat(10_000)).unwrap();
+
+        let e = conn.receive::<String>().unwrap_err();
+        assert_eq!(e.to_string(), "Message decompresses to more than 1000 bytes");
+    }
+
+    #[test]
+    fn garbled_headers_are_skipped_past() {
+        let (mut stream, mut conn) = receiver();
+
+        let mut frames = frames_for(&["one", "two", "three"], true);
+        frames[1][0] ^= 0xFF;
+        stream.write_all(&frames.concat()).unwrap();
+
+        assert_eq!(conn.receive::<String>().unwrap(), "one");
+        assert!(matches!(conn.receive::<String>(), Err(RecvError::Garbled)));
+        conn.discard_frame();
+        assert_eq!(conn.receive::<String>().unwrap(), "three");
+        assert_eq!(conn.sequence_stats().missed, 1);
+    }
+
+    #[test]
+    fn resync_finds_a_marker_cut_off_by_the_end() {
+        let (mut stream, mut conn) = receiver();
+
+        let mut frame = Vec::new();
+        encode_frame(&"whole", &mut frame).unwrap();
+        let mut garbage = b"garbage".to_vec();
+        garbage.extend_from_slice(&frame[..2]);
+        stream.write_all(&garbage).unwrap();
+        while conn.buffer.len() < garbage.len() {
+            let _ = conn.try_receive::<String>();
+        }
+
+        assert_eq!(conn.resync(), b"garbage".len());
+        stream.write_all(&frame[2..]).unwrap();
+        assert_eq!(conn.receive::<String>().unwrap(), "whole");
+    }
+
+    #[test]
+    fn chunked_messages_are_put_back_together() {
+        let (stream, mut conn) = receiver();
+        let mut sender = TcpConn::new(stream).unwrap();
+
+        let data = noise(CHUNK_SIZE * 3);
+        let mut frame = Vec::new();
+        encode_frame(&data, &mut frame).unwrap();
+        assert_ne!(frame[FLAGS_AT] & FLAG_CHUNK, 0);
+
+        sender.send(&data).unwrap();
+        sender.send(&"after").unwrap();
+
+        assert_eq!(conn.receive::<String>().unwrap(), data);
+        assert_eq!(conn.receive::<String>().unwrap(), "after");
+    }
+
+    #[test]
+    fn duplicate_frames_are_dropped() {
+        let (mut stream, mut conn) = receiver();
+        conn.set_nonblocking(true).unwrap();
+
+        let frames = frames_for(&["one", "two"], false);
+        stream.write_all(&frames[0]).unwrap();
+        stream.write_all(&frames[0]).unwrap();
+        stream.write_all(&frames[1]).unwrap();
+        stream.write_all(&frames[0]).unwrap();
+
+        assert_eq!(conn.receive::<String>().unwrap(), "one");
+        assert_eq!(conn.receive::<String>().unwrap(), "two");
+        assert!(conn.try_receive::<String>().unwrap().is_none());
+
+        let stats = conn.sequence_stats();
+        assert_eq!(stats.duplicates, 2);
+        assert_eq!(stats.missed, 0);
+        assert_eq!(stats.next_received, 2);
+    }
+
+    #[test]
+    fn skipped_frames_are_counted() {
+        let (mut stream, mut conn) = receiver();
+
+        let frames = frames_for(&["one", "two", "three"], false);
+        stream.write_all(&frames[0]).unwrap();
+        stream.write_all(&frames[2]).unwrap();
+
+        assert_eq!(conn.receive::<String>().unwrap(), "one");
+        assert_eq!(conn.receive::<String>().unwrap(), "three");
+        assert_eq!(conn.sequence_stats().missed, 1);
+    }
+
+    #[test]
+    fn non_blocking_connections_wait_for_the_rest_of_a_streamed_message() {
+        let (stream, mut conn) = receiver();
+        let mut sender = TcpConn::new(stream).unwrap();
+        conn.set_nonblocking(true).unwrap();
+
+        // more than the pipe holds, so the rest is still being sent when streaming starts
+        let data = noise(CHUNK_SIZE * 8);
+        let sending = thread::spawn(move || {
+            sender.send(&data).unwrap();
+            data
+        });
+
+        let received = conn.receive_streaming_timeout::<String>(Duration::from_secs(10)).unwrap();
+        assert_eq!(received, sending.join().unwrap());
+    }
+}
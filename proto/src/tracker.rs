@@ -0,0 +1,26 @@
+// What servers and clients exchange with a tracker, which lists public rooms. Servers announce
+// themselves to it periodically, and clients ask it which rooms are around so they don't need to
+// know an address ahead of time.
+
+use std::net::SocketAddr;
+
+use serde::{Serialize, Deserialize};
+
+
+
+/// What a server sends the tracker's `POST /announce`. The address isn't included since the
+/// tracker uses the one the announcement came from.
+#[derive(Serialize, Deserialize, Debug)]
+pub struct Announcement {
+    pub name: String,
+    pub port: u16,
+    pub user_count: usize,
+}
+
+/// A room listed by the tracker's `GET /rooms`
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct PublicRoom {
+    pub name: String,
+    pub address: SocketAddr,
+    pub user_count: usize,
+}
@@ -0,0 +1,373 @@
+// What a `TcpConn` needs from the stream underneath it. Framing, chunking, encryption and the rest
+// only ever read and write bytes, so they work the same over anything that implements this.
+
+use std::fmt;
+use std::io::{self, IoSlice, Read, Write};
+use std::net::{SocketAddr, TcpListener, TcpStream, ToSocketAddrs};
+#[cfg(unix)]
+use std::os::unix::net::{UnixListener, UnixStream};
+use std::path::PathBuf;
+use std::time::Duration;
+
+use crate::local::{LocalConnector, LocalStream};
+#[cfg(windows)]
+use crate::pipe::{self, PipeListener, PipeStream};
+#[cfg(feature = "quic")]
+use crate::quic::{self, QuicListener, QuicStream};
+
+
+
+/// What an address starts with when it's the path of a Unix socket rather than a host. On Windows
+/// it's the name of a named pipe instead, see `pipe`.
+pub const UNIX_PREFIX: &str = "unix:";
+
+/// What an address starts with when the room should be reached over QUIC rather than TCP
+pub const QUIC_PREFIX: &str = "quic:";
+
+/// A two-way byte stream a `TcpConn` can be built on. Reads that would block past a timeout or
+/// while non-blocking should fail with `io::ErrorKind::WouldBlock` or `io::ErrorKind::TimedOut`,
+/// and a closed stream should read 0 bytes, the same as a `TcpStream`.
+pub trait Transport: Read + Write + Sized {
+    /// Have reads and writes fail with `io::ErrorKind::WouldBlock` instead of waiting
+    ///
+    /// # Errors
+    /// Fails if the stream can't be switched.
+    fn set_nonblocking(&self, nonblocking: bool) -> io::Result<()>;
+
+    /// Have blocking reads give up after `timeout`, or wait forever if it's `None`
+    ///
+    /// # Errors
+    /// Fails if the stream can't be given a timeout.
+    fn set_read_timeout(&self, timeout: Option<Duration>) -> io::Result<()>;
+
+    /// Another handle to the same stream, so one can read while the other writes
+    ///
+    /// # Errors
+    /// Fails if the stream can't be shared.
+    fn try_clone(&self) -> io::Result<Self>;
+}
+
+impl Transport for TcpStream {
+    fn set_nonblocking(&self, nonblocking: bool) -> io::Result<()> {
+        TcpStream::set_nonblocking(self, nonblocking)
+    }
+
+    fn set_read_timeout(&self, timeout: Option<Duration>) -> io::Result<()> {
+        TcpStream::set_read_timeout(self, timeout)
+    }
+
+    fn try_clone(&self) -> io::Result<Self> {
+        TcpStream::try_clone(self)
+    }
+}
+
+/// Where a room can be reached
+#[derive(Debug, Clone)]
+pub enum Address {
+    /// Any of these, tried in order
+    Tcp(Vec<SocketAddr>),
+    /// A Unix socket on this machine, given as `unix:<path>`, or on Windows the named pipe
+    /// `<path>` names. Only on Unix and Windows.
+    Unix(PathBuf),
+    /// Any of these over QUIC, tried in order, given as `quic:<host>[:<port>]`. Only with the
+    /// `quic` feature.
+    Quic(Vec<SocketAddr>),
+    /// A server running in this process, see `LocalConnector`
+    Local(LocalConnector),
+}
+
+impl Address {
+    /// The Unix socket `address` names, if it starts with `UNIX_PREFIX`
+    pub fn unix(address: &str) -> Option<Self> {
+        let path = address.strip_prefix(UNIX_PREFIX)?;
+        (!path.is_empty()).then(|| Self::Unix(PathBuf::from(path)))
+    }
+
+    /// Whether the room is on this machine, so nobody could be listening in between
+    pub fn is_local(&self) -> bool {
+        match self {
+            Self::Tcp(addrs) | Self::Quic(addrs) => addrs.iter().all(|addr| addr.ip().is_loopback()),
+            Self::Unix(_) | Self::Local(_) => true,
+        }
+    }
+
+    /// Connect to the room
+    ///
+    /// # Errors
+    /// Fails if nothing answers, or with `io::ErrorKind::Unsupported` for a Unix socket on a
+    /// platform without them or named pipes, or for QUIC without the `quic` feature.
+    pub fn connect(&self) -> io::Result<Socket> {
+        match self {
+            Self::Tcp(addrs) => TcpStream::connect(&addrs[..]).map(Socket::Tcp),
+            #[cfg(unix)]
+            Self::Unix(path) => UnixStream::connect(path).map(Socket::Unix),
+            #[cfg(windows)]
+            Self::Unix(path) => pipe::connect(path).map(Socket::Pipe),
+            #[cfg(not(any(unix, windows)))]
+            Self::Unix(_) => Err(unix_unsupported()),
+            // QUIC can't be left to wait as long as the OS does, it has to give up on its own
+            #[cfg(feature = "quic")]
+            Self::Quic(addrs) => quic::connect(addrs, quic::HANDSHAKE_TIMEOUT).map(Socket::Quic),
+            #[cfg(not(feature = "quic"))]
+            Self::Quic(_) => Err(quic_unsupported()),
+            Self::Local(connector) => connector.connect(),
+        }
+    }
+
+    /// Same as `connect`, giving up on each address that takes longer than `timeout` to answer
+    /// rather than waiting as long as the OS does, which can be minutes
+    ///
+    /// # Errors
+    /// The same as `connect`, or with `io::ErrorKind::TimedOut` if nothing answered in time.
+    pub fn connect_timeout(&self, timeout: Duration) -> io::Result<Socket> {
+        #[cfg(feature = "quic")]
+        if let Self::Quic(addrs) = self {
+            return quic::connect(addrs, timeout).map(Socket::Quic);
+        }
+        let Self::Tcp(addrs) = self else {
+            // nothing else has to wait on the network
+            return self.connect();
+        };
+        let mut last_error = io::Error::new(io::ErrorKind::InvalidInput, "No addresses to connect to");
+        for addr in addrs {
+            match TcpStream::connect_timeout(addr, timeout) {
+                Ok(stream) => return Ok(Socket::Tcp(stream)),
+                Err(e) => last_error = e,
+            }
+        }
+        Err(last_error)
+    }
+}
+
+impl fmt::Display for Address {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::Tcp(addrs) => {
+                let addrs: Vec<_> = addrs.iter().map(SocketAddr::to_string).collect();
+                write!(f, "{}", addrs.join(", "))
+            },
+            Self::Unix(path) => write!(f, "{UNIX_PREFIX}{}", path.display()),
+            Self::Quic(addrs) => {
+                let addrs: Vec<_> = addrs.iter().map(SocketAddr::to_string).collect();
+                write!(f, "{QUIC_PREFIX}{}", addrs.join(", "))
+            },
+            Self::Local(_) => f.write_str("this process"),
+        }
+    }
+}
+
+/// A connection to a room over whichever kind of socket its `Address` is
+pub enum Socket {
+    Tcp(TcpStream),
+    #[cfg(unix)]
+    Unix(UnixStream),
+    #[cfg(windows)]
+    Pipe(PipeStream),
+    Local(LocalStream),
+    #[cfg(feature = "quic")]
+    Quic(QuicStream),
+}
+
+impl Socket {
+    /// The address of the other end. Unix sockets and named pipes don't have one, and neither do
+    /// in-process connections unless they were opened on someone's behalf.
+    pub fn peer_addr(&self) -> Option<SocketAddr> {
+        match self {
+            Self::Tcp(stream) => stream.peer_addr().ok(),
+            #[cfg(unix)]
+            Self::Unix(_) => None,
+            #[cfg(windows)]
+            Self::Pipe(_) => None,
+            Self::Local(stream) => stream.peer_addr(),
+            #[cfg(feature = "quic")]
+            Self::Quic(stream) => Some(stream.peer_addr()),
+        }
+    }
+}
+
+impl Read for Socket {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        match self {
+            Self::Tcp(stream) => stream.read(buf),
+            #[cfg(unix)]
+            Self::Unix(stream) => stream.read(buf),
+            #[cfg(windows)]
+            Self::Pipe(stream) => stream.read(buf),
+            Self::Local(stream) => stream.read(buf),
+            #[cfg(feature = "quic")]
+            Self::Quic(stream) => stream.read(buf),
+        }
+    }
+}
+
+impl Write for Socket {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        match self {
+            Self::Tcp(stream) => stream.write(buf),
+            #[cfg(unix)]
+            Self::Unix(stream) => stream.write(buf),
+            #[cfg(windows)]
+            Self::Pipe(stream) => stream.write(buf),
+            Self::Local(stream) => stream.write(buf),
+            #[cfg(feature = "quic")]
+            Self::Quic(stream) => stream.write(buf),
+        }
+    }
+
+    fn write_vectored(&mut self, bufs: &[IoSlice<'_>]) -> io::Result<usize> {
+        match self {
+            Self::Tcp(stream) => stream.write_vectored(bufs),
+            #[cfg(unix)]
+            Self::Unix(stream) => stream.write_vectored(bufs),
+            #[cfg(windows)]
+            Self::Pipe(stream) => stream.write_vectored(bufs),
+            Self::Local(stream) => stream.write_vectored(bufs),
+            #[cfg(feature = "quic")]
+            Self::Quic(stream) => stream.write_vectored(bufs),
+        }
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        match self {
+            Self::Tcp(stream) => stream.flush(),
+            #[cfg(unix)]
+            Self::Unix(stream) => stream.flush(),
+            #[cfg(windows)]
+            Self::Pipe(stream) => stream.flush(),
+            Self::Local(stream) => stream.flush(),
+            #[cfg(feature = "quic")]
+            Self::Quic(stream) => stream.flush(),
+        }
+    }
+}
+
+impl Transport for Socket {
+    fn set_nonblocking(&self, nonblocking: bool) -> io::Result<()> {
+        match self {
+            Self::Tcp(stream) => stream.set_nonblocking(nonblocking),
+            #[cfg(unix)]
+            Self::Unix(stream) => stream.set_nonblocking(nonblocking),
+            #[cfg(windows)]
+            Self::Pipe(stream) => stream.set_nonblocking(nonblocking),
+            Self::Local(stream) => stream.set_nonblocking(nonblocking),
+            #[cfg(feature = "quic")]
+            Self::Quic(stream) => stream.set_nonblocking(nonblocking),
+        }
+    }
+
+    fn set_read_timeout(&self, timeout: Option<Duration>) -> io::Result<()> {
+        match self {
+            Self::Tcp(stream) => stream.set_read_timeout(timeout),
+            #[cfg(unix)]
+            Self::Unix(stream) => stream.set_read_timeout(timeout),
+            #[cfg(windows)]
+            Self::Pipe(stream) => stream.set_read_timeout(timeout),
+            Self::Local(stream) => stream.set_read_timeout(timeout),
+            #[cfg(feature = "quic")]
+            Self::Quic(stream) => stream.set_read_timeout(timeout),
+        }
+    }
+
+    fn try_clone(&self) -> io::Result<Self> {
+        match self {
+            Self::Tcp(stream) => stream.try_clone().map(Self::Tcp),
+            #[cfg(unix)]
+            Self::Unix(stream) => stream.try_clone().map(Self::Unix),
+            #[cfg(windows)]
+            Self::Pipe(stream) => stream.try_clone().map(Self::Pipe),
+            Self::Local(stream) => stream.try_clone().map(Self::Local),
+            #[cfg(feature = "quic")]
+            Self::Quic(stream) => stream.try_clone().map(Self::Quic),
+        }
+    }
+}
+
+/// Accepts connections to a room on any kind of socket
+pub enum Listener {
+    Tcp(TcpListener),
+    #[cfg(unix)]
+    Unix(UnixListener),
+    #[cfg(windows)]
+    Pipe(PipeListener),
+    #[cfg(feature = "quic")]
+    Quic(QuicListener),
+}
+
+impl Listener {
+    /// Listen for TCP connections on `addr`
+    ///
+    /// # Errors
+    /// Fails if `addr` can't be bound, usually because something else is using the port.
+    pub fn bind_tcp(addr: impl ToSocketAddrs) -> io::Result<Self> {
+        TcpListener::bind(addr).map(Self::Tcp)
+    }
+
+    /// Listen on the Unix socket at `path`. A socket left behind there by a room that's no longer
+    /// running is replaced, but one that's still answering isn't. On Windows, listen on the named
+    /// pipe `path` names instead, which goes away with the room that made it.
+    ///
+    /// # Errors
+    /// Fails if the path can't be bound, or with `io::ErrorKind::Unsupported` on a platform
+    /// without Unix sockets or named pipes.
+    #[cfg(unix)]
+    pub fn bind_unix(path: &std::path::Path) -> io::Result<Self> {
+        match UnixListener::bind(path) {
+            Err(e) if e.kind() == io::ErrorKind::AddrInUse && UnixStream::connect(path).is_err() => {
+                std::fs::remove_file(path)?;
+                UnixListener::bind(path).map(Self::Unix)
+            },
+            bound => bound.map(Self::Unix),
+        }
+    }
+
+    #[cfg(windows)]
+    pub fn bind_unix(path: &std::path::Path) -> io::Result<Self> {
+        PipeListener::bind(path).map(Self::Pipe)
+    }
+
+    #[cfg(not(any(unix, windows)))]
+    pub fn bind_unix(_path: &std::path::Path) -> io::Result<Self> {
+        Err(unix_unsupported())
+    }
+
+    /// Listen for QUIC connections on `addr`
+    ///
+    /// # Errors
+    /// Fails if `addr` can't be bound, or with `io::ErrorKind::Unsupported` without the `quic`
+    /// feature.
+    #[cfg(feature = "quic")]
+    pub fn bind_quic(addr: SocketAddr) -> io::Result<Self> {
+        QuicListener::bind(addr).map(Self::Quic)
+    }
+
+    #[cfg(not(feature = "quic"))]
+    pub fn bind_quic(_addr: SocketAddr) -> io::Result<Self> {
+        Err(quic_unsupported())
+    }
+
+    /// Wait for the next connection
+    ///
+    /// # Errors
+    /// Fails if accepting the connection does.
+    pub fn accept(&self) -> io::Result<Socket> {
+        match self {
+            Self::Tcp(listener) => listener.accept().map(|(stream, _)| Socket::Tcp(stream)),
+            #[cfg(unix)]
+            Self::Unix(listener) => listener.accept().map(|(stream, _)| Socket::Unix(stream)),
+            #[cfg(windows)]
+            Self::Pipe(listener) => listener.accept().map(Socket::Pipe),
+            #[cfg(feature = "quic")]
+            Self::Quic(listener) => listener.accept().map(Socket::Quic),
+        }
+    }
+}
+
+#[cfg(not(any(unix, windows)))]
+fn unix_unsupported() -> io::Error {
+    io::Error::new(io::ErrorKind::Unsupported, "Unix sockets and named pipes aren't supported on this platform")
+}
+
+#[cfg(not(feature = "quic"))]
+fn quic_unsupported() -> io::Error {
+    io::Error::new(io::ErrorKind::Unsupported, "QUIC isn't supported by this build, it needs the `quic` feature")
+}
@@ -0,0 +1,317 @@
+// A `TcpConn` that only sends one type and only receives another, so asking for the wrong type is
+// a compile error instead of `InvalidData` at runtime. Everything else about the connection is
+// still reachable through `get_ref` and `get_mut`.
+
+use std::io;
+use std::marker::PhantomData;
+use std::sync::Arc;
+use std::net::TcpStream;
+use std::time::Duration;
+
+use serde::Serialize;
+use serde::de::DeserializeOwned;
+
+use crate::codec::{Codec, Json};
+use crate::packet::{ClientMessage, ServerMessage};
+use crate::tcp_conn::{ConnReader, RecvError, SendError, SharedWriter, TcpConn};
+use crate::transport::Transport;
+
+
+
+/// The end of a connection a client holds: it sends `ClientMessage`s and receives `ServerMessage`s
+pub type ClientConn<C = Json, S = TcpStream> = TypedConn<ClientMessage, ServerMessage, C, S>;
+
+/// The half of a `ClientConn` that sends, see `TypedConn::split`
+pub type ClientWriter<C = Json, S = TcpStream> = TypedWriter<ClientMessage, C, S>;
+
+/// The half of a `ClientConn` that receives
+pub type ClientReader<C = Json, S = TcpStream> = TypedReader<ServerMessage, C, S>;
+
+/// The end of a connection the server holds for each client
+pub type ServerConn<C = Json, S = TcpStream> = TypedConn<ServerMessage, ClientMessage, C, S>;
+
+/// Wraps a `TcpConn` so it can only send `Tx` and receive `Rx`. Raw bytes (see
+/// `TcpConn::send_raw`) aren't typed, so they can still be sent and received alongside.
+pub struct TypedConn<Tx, Rx, C = Json, S = TcpStream> {
+    conn: TcpConn<C, S>,
+
+    // neither is ever held, so they don't affect whether the connection can be sent or shared
+    types: PhantomData<fn(Tx) -> Rx>,
+}
+
+impl<Tx, Rx, C, S> TypedConn<Tx, Rx, C, S>
+where Tx: Serialize, Rx: DeserializeOwned, C: Codec, S: Transport {
+    /// Wrap `conn`, which should be set up (handshakes, codec) beforehand since that usually takes
+    /// exchanging messages of other types
+    pub fn new(conn: TcpConn<C, S>) -> Self {
+        Self { conn, types: PhantomData }
+    }
+
+    /// The connection underneath
+    pub fn get_ref(&self) -> &TcpConn<C, S> {
+        &self.conn
+    }
+
+    /// The connection underneath, for anything this doesn't wrap. Sending or receiving other
+    /// types through it defeats the point.
+    pub fn get_mut(&mut self) -> &mut TcpConn<C, S> {
+        &mut self.conn
+    }
+
+    /// Unwrap the connection underneath
+    pub fn into_inner(self) -> TcpConn<C, S> {
+        self.conn
+    }
+
+    pub fn codec(&self) -> &C {
+        self.conn.codec()
+    }
+
+    /// See `TcpConn::set_nonblocking`
+    pub fn set_nonblocking(&mut self, nonblocking: bool) -> io::Result<()> {
+        self.conn.set_nonblocking(nonblocking)
+    }
+
+    /// See `TcpConn::split`. The halves are typed the same way.
+    ///
+    /// # Errors
+    /// Fails if the socket can't be cloned.
+    #[allow(clippy::type_complexity)]
+    pub fn split(self) -> io::Result<(TypedReader<Rx, C, S>, TypedWriter<Tx, C, S>)>
+    where C: Clone {
+        let (reader, writer) = self.conn.split()?;
+        Ok((
+            TypedReader { conn: reader, types: PhantomData },
+            TypedWriter { conn: writer.into_shared(), types: PhantomData },
+        ))
+    }
+
+    /// See `TcpConn::send`
+    ///
+    /// # Errors
+    /// The same as `TcpConn::send`.
+    pub fn send(&mut self, data: &Tx) -> Result<(), SendError> {
+        self.conn.send(data)
+    }
+
+    /// See `TcpConn::queue`
+    ///
+    /// # Errors
+    /// The same as `TcpConn::queue`.
+    pub fn queue(&mut self, data: &Tx) -> Result<(), SendError> {
+        self.conn.queue(data)
+    }
+
+    /// Same as `queue`, for a `Tx` that was already framed with `encode_frame_with` in this
+    /// connection's codec
+    pub fn queue_frame(&mut self, frame: &[u8]) {
+        self.conn.queue_frame(frame)
+    }
+
+    /// Same as `queue_frame`, on `channel` instead of the main one
+    pub fn queue_frame_on(&mut self, channel: u8, frame: &[u8]) {
+        self.conn.queue_frame_on(channel, frame)
+    }
+
+    /// Same as `queue_frame`, for a `Tx` frame queued on many connections at once, see
+    /// `TcpConn::queue_shared`
+    pub fn queue_shared(&mut self, frame: &Arc<[u8]>) {
+        self.conn.queue_shared(frame)
+    }
+
+    /// Same as `queue_shared`, on `channel` instead of the main one
+    pub fn queue_shared_on(&mut self, channel: u8, frame: &Arc<[u8]>) {
+        self.conn.queue_shared_on(channel, frame)
+    }
+
+    /// See `TcpConn::queue_raw_on`
+    ///
+    /// # Errors
+    /// The same as `TcpConn::queue_raw_on`.
+    pub fn queue_raw_on(&mut self, channel: u8, bytes: &[u8]) -> Result<(), SendError> {
+        self.conn.queue_raw_on(channel, bytes)
+    }
+
+    /// See `TcpConn::flush_queued`
+    ///
+    /// # Errors
+    /// The same as `TcpConn::flush_queued`.
+    pub fn flush_queued(&mut self) -> Result<(), SendError> {
+        self.conn.flush_queued()
+    }
+
+    /// See `TcpConn::receive`
+    ///
+    /// # Errors
+    /// The same as `TcpConn::receive`, except that the other end sending something other than `Rx`
+    /// is the only way left to get `RecvError::Invalid` from deserializing.
+    pub fn receive(&mut self) -> Result<Rx, RecvError> {
+        self.conn.receive()
+    }
+
+    /// See `TcpConn::receive_timeout`
+    ///
+    /// # Errors
+    /// The same as `TcpConn::receive_timeout`.
+    pub fn receive_timeout(&mut self, timeout: Duration) -> Result<Rx, RecvError> {
+        self.conn.receive_timeout(timeout)
+    }
+
+    /// See `TcpConn::try_receive`
+    ///
+    /// # Errors
+    /// The same as `TcpConn::try_receive`.
+    pub fn try_receive(&mut self) -> Result<Option<Rx>, RecvError> {
+        self.conn.try_receive()
+    }
+
+    /// See `TcpConn::receive_streaming`
+    ///
+    /// # Errors
+    /// The same as `TcpConn::receive_streaming`.
+    pub fn receive_streaming(&mut self) -> Result<Rx, RecvError>
+    where C: Clone {
+        self.conn.receive_streaming()
+    }
+
+    /// See `TcpConn::receive_streaming_timeout`
+    ///
+    /// # Errors
+    /// The same as `TcpConn::receive_streaming_timeout`.
+    pub fn receive_streaming_timeout(&mut self, timeout: Duration) -> Result<Rx, RecvError>
+    where C: Clone {
+        self.conn.receive_streaming_timeout(timeout)
+    }
+
+    /// See `TcpConn::receive_raw_on`
+    ///
+    /// # Errors
+    /// The same as `TcpConn::receive_raw_on`.
+    pub fn receive_raw_on(&mut self, channel: u8) -> Result<Vec<u8>, RecvError> {
+        self.conn.receive_raw_on(channel)
+    }
+
+    /// See `TcpConn::try_receive_raw_on`
+    ///
+    /// # Errors
+    /// The same as `TcpConn::try_receive_raw_on`.
+    pub fn try_receive_raw_on(&mut self, channel: u8) -> Result<Option<Vec<u8>>, RecvError> {
+        self.conn.try_receive_raw_on(channel)
+    }
+
+    /// See `TcpConn::discard_frame_on`
+    pub fn discard_frame_on(&mut self, channel: u8) {
+        self.conn.discard_frame_on(channel)
+    }
+}
+
+/// The receiving half of a `TypedConn`, which can only receive `Rx`
+pub struct TypedReader<Rx, C = Json, S = TcpStream> {
+    conn: ConnReader<C, S>,
+    types: PhantomData<fn() -> Rx>,
+}
+
+impl<Rx, C, S> TypedReader<Rx, C, S>
+where Rx: DeserializeOwned, C: Codec, S: Transport {
+    /// The half underneath, for anything this doesn't wrap
+    pub fn get_mut(&mut self) -> &mut ConnReader<C, S> {
+        &mut self.conn
+    }
+
+    /// See `TcpConn::receive`
+    ///
+    /// # Errors
+    /// The same as `TcpConn::receive`.
+    pub fn receive(&mut self) -> Result<Rx, RecvError> {
+        self.conn.receive()
+    }
+
+    /// See `TcpConn::receive_timeout`
+    ///
+    /// # Errors
+    /// The same as `TcpConn::receive_timeout`.
+    pub fn receive_timeout(&mut self, timeout: Duration) -> Result<Rx, RecvError> {
+        self.conn.receive_timeout(timeout)
+    }
+
+    /// See `TcpConn::try_receive`
+    ///
+    /// # Errors
+    /// The same as `TcpConn::try_receive`.
+    pub fn try_receive(&mut self) -> Result<Option<Rx>, RecvError> {
+        self.conn.try_receive()
+    }
+
+    /// See `TcpConn::receive_streaming`
+    ///
+    /// # Errors
+    /// The same as `TcpConn::receive_streaming`.
+    pub fn receive_streaming(&mut self) -> Result<Rx, RecvError>
+    where C: Clone {
+        self.conn.receive_streaming()
+    }
+
+    /// See `TcpConn::receive_streaming_timeout`
+    ///
+    /// # Errors
+    /// The same as `TcpConn::receive_streaming_timeout`.
+    pub fn receive_streaming_timeout(&mut self, timeout: Duration) -> Result<Rx, RecvError>
+    where C: Clone {
+        self.conn.receive_streaming_timeout(timeout)
+    }
+
+    /// See `TcpConn::receive_raw_on`
+    ///
+    /// # Errors
+    /// The same as `TcpConn::receive_raw_on`.
+    pub fn receive_raw_on(&mut self, channel: u8) -> Result<Vec<u8>, RecvError> {
+        self.conn.receive_raw_on(channel)
+    }
+
+}
+
+/// The sending half of a `TypedConn`, which can only send `Tx`. Like `SharedWriter`, which it
+/// wraps, clones are handles to the same connection that any thread can send on.
+pub struct TypedWriter<Tx, C = Json, S = TcpStream> {
+    conn: SharedWriter<C, S>,
+    types: PhantomData<fn(Tx)>,
+}
+
+impl<Tx, C, S> Clone for TypedWriter<Tx, C, S> {
+    fn clone(&self) -> Self {
+        Self { conn: self.conn.clone(), types: PhantomData }
+    }
+}
+
+impl<Tx, C, S> TypedWriter<Tx, C, S>
+where Tx: Serialize, C: Codec, S: Transport {
+    /// The shared half underneath, for anything this doesn't wrap
+    pub fn get_ref(&self) -> &SharedWriter<C, S> {
+        &self.conn
+    }
+
+    /// See `SharedWriter::replace`
+    pub fn replace(&self, new: &Self) {
+        self.conn.replace(&new.conn)
+    }
+
+    /// See `TcpConn::send`
+    ///
+    /// # Errors
+    /// The same as `TcpConn::send`.
+    pub fn send(&self, data: &Tx) -> Result<(), SendError> {
+        self.conn.send(data)
+    }
+
+    /// Send `data` with `bytes` right behind it on `channel`, without another thread's messages
+    /// in between. See `TcpConn::send_raw`.
+    ///
+    /// # Errors
+    /// The same as `TcpConn::send` and `TcpConn::send_raw`.
+    pub fn send_with_raw(&self, data: &Tx, channel: u8, bytes: &[u8]) -> Result<(), SendError> {
+        let mut conn = self.conn.lock();
+        conn.queue(data)?;
+        conn.queue_raw_on(channel, bytes)?;
+        conn.flush_queued()
+    }
+}
@@ -0,0 +1,468 @@
+// A soak test for the server: hosts a room in-process and has randomized clients join, leave, drop
+// off, chat, rename, and get kicked for as long as it's told to run, mixed in with connections that
+// send garbage. Every so often the roster the server reports is checked against who the soak
+// thinks is in the room, and on Linux the process's memory and thread count are checked for
+// leaks. Any mismatch stops the run with the seed, so it can be repeated.
+//
+//     cargo run --release -p tcp_chat_server --bin tcp_chat_soak -- --duration-secs 7200
+//
+// The room is hosted on the usual port, so nothing else can be hosting on this machine meanwhile.
+
+use std::collections::{HashMap, HashSet};
+use std::fs;
+use std::io::Write;
+use std::net::TcpStream;
+use std::process::exit;
+use std::thread::sleep;
+use std::time::{Duration, Instant, SystemTime, UNIX_EPOCH};
+
+use tcp_chat_proto::capability::Capabilities;
+use tcp_chat_proto::constants::{FRAME_MAGIC, FRAME_VERSION, HEARTBEAT_INTERVAL, LOOPBACK_SOCKET, MAX_FIELD_LEN};
+use tcp_chat_proto::packet::{ClientMessage::*, ServerMessage::{self, *}};
+use tcp_chat_proto::tcp_conn::{encode_frame, frame_payload, TcpConn};
+use tcp_chat_server::{server, ServerConfig};
+
+
+
+/// How long to give the server to act on what it was sent before checking on it. A little over
+/// its slowest poll.
+const SETTLE: Duration = Duration::from_millis(500);
+
+/// How long to wait on the server for a reply before calling it stuck
+const REPLY_TIMEOUT: Duration = Duration::from_secs(10);
+
+/// The most clients in the room at once, besides the host
+const MAX_CLIENTS: usize = 30;
+
+/// Steps between checks of the roster and memory
+const CHECK_EVERY: u64 = 500;
+
+/// Checks to run before taking the memory baseline, so the server's history and connection log
+/// have had time to fill up
+const WARMUP_CHECKS: u64 = 5;
+
+/// How much the process may grow past its baseline before it's considered a leak
+const MAX_GROWTH_KB: u64 = 64 * 1024;
+
+/// How long the server makes people wait between renames, plus a little
+const RENAME_COOLDOWN: Duration = Duration::from_secs(31);
+
+
+/// xorshift64*, which is plenty random for picking what to do next and repeatable from a seed
+struct Rng(u64);
+
+impl Rng {
+    fn next(&mut self) -> u64 {
+        self.0 ^= self.0 >> 12;
+        self.0 ^= self.0 << 25;
+        self.0 ^= self.0 >> 27;
+        self.0.wrapping_mul(0x2545_f491_4f6c_dd1d)
+    }
+
+    /// A number in `0..n`
+    fn below(&mut self, n: usize) -> usize {
+        (self.next() % n as u64) as usize
+    }
+}
+
+/// A client the soak has in the room
+struct SoakClient {
+    conn: TcpConn,
+    name: String,
+    /// Learned from the roster, since the server doesn't tell clients their own id
+    id: Option<u64>,
+    last_rename: Option<Instant>,
+}
+
+struct Soak {
+    rng: Rng,
+    seed: u64,
+    step: u64,
+    host: TcpConn,
+    clients: Vec<SoakClient>,
+    /// For unique names and texts, since repeats are refused
+    counter: u64,
+    /// The process's memory and threads once warmed up
+    baseline: Option<(u64, u64)>,
+    checks: u64,
+    /// When everyone last sent a heartbeat, since the server drops whoever goes quiet
+    last_ping: Instant,
+}
+
+impl Soak {
+    /// Stop the run, saying what went wrong and how to repeat it
+    fn fail(&self, what: &str) -> ! {
+        eprintln!("[soak] FAILED at step {}: {what}", self.step);
+        eprintln!("[soak] Repeat with --seed {}", self.seed);
+        exit(1);
+    }
+
+    fn unique(&mut self) -> u64 {
+        self.counter += 1;
+        self.counter
+    }
+
+    fn act(&mut self) {
+        match self.rng.below(100) {
+            0..=24 if self.clients.len() < MAX_CLIENTS => self.join(),
+            0..=44 => self.chat(),
+            45..=54 => self.leave(),
+            55..=62 => self.drop_off(),
+            63..=70 => self.kick(),
+            71..=78 => self.rename(),
+            79..=84 => self.presence(),
+            _ => self.garbage(),
+        }
+    }
+
+    fn join(&mut self) {
+        let name = format!("soak{}", self.unique());
+        let conn = connect(&name).unwrap_or_else(|e| self.fail(&format!("{name} couldn't join: {e}")));
+        self.clients.push(SoakClient { conn, name, id: None, last_rename: None });
+    }
+
+    fn chat(&mut self) {
+        if self.clients.is_empty() {
+            return;
+        }
+        let id = self.unique();
+        let text = format!("message {id} {}", "x".repeat(self.rng.below(500)));
+        let index = self.rng.below(self.clients.len());
+        let client = &mut self.clients[index];
+        if let Err(e) = client.conn.send(&ClientTrackedText(id, text)) {
+            let name = client.name.clone();
+            self.fail(&format!("{name} couldn't send a message: {e}"));
+        }
+    }
+
+    fn leave(&mut self) {
+        if self.clients.is_empty() {
+            return;
+        }
+        let index = self.rng.below(self.clients.len());
+        let mut client = self.clients.swap_remove(index);
+        // it may already be gone from the server's side, which is what the roster check is for
+        let _ = client.conn.send(&ClientGoodbye);
+    }
+
+    /// Disappear without saying goodbye, like a crash or a lost connection
+    fn drop_off(&mut self) {
+        if self.clients.is_empty() {
+            return;
+        }
+        let index = self.rng.below(self.clients.len());
+        self.clients.swap_remove(index);
+    }
+
+    fn kick(&mut self) {
+        let known: Vec<usize> = (0..self.clients.len()).filter(|&i| self.clients[i].id.is_some()).collect();
+        if known.is_empty() {
+            return;
+        }
+        let index = known[self.rng.below(known.len())];
+        let client = self.clients.swap_remove(index);
+        if let Err(e) = self.host.send(&ClientKick(client.id.unwrap())) {
+            self.fail(&format!("The host couldn't kick {}: {e}", client.name));
+        }
+    }
+
+    fn rename(&mut self) {
+        let ready: Vec<usize> = (0..self.clients.len())
+            .filter(|&i| self.clients[i].last_rename.is_none_or(|at| at.elapsed() > RENAME_COOLDOWN))
+            .collect();
+        if ready.is_empty() {
+            return;
+        }
+        let index = ready[self.rng.below(ready.len())];
+        let name = format!("soak{}", self.unique());
+        let client = &mut self.clients[index];
+        if let Err(e) = client.conn.send(&ClientRename(name.clone())) {
+            let old = client.name.clone();
+            self.fail(&format!("{old} couldn't rename: {e}"));
+        }
+        client.name = name;
+        client.last_rename = Some(Instant::now());
+    }
+
+    fn presence(&mut self) {
+        if self.clients.is_empty() {
+            return;
+        }
+        let index = self.rng.below(self.clients.len());
+        let status = format!("status {}", self.unique());
+        let _ = self.clients[index].conn.send(&ClientSetStatus(Some(status)));
+    }
+
+    /// Connect without joining properly, sending something the server has to turn away. The
+    /// connection is closed right after so the server isn't left waiting on the rest of a message.
+    fn garbage(&mut self) {
+        let Ok(mut stream) = TcpStream::connect(LOOPBACK_SOCKET) else {
+            self.fail("Couldn't connect to send garbage");
+        };
+
+        let mut frame = Vec::new();
+        let bytes = match self.rng.below(7) {
+            // random bytes
+            0 => (0..self.rng.below(64) + 1).map(|_| self.rng.next() as u8).collect(),
+            // a valid frame with its payload mangled
+            1 => {
+                encode_frame(&ClientHello(String::from("mangled")), &mut frame).unwrap();
+                let last = frame.len() - 1;
+                frame[last] = b'{';
+                frame
+            },
+            // a header claiming an enormous message
+            2 => {
+                frame_payload(&[], &mut frame).unwrap();
+                let len = frame.len();
+                frame[len - 4..].fill(0xff);
+                frame
+            },
+            // nested far too deep
+            3 => {
+                let nested = format!("{}{}", "[".repeat(200), "]".repeat(200));
+                frame_payload(nested.as_bytes(), &mut frame).unwrap();
+                frame
+            },
+            // a name past the field limit
+            4 => {
+                encode_frame(&ClientHello("n".repeat(MAX_FIELD_LEN + 1)), &mut frame).unwrap();
+                frame
+            },
+            // a frame from a different version of the framing
+            5 => {
+                encode_frame(&ClientHello(String::from("future")), &mut frame).unwrap();
+                frame[FRAME_MAGIC.len()] = FRAME_VERSION.wrapping_add(1);
+                frame
+            },
+            // half of a valid frame
+            _ => {
+                encode_frame(&ClientHello(String::from("halfway")), &mut frame).unwrap();
+                frame.truncate(frame.len() / 2);
+                frame
+            },
+        };
+
+        let _ = stream.write_all(&bytes);
+    }
+
+    /// Have everyone send a heartbeat if it's time, like the client does
+    fn heartbeat(&mut self) {
+        if self.last_ping.elapsed() < HEARTBEAT_INTERVAL {
+            return;
+        }
+        self.last_ping = Instant::now();
+
+        if let Err(e) = self.host.send(&ClientPing) {
+            self.fail(&format!("The host couldn't send a heartbeat: {e}"));
+        }
+        for index in 0..self.clients.len() {
+            if let Err(e) = self.clients[index].conn.send(&ClientPing) {
+                let name = self.clients[index].name.clone();
+                self.fail(&format!("{name} couldn't send a heartbeat: {e}"));
+            }
+        }
+    }
+
+    /// Read whatever everyone has been sent, so nobody's buffers fill up. Someone being kicked or
+    /// dropped that the soak didn't ask for is a failure.
+    fn drain(&mut self) {
+        loop {
+            match self.host.try_receive::<ServerMessage>() {
+                Ok(Some(_)) => {},
+                Ok(None) => break,
+                Err(e) => self.fail(&format!("The host lost its connection: {e}")),
+            }
+        }
+
+        for index in 0..self.clients.len() {
+            loop {
+                match self.clients[index].conn.try_receive::<ServerMessage>() {
+                    Ok(Some(ServerNotifyKick)) => {
+                        let name = self.clients[index].name.clone();
+                        self.fail(&format!("{name} was kicked without the host asking"));
+                    },
+                    Ok(Some(ServerRejected(reason))) => {
+                        let name = self.clients[index].name.clone();
+                        self.fail(&format!("{name} had a message rejected: {reason}"));
+                    },
+                    Ok(Some(_)) => {},
+                    Ok(None) => break,
+                    Err(e) => {
+                        let name = self.clients[index].name.clone();
+                        self.fail(&format!("{name} lost its connection: {e}"));
+                    },
+                }
+            }
+        }
+    }
+
+    /// Compare the roster the server reports with who should be in the room, and the process's
+    /// memory and threads with how they were after warming up
+    fn check(&mut self) {
+        sleep(SETTLE);
+        self.drain();
+
+        if let Err(e) = self.host.send(&ClientRequestIDs) {
+            self.fail(&format!("The host couldn't ask for the roster: {e}"));
+        }
+        let roster = self.wait_for_roster();
+
+        let ids: HashSet<u64> = roster.values().copied().collect();
+        if ids.len() != roster.len() {
+            self.fail(&format!("The roster has repeated ids: {roster:?}"));
+        }
+
+        let expected: HashSet<&str> = self.clients.iter().map(|client| client.name.as_str())
+            .chain(["host"])
+            .collect();
+        let actual: HashSet<&str> = roster.keys().map(String::as_str).collect();
+        if expected != actual {
+            let missing: Vec<_> = expected.difference(&actual).collect();
+            let extra: Vec<_> = actual.difference(&expected).collect();
+            self.fail(&format!("The roster is off. Missing: {missing:?}, shouldn't be there: {extra:?}"));
+        }
+
+        for client in &mut self.clients {
+            client.id = roster.get(&client.name).copied();
+        }
+
+        self.checks += 1;
+        let Some((memory, threads)) = process_stats() else {
+            return;
+        };
+        match self.baseline {
+            None if self.checks >= WARMUP_CHECKS => {
+                println!("[soak] Baseline after warming up: {memory} kB, {threads} threads");
+                self.baseline = Some((memory, threads));
+            },
+            None => {},
+            Some((base_memory, base_threads)) => {
+                if memory > base_memory + MAX_GROWTH_KB {
+                    self.fail(&format!("Memory grew from {base_memory} kB to {memory} kB"));
+                }
+                if threads > base_threads {
+                    self.fail(&format!("Threads went from {base_threads} to {threads}"));
+                }
+            },
+        }
+    }
+
+    /// The host's next roster, by name, skipping anything else it was sent meanwhile
+    fn wait_for_roster(&mut self) -> HashMap<String, u64> {
+        let started = Instant::now();
+        while started.elapsed() < REPLY_TIMEOUT {
+            match self.host.try_receive::<ServerMessage>() {
+                Ok(Some(ServerResponseIDs(roster))) => {
+                    return roster.into_iter().map(|entry| (entry.name, entry.id)).collect();
+                },
+                Ok(Some(_)) => {},
+                Ok(None) => sleep(Duration::from_millis(10)),
+                Err(e) => self.fail(&format!("The host lost its connection: {e}")),
+            }
+        }
+        self.fail("The server never sent the roster");
+    }
+}
+
+
+fn main() {
+    let duration = Duration::from_secs(flag_value("--duration-secs").unwrap_or(3600));
+    let seed = flag_value("--seed").unwrap_or_else(|| {
+        SystemTime::now().duration_since(UNIX_EPOCH).map(|d| d.as_nanos() as u64).unwrap_or(1)
+    }).max(1);
+    println!("[soak] Running for {}s with --seed {seed}", duration.as_secs());
+
+    server(ServerConfig { show_qr: false, ..ServerConfig::default() });
+
+    // the first to connect is the host, and stays for the whole run to kick people and check
+    let host = connect("host").unwrap_or_else(|e| {
+        eprintln!("[soak] The host couldn't join: {e}");
+        exit(1);
+    });
+
+    let mut soak = Soak {
+        rng: Rng(seed),
+        seed,
+        step: 0,
+        host,
+        clients: Vec::new(),
+        counter: 0,
+        baseline: None,
+        checks: 0,
+        last_ping: Instant::now(),
+    };
+
+    let started = Instant::now();
+    let mut last_report = Instant::now();
+    while started.elapsed() < duration {
+        soak.step += 1;
+        soak.act();
+        soak.heartbeat();
+        soak.drain();
+
+        if soak.step.is_multiple_of(CHECK_EVERY) {
+            soak.check();
+        }
+        if last_report.elapsed() > Duration::from_secs(60) {
+            println!(
+                "[soak] {}m in, step {}, {} clients, {} checks passed",
+                started.elapsed().as_secs() / 60,
+                soak.step,
+                soak.clients.len(),
+                soak.checks
+            );
+            last_report = Instant::now();
+        }
+
+        sleep(Duration::from_millis(soak.rng.below(20) as u64));
+    }
+
+    soak.check();
+    println!("[soak] Passed: {} steps and {} checks", soak.step, soak.checks);
+}
+
+/// Join the room as `name` with every capability, waiting for the server to say so
+fn connect(name: &str) -> std::io::Result<TcpConn> {
+    let mut conn = TcpConn::new(TcpStream::connect(LOOPBACK_SOCKET)?)?;
+    conn.send(&ClientCapabilities(Capabilities::all().names()))?;
+    match conn.receive_timeout::<ServerMessage>(REPLY_TIMEOUT)? {
+        ServerHello(names) if Capabilities::from_names(&names) == Capabilities::all() => {},
+        other => return Err(std::io::Error::other(format!("Expected every capability to be agreed to, got {other:?}"))),
+    }
+
+    conn.send(&ClientHello(name.to_string()))?;
+
+    // the join notice comes after, and is drained along with everything else
+    match conn.receive_timeout::<ServerMessage>(REPLY_TIMEOUT)? {
+        ServerWelcome(_) => {},
+        other => return Err(std::io::Error::other(format!("Expected to be welcomed, got {other:?}"))),
+    }
+
+    Ok(conn)
+}
+
+/// The process's resident memory in kB and number of threads, where the OS says
+fn process_stats() -> Option<(u64, u64)> {
+    let status = fs::read_to_string("/proc/self/status").ok()?;
+    let field = |name: &str| {
+        status.lines()
+            .find_map(|line| line.strip_prefix(name))?
+            .split_whitespace()
+            .next()?
+            .parse()
+            .ok()
+    };
+    Some((field("VmRSS:")?, field("Threads:")?))
+}
+
+/// The number following `flag` on the command line
+fn flag_value(flag: &str) -> Option<u64> {
+    let mut args = std::env::args();
+    args.position(|arg| arg == flag)?;
+    let Some(value) = args.next().and_then(|value| value.parse().ok()) else {
+        eprintln!("Usage: tcp_chat_soak [--duration-secs <secs>] [--seed <seed>]");
+        exit(2);
+    };
+    Some(value)
+}
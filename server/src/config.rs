@@ -0,0 +1,194 @@
+use std::net::IpAddr;
+use std::path::PathBuf;
+
+use serde::{Deserialize, Deserializer};
+use tcp_chat_proto::constants::BIND_ADDR;
+use tcp_chat_proto::tcp_conn::TcpConnBuilder;
+use tcp_chat_proto::noise::Keypair;
+use tcp_chat_proto::packet::Limits;
+use tcp_chat_proto::secrets::SecretStore;
+
+use crate::constants::NOISE_KEY_SECRET;
+use crate::webhook::{OutgoingWebhook, IncomingWebhook};
+
+
+
+/// Settings only relevant when hosting the room
+#[derive(Deserialize, Debug, Clone)]
+#[serde(default)]
+pub struct ServerConfig {
+    /// Name of the room, reported to webhooks and anything else that wants to identify the room
+    pub room_name: String,
+
+    /// Print a QR code of the room's LAN address when the server starts
+    pub show_qr: bool,
+
+    /// Only let the host and the operator send urgent messages
+    pub urgent_moderators_only: bool,
+
+    /// The longest a message can be, in bytes. Longer messages are refused rather than sent to
+    /// everyone.
+    pub max_message_len: usize,
+
+    /// The biggest voice clip anyone can send, in bytes. Voice clips aren't allowed at all when
+    /// this is 0.
+    pub max_voice_len: usize,
+
+    /// The biggest file anyone can attach, in bytes. Attachments aren't allowed at all when this
+    /// is 0.
+    pub max_attachment_len: usize,
+
+    /// End-to-end encrypt the room's messages with a key derived from a passphrase everyone,
+    /// including the host, enters in their client. The server never learns the passphrase, so it
+    /// can only pass messages on without reading them.
+    pub encrypted: bool,
+
+    /// Tracker to list the room on, e.g. `http://tracker.example.com:42071`. The room is private
+    /// (not announced anywhere) when this is unset.
+    pub tracker_url: Option<String>,
+
+    /// Directory for state the server keeps across restarts, like when users were last seen.
+    /// Nothing is persisted when this is unset.
+    pub storage_dir: Option<PathBuf>,
+
+    /// Record everything the server receives to this file, replacing what was there, so the
+    /// session can be played back later with `--replay`. Nothing is recorded when this is unset.
+    pub record_file: Option<PathBuf>,
+
+    /// Tell the room whenever someone sets their status, which also means statuses set right
+    /// after joining show up under the join message
+    pub announce_status: bool,
+
+    /// URLs to notify whenever someone sends a message
+    pub webhooks: Vec<OutgoingWebhook>,
+
+    /// Port to listen on for incoming webhook requests. The endpoint is only opened if this is set
+    /// and at least one incoming webhook is configured.
+    pub webhook_port: Option<u16>,
+
+    /// Named endpoints external systems can post messages to
+    pub incoming_webhooks: Vec<IncomingWebhook>,
+
+    /// Port to accept browsers on over WebSocket, usually the one after the room's. Each text
+    /// message is a `ClientMessage` or `ServerMessage` as JSON. No gateway is opened when this is
+    /// unset.
+    pub websocket_port: Option<u16>,
+
+    /// Pages allowed to open the WebSocket gateway, by their origin, like `https://chat.example.com`.
+    /// Browsers send the origin of the page doing the connecting, so this stops any other site a
+    /// visitor has open from joining the room in their name. When empty, only pages served from
+    /// the same host the gateway was reached at are let in.
+    pub websocket_origins: Vec<String>,
+
+    /// Look up the reverse DNS names of the addresses clients connect from, to show alongside the
+    /// addresses in `!ids` and `!connections`
+    pub resolve_hostnames: bool,
+
+    /// Options for the sockets of clients that connect, like `nodelay` and `keepalive`
+    pub socket: TcpConnBuilder,
+
+    /// Clients that agreed to heartbeats but haven't sent anything for this many seconds, not even
+    /// a `ClientPing`, are taken to have gone and are dropped as if they'd said goodbye. Clients ping every
+    /// `HEARTBEAT_INTERVAL`, so this should be a few times that. Nobody is dropped for being quiet
+    /// when this is 0.
+    pub heartbeat_timeout_secs: u64,
+
+    /// Host on the Unix socket at this path instead of the usual port, so only this machine can
+    /// join (with the address `unix:<path>`). Rooms hosted this way aren't announced to the
+    /// tracker. On Windows this names a named pipe instead, `\\.\pipe\<path>` unless it's a
+    /// full pipe path already. Refused on platforms with neither.
+    #[serde(deserialize_with = "unix_socket_path")]
+    pub unix_socket: Option<PathBuf>,
+
+    /// Port to accept clients on over QUIC as well, usually the same number as the room's (QUIC
+    /// is UDP, so they don't clash). Clients join with the address `quic:<host>[:<port>]`. Not
+    /// opened when hosting on `unix_socket`. Only with the `quic` feature, a config file setting
+    /// it is refused without.
+    #[serde(deserialize_with = "quic_port")]
+    pub quic_port: Option<u16>,
+
+    /// The key the server makes Noise handshakes with, which clients use to recognize it. Kept in
+    /// the secret store rather than the config, and filled in when hosting. Without one, a new key
+    /// is made every time the server starts.
+    #[serde(skip)]
+    pub noise_key: Option<Keypair>,
+
+    /// The address the room's port and `quic_port` are opened on. Every interface unless something
+    /// starting the server itself (like a replay) says otherwise, which a config file can't.
+    #[serde(skip)]
+    pub bind_addr: IpAddr,
+}
+
+/// Read `unix_socket`, refusing it where neither Unix sockets nor named pipes can be hosted on, so
+/// it's caught when the config is loaded rather than once the server fails to start
+fn unix_socket_path<'de, D: Deserializer<'de>>(deserializer: D) -> Result<Option<PathBuf>, D::Error> {
+    let path = Option::<PathBuf>::deserialize(deserializer)?;
+    if cfg!(not(any(unix, windows))) && path.is_some() {
+        return Err(serde::de::Error::custom(
+            "`unix_socket` isn't supported on this platform, remove it to host on the usual port"
+        ));
+    }
+    Ok(path)
+}
+
+/// Read `quic_port`, refusing it in builds without QUIC, so it's caught when the config is loaded
+/// rather than once the server fails to start
+fn quic_port<'de, D: Deserializer<'de>>(deserializer: D) -> Result<Option<u16>, D::Error> {
+    let port = Option::<u16>::deserialize(deserializer)?;
+    if cfg!(not(feature = "quic")) && port.is_some() {
+        return Err(serde::de::Error::custom(
+            "`quic_port` isn't supported by this build, rebuild with `--features quic` or remove it"
+        ));
+    }
+    Ok(port)
+}
+
+impl Default for ServerConfig {
+    fn default() -> Self {
+        Self {
+            room_name: String::from("lobby"),
+            show_qr: true,
+            announce_status: true,
+            storage_dir: None,
+            record_file: None,
+            urgent_moderators_only: true,
+            max_message_len: 4 * 1024,
+            max_voice_len: 256 * 1024,
+            max_attachment_len: 4 * 1024 * 1024,
+            encrypted: false,
+            tracker_url: None,
+            webhooks: Vec::new(),
+            webhook_port: None,
+            incoming_webhooks: Vec::new(),
+            websocket_port: None,
+            websocket_origins: Vec::new(),
+            resolve_hostnames: false,
+            socket: TcpConnBuilder::default(),
+            heartbeat_timeout_secs: 15,
+            unix_socket: None,
+            quic_port: None,
+            noise_key: None,
+            bind_addr: BIND_ADDR,
+        }
+    }
+}
+
+impl ServerConfig {
+    /// What clients are told the room accepts when they join
+    pub fn limits(&self) -> Limits {
+        Limits {
+            max_message_len: self.max_message_len,
+            max_voice_len: self.max_voice_len,
+            max_attachment_len: self.max_attachment_len,
+        }
+    }
+
+    /// Fill in the Noise key from `secrets`, making one the first time so clients see the same key
+    /// every time the server is hosted
+    pub fn load_noise_key(&mut self, secrets: &SecretStore) {
+        match Keypair::load_or_create(secrets, NOISE_KEY_SECRET) {
+            Ok(keypair) => self.noise_key = Some(keypair),
+            Err(e) => println!("[server] Unable to load the Noise key, using a new one until the server stops: {e}"),
+        }
+    }
+}
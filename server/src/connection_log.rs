@@ -0,0 +1,54 @@
+use std::collections::VecDeque;
+use std::net::SocketAddr;
+
+use crate::constants::MAX_CONNECTION_LOG;
+use tcp_chat_proto::helpers::unix_time;
+use tcp_chat_proto::packet::{ConnectionChange, ConnectionEvent};
+
+
+
+/// The most recent connects, disconnects, and kicks, so the host can catch up on what happened
+/// while they were away. Only kept in memory, the oldest events are dropped once it's full.
+#[derive(Debug, Default)]
+pub struct ConnectionLog {
+    events: VecDeque<ConnectionEvent>,
+}
+
+impl ConnectionLog {
+    /// A log picking up where `events` left off, like after a restart
+    pub fn restore(events: Vec<ConnectionEvent>) -> Self {
+        let skip = events.len().saturating_sub(MAX_CONNECTION_LOG);
+        Self { events: events.into_iter().skip(skip).collect() }
+    }
+
+    /// Note that client `id` going by `name` just connected, disconnected, or was kicked
+    pub fn record(&mut self, change: ConnectionChange, id: u64, name: &str, address: SocketAddr) {
+        self.push(change, id, name, address, None);
+    }
+
+    /// Note that client `id` going by `name` was reported by `reporter` for `reason`
+    pub fn report(&mut self, id: u64, name: &str, address: SocketAddr, reporter: &str, reason: &str) {
+        let detail = format!("by {reporter}: {reason}");
+        self.push(ConnectionChange::Reported, id, name, address, Some(detail));
+    }
+
+    fn push(&mut self, change: ConnectionChange, id: u64, name: &str, address: SocketAddr, detail: Option<String>) {
+        self.events.push_back(ConnectionEvent {
+            time: unix_time(),
+            change,
+            id,
+            name: name.to_string(),
+            address,
+            hostname: None,
+            detail,
+        });
+        if self.events.len() > MAX_CONNECTION_LOG {
+            self.events.pop_front();
+        }
+    }
+
+    /// Every event still in the log, oldest first
+    pub fn events(&self) -> Vec<ConnectionEvent> {
+        self.events.iter().cloned().collect()
+    }
+}
@@ -0,0 +1,81 @@
+use std::time::Duration;
+
+
+
+/// How long the server waits between checking for client messages while they're arriving
+pub const MIN_POLL_DELAY: Duration = Duration::from_millis(5);
+
+/// How long the server waits between checking for client messages once the room goes quiet. The
+/// wait doubles every check that finds nothing until it gets here.
+pub const MAX_POLL_DELAY: Duration = Duration::from_millis(200);
+
+/// The longest status line the server will store. Longer statuses are cut off.
+pub const MAX_STATUS_LEN: usize = 64;
+
+/// What the server's Noise key is stored under in the `SecretStore`
+pub const NOISE_KEY_SECRET: &str = "noise/server";
+
+/// How long someone has to wait after reporting someone before they can report again
+pub const REPORT_COOLDOWN: Duration = Duration::from_secs(10);
+
+/// How many of the reported person's latest messages go along with a report
+pub const REPORT_CONTEXT: usize = 5;
+
+/// How long someone has to wait after sending a voice clip before they can send another
+pub const VOICE_COOLDOWN: Duration = Duration::from_secs(15);
+
+/// How many attachments a client can have announced without all their bytes having arrived yet.
+/// Announcing more than this is taken as an attempt to run the server out of memory.
+pub const MAX_PENDING_ATTACHMENTS: usize = 4;
+
+/// How long someone has to wait after changing their name before they can change it again
+pub const RENAME_COOLDOWN: Duration = Duration::from_secs(30);
+
+/// The longest an ephemeral message is allowed to stick around
+pub const MAX_EPHEMERAL_SECS: u64 = 24 * 60 * 60;
+
+/// Sending the same text again within this long of the last time is refused as a repeat
+pub const REPEAT_WINDOW: Duration = Duration::from_secs(10);
+
+/// Misbehaving clients get this many strikes before they're slowed down
+pub const FREE_STRIKES: u32 = 2;
+
+/// How long the first strike past the free ones stops a client from being read. Every strike
+/// after that doubles it.
+pub const TARPIT_DELAY: Duration = Duration::from_millis(500);
+
+/// Clients are dropped once they reach this many strikes
+pub const MAX_STRIKES: u32 = 8;
+
+/// Strikes are forgotten once a client goes this long without another one
+pub const STRIKE_EXPIRY: Duration = Duration::from_secs(5 * 60);
+
+/// How many broadcasts the server keeps so clients can recover ones they missed
+pub const MAX_HISTORY: usize = 500;
+
+/// How many connects, disconnects, and kicks the server remembers for `!connections`
+pub const MAX_CONNECTION_LOG: usize = 200;
+
+/// How long to wait on a reverse DNS lookup before showing the address without a name
+pub const HOSTNAME_TIMEOUT: Duration = Duration::from_secs(2);
+
+/// How long a reverse DNS name is trusted before it's looked up again
+pub const HOSTNAME_EXPIRY: Duration = Duration::from_secs(60 * 60);
+
+/// How often the room's state is snapshotted to the storage directory, when there is one
+pub const SNAPSHOT_INTERVAL: Duration = Duration::from_secs(30);
+
+/// How many clients can be making their handshake and saying hello at once before more are turned
+/// away
+pub const MAX_GREETING: usize = 64;
+
+/// How many incoming webhook requests can be read at once before more are refused
+pub const MAX_WEBHOOK_REQUESTS: usize = 16;
+
+/// How many browsers can be connected through the WebSocket gateway at once before more are
+/// refused
+pub const MAX_WEBSOCKET_CONNECTIONS: usize = 64;
+
+/// The biggest message a browser can send through the WebSocket gateway, which is enough for the
+/// largest attachment the server can be set to take
+pub const MAX_WEBSOCKET_MESSAGE: usize = 16 * 1024 * 1024;
@@ -0,0 +1,81 @@
+use std::collections::HashMap;
+use std::net::IpAddr;
+use std::sync::{Arc, Mutex};
+use std::thread;
+use std::time::Instant;
+
+use crate::constants::{HOSTNAME_EXPIRY, HOSTNAME_TIMEOUT};
+
+
+
+/// Reverse DNS names for the addresses clients connect from, so the host's views can show
+/// `laptop.local (192.168.1.23:51234)` instead of just the address. Names are looked up in the
+/// background and cached, and nothing ever waits on a lookup: until one finishes, the address is
+/// shown on its own.
+#[derive(Debug, Default, Clone)]
+pub struct Hostnames {
+    /// Lookups are only made when turned on in the config
+    enabled: bool,
+    cache: Arc<Mutex<HashMap<IpAddr, Lookup>>>,
+}
+
+#[derive(Debug)]
+enum Lookup {
+    /// Started at this time
+    Pending(Instant),
+    /// Finished at this time, with the name if there is one
+    Done(Option<String>, Instant),
+}
+
+impl Hostnames {
+    pub fn new(enabled: bool) -> Self {
+        Self { enabled, ..Self::default() }
+    }
+
+    /// Start looking up the name for `ip` unless it's already known or being looked up
+    pub fn resolve(&self, ip: IpAddr) {
+        if !self.enabled {
+            return;
+        }
+
+        let mut cache = self.cache.lock().unwrap();
+        let fresh = match cache.get(&ip) {
+            Some(Lookup::Pending(started)) => started.elapsed() < HOSTNAME_TIMEOUT,
+            Some(Lookup::Done(_, finished)) => finished.elapsed() < HOSTNAME_EXPIRY,
+            None => false,
+        };
+        if fresh {
+            return;
+        }
+        let started = Instant::now();
+        cache.insert(ip, Lookup::Pending(started));
+        drop(cache);
+
+        let cache = Arc::clone(&self.cache);
+        let spawned = thread::Builder::new()
+            .name(String::from("server hostname lookup"))
+            .spawn(move || {
+                // without a name, the address comes back as it was given
+                let name = dns_lookup::lookup_addr(&ip).ok().filter(|name| name != &ip.to_string());
+
+                // a lookup that took too long was already given up on, and may have been
+                // started over since
+                let mut cache = cache.lock().unwrap();
+                if matches!(cache.get(&ip), Some(Lookup::Pending(at)) if *at == started) {
+                    let name = name.filter(|_| started.elapsed() < HOSTNAME_TIMEOUT);
+                    cache.insert(ip, Lookup::Done(name, Instant::now()));
+                }
+            });
+        if spawned.is_err() {
+            println!("[server] Unable to look up the hostname of {ip}");
+        }
+    }
+
+    /// The name for `ip`, if it's been looked up and has one
+    pub fn get(&self, ip: IpAddr) -> Option<String> {
+        match self.cache.lock().unwrap().get(&ip)? {
+            Lookup::Done(name, _) => name.clone(),
+            Lookup::Pending(_) => None,
+        }
+    }
+}
@@ -0,0 +1,20 @@
+//! The tcp_chat server, which hosts a room, along with the tracker that lists public rooms
+
+pub mod tracker;
+pub mod replay;
+
+mod server;
+mod config;
+mod constants;
+mod webhook;
+mod websocket;
+mod qr;
+mod seen;
+mod names;
+mod connection_log;
+mod snapshot;
+mod hostnames;
+
+pub use server::{server, ServerHandle};
+pub use config::ServerConfig;
+pub use webhook::{OutgoingWebhook, IncomingWebhook};
@@ -0,0 +1,96 @@
+// Keeping names honest. Names are normalized so the same name typed two different ways is the same
+// name, and names that only look like someone else's (e.g., with a Cyrillic 'а' in place of a Latin
+// 'a') are refused so nobody can pass themselves off as someone else, or as the server.
+
+use unicode_normalization::UnicodeNormalization;
+use unicode_security::skeleton;
+
+
+
+/// The name server notices are sent under
+const SERVER_NAME: &str = "[server]";
+
+/// What a name looks like, ignoring case and lookalike characters. Names with the same skeleton
+/// are easily mistaken for each other.
+fn looks_like(name: &str) -> String {
+    skeleton(&name.to_lowercase()).collect()
+}
+
+/// `name` the way it's stored once accepted: NFC, without surrounding whitespace. Anything looking
+/// a name up should go through this first so it matches however the name was typed.
+pub fn normalize(name: &str) -> String {
+    name.trim().nfc().collect()
+}
+
+/// Check that `name` can be used alongside the names in `others`, returning it normalized (see
+/// `normalize`).
+/// 
+/// # Errors
+/// Returns why the name can't be used, to pass along to whoever asked for it.
+pub fn check_name<'a>(name: &str, others: impl IntoIterator<Item = &'a str>) -> Result<String, String> {
+    let name = normalize(name);
+
+    if name.is_empty() {
+        return Err(String::from("Names can't be empty"));
+    }
+
+    let skeleton = looks_like(&name);
+
+    if skeleton == looks_like(SERVER_NAME) {
+        return Err(format!("'{name}' looks too much like the server"));
+    }
+    if let Some(other) = others.into_iter().find(|other| looks_like(other) == skeleton) {
+        return Err(format!("'{name}' looks too much like '{other}', who is already here"));
+    }
+
+    Ok(name)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn names_are_stored_composed() {
+        // "José" with the accent as its own character, then combined with the 'e'
+        let decomposed = "Jose\u{301}";
+        let composed = "Jos\u{e9}";
+        assert_ne!(decomposed, composed);
+
+        assert_eq!(normalize(decomposed), composed);
+        assert_eq!(normalize(&format!("  {decomposed}\t")), composed);
+        assert_eq!(check_name(decomposed, []).unwrap(), composed);
+
+        // either way of typing it finds the other
+        assert!(check_name(decomposed, [composed]).is_err());
+        assert!(check_name(composed, [normalize(decomposed).as_str()]).is_err());
+    }
+
+    #[test]
+    fn lookalikes_are_refused() {
+        // a Cyrillic 'а' in place of the Latin 'a'
+        let lookalike = "\u{430}lice";
+        assert_ne!(lookalike, "alice");
+
+        assert!(check_name(lookalike, ["alice"]).is_err());
+        assert!(check_name("ALICE", ["alice"]).is_err());
+        assert_eq!(check_name(lookalike, ["bob"]).unwrap(), lookalike);
+        assert_eq!(check_name("alice", ["bob", "carol"]).unwrap(), "alice");
+    }
+
+    #[test]
+    fn nobody_can_pass_as_the_server() {
+        assert!(check_name(SERVER_NAME, []).is_err());
+        assert!(check_name("[SERVER]", []).is_err());
+        // Cyrillic 'е' and 'ѕ'
+        assert!(check_name("[s\u{435}rver]", []).is_err());
+        assert!(check_name("[\u{455}erver]", []).is_err());
+        assert_eq!(check_name("server", []).unwrap(), "server");
+    }
+
+    #[test]
+    fn empty_names_are_refused() {
+        assert!(check_name("", []).is_err());
+        assert!(check_name(" \t ", []).is_err());
+    }
+}
@@ -0,0 +1,33 @@
+use std::net::{IpAddr, SocketAddr, UdpSocket};
+
+use qrcode::QrCode;
+use qrcode::render::unicode::Dense1x2;
+
+
+
+/// Figure out which address other machines on the LAN can reach this one at. Connecting a UDP
+/// socket doesn't send anything, but it does make the OS pick the outgoing interface, which is
+/// the one we want.
+pub fn lan_address() -> Option<IpAddr> {
+    let socket = UdpSocket::bind("0.0.0.0:0").ok()?;
+    socket.connect("8.8.8.8:80").ok()?;
+    socket.local_addr().ok().map(|addr| addr.ip())
+}
+
+/// Print a QR code encoding `addr` in the same "host:port" form `prompt_address` accepts, so people
+/// can scan it instead of typing it.
+pub fn print_address_qr(addr: SocketAddr) {
+    let Ok(code) = QrCode::new(addr.to_string()) else {
+        println!("[server] Unable to generate a QR code for {addr}");
+        return;
+    };
+
+    // colors are inverted because most terminals are light text on a dark background
+    let image = code.render::<Dense1x2>()
+        .dark_color(Dense1x2::Light)
+        .light_color(Dense1x2::Dark)
+        .build();
+
+    println!("[server] Scan to join at {addr}");
+    println!("{image}");
+}
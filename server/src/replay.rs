@@ -0,0 +1,197 @@
+// Recording everything a server receives, and playing a recording back into a fresh server, so a
+// problem from a real session can be reproduced on demand
+
+use std::collections::HashMap;
+use std::fs::File;
+use std::io::{self, BufRead, BufReader, LineWriter, Write};
+use std::net::TcpStream;
+use std::path::Path;
+use std::sync::Mutex;
+use std::thread;
+use std::time::{Duration, Instant};
+
+use serde::{Serialize, Deserialize};
+
+use tcp_chat_proto::capability::Capability;
+use tcp_chat_proto::codec::Json;
+use tcp_chat_proto::constants::{HOST_ID, LOOPBACK, LOOPBACK_SOCKET};
+use tcp_chat_proto::packet::{ClientMessage::{self, *}, ServerMessage::{self, *}};
+use tcp_chat_proto::tcp_conn::{encode_frame, TcpConn};
+use tcp_chat_proto::transport::Socket;
+use crate::config::ServerConfig;
+use crate::server::server;
+
+
+
+/// How long to wait for the server to welcome a replayed client before giving up on the replay
+const JOIN_TIMEOUT: Duration = Duration::from_secs(5);
+
+/// How long to give the server to handle the last events before the replay is over
+const SETTLE: Duration = Duration::from_secs(1);
+
+
+/// Something the server received from a client
+#[derive(Serialize, Deserialize, Debug)]
+pub(crate) enum Event {
+    /// They were let in under this name
+    Joined(String),
+    Sent(ClientMessage),
+    /// They sent something that couldn't be read as a message
+    Invalid,
+    /// Their connection closed without a goodbye
+    Left,
+}
+
+/// One line of a recording
+#[derive(Serialize, Deserialize, Debug)]
+struct Entry {
+    /// Milliseconds since the server started
+    ms: u64,
+    client: u64,
+    event: Event,
+}
+
+/// Writes every event the server receives to a file as it happens, one JSON object per line, so
+/// a recording survives the server crashing partway through
+pub(crate) struct Recorder {
+    started: Instant,
+    file: Mutex<LineWriter<File>>,
+}
+
+impl Recorder {
+    /// Start a new recording at `path`, replacing any recording already there
+    pub fn create(path: &Path) -> io::Result<Self> {
+        Ok(Self {
+            started: Instant::now(),
+            file: Mutex::new(LineWriter::new(File::create(path)?)),
+        })
+    }
+
+    /// Note that client `client` did `event`. The text of ephemeral messages is replaced with
+    /// filler of the same length, since they're never to be kept anywhere, but the length still
+    /// matters to the server when they're played back.
+    pub fn record(&self, client: u64, event: Event) {
+        let event = match event {
+            Event::Sent(ClientEphemeralText(ttl, text)) => Event::Sent(ClientEphemeralText(ttl, "x".repeat(text.len()))),
+            event => event,
+        };
+
+        // timed under the lock so the recording is always in order
+        let mut file = self.file.lock().unwrap();
+        let entry = Entry { ms: self.started.elapsed().as_millis() as u64, client, event };
+
+        let written = serde_json::to_writer(&mut *file, &entry)
+            .map_err(io::Error::from)
+            .and_then(|_| file.write_all(b"\n"));
+        if let Err(e) = written {
+            println!("[server] Unable to record an event: {e}");
+        }
+    }
+}
+
+
+/// Start a server with `config` and play the recording at `path` into it, `speed` times faster
+/// than it happened. Each recorded client connects from this machine in the same order as before,
+/// so they get the same ids and anything naming them (like kicks) still does. The host joins the
+/// way the host does, through the server's connector.
+///
+/// Nothing the replay does leaves the machine or outlives it: webhooks, the tracker, and storage
+/// are all turned off, and the room is only opened over plain TCP on loopback, whatever the config
+/// says about Noise, Unix sockets, WebSockets, or QUIC.
+pub fn replay(path: &Path, speed: f64, config: ServerConfig) -> io::Result<()> {
+    let recording = BufReader::new(File::open(path)?);
+
+    // the replayed clients connect without Noise, so the server can't insist on it
+    let socket = config.socket.clone().noise(false);
+    let handle = server(ServerConfig {
+        show_qr: false,
+        bind_addr: LOOPBACK,
+        socket,
+        unix_socket: None,
+        websocket_port: None,
+        quic_port: None,
+        tracker_url: None,
+        storage_dir: None,
+        record_file: None,
+        webhooks: Vec::new(),
+        webhook_port: None,
+        ..config
+    });
+
+    let started = Instant::now();
+    let mut conns = HashMap::<u64, TcpConn<Json, Socket>>::new();
+    let mut replayed = 0;
+
+    for line in recording.lines() {
+        let line = line?;
+        if line.trim().is_empty() {
+            continue;
+        }
+        let entry: Entry = serde_json::from_str(&line).map_err(|e| io::Error::new(
+            io::ErrorKind::InvalidData,
+            format!("Line {} of the recording is invalid: {e}", replayed + 1)
+        ))?;
+
+        let due = Duration::from_secs_f64(entry.ms as f64 / 1000.0 / speed);
+        if let Some(wait) = due.checked_sub(started.elapsed()) {
+            thread::sleep(wait);
+        }
+
+        println!("[replay] {:.1}s client {} {:?}", entry.ms as f64 / 1000.0, entry.client, entry.event);
+        match entry.event {
+            Event::Joined(name) => {
+                let socket = if entry.client == HOST_ID {
+                    handle.connector().connect()?
+                } else {
+                    Socket::Tcp(TcpStream::connect(LOOPBACK_SOCKET)?)
+                };
+                conns.insert(entry.client, replay_join(socket, &name)?);
+            },
+            Event::Sent(msg) => {
+                if let Some(conn) = conns.get_mut(&entry.client) {
+                    conn.send(&msg)?;
+                }
+            },
+            Event::Invalid => {
+                if let Some(conn) = conns.get_mut(&entry.client) {
+                    let mut frame = Vec::new();
+                    encode_frame(&"not a message", &mut frame)?;
+                    conn.send_frame(&frame)?;
+                }
+            },
+            Event::Left => {
+                conns.remove(&entry.client);
+            },
+        }
+        replayed += 1;
+
+        // nobody's reading what the server sends, so throw it away before it backs up
+        for conn in conns.values_mut() {
+            while let Ok(Some(_)) = conn.try_receive::<ServerMessage>() {}
+        }
+    }
+
+    thread::sleep(SETTLE);
+    println!("[replay] Replayed {replayed} events in {:.1}s", started.elapsed().as_secs_f64());
+    Ok(())
+}
+
+/// Join a replayed client named `name` over `socket` and wait for it to be let in. Only
+/// `Capability::Welcome` is asked for, so the server doesn't expect anything of the client the
+/// recording didn't capture, like heartbeats.
+fn replay_join(socket: Socket, name: &str) -> io::Result<TcpConn<Json, Socket>> {
+    let mut conn = TcpConn::new(socket)?;
+    conn.send(&ClientCapabilities(vec![Capability::Welcome.name().to_string()]))?;
+    match conn.receive_timeout::<ServerMessage>(JOIN_TIMEOUT)? {
+        ServerHello(_) => {},
+        other => return Err(io::Error::other(format!("The server didn't agree to capabilities during the replay: {other:?}"))),
+    }
+
+    conn.send(&ClientHello(name.to_string()))?;
+    match conn.receive_timeout::<ServerMessage>(JOIN_TIMEOUT)? {
+        ServerWelcome(_) => {},
+        other => return Err(io::Error::other(format!("{name} wasn't let in during the replay: {other:?}"))),
+    }
+
+    Ok(conn)
+}
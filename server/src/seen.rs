@@ -0,0 +1,99 @@
+use std::collections::HashMap;
+use std::fs;
+use std::io;
+use std::path::PathBuf;
+use std::time::Duration;
+
+use serde::{Serialize, Deserialize};
+
+use tcp_chat_proto::helpers::{unix_time, humanize_duration};
+
+
+
+/// The last thing someone was seen doing
+#[derive(Serialize, Deserialize, Debug, Clone, Copy)]
+pub enum SeenEvent {
+    Joined,
+    Left,
+    Kicked,
+    Spoke,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone)]
+struct SeenRecord {
+    /// The name as it was last used, since records are looked up case-insensitively
+    name: String,
+    /// Seconds since the unix epoch
+    time: u64,
+    event: SeenEvent,
+}
+
+/// Keeps track of when each user was last seen, optionally persisting it to disk so it survives
+/// server restarts
+#[derive(Debug, Default)]
+pub struct LastSeen {
+    records: HashMap<String, SeenRecord>,
+    path: Option<PathBuf>,
+    dirty: bool,
+}
+
+impl LastSeen {
+    /// Load the last-seen records from `storage_dir`. With no directory, records are only kept in
+    /// memory.
+    pub fn load(storage_dir: Option<&PathBuf>) -> Self {
+        let path = storage_dir.map(|dir| dir.join("seen.json"));
+
+        let records = path.as_ref()
+            .and_then(|path| fs::read_to_string(path).ok())
+            .and_then(|contents| serde_json::from_str(&contents).ok())
+            .unwrap_or_default();
+
+        Self { records, path, dirty: false }
+    }
+
+    /// Note that `name` was just seen doing `event`
+    pub fn record(&mut self, name: &str, event: SeenEvent) {
+        self.records.insert(name.to_lowercase(), SeenRecord {
+            name: name.to_string(),
+            time: unix_time(),
+            event,
+        });
+        self.dirty = true;
+    }
+
+    /// A human readable description of when `name` was last seen
+    pub fn describe(&self, name: &str) -> String {
+        let Some(record) = self.records.get(&name.to_lowercase()) else {
+            return format!("I haven't seen {name}");
+        };
+
+        let ago = Duration::from_secs(unix_time().saturating_sub(record.time));
+        let what = match record.event {
+            SeenEvent::Joined => "joined the room",
+            SeenEvent::Left => "left the room",
+            SeenEvent::Kicked => "was kicked",
+            SeenEvent::Spoke => "sent a message",
+        };
+
+        format!("{} was last seen {} ago ({what})", record.name, humanize_duration(ago))
+    }
+
+    /// Write the records to disk if anything changed since the last save. Does nothing if the
+    /// records aren't being persisted. They're written to a temporary file first, so crashing
+    /// partway through leaves the last records intact.
+    pub fn flush(&mut self) -> io::Result<()> {
+        let Some(path) = self.path.as_ref().filter(|_| self.dirty) else {
+            return Ok(());
+        };
+
+        if let Some(dir) = path.parent() {
+            fs::create_dir_all(dir)?;
+        }
+        let temporary = path.with_extension("json.tmp");
+        fs::write(&temporary, serde_json::to_string(&self.records)?)?;
+        fs::rename(temporary, path)?;
+
+        self.dirty = false;
+        Ok(())
+    }
+}
@@ -0,0 +1,2012 @@
+use std::sync::{Mutex, Arc};
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::mpsc::{self, Receiver, Sender};
+use std::collections::{HashMap, HashSet, VecDeque};
+use std::ops::{Deref, DerefMut};
+use std::net::{Ipv4Addr, TcpListener, SocketAddr};
+use std::thread;
+use std::time::{Duration, Instant};
+use std::io;
+
+use tcp_chat_proto::tcp_conn::{encode_frame_with, encode_raw_frame, restamp_frames, RecvError, SendError, TcpConn, TcpConnBuilder, MAIN_CHANNEL};
+use tcp_chat_proto::codec::WireFormat;
+use tcp_chat_proto::capability::{Capabilities, Capability};
+use tcp_chat_proto::typed_conn::ServerConn;
+use tcp_chat_proto::transport::{Listener, Socket, UNIX_PREFIX};
+use tcp_chat_proto::local::LocalConnector;
+use tcp_chat_proto::buffer_pool::POOL;
+use tcp_chat_proto::packet::{Attachment, ConnectionChange, ConnectionEvent, HistoryEntry, Limits, Presence, Report, RoomInfo, RosterEntry, Sealed, VoiceClip, ClientMessage::{self, *}, ServerMessage::{self, *}};
+use tcp_chat_proto::constants::*;
+use tcp_chat_proto::e2e::{new_salt, TAG_LEN};
+use tcp_chat_proto::noise::{fingerprint, Keypair};
+use crate::constants::*;
+use crate::config::ServerConfig;
+use crate::webhook::{fire_webhooks, accept_webhooks};
+use crate::websocket::accept_websockets;
+use crate::qr::{lan_address, print_address_qr};
+use crate::seen::{LastSeen, SeenEvent};
+use crate::tracker::announce;
+use tcp_chat_proto::helpers::{humanize_duration, unix_time, unix_time_ms};
+use crate::names::{check_name, normalize};
+use crate::connection_log::ConnectionLog;
+use crate::replay::{Event, Recorder};
+use crate::snapshot::{SavedMessage, Snapshot};
+use crate::hostnames::Hostnames;
+
+
+
+struct Client {
+    id: u64,
+    /// Shared so writes can happen without the room locked, see `server_flush`. Speaks whichever
+    /// format the client asked for while connecting.
+    conn: Arc<Mutex<ServerConn<WireFormat, Socket>>>,
+    address: SocketAddr,
+    /// What they agreed to while connecting, see `capability`
+    capabilities: Capabilities,
+    status: Option<String>,
+    /// When the client last sent anything, used to show who's idle
+    last_activity: Instant,
+    /// When anything at all last arrived from the client, heartbeats included, to notice when
+    /// they've silently gone
+    last_heard: Instant,
+    presence: Presence,
+    /// The last text they sent, when, and how many times in a row, to catch repeats
+    last_text: Option<(String, Instant, u32)>,
+    /// Their messages are acknowledged as usual, but nobody else sees them
+    shadowbanned: bool,
+    /// How many times they've recently sent something invalid or that had to be refused
+    strikes: u32,
+    last_strike: Instant,
+    /// They aren't read from until this time, to slow down abuse
+    tarpit_until: Instant,
+    /// When they last changed their name, so they can't keep changing it to confuse people
+    last_rename: Option<Instant>,
+    /// When they last reported someone, so reports can't be used to flood the moderators
+    last_report: Option<Instant>,
+    /// When they last sent a voice clip, since clips are far bigger than anything else
+    last_voice: Option<Instant>,
+    /// The attachments they announced whose bytes haven't all arrived yet, oldest first. Refused
+    /// ones are `None`, their bytes are still on the way but only to be thrown away.
+    attachments: VecDeque<Option<Attachment>>,
+}
+
+impl Client {
+    /// Note that the client sent something invalid or that had to be refused. After a few strikes
+    /// they're read from less and less often, so a misbehaving client gets slower the longer it
+    /// keeps at it. Strikes are forgotten after a while of good behavior. Returns true once they
+    /// should be dropped altogether.
+    fn strike(&mut self) -> bool {
+        let now = Instant::now();
+
+        if now.duration_since(self.last_strike) > STRIKE_EXPIRY {
+            self.strikes = 0;
+        }
+        self.strikes += 1;
+        self.last_strike = now;
+
+        // the first few are free since anyone can make a mistake
+        if let Some(over) = self.strikes.checked_sub(FREE_STRIKES + 1) {
+            self.tarpit_until = now + TARPIT_DELAY * 2u32.pow(over);
+        }
+
+        self.strikes >= MAX_STRIKES
+    }
+}
+
+/// The active connections, along with the broadcasts they've been sent. These share a lock so
+/// broadcasts are numbered in the same order they go out. Derefs to the list of clients.
+#[derive(Default)]
+struct Room {
+    clients: Vec<Client>,
+    history: History,
+    connections: ConnectionLog,
+    /// Someone other than the host who can moderate: a client standing in while the host is gone,
+    /// or the host once they're back under a new id
+    operator: Option<u64>,
+    /// The host's name while they're gone after disconnecting unexpectedly, so they're recognized
+    /// when they come back
+    absent_host: Option<String>,
+    /// Lowercase names that are shadowbanned, so reconnecting under the same name doesn't shake
+    /// it off
+    shadowbanned: HashSet<String>,
+    hostnames: Hostnames,
+    /// The salt everyone derives the room's key from, when the room is end-to-end encrypted
+    salt: Option<Vec<u8>>,
+    /// Set once the host shuts the room down, after which the server stops
+    closed: bool,
+}
+
+impl Room {
+    /// A room picking up from `snapshot`, with nobody in it yet
+    fn restore(snapshot: Snapshot) -> Self {
+        let messages = snapshot.history.into_iter()
+            .map(|saved| Recorded { seq: saved.seq, msg: saved.msg, excluded: Vec::new(), time: saved.time })
+            .collect();
+
+        Self {
+            history: History { next_seq: snapshot.next_seq, messages },
+            connections: ConnectionLog::restore(snapshot.connections),
+            shadowbanned: snapshot.shadowbanned.into_iter().collect(),
+            salt: snapshot.salt,
+            ..Self::default()
+        }
+    }
+
+    /// The state worth keeping across a restart. Ephemeral messages are left out since nothing
+    /// would expire them afterwards, the shutdown notice since anyone catching up on it later
+    /// would take it as the room closing again, and who a broadcast was held back from since
+    /// client ids start over.
+    fn snapshot(&self) -> Snapshot {
+        let history = self.history.messages.iter()
+            .filter(|recorded| !matches!(recorded.msg, ServerEphemeralText(..) | ServerShutdown))
+            .map(|recorded| SavedMessage { seq: recorded.seq, time: recorded.time, msg: recorded.msg.clone() })
+            .collect();
+
+        Snapshot {
+            taken: unix_time(),
+            next_seq: self.history.next_seq,
+            history,
+            connections: self.connections.events(),
+            shadowbanned: self.shadowbanned.iter().cloned().collect(),
+            salt: self.salt.clone(),
+        }
+    }
+
+    /// Whether `id` can kick, shadowban, and see where people are connecting from, and send urgent
+    /// messages when only moderators can (see `ServerConfig::urgent_moderators_only`)
+    fn can_moderate(&self, id: u64) -> bool {
+        id == HOST_ID || self.operator == Some(id)
+    }
+
+    /// Whether `id` is the one whose unexpected disconnect leaves the room without a moderator,
+    /// which is the host, or the host again after they came back
+    fn is_lead(&self, id: u64) -> bool {
+        id == HOST_ID || (self.absent_host.is_none() && self.operator == Some(id))
+    }
+}
+
+impl Deref for Room {
+    type Target = Vec<Client>;
+
+    fn deref(&self) -> &Self::Target {
+        &self.clients
+    }
+}
+
+impl DerefMut for Room {
+    fn deref_mut(&mut self) -> &mut Self::Target {
+        &mut self.clients
+    }
+}
+
+/// A list of TcpConns which represents the active connections
+type Clients = Arc<Mutex<Room>>;
+
+/// Recent broadcasts by sequence number, kept so clients can ask for ones they missed or page
+/// back through the room
+#[derive(Default)]
+struct History {
+    next_seq: u64,
+    messages: VecDeque<Recorded>,
+}
+
+/// A broadcast as kept in the history
+struct Recorded {
+    seq: u64,
+    msg: ServerMessage,
+    /// Clients it was held back from
+    excluded: Vec<u64>,
+    /// Seconds since the unix epoch
+    time: u64,
+}
+
+impl History {
+    /// Store a broadcast, returning its sequence number. Voice clips are kept without their
+    /// audio, so the history can't grow to hundreds of clips' worth.
+    fn record(&mut self, mut msg: ServerMessage, exclude: &[u64]) -> u64 {
+        let seq = self.next_seq;
+        self.next_seq += 1;
+
+        if let ServerVoice(_, clip) = &mut msg {
+            clip.data = Vec::new();
+        }
+
+        self.messages.push_back(Recorded { seq, msg, excluded: exclude.to_vec(), time: unix_time() });
+        if self.messages.len() > MAX_HISTORY {
+            self.messages.pop_front();
+        }
+        seq
+    }
+
+    /// Drop an ephemeral message so it can't be recovered after it expires
+    fn forget_ephemeral(&mut self, id: u64) {
+        self.messages.retain(|recorded| !matches!(recorded.msg, ServerEphemeralText(i, ..) if i == id));
+    }
+
+    /// Up to `limit` of the messages people said (and server notices) from before `before`, or the
+    /// latest ones without it, oldest first. Ephemeral messages are left out, since they'd outlive
+    /// their expiry on the client, as is everything else that isn't chat.
+    fn page(&self, before: Option<u64>, limit: usize) -> Vec<HistoryEntry> {
+        let before = before.unwrap_or(u64::MAX);
+        let mut page: Vec<HistoryEntry> = self.messages.iter().rev()
+            .filter(|recorded| recorded.seq < before)
+            .filter_map(|recorded| {
+                let (sender, text, urgent) = match &recorded.msg {
+                    ServerText(name, text) => (name.clone(), text.clone(), false),
+                    ServerUrgentText(name, text) => (name.clone(), text.clone(), true),
+                    ServerRenamed(old_name, new_name) => {
+                        ("[server]".to_string(), format!("{old_name} is now known as {new_name}"), false)
+                    },
+                    _ => return None,
+                };
+                Some(HistoryEntry { id: recorded.seq, time: recorded.time, sender, text, urgent })
+            })
+            .take(limit)
+            .collect();
+        page.reverse();
+        page
+    }
+
+    /// Up to `limit` of the latest things `name` said, oldest first
+    fn recent_from(&self, name: &str, limit: usize) -> Vec<HistoryEntry> {
+        let mut recent: Vec<HistoryEntry> = self.page(None, MAX_HISTORY).into_iter()
+            .rev()
+            .filter(|entry| entry.sender == name)
+            .take(limit)
+            .collect();
+        recent.reverse();
+        recent
+    }
+}
+
+/// A map of id -> name to allow the server to lookup client names
+type ClientNames = Arc<Mutex<HashMap<u64, String>>>;
+
+/// When each user was last seen, shared with the listener thread so it can record joins
+type Seen = Arc<Mutex<LastSeen>>;
+
+/// Where received events are recorded, if anywhere
+type Recording = Option<Arc<Recorder>>;
+
+/// Ephemeral messages that still need to be expired
+#[derive(Default)]
+struct Ephemerals {
+    next_id: u64,
+    /// (when it expires, message id)
+    pending: Vec<(Instant, u64)>,
+}
+
+/// How the sockets of clients that connect are set up before they say hello, and what they're
+/// told once they have
+struct Accepting {
+    socket: TcpConnBuilder,
+    /// What the server makes Noise handshakes with
+    noise_key: Keypair,
+    limits: Limits,
+}
+
+
+/// A way for the program running the server to put messages into the room. Cloning it gives
+/// another handle to the same room.
+#[derive(Clone)]
+pub struct ServerHandle {
+    clients: Clients,
+    /// Only ever handed to the host, see `connector`
+    local: LocalConnector,
+}
+
+impl ServerHandle {
+    /// Opens connections to the room from within this process, which is how the host joins. The
+    /// first client to join through it is the host, whoever else got in before them.
+    pub fn connector(&self) -> LocalConnector {
+        self.local.clone()
+    }
+
+    /// Send `text` to everyone in the room as a server notice
+    pub fn broadcast(&self, text: &str) {
+        self.broadcast_from("[server]", text);
+    }
+
+    /// Send `text` to everyone in the room as if `name` had said it
+    fn broadcast_from(&self, name: &str, text: &str) {
+        server_distribute_message(&self.clients, &ServerText(name.to_string(), text.to_string()), &[]);
+    }
+
+    /// Send `text` from the server to client `id` alone
+    /// 
+    /// # Errors
+    /// Fails if there's no client with that id, or the message couldn't be sent to them.
+    pub fn dm(&self, id: u64, text: &str) -> io::Result<()> {
+        let conn = self.clients.lock().unwrap().iter()
+            .find(|client| client.id == id)
+            .map(|client| Arc::clone(&client.conn))
+            .ok_or_else(|| io::Error::new(
+                io::ErrorKind::NotFound,
+                format!("Client with id {id} could not be found")
+            ))?;
+
+        // sent without the room locked, so a slow client doesn't hold everyone else up
+        let mut conn = conn.lock().unwrap();
+        Ok(conn.send(&ServerDirectText("[server]".to_string(), text.to_string()))?)
+    }
+}
+
+
+/// Pass on every connection `listener` accepts to `incoming` until the server stops taking them
+fn server_listen(listener: &Listener, incoming: &Sender<Socket>) {
+    while let Ok(client) = listener.accept() {
+        if incoming.send(client).is_err() {
+            break;
+        }
+    }
+}
+
+/// Start listening for new clients and distributing incoming messages in the background. Returns
+/// once the server is ready for connections.
+pub fn server(config: ServerConfig) -> ServerHandle {
+    
+    // pick up where the room was before the server last stopped
+    let room = match config.storage_dir.as_deref().and_then(Snapshot::load) {
+        Some(snapshot) => {
+            let ago = Duration::from_secs(unix_time().saturating_sub(snapshot.taken));
+            println!("[server] Restored the room as it was {} ago", humanize_duration(ago));
+            Room::restore(snapshot)
+        },
+        None => Room::default(),
+    };
+    let salt = config.encrypted.then(|| room.salt.clone().unwrap_or_else(new_salt));
+    if salt.is_some() {
+        println!("[server] The room is end-to-end encrypted, everyone will need its passphrase");
+    }
+    let room = Room { hostnames: Hostnames::new(config.resolve_hostnames), salt, ..room };
+
+    // TcpListener will create a stream for each client
+    let clients: Clients = Arc::new(Mutex::new(room));
+    let client_names: ClientNames = Arc::new(Mutex::new(HashMap::new()));
+    let seen: Seen = Arc::new(Mutex::new(LastSeen::load(config.storage_dir.as_ref())));
+    
+    let listener = match &config.unix_socket {
+        Some(path) => Listener::bind_unix(path).unwrap_or_else(|e| panic!(
+            "[error] Unable to bind to {}: {e}", path.display()
+        )),
+        None => Listener::bind_tcp(SocketAddr::new(config.bind_addr, PORT)).unwrap_or_else(|_| panic!(
+            "[error] Unable to bind to port {PORT}",
+        )),
+    };
+
+    // connections from the listener and from within this process are accepted in the order they
+    // arrive. The host's are marked, so arriving first doesn't make anyone else the host.
+    let (incoming_sender, incoming) = mpsc::channel();
+    let local = LocalConnector::host(incoming_sender.clone());
+    let gateway = LocalConnector::new(incoming_sender.clone());
+
+    // as do ones over QUIC, unless the room is only for this machine
+    if let Some(port) = config.quic_port.filter(|_| config.unix_socket.is_none()) {
+        match Listener::bind_quic(SocketAddr::new(config.bind_addr, port)) {
+            Ok(quic_listener) => {
+                println!("[server] Also accepting clients over QUIC on port {port}");
+                let incoming_sender = incoming_sender.clone();
+                thread::Builder::new()
+                    .name(String::from("server quic listener thread"))
+                    .spawn(move || server_listen(&quic_listener, &incoming_sender))
+                    .unwrap();
+            },
+            Err(e) => println!("[server] Unable to bind QUIC listener to port {port}: {e}"),
+        }
+    }
+
+    thread::Builder::new()
+        .name(String::from("server socket listener thread"))
+        .spawn(move || server_listen(&listener, &incoming_sender))
+        .unwrap();
+
+    if let Some(path) = &config.unix_socket {
+        println!("[server] Hosting on {UNIX_PREFIX}{}, so only this machine can join", path.display());
+    } else if config.show_qr {
+        match lan_address() {
+            Some(ip) => print_address_qr(SocketAddr::new(ip, PORT)),
+            None => println!("[server] Unable to determine LAN address for the QR code"),
+        }
+    }
+
+    let recording: Recording = config.record_file.as_ref().and_then(|path| {
+        match Recorder::create(path) {
+            Ok(recorder) => {
+                println!("[server] Recording this session to {}", path.display());
+                Some(Arc::new(recorder))
+            },
+            Err(e) => {
+                println!("[server] Unable to record this session to {}: {e}", path.display());
+                None
+            },
+        }
+    });
+    
+
+    // listen for incoming connections in another thread
+    let clients_clone = Arc::clone(&clients);
+    let client_names_clone = Arc::clone(&client_names);
+    let seen_clone = Arc::clone(&seen);
+    let room_name = config.room_name.clone();
+    let recording_clone = recording.clone();
+    let accepting = Accepting {
+        socket: config.socket.clone(),
+        noise_key: config.noise_key.clone().unwrap_or_else(Keypair::generate),
+        limits: config.limits(),
+    };
+    println!("[server] Clients connecting with Noise will see the key {}", fingerprint(&accepting.noise_key.public()));
+    thread::Builder::new()
+        .name(String::from("server listener thread"))
+        .spawn(move || {
+            server_accept_connections(
+                incoming,
+                clients_clone,
+                client_names_clone,
+                seen_clone,
+                room_name,
+                accepting,
+                recording_clone
+            )
+        })
+        .unwrap();
+
+    let handle = ServerHandle { clients: Arc::clone(&clients), local };
+
+    // listen for incoming webhook requests in another thread if any are configured
+    if let Some(port) = config.webhook_port.filter(|_| !config.incoming_webhooks.is_empty()) {
+        match TcpListener::bind(SocketAddr::new(BIND_ADDR, port)) {
+            Ok(webhook_listener) => {
+                let handle = handle.clone();
+                let webhooks = config.incoming_webhooks.clone();
+                thread::Builder::new()
+                    .name(String::from("server webhook listener thread"))
+                    .spawn(move || {
+                        accept_webhooks(webhook_listener, webhooks, move |name, text| {
+                            handle.broadcast_from(&name, &text);
+                        })
+                    })
+                    .unwrap();
+            },
+            Err(e) => println!("[server] Unable to bind webhook listener to port {port}: {e}"),
+        }
+    }
+
+    // let browsers in through a WebSocket gateway in another thread if there's a port for it
+    if let Some(port) = config.websocket_port {
+        match TcpListener::bind(SocketAddr::new(BIND_ADDR, port)) {
+            Ok(websocket_listener) => {
+                let room = gateway;
+                let socket = config.socket.clone();
+                let origins = config.websocket_origins.clone();
+                thread::Builder::new()
+                    .name(String::from("server websocket listener thread"))
+                    .spawn(move || accept_websockets(websocket_listener, room, socket, origins))
+                    .unwrap();
+            },
+            Err(e) => println!("[server] Unable to bind WebSocket listener to port {port}: {e}"),
+        }
+    }
+
+    if let Some(tracker_url) = config.tracker_url.clone().filter(|_| config.unix_socket.is_none()) {
+        let clients_clone = Arc::clone(&clients);
+        announce(tracker_url, config.room_name.clone(), move || clients_clone.lock().unwrap().len());
+    }
+
+    thread::Builder::new()
+        .name(String::from("server main"))
+        .spawn(move || server_poll_clients(clients, client_names, seen, config, recording))
+        .unwrap();
+
+    handle
+}
+
+/// Read from every client in turn forever, handling whatever they sent
+fn server_poll_clients(
+    clients: Clients,
+    client_names: ClientNames,
+    seen: Seen,
+    config: ServerConfig,
+    recording: Recording
+) {
+
+    // a queue to store messages while the `clients` mutex is locked and borrowed
+    let mut queue = Vec::<(u64, ClientMessage)>::new();
+
+    let mut ephemerals = Ephemerals::default();
+
+    // the host's name, if they disconnected without shutting down the room this tick
+    let mut lost_host = None;
+
+    let mut poll_delay = MAX_POLL_DELAY;
+
+    let mut last_snapshot = Instant::now();
+
+    let heartbeat_timeout = (config.heartbeat_timeout_secs > 0)
+        .then(|| Duration::from_secs(config.heartbeat_timeout_secs));
+
+    // process messages and distribute them
+    loop {
+        // the sockets are non-blocking, so sleep to avoid excessive cpu usage on the server. The
+        // sleep is short while people are talking so messages go out quickly, and backs off when
+        // the room is quiet.
+        thread::sleep(poll_delay);
+        
+        let now = Instant::now();
+        let mut room = clients.lock().unwrap();
+        if room.closed {
+            return;
+        }
+        let lead = room.iter().map(|client| client.id).find(|&id| room.is_lead(id));
+        for client in room.iter_mut() {
+
+            if client.tarpit_until > now {
+                continue;
+            }
+
+            let received = server_receive(client);
+            if let Some(recorder) = &recording {
+                match &received {
+                    // the bytes aren't recorded, so there'd be nothing to play back
+                    Ok(Some(ClientAttachment(_))) => {},
+                    Ok(Some(msg)) => recorder.record(client.id, Event::Sent(msg.clone())),
+                    Err(RecvError::Invalid(_) | RecvError::Corrupt(_) | RecvError::Garbled) => {
+                        recorder.record(client.id, Event::Invalid)
+                    },
+                    Err(RecvError::TooLarge(_) | RecvError::Backlog(_) | RecvError::Unsupported(_) | RecvError::Undecryptable) => {
+                        recorder.record(client.id, Event::Left)
+                    },
+                    Err(e) if e.is_disconnect() => recorder.record(client.id, Event::Left),
+                    _ => {},
+                }
+            }
+            if let Ok(Some(_)) = received {
+                client.last_heard = now;
+            }
+            match received {
+                // a connection that went away without closing (say, a laptop lid shut) is only
+                // noticed by the heartbeats stopping
+                Ok(None) if client.capabilities.contains(Capability::Heartbeats)
+                    && heartbeat_timeout.is_some_and(|timeout| now.duration_since(client.last_heard) > timeout) => {
+                    println!("[server] Client {} stopped sending heartbeats", client.id);
+                    queue.push((client.id, ClientGoodbye));
+                    if lead == Some(client.id) {
+                        lost_host = client_names.lock().unwrap().get(&client.id).cloned();
+                    }
+                },
+                // nothing new, or not all of it yet
+                Ok(None) => {},
+                // presence updates and heartbeats are automatic, so they don't count as activity
+                Ok(Some(ClientSetPresence(presence))) => client.presence = presence,
+                // only answered for clients that agreed to it, since others wouldn't expect the pong
+                Ok(Some(ClientPing)) => {
+                    let answer = client.capabilities.contains(Capability::Heartbeats);
+                    if answer && client.conn.lock().unwrap().queue(&ServerPong).is_err() {
+                        println!("[server] Unable to answer a heartbeat");
+                    }
+                },
+                // answered right away since any delay skews the client's estimate of the time
+                Ok(Some(ClientSyncClock(sent))) => {
+                    if client.conn.lock().unwrap().send(&ServerClock(sent, unix_time_ms())).is_err() {
+                        println!("[server] Unable to reply to a clock sync");
+                    }
+                },
+                // treated the same as garbage, it can't be acted on either way
+                Ok(Some(msg)) if !msg.within_limits() => {
+                    println!("[server] Client sent a message over the size limits.");
+                    let reason = String::from("Message is over the size limits of every room");
+                    let _ = client.conn.lock().unwrap().send(&ServerRejected(reason));
+
+                    if client.strike() {
+                        println!("[server] Dropping client {} for sending too much garbage", client.id);
+                        queue.push((client.id, ClientGoodbye));
+                    }
+                },
+                // acted on once its bytes have arrived too, see `server_receive`
+                Ok(Some(ClientAttachment(attachment))) if attachment.data.is_empty() && attachment.len > 0 => {
+                    if client.attachments.len() >= MAX_PENDING_ATTACHMENTS {
+                        println!("[server] Dropping client {} for announcing too many attachments", client.id);
+                        let reason = String::from("Too many attachments at once, so the server has disconnected you");
+                        let _ = client.conn.lock().unwrap().send(&ServerRejected(reason));
+                        queue.push((client.id, ClientGoodbye));
+                    } else if let Some(reason) = server_refuse_attachment(&attachment, &config) {
+                        let _ = client.conn.lock().unwrap().send(&ServerRejected(reason));
+                        client.attachments.push_back(None);
+
+                        if client.strike() {
+                            println!("[server] Dropping client {} for misbehaving, last sent {attachment:?}", client.id);
+                            queue.push((client.id, ClientGoodbye));
+                        }
+                    } else {
+                        client.attachments.push_back(Some(attachment));
+                    }
+                },
+                // the host leaving on purpose still leaves the room without anyone to moderate it
+                Ok(Some(ClientGoodbye)) if lead == Some(client.id) => {
+                    queue.push((client.id, ClientGoodbye));
+                    lost_host = client_names.lock().unwrap().get(&client.id).cloned();
+                },
+                Ok(Some(msg)) => {
+                    client.last_activity = Instant::now();
+                    queue.push((client.id, msg));
+                },
+
+                // someone left without saying goodbye
+                Err(e) if e.is_disconnect() => {
+                    queue.push((client.id, ClientGoodbye));
+                    if lead == Some(client.id) {
+                        lost_host = client_names.lock().unwrap().get(&client.id).cloned();
+                    }
+                },
+                // nothing more they send can be read, so there's no point waiting for strikes
+                Err(e @ (RecvError::Unsupported(_) | RecvError::TooLarge(_) | RecvError::Backlog(_) | RecvError::Undecryptable)) => {
+                    println!("[server] Dropping client {}: {e}", client.id);
+
+                    // sending too much is worth explaining, since the client may not know the limits
+                    if matches!(e, RecvError::TooLarge(_) | RecvError::Backlog(_)) {
+                        let reason = format!("{e}, so the server has disconnected you");
+                        let _ = client.conn.lock().unwrap().send(&ServerRejected(reason));
+                    }
+                    queue.push((client.id, ClientGoodbye));
+                },
+                // only the one message is lost, anything after it is still fine
+                Err(e @ RecvError::Corrupt(_)) => {
+                    println!("[server] Client {} sent a corrupted message: {e}", client.id);
+
+                    if client.strike() {
+                        println!("[server] Dropping client {} for sending too much garbage", client.id);
+                        queue.push((client.id, ClientGoodbye));
+                    }
+                },
+                // client sent the wrong type, or something garbled. Whatever arrived after it is
+                // kept, since it may well be fine
+                Err(RecvError::Invalid(_) | RecvError::Garbled) => {
+                    println!("[server] Client sent an invalid type.");
+
+                    if client.strike() {
+                        println!("[server] Dropping client {} for sending too much garbage", client.id);
+                        queue.push((client.id, ClientGoodbye));
+                    }
+                },
+                Err(e) => {
+                    println!("[server] Error reading client's connection: {:?}", e);
+                },
+            }
+
+        }
+        drop(room);
+
+        poll_delay = if queue.is_empty() {
+            (poll_delay * 2).min(MAX_POLL_DELAY)
+        } else {
+            MIN_POLL_DELAY
+        };
+
+        // read back the messages received and determine what to do with them
+        for (id, msg) in queue.iter() {
+            
+            server_handle_message(msg, id, &clients, &client_names, &seen, &mut ephemerals, &config);
+        }
+
+        queue.clear();
+
+        if let Some(name) = lost_host.take() {
+            println!("[server] The host left without closing the room");
+            let mut room = clients.lock().unwrap();
+            room.absent_host = Some(name);
+            room.operator = None;
+        }
+        server_elect_operator(&clients, &client_names);
+
+        // let everyone know which ephemeral messages are gone
+        let now = Instant::now();
+        ephemerals.pending.retain(|&(expires, id)| {
+            if expires > now {
+                return true;
+            }
+            clients.lock().unwrap().history.forget_ephemeral(id);
+            server_distribute_message(&clients, &ServerExpire(id), &[]);
+            false
+        });
+
+        // everything sent to a client this tick goes out together
+        for id in server_flush(&clients) {
+            if let Some(recorder) = &recording {
+                recorder.record(id, Event::Left);
+            }
+            server_handle_message(&ClientGoodbye, &id, &clients, &client_names, &seen, &mut ephemerals, &config);
+        }
+
+        if let Err(e) = seen.lock().unwrap().flush() {
+            println!("[server] Unable to save last seen times: {e}");
+        }
+
+        if last_snapshot.elapsed() > SNAPSHOT_INTERVAL {
+            server_snapshot(&clients, &config);
+            last_snapshot = Instant::now();
+        }
+    }
+}
+
+
+/// Receive whatever `client` sent next. The bytes of the attachments they announce come on
+/// `ATTACHMENT_CHANNEL`, and each attachment is returned with them once they've all arrived. Until
+/// then, their other messages are received as usual. A frame that fails to be received is skipped,
+/// so the next call gets whatever came after it.
+fn server_receive(client: &mut Client) -> Result<Option<ClientMessage>, RecvError> {
+    let mut conn = client.conn.lock().unwrap();
+    if client.attachments.is_empty() {
+        let received = conn.try_receive();
+        return skip_failed(&mut conn, MAIN_CHANNEL, received);
+    }
+
+    // anything going wrong means the attachment is given up on
+    let data = match conn.try_receive_raw_on(ATTACHMENT_CHANNEL) {
+        Ok(Some(data)) => data,
+        Ok(None) => {
+            let received = conn.try_receive();
+            return skip_failed(&mut conn, MAIN_CHANNEL, received);
+        },
+        received => {
+            client.attachments.pop_front();
+            return skip_failed(&mut conn, ATTACHMENT_CHANNEL, received.map(|_| None));
+        },
+    };
+    // refused when it was announced
+    let Some(mut attachment) = client.attachments.pop_front().unwrap() else {
+        return Ok(None);
+    };
+    if data.len() as u64 != attachment.len {
+        return Err(RecvError::Invalid(format!(
+            "Attachment was {} bytes instead of the {} it was announced as",
+            data.len(),
+            attachment.len
+        )));
+    }
+    attachment.data = data;
+    Ok(Some(ClientAttachment(attachment)))
+}
+
+/// Skip the frame on `channel` that `received` failed on, if it's one that can be skipped. Frames
+/// too big to receive aren't, since the client is dropped for those anyway.
+fn skip_failed<T>(
+    conn: &mut ServerConn<WireFormat, Socket>,
+    channel: u8,
+    received: Result<T, RecvError>
+) -> Result<T, RecvError> {
+    if let Err(RecvError::Invalid(_) | RecvError::Corrupt(_) | RecvError::Garbled) = &received {
+        conn.discard_frame_on(channel);
+    }
+    received
+}
+
+
+/// Respond to the given message
+fn server_handle_message(
+    msg: &ClientMessage,
+    sender: &u64,
+    clients: &Clients,
+    client_names: &ClientNames,
+    seen: &Seen,
+    ephemerals: &mut Ephemerals,
+    config: &ServerConfig
+) {
+    // anything said in the clear would give away what the room is keeping secret
+    let plaintext = matches!(msg, ClientText(_) | ClientTrackedText(..) | ClientEphemeralText(..) | ClientUrgentText(_) | ClientVoice(_) | ClientAttachment(_));
+    if plaintext && clients.lock().unwrap().salt.is_some() {
+        let reason = "This room is end-to-end encrypted, so only messages sealed with its passphrase can be sent. Enter it with !passphrase <passphrase>.";
+        server_send_message(clients, *sender, &ServerRejected(reason.to_string()));
+        return;
+    }
+
+    if let ClientText(text) | ClientTrackedText(_, text) | ClientEphemeralText(_, text) | ClientUrgentText(text) = msg {
+        if let Some(reason) = server_refuse_text(text, sender, clients, config) {
+            server_send_message(clients, *sender, &ServerRejected(reason));
+            server_strike(msg, sender, clients, client_names, seen, ephemerals, config);
+            return;
+        }
+        if server_hide_if_shadowbanned(sender, clients) {
+            if let ClientTrackedText(id, _) = msg {
+                server_acknowledge(clients, *sender, *id);
+            }
+            return;
+        }
+    }
+
+    match msg {
+        ClientShutdown => {
+
+            if sender != &HOST_ID {
+                println!("[server] Client {sender} tried to shut down the server without being the host");
+                server_strike(msg, sender, clients, client_names, seen, ephemerals, config);
+                return;
+            }
+
+            println!("[server] Server shutting down");
+            server_distribute_message(clients, &ServerShutdown, &[]);
+            server_flush(clients);
+            server_snapshot(clients, config);
+
+            // the host's client shares the process and exits on its own once it hears about the
+            // shutdown, so only the room is stopped here. Everyone's connection is closed after
+            // giving the notice time to arrive.
+            thread::sleep(Duration::from_secs(1));
+            let mut room = clients.lock().unwrap();
+            room.closed = true;
+            room.clients.clear();
+
+        },
+        ClientText(text) | ClientTrackedText(_, text) => {
+
+            if let Some(name) = client_names.lock().unwrap().get(sender) {
+
+                server_distribute_message(
+                    clients,
+                    &ServerText(name.clone(), text.clone()),
+                    &[*sender]
+                );
+                if let ClientTrackedText(id, _) = msg {
+                    server_acknowledge(clients, *sender, *id);
+                }
+
+                fire_webhooks(&config.webhooks, name, text, &config.room_name);
+                seen.lock().unwrap().record(name, SeenEvent::Spoke);
+
+            } else {
+                println!("[server] Unable to get client name by id.");
+            }
+
+        }
+        ClientEphemeralText(secs, text) => {
+
+            // ephemeral messages intentionally skip webhooks since those could keep them forever
+            if let Some(name) = client_names.lock().unwrap().get(sender) {
+
+                let secs = (*secs).min(MAX_EPHEMERAL_SECS);
+                let id = ephemerals.next_id;
+                ephemerals.next_id += 1;
+
+                server_distribute_message(
+                    clients,
+                    &ServerEphemeralText(id, name.clone(), text.clone(), secs),
+                    &[*sender]
+                );
+
+                ephemerals.pending.push((Instant::now() + Duration::from_secs(secs), id));
+                seen.lock().unwrap().record(name, SeenEvent::Spoke);
+
+            } else {
+                println!("[server] Unable to get client name by id.");
+            }
+
+        },
+        ClientUrgentText(text) => {
+
+            if config.urgent_moderators_only && !clients.lock().unwrap().can_moderate(*sender) {
+                server_send_message(
+                    clients,
+                    *sender,
+                    &ServerRejected("Only the host and the operator can send urgent messages".to_string())
+                );
+                server_strike(msg, sender, clients, client_names, seen, ephemerals, config);
+                return;
+            }
+
+            if let Some(name) = client_names.lock().unwrap().get(sender) {
+
+                server_distribute_message(
+                    clients,
+                    &ServerUrgentText(name.clone(), text.clone()),
+                    &[*sender]
+                );
+
+                fire_webhooks(&config.webhooks, name, text, &config.room_name);
+                seen.lock().unwrap().record(name, SeenEvent::Spoke);
+
+            } else {
+                println!("[server] Unable to get client name by id.");
+            }
+
+        },
+        ClientSealedText(sealed) => {
+            if let Some(reason) = server_refuse_sealed(sealed, clients, config) {
+                server_send_message(clients, *sender, &ServerRejected(reason));
+                server_strike(msg, sender, clients, client_names, seen, ephemerals, config);
+                return;
+            }
+            if server_hide_if_shadowbanned(sender, clients) {
+                return;
+            }
+
+            // webhooks can't read it, so it isn't passed on to them
+            if let Some(name) = client_names.lock().unwrap().get(sender) {
+
+                server_distribute_message(
+                    clients,
+                    &ServerSealedText(name.clone(), sealed.clone()),
+                    &[*sender]
+                );
+
+                seen.lock().unwrap().record(name, SeenEvent::Spoke);
+
+            } else {
+                println!("[server] Unable to get client name by id.");
+            }
+
+        },
+        ClientVoice(clip) => {
+            if let Some(reason) = server_refuse_voice(clip, config) {
+                server_send_message(clients, *sender, &ServerRejected(reason));
+                server_strike(msg, sender, clients, client_names, seen, ephemerals, config);
+                return;
+            }
+
+            let since_last = {
+                let mut room = clients.lock().unwrap();
+                let Some(client) = room.iter_mut().find(|client| &client.id == sender) else {
+                    return;
+                };
+                let since_last = client.last_voice.map(|at| at.elapsed());
+                if since_last.is_none_or(|elapsed| elapsed >= VOICE_COOLDOWN) {
+                    client.last_voice = Some(Instant::now());
+                }
+                since_last
+            };
+            if let Some(wait) = since_last.and_then(|elapsed| VOICE_COOLDOWN.checked_sub(elapsed)) {
+                let reason = format!("You can send another voice clip in {}", humanize_duration(wait));
+                server_send_message(clients, *sender, &ServerRejected(reason));
+                return;
+            }
+
+            if server_hide_if_shadowbanned(sender, clients) {
+                return;
+            }
+
+            // clips skip webhooks, which only know how to pass on text
+            if let Some(name) = client_names.lock().unwrap().get(sender) {
+
+                server_distribute_message(
+                    clients,
+                    &ServerVoice(name.clone(), clip.clone()),
+                    &[*sender]
+                );
+
+                seen.lock().unwrap().record(name, SeenEvent::Spoke);
+
+            } else {
+                println!("[server] Unable to get client name by id.");
+            }
+
+        },
+        ClientAttachment(attachment) => {
+            if let Some(reason) = server_refuse_attachment(attachment, config) {
+                server_send_message(clients, *sender, &ServerRejected(reason));
+                server_strike(msg, sender, clients, client_names, seen, ephemerals, config);
+                return;
+            }
+
+            // attachments aren't acknowledged, so there's nothing to fake for the shadowbanned
+            let shadowbanned = clients.lock().unwrap().iter()
+                .any(|client| &client.id == sender && client.shadowbanned);
+            if shadowbanned {
+                return;
+            }
+
+            // like clips, attachments skip webhooks
+            if let Some(name) = client_names.lock().unwrap().get(sender) {
+
+                server_distribute_attachment(clients, name, attachment, *sender);
+                seen.lock().unwrap().record(name, SeenEvent::Spoke);
+
+            } else {
+                println!("[server] Unable to get client name by id.");
+            }
+
+        },
+        ClientGoodbye => {
+            // perform removal of client
+            let leaver = {
+                let mut unlocked = clients.lock().unwrap();
+                let index = unlocked.iter().position(|client| &client.id == sender);
+                index.map(|i| unlocked.remove(i))
+            };
+
+            // let everyone else know they left
+            if let Some(name) = client_names.lock().unwrap().remove(sender) {                     
+
+                if let Some(leaver) = leaver {
+                    clients.lock().unwrap().connections
+                        .record(ConnectionChange::Disconnected, leaver.id, &name, leaver.address);
+                }
+                seen.lock().unwrap().record(&name, SeenEvent::Left);
+                server_distribute_message(
+                    clients,
+                    &ServerText("[server]".to_string(), format!("{name} has left the room")),
+                    &[]
+                );
+
+            } else {
+                println!("[server] A client was removed, but I was unable to tell the other clients");
+            }
+
+        },
+        ClientRename(new_name) => {
+            let since_last = clients.lock().unwrap().iter()
+                .find(|client| &client.id == sender)
+                .and_then(|client| client.last_rename)
+                .map(|time| time.elapsed());
+
+            if let Some(wait) = since_last.and_then(|elapsed| RENAME_COOLDOWN.checked_sub(elapsed)) {
+                let text = format!("You can change your name again in {}", humanize_duration(wait));
+                server_send_message(clients, *sender, &ServerText("[server]".to_string(), text));
+                server_strike(msg, sender, clients, client_names, seen, ephemerals, config);
+                return;
+            }
+
+            let mut names = client_names.lock().unwrap();
+
+            let others = names.iter()
+                .filter(|(id, _)| id != &sender)
+                .map(|(_, name)| name.as_str());
+
+            match check_name(new_name, others) {
+                Ok(new_name) => {
+                    let old_name = names.insert(*sender, new_name.clone());
+                    drop(names);
+
+                    let Some(old_name) = old_name.filter(|old_name| old_name != &new_name) else {
+                        return;
+                    };
+
+                    let mut room = clients.lock().unwrap();
+                    if let Some(client) = room.clients.iter_mut().find(|client| &client.id == sender) {
+                        client.last_rename = Some(Instant::now());
+
+                        // a new name doesn't shake off a shadowban either
+                        if client.shadowbanned {
+                            room.shadowbanned.remove(&old_name.to_lowercase());
+                            room.shadowbanned.insert(new_name.to_lowercase());
+                        }
+                    }
+                    drop(room);
+
+                    // everyone is told, so nobody can quietly take on someone else's identity
+                    server_distribute_message(clients, &ServerRenamed(old_name, new_name), &[]);
+                },
+                Err(reason) => {
+                    drop(names);
+                    server_send_message(clients, *sender, &ServerText("[server]".to_string(), reason));
+                },
+            }
+        },
+        ClientKick(who) => {
+
+            if !clients.lock().unwrap().can_moderate(*sender) {
+                println!("[server] Client {sender} tried to kick someone without being the host or operator");
+                server_strike(msg, sender, clients, client_names, seen, ephemerals, config);
+                return;
+            }
+
+            if who == &HOST_ID || who == sender {
+                println!("[server] Client {sender} cannot kick the host or themselves");
+                return;
+            }
+            
+            // take the client out of the list first so the lock isn't held while notifying them
+            let kickee = {
+                let mut unlocked = clients.lock().unwrap();
+                let index = unlocked.iter().position(|client| &client.id == who);
+                index.map(|i| unlocked.remove(i))
+            };
+
+            match kickee {
+                Some(kickee) => {
+                    if kickee.conn.lock().unwrap().send(&ServerNotifyKick).is_err() {
+                        println!("[server] Unable to notify client that they were kicked.");
+                    }
+
+                    // let everyone else know they left
+                    if let Some(name) = client_names.lock().unwrap().remove(who) {                     
+
+                        clients.lock().unwrap().connections
+                            .record(ConnectionChange::Kicked, kickee.id, &name, kickee.address);
+                        seen.lock().unwrap().record(&name, SeenEvent::Kicked);
+                        let by = if sender == &HOST_ID { "the host" } else { "the operator" };
+                        server_distribute_message(
+                            clients,
+                            &ServerText("[server]".to_string(), format!("{name} was kicked by {by}")),
+                            &[]
+                        );
+
+                    } else {
+                        println!("[server] A client was kicked, but I was unable to tell the other clients");
+                    }
+                },
+                None => println!("[server] Client with id {who} does not exist"),
+            }
+        },
+        ClientRequestIDs => {
+            let unlocked = clients.lock().unwrap();
+
+            let roster: Vec<_> = {
+                let names = client_names.lock().unwrap();
+                unlocked.iter()
+                    .filter_map(|client| Some(RosterEntry {
+                        id: client.id,
+                        name: names.get(&client.id)?.clone(),
+                        status: client.status.clone(),
+                        idle_secs: client.last_activity.elapsed().as_secs(),
+                        presence: client.presence,
+                        address: unlocked.can_moderate(*sender).then_some(client.address),
+                        hostname: unlocked.can_moderate(*sender)
+                            .then(|| unlocked.hostnames.get(client.address.ip()))
+                            .flatten(),
+                    }))
+                    .collect()
+            };
+            
+            drop(unlocked);
+            server_send_message(clients, *sender, &ServerResponseIDs(roster));
+        },
+        ClientSetStatus(status) => {
+            let status: Option<String> = status.as_ref()
+                .map(|s| s.chars().take(MAX_STATUS_LEN).collect());
+
+            match clients.lock().unwrap().iter_mut().find(|client| &client.id == sender) {
+                Some(client) => client.status = status.clone(),
+                None => {
+                    println!("[server] Client with id {sender} could not be found");
+                    return;
+                },
+            }
+
+            if !config.announce_status {
+                return;
+            }
+
+            let Some(name) = client_names.lock().unwrap().get(sender).cloned() else {
+                println!("[server] Unable to get client name by id.");
+                return;
+            };
+
+            let text = match status {
+                Some(status) => format!("{name}'s status: {status}"),
+                None => format!("{name} cleared their status"),
+            };
+            server_distribute_message(clients, &ServerText("[server]".to_string(), text), &[]);
+        },
+        ClientShadowban(who) => {
+
+            if !clients.lock().unwrap().can_moderate(*sender) {
+                println!("[server] Client {sender} tried to shadowban someone without being the host or operator");
+                server_strike(msg, sender, clients, client_names, seen, ephemerals, config);
+                return;
+            }
+
+            let name = client_names.lock().unwrap().get(who).cloned().unwrap_or_default();
+
+            let mut room = clients.lock().unwrap();
+            let banned = match room.clients.iter_mut().find(|client| &client.id == who) {
+                Some(client) => {
+                    client.shadowbanned = !client.shadowbanned;
+                    client.shadowbanned
+                },
+                None => {
+                    println!("[server] Client with id {who} could not be found");
+                    return;
+                },
+            };
+            if banned {
+                room.shadowbanned.insert(name.to_lowercase());
+            } else {
+                room.shadowbanned.remove(&name.to_lowercase());
+            }
+            drop(room);
+
+            let text = if banned {
+                format!("{name} is shadowbanned, only they can see their messages now")
+            } else {
+                format!("{name} is no longer shadowbanned")
+            };
+            server_send_message(clients, *sender, &ServerText("[server]".to_string(), text));
+        },
+        ClientRequestConnections => {
+
+            if !clients.lock().unwrap().can_moderate(*sender) {
+                println!("[server] Client {sender} tried to see the connection log without being the host or operator");
+                server_strike(msg, sender, clients, client_names, seen, ephemerals, config);
+                return;
+            }
+
+            let room = clients.lock().unwrap();
+            let events = room.connections.events().into_iter()
+                .map(|event| {
+                    // names expire, so the lookup is started over for anyone who's been around a while
+                    room.hostnames.resolve(event.address.ip());
+                    ConnectionEvent { hostname: room.hostnames.get(event.address.ip()), ..event }
+                })
+                .collect();
+            drop(room);
+            server_send_message(clients, *sender, &ServerConnections(events));
+        },
+        ClientSeen(name) => {
+            let name = normalize(name);
+            let is_here = client_names.lock().unwrap().values()
+                .any(|n| n.to_lowercase() == name.to_lowercase());
+
+            let text = if is_here {
+                format!("{name} is in the room right now")
+            } else {
+                seen.lock().unwrap().describe(&name)
+            };
+
+            server_send_message(clients, *sender, &ServerText("[server]".to_string(), text));
+        },
+        ClientReport(who, reason) => {
+            let (reporter, reported) = {
+                let names = client_names.lock().unwrap();
+                (names.get(sender).cloned().unwrap_or_default(), names.get(who).cloned())
+            };
+            let Some(reported) = reported.filter(|_| who != sender) else {
+                let text = format!("There's nobody else with id {who} to report, !who lists everyone's ids");
+                server_send_message(clients, *sender, &ServerText("[server]".to_string(), text));
+                server_strike(msg, sender, clients, client_names, seen, ephemerals, config);
+                return;
+            };
+
+            let mut room = clients.lock().unwrap();
+            let Some(client) = room.clients.iter_mut().find(|client| &client.id == sender) else {
+                return;
+            };
+            if let Some(wait) = client.last_report.and_then(|at| REPORT_COOLDOWN.checked_sub(at.elapsed())) {
+                drop(room);
+                let text = format!("You can report someone again in {}", humanize_duration(wait));
+                server_send_message(clients, *sender, &ServerText("[server]".to_string(), text));
+                return;
+            }
+            client.last_report = Some(Instant::now());
+
+            let reason = match reason.trim() {
+                "" => "no reason given",
+                reason => reason,
+            };
+            if let Some(address) = room.iter().find(|client| &client.id == who).map(|client| client.address) {
+                room.connections.report(*who, &reported, address, &reporter, reason);
+            }
+
+            let report = Report {
+                reporter: reporter.clone(),
+                id: *who,
+                name: reported.clone(),
+                reason: reason.to_string(),
+                recent: room.history.recent_from(&reported, REPORT_CONTEXT),
+            };
+            // whoever was reported doesn't get to see it, even if they're a moderator
+            let moderators: Vec<u64> = room.iter()
+                .map(|client| client.id)
+                .filter(|id| room.can_moderate(*id) && id != who)
+                .collect();
+            drop(room);
+
+            println!("[server] {reporter} reported {reported}: {reason}");
+            for moderator in &moderators {
+                server_send_message(clients, *moderator, &ServerReport(report.clone()));
+            }
+
+            let text = if moderators.is_empty() {
+                format!("Nobody is moderating right now, but your report about {reported} was logged")
+            } else {
+                format!("Your report about {reported} was passed on to the moderators")
+            };
+            server_send_message(clients, *sender, &ServerText("[server]".to_string(), text));
+        },
+        ClientRequestHistory { before_id, limit } => {
+            let page = clients.lock().unwrap().history
+                .page(*before_id, (*limit).min(MAX_HISTORY_PAGE) as usize);
+            server_send_message(clients, *sender, &ServerHistoryPage(page));
+        },
+        ClientBackfill(first, last) => {
+            let mut room = clients.lock().unwrap();
+
+            // clients picking up after reconnecting ask for everything from where they left off
+            let Some(last) = room.history.next_seq.checked_sub(1).map(|latest| latest.min(*last)) else {
+                return;
+            };
+            let first = *first;
+            if first > last {
+                return;
+            }
+
+            // whoever a broadcast was held back from still only gets its number
+            let found: Vec<ServerMessage> = room.history.messages.iter()
+                .filter(|recorded| (first..=last).contains(&recorded.seq))
+                .map(|recorded| if recorded.excluded.contains(sender) {
+                    ServerSent(recorded.seq)
+                } else {
+                    ServerSequenced(recorded.seq, Box::new(recorded.msg.clone()))
+                })
+                .collect();
+
+            let Some(client) = room.iter_mut().find(|client| &client.id == sender) else {
+                println!("[server] Client with id {sender} could not be found");
+                return;
+            };
+
+            // older messages may have been dropped from the history or expired
+            let lost = (last - first + 1).saturating_sub(found.len() as u64);
+            let recovered = found.iter().filter(|msg| matches!(msg, ServerSequenced(..))).count();
+
+            let mut replies = Vec::new();
+            if recovered > 0 {
+                replies.push(ServerText(
+                    "[server]".to_string(),
+                    format!("Recovering {recovered} missed message(s)")
+                ));
+            }
+            if lost > 0 {
+                replies.push(ServerText(
+                    "[server]".to_string(),
+                    format!("{lost} missed message(s) could not be recovered")
+                ));
+            }
+
+            // queued so the whole lot goes out in one write at the end of the tick
+            for reply in replies.iter().chain(&found) {
+                if client.conn.lock().unwrap().queue(reply).is_err() {
+                    println!("[server] A client did not receive a message!");
+                    break;
+                }
+            }
+        },
+        other => println!("[server] Got unusual message from client: {other:?}."),
+    }  
+}
+
+
+/// While the host is gone, make sure someone is standing in for them. The longest connected client
+/// becomes the operator, and if they leave too, the next longest takes over.
+fn server_elect_operator(clients: &Clients, client_names: &ClientNames) {
+    let mut room = clients.lock().unwrap();
+    let Some(host_name) = room.absent_host.clone() else {
+        return;
+    };
+    if room.operator.is_some_and(|id| room.iter().any(|client| client.id == id)) {
+        return;
+    }
+
+    // clients are kept in the order they joined
+    let Some(client) = room.first_mut() else {
+        room.operator = None;
+        return;
+    };
+    let id = client.id;
+    if client.conn.lock().unwrap().queue(&ServerOperator(true)).is_err() {
+        println!("[server] Unable to tell client {id} they're the operator");
+    }
+    room.operator = Some(id);
+    drop(room);
+
+    let name = client_names.lock().unwrap().get(&id).cloned().unwrap_or_default();
+    println!("[server] Client {id} is standing in for the host");
+    server_distribute_message(
+        clients,
+        &ServerText("[server]".to_string(), format!("{name} is the operator until {host_name} is back")),
+        &[]
+    );
+}
+
+
+/// Give `sender` a strike for `msg` (see `Client::strike`), dropping them if they've had too many
+fn server_strike(
+    msg: &ClientMessage,
+    sender: &u64,
+    clients: &Clients,
+    client_names: &ClientNames,
+    seen: &Seen,
+    ephemerals: &mut Ephemerals,
+    config: &ServerConfig
+) {
+    let struck_out = clients.lock().unwrap().iter_mut()
+        .find(|client| &client.id == sender)
+        .is_some_and(|client| client.strike());
+
+    if struck_out {
+        println!("[server] Dropping client {sender} for misbehaving, last sent {msg:?}");
+        server_handle_message(&ClientGoodbye, sender, clients, client_names, seen, ephemerals, config);
+    }
+}
+
+
+/// Decide whether a message from `sender` shouldn't be sent to the room, returning why. Messages
+/// that are too long are refused, as are repeats of the sender's last message within
+/// `REPEAT_WINDOW`, whether they're accidental double-sends or flooding.
+fn server_refuse_text(
+    text: &str,
+    sender: &u64,
+    clients: &Clients,
+    config: &ServerConfig
+) -> Option<String> {
+
+    if text.len() > config.max_message_len {
+        return Some(format!(
+            "Message is too long ({} bytes, the limit is {}). Try splitting it up.",
+            text.len(),
+            config.max_message_len
+        ));
+    }
+
+    let mut room = clients.lock().unwrap();
+    let client = room.iter_mut().find(|client| &client.id == sender)?;
+    let now = Instant::now();
+
+    match &mut client.last_text {
+        Some((last, at, times)) if last == text && now.duration_since(*at) < REPEAT_WINDOW => {
+            *at = now;
+            *times += 1;
+            Some(format!("You just sent that (x{times})"))
+        },
+        _ => {
+            client.last_text = Some((text.to_string(), now, 1));
+            None
+        },
+    }
+}
+
+
+/// Why a sealed message can't be sent to the room, if it can't. Only its length can be checked,
+/// since the server can't read it.
+fn server_refuse_sealed(sealed: &Sealed, clients: &Clients, config: &ServerConfig) -> Option<String> {
+    if clients.lock().unwrap().salt.is_none() {
+        return Some("This room isn't encrypted, so there's no need to seal messages".to_string());
+    }
+
+    let len = sealed.ciphertext.len().saturating_sub(TAG_LEN);
+    if len > config.max_message_len {
+        return Some(format!(
+            "Message is too long ({len} bytes, the limit is {}). Try splitting it up.",
+            config.max_message_len
+        ));
+    }
+
+    None
+}
+
+
+/// Why a voice clip can't be sent to the room, if it can't
+fn server_refuse_voice(clip: &VoiceClip, config: &ServerConfig) -> Option<String> {
+    if config.max_voice_len == 0 {
+        return Some("Voice clips aren't allowed in this room".to_string());
+    }
+
+    if clip.data.len() > config.max_voice_len {
+        return Some(format!(
+            "Voice clip is too big ({} KiB, the limit is {} KiB). Try a shorter one.",
+            clip.data.len().div_ceil(1024),
+            config.max_voice_len / 1024
+        ));
+    }
+
+    if clip.data.is_empty() || !clip.has_valid_format() {
+        return Some(format!("`{}` isn't an audio format that can be sent", clip.format));
+    }
+
+    None
+}
+
+
+/// Why an attachment can't be sent to the room, if it can't. Checked as soon as it's announced, by
+/// the length it's announced with, so nothing is held on to for one that's going to be refused.
+fn server_refuse_attachment(attachment: &Attachment, config: &ServerConfig) -> Option<String> {
+    if config.max_attachment_len == 0 {
+        return Some("Attachments aren't allowed in this room".to_string());
+    }
+
+    if attachment.len > config.max_attachment_len as u64 {
+        return Some(format!(
+            "{} is too big to attach ({} KiB, the limit is {} KiB)",
+            attachment.name,
+            attachment.len.div_ceil(1024),
+            config.max_attachment_len / 1024
+        ));
+    }
+
+    if attachment.len == 0 || !attachment.has_valid_name() {
+        return Some(format!("`{}` isn't a file that can be attached", attachment.name));
+    }
+
+    None
+}
+
+
+/// If `sender` is shadowbanned, acknowledge their message as if it had been sent to the room and
+/// return true. The acknowledgement repeats the latest sequence number rather than using up a new
+/// one, since everyone else would notice the gap.
+fn server_hide_if_shadowbanned(sender: &u64, clients: &Clients) -> bool {
+    let mut room = clients.lock().unwrap();
+    let latest = room.history.next_seq.saturating_sub(1);
+
+    let Some(client) = room.iter_mut().find(|client| &client.id == sender) else {
+        return false;
+    };
+    if !client.shadowbanned {
+        return false;
+    }
+
+    if client.conn.lock().unwrap().queue(&ServerSent(latest)).is_err() {
+        println!("[server] A client did not receive a message!");
+    }
+    true
+}
+
+
+/// A client that's made its handshake and said hello, waiting to be let in
+struct Greeted {
+    conn: TcpConn<WireFormat, Socket>,
+    address: SocketAddr,
+    /// Whether they came in through the host's connector, see `ServerHandle::connector`
+    host: bool,
+    /// The key they made a Noise handshake with, if they did
+    client_key: Option<[u8; 32]>,
+    capabilities: Capabilities,
+    /// The name they asked for, which hasn't been checked yet
+    name: String,
+}
+
+/// Continuously listen for incoming connections. Each one makes its handshake and says hello on a
+/// thread of its own, so someone who connects and then goes quiet can't hold up everyone behind
+/// them, and only the clients that get that far are handed on to `server_admit_clients`.
+fn server_accept_connections(
+    incoming: Receiver<Socket>,
+    clients: Clients,
+    client_names: ClientNames,
+    seen: Seen,
+    room_name: String,
+    accepting: Accepting,
+    recording: Recording
+) {
+    let Accepting { socket, noise_key, limits } = accepting;
+
+    let (greeted_sender, greeted) = mpsc::channel();
+    let clients_clone = Arc::clone(&clients);
+    thread::Builder::new()
+        .name(String::from("server admission thread"))
+        .spawn(move || server_admit_clients(greeted, clients_clone, client_names, seen, limits, recording))
+        .unwrap();
+
+    println!("[server] Open for connections");
+
+    let socket = Arc::new(socket);
+    let noise_key = Arc::new(noise_key);
+    let room_name = Arc::new(room_name);
+    let greeting = Arc::new(AtomicUsize::new(0));
+
+    // Receive incoming client connections until the room is shut down
+    for client in incoming {
+        if clients.lock().unwrap().closed {
+            break;
+        }
+
+        // clients on a Unix socket or in this process are on this machine, so they're treated like
+        // loopback ones
+        let address = client.peer_addr().unwrap_or(SocketAddr::new(Ipv4Addr::LOCALHOST.into(), 0));
+
+        if greeting.fetch_add(1, Ordering::SeqCst) >= MAX_GREETING {
+            greeting.fetch_sub(1, Ordering::SeqCst);
+            println!("[server] Turned away {address}, too many clients are connecting at once");
+            continue;
+        }
+
+        let socket = Arc::clone(&socket);
+        let noise_key = Arc::clone(&noise_key);
+        let room_name = Arc::clone(&room_name);
+        let clients = Arc::clone(&clients);
+        let greeted_sender = greeted_sender.clone();
+        let greeting_clone = Arc::clone(&greeting);
+        let spawned = thread::Builder::new()
+            .name(String::from("server greeting thread"))
+            .spawn(move || {
+                if let Some(greeted) = server_greet(client, address, &socket, &noise_key, &clients, &room_name) {
+                    // nobody's being let in anymore if this fails, and dropping them hangs up
+                    let _ = greeted_sender.send(greeted);
+                }
+                greeting_clone.fetch_sub(1, Ordering::SeqCst);
+            });
+        if let Err(e) = spawned {
+            greeting.fetch_sub(1, Ordering::SeqCst);
+            println!("[server] Unable to start greeting {address}: {e}");
+        }
+    }
+    println!("[server] Stopped listening for connections");
+}
+
+/// Make the handshake with a new client and settle how they'll talk, up to their hello. Room info
+/// queries are answered here too. Returns `None` for anyone who isn't joining, or who didn't get
+/// that far.
+fn server_greet(
+    client: Socket,
+    address: SocketAddr,
+    socket: &TcpConnBuilder,
+    noise_key: &Keypair,
+    clients: &Clients,
+    room_name: &str
+) -> Option<Greeted> {
+    let host = matches!(&client, Socket::Local(stream) if stream.is_host());
+
+    // block for first message from new client before moving on so we can get their name
+    let mut conn = socket.build_socket(client).ok()?;
+
+    // clients that open with a Noise handshake are encrypted from here on
+    let client_key = match conn.noise_accept(noise_key) {
+        Ok(key) => key,
+        Err(e) => {
+            println!("[server] Unable to make a Noise handshake with {address}: {e}");
+            return None;
+        },
+    };
+    if client_key.is_none() && socket.uses_noise() {
+        let reason = "This server only accepts encrypted connections. Turn on `noise` in your socket settings and try again.";
+        if conn.send(&ServerText("[server]".to_string(), reason.to_string())).is_err() {
+            println!("[server] Unable to tell {address} it needs to use Noise");
+        }
+        return None;
+    }
+
+    // clients that want something other than JSON ask before their hello
+    let mut conn = conn.into_codec(WireFormat::Json);
+    let first = match conn.receive_timeout::<ClientMessage>(Duration::from_secs(5)) {
+        Ok(ClientWireFormats(offered)) if offered.len() <= MAX_WIRE_FORMATS => {
+            let format = offered.iter()
+                .find_map(|name| WireFormat::from_name(name))
+                .unwrap_or_default();
+            if conn.send(&ServerWireFormat(format.name().to_string())).is_err() {
+                println!("[server] Unable to tell {address} which wire format to use");
+                return None;
+            }
+            conn = conn.into_codec(format);
+            conn.receive_timeout::<ClientMessage>(Duration::from_secs(5))
+        },
+        other => other,
+    };
+
+    // as do clients that can do more than the basics
+    let mut capabilities = Capabilities::default();
+    let first = match first {
+        Ok(ClientCapabilities(offered)) if offered.len() <= MAX_CAPABILITIES => {
+            capabilities = Capabilities::from_names(&offered);
+            if conn.send(&ServerHello(capabilities.names())).is_err() {
+                println!("[server] Unable to tell {address} which capabilities to use");
+                return None;
+            }
+            conn.receive_timeout::<ClientMessage>(Duration::from_secs(5))
+        },
+        other => other,
+    };
+
+    match first {
+        Ok(msg) if !msg.within_limits() => {
+            println!("[server] Client sent a message over the size limits, expected `ClientHello(<some name>)`");
+            None
+        },
+        Ok(ClientHello(name)) => Some(Greeted { conn, address, host, client_key, capabilities, name }),
+        Ok(ClientQueryInfo) => {
+            let room = clients.lock().unwrap();
+            let info = RoomInfo {
+                room_name: room_name.to_string(),
+                user_count: room.len(),
+                password_required: false,
+                encrypted: room.salt.is_some(),
+                protocol_version: PROTOCOL_VERSION,
+            };
+            drop(room);
+            if conn.send(&ServerInfo(info)).is_err() {
+                println!("[server] Unable to reply to a room info query");
+            }
+            // they'll connect again if they decide to join
+            None
+        },
+        Ok(other) => {
+            println!("[server] Client sent invalid response. Expected `ClientHello(<some name>)`, got `{:?}`", other);
+            // we skip this bad client
+            None
+        },
+        Err(e) => {
+            println!("[server] Error reading client's connection: {}", e);
+            // skip client
+            None
+        },
+    }
+}
+
+/// Let in every client `greeted` hands over, one at a time, until the room is shut down. Names are
+/// checked here rather than while greeting, so two clients joining at once can't both get the same
+/// one.
+fn server_admit_clients(
+    greeted: Receiver<Greeted>,
+    clients: Clients,
+    client_names: ClientNames,
+    seen: Seen,
+    limits: Limits,
+    recording: Recording
+) {
+    let mut next_id = HOST_ID + 1;
+    let mut host_joined = false;
+
+    for Greeted { mut conn, address, host, client_key, capabilities, name } in greeted {
+        if clients.lock().unwrap().closed {
+            break;
+        }
+
+        let checked = check_name(
+            &name,
+            client_names.lock().unwrap().values().map(String::as_str)
+        );
+
+        // turn away lookalikes before anyone sees them join
+        let client_name = match checked {
+            Ok(name) => name,
+            Err(reason) => {
+                let reason = format!("{reason}. Pick another name and try again.");
+                if conn.send(&ServerText("[server]".to_string(), reason)).is_err() {
+                    println!("[server] Unable to tell a client why their name was refused");
+                }
+                continue;
+            },
+        };
+
+        // say which name they got before anyone hears about them, so a client that can't be
+        // told never shows up in the room
+        if capabilities.contains(Capability::Welcome) && conn.send(&ServerWelcome(client_name.clone())).is_err() {
+            println!("[server] Unable to welcome {client_name}");
+            continue;
+        }
+
+        let msg = ServerText("[server]".to_string(), format!("{client_name} has joined the room!"));
+
+        // let the new client and everyone else know someone joined
+        server_distribute_message(
+            &clients,
+            &msg,
+            &[]
+        );
+
+        if conn.send(&msg).is_err() {
+            // let everyone know this client could not be connected with
+            server_distribute_message(
+                &clients,
+                &ServerText("[server]".to_string(), format!("{client_name} left the room")),
+                &[]
+            );
+            // skip adding the client
+            continue;
+        }
+
+        conn.set_nonblocking(true).unwrap();
+
+        // the host's first time in, they get the id that makes them the host
+        let id = if host && !host_joined {
+            host_joined = true;
+            HOST_ID
+        } else {
+            next_id += 1;
+            next_id - 1
+        };
+
+        let mut new_client = Client {
+            id,
+            conn: Arc::new(Mutex::new(ServerConn::new(conn))),
+            address,
+            capabilities,
+            status: None,
+            last_activity: Instant::now(),
+            last_heard: Instant::now(),
+            presence: Presence::Online,
+            last_text: None,
+            shadowbanned: false,
+            strikes: 0,
+            last_strike: Instant::now(),
+            tarpit_until: Instant::now(),
+            last_rename: None,
+            last_report: None,
+            last_voice: None,
+            attachments: VecDeque::new(),
+        };
+        
+        if let Some(key) = client_key {
+            println!("[server] {client_name} connected with the key {}", fingerprint(&key));
+        }
+        seen.lock().unwrap().record(&client_name, SeenEvent::Joined);
+        if let Some(recorder) = &recording {
+            recorder.record(id, Event::Joined(client_name.clone()));
+        }
+        let mut room = clients.lock().unwrap();
+        new_client.shadowbanned = room.shadowbanned.contains(&client_name.to_lowercase());
+        if capabilities.contains(Capability::Limits) && new_client.conn.lock().unwrap().queue(&ServerLimits(limits)).is_err() {
+            println!("[server] Unable to tell client {id} what the room accepts");
+        }
+        if let Some(salt) = &room.salt {
+            if new_client.conn.lock().unwrap().queue(&ServerEncrypted(salt.clone())).is_err() {
+                println!("[server] Unable to tell client {id} the room is encrypted");
+            }
+        }
+        room.connections.record(ConnectionChange::Connected, id, &client_name, address);
+        room.hostnames.resolve(address.ip());
+        room.push(new_client);
+
+        // the host coming back joins through their connector again
+        let host_returned = host && room.absent_host.is_some();
+        if host_returned {
+            room.absent_host = None;
+            let previous = room.operator.replace(id);
+            for client in room.iter_mut() {
+                let operator = if client.id == id {
+                    true
+                } else if Some(client.id) == previous {
+                    false
+                } else {
+                    continue;
+                };
+                if client.conn.lock().unwrap().queue(&ServerOperator(operator)).is_err() {
+                    println!("[server] Unable to tell client {} whether they're the operator", client.id);
+                }
+            }
+        }
+        drop(room);
+
+        if host_returned {
+            server_distribute_message(
+                &clients,
+                &ServerText("[server]".to_string(), format!("{client_name} is back and moderating the room again")),
+                &[]
+            );
+        }
+        client_names.lock().unwrap().insert(id, client_name);
+    }
+}
+
+
+/// Send `msg` to every client. Improvement idea: accept iterator instead of `&Clients` to allow
+/// easy filtering of which clients receive messages. Each broadcast is numbered and kept in the
+/// history, and excluded clients are just told its number so they don't think they missed it. It's
+/// queued to go out with the next `server_flush`.
+fn server_distribute_message(clients: &Clients, msg: &ServerMessage, exclude: &[u64]) {
+    let mut room = clients.lock().unwrap();
+
+    let seq = room.history.record(msg.clone(), exclude);
+
+    // serialized once per wire format in use rather than for every client, and shared between
+    // every client that uses it
+    let sequenced_msg = ServerSequenced(seq, Box::new(msg.clone()));
+    let mut frames = Vec::new();
+
+    // sent at the end of the tick along with anything else for the same client
+    for client in room.iter_mut() {
+        let mut conn = client.conn.lock().unwrap();
+        let format = *conn.codec();
+
+        let index = match frames.iter().position(|(framed, _, _)| *framed == format) {
+            Some(index) => index,
+            None => {
+                let mut sequenced = POOL.take();
+                let mut sent = POOL.take();
+                let framed = encode_frame_with(&format, &sequenced_msg, &mut sequenced)
+                    .and_then(|_| encode_frame_with(&format, &ServerSent(seq), &mut sent));
+                if let Err(e) = framed {
+                    println!("[server] Unable to serialize a broadcast: {e}");
+                    return;
+                }
+                frames.push((format, Arc::<[u8]>::from(&sequenced[..]), Arc::<[u8]>::from(&sent[..])));
+                frames.len() - 1
+            },
+        };
+
+        let (_, sequenced, sent) = &frames[index];
+        let frame = if exclude.contains(&client.id) { sent } else { sequenced };
+        conn.queue_shared(frame);
+    }
+}
+
+
+/// Send an attachment from `sender` to everyone else, its bytes in a raw frame right behind the
+/// message announcing it. Unlike other broadcasts it isn't numbered or kept, see
+/// `ServerAttachment`.
+fn server_distribute_attachment(clients: &Clients, from: &str, attachment: &Attachment, sender: u64) {
+    let mut raw = POOL.take();
+    if let Err(e) = encode_raw_frame(&attachment.data, &mut raw) {
+        println!("[server] Unable to frame an attachment: {e}");
+        return;
+    }
+    // stamped for its channel up front, so everyone can share the one copy
+    let mut stamped = POOL.take();
+    restamp_frames(&raw, ATTACHMENT_CHANNEL, false, &mut stamped);
+    let raw = Arc::<[u8]>::from(&stamped[..]);
+
+    let msg = ServerAttachment(from.to_string(), attachment.clone());
+    let mut frames = Vec::new();
+
+    let mut room = clients.lock().unwrap();
+    for client in room.iter_mut().filter(|client| client.id != sender) {
+        let mut conn = client.conn.lock().unwrap();
+        let format = *conn.codec();
+
+        let index = match frames.iter().position(|(framed, _)| *framed == format) {
+            Some(index) => index,
+            None => {
+                let mut framed = POOL.take();
+                if let Err(e) = encode_frame_with(&format, &msg, &mut framed) {
+                    println!("[server] Unable to serialize an attachment: {e}");
+                    return;
+                }
+                frames.push((format, Arc::<[u8]>::from(&framed[..])));
+                frames.len() - 1
+            },
+        };
+
+        conn.queue_shared(&frames[index].1);
+        conn.queue_shared_on(ATTACHMENT_CHANNEL, &raw);
+    }
+}
+
+
+/// Send `msg` to a single client with the next `server_flush`
+fn server_send_message(clients: &Clients, id: u64, msg: &ServerMessage) {
+    match clients.lock().unwrap().iter_mut().find(|client| client.id == id) {
+        Some(client) => {
+            if client.conn.lock().unwrap().queue(msg).is_err() {
+                println!("[server] A client did not receive a message!");
+            }
+        },
+        None => println!("[server] Client with id {id} could not be found"),
+    }
+}
+
+
+/// Let `id` know their `ClientTrackedText` with `msg_id` went out, if they agreed to `Capability::Acks`
+fn server_acknowledge(clients: &Clients, id: u64, msg_id: u64) {
+    let mut room = clients.lock().unwrap();
+    let Some(client) = room.iter_mut().find(|client| client.id == id) else {
+        println!("[server] Client with id {id} could not be found");
+        return;
+    };
+    if client.capabilities.contains(Capability::Acks) && client.conn.lock().unwrap().queue(&ServerAck(msg_id)).is_err() {
+        println!("[server] A client did not receive a message!");
+    }
+}
+
+
+/// Save the room's state to the storage directory, if there is one, so it survives the server
+/// stopping
+fn server_snapshot(clients: &Clients, config: &ServerConfig) {
+    let Some(storage_dir) = &config.storage_dir else {
+        return;
+    };
+
+    let snapshot = clients.lock().unwrap().snapshot();
+    if let Err(e) = snapshot.save(storage_dir) {
+        println!("[server] Unable to save a snapshot of the room: {e}");
+    }
+}
+
+
+/// Send every client whatever was queued for them, in one write each. Whatever a client's
+/// connection can't take yet waits for the next flush, and the clients returned have fallen so
+/// far behind that they should be dropped.
+fn server_flush(clients: &Clients) -> Vec<u64> {
+    // the room is only locked long enough to see who's in it, so a client that's slow to take
+    // what it's sent doesn't hold up accepting new clients or reading from the rest
+    let conns: Vec<_> = clients.lock().unwrap().iter()
+        .map(|client| (client.id, Arc::clone(&client.conn)))
+        .collect();
+
+    let mut behind = Vec::new();
+    for (id, conn) in conns {
+        match conn.lock().unwrap().flush_queued() {
+            Ok(()) => {},
+            Err(e @ SendError::Backlog(_)) => {
+                println!("[server] Dropping client {id}, who isn't keeping up with what they're sent: {e}");
+                behind.push(id);
+            },
+            // a connection that's gone is noticed when it's next read from
+            Err(e) => println!("[server] Unable to send to client {id}: {e}"),
+        }
+    }
+    behind
+}
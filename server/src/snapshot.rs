@@ -0,0 +1,79 @@
+use std::fs;
+use std::io;
+use std::path::{Path, PathBuf};
+
+use serde::{Serialize, Deserialize};
+
+use tcp_chat_proto::packet::{ConnectionEvent, ServerMessage};
+
+
+
+const SNAPSHOT_FILE_NAME: &str = "snapshot.json";
+
+/// The room's state as of the last snapshot, written to the storage directory every so often so a
+/// crash or reboot doesn't lose it. Who's connected isn't included, since they all have to
+/// reconnect anyway.
+#[derive(Serialize, Deserialize, Debug, Default)]
+pub struct Snapshot {
+    /// Seconds since the unix epoch
+    pub taken: u64,
+    /// Carried on so message ids keep counting up from where they were, and clients never see
+    /// one reused
+    pub next_seq: u64,
+    pub history: Vec<SavedMessage>,
+    pub connections: Vec<ConnectionEvent>,
+    /// Lowercase names that are shadowbanned
+    pub shadowbanned: Vec<String>,
+    /// The salt the room's key is derived from, if it's end-to-end encrypted. Keeping it means
+    /// everyone's passphrase still opens the messages from before the restart.
+    #[serde(default)]
+    pub salt: Option<Vec<u8>>,
+}
+
+/// A broadcast from the history
+#[derive(Serialize, Deserialize, Debug)]
+pub struct SavedMessage {
+    pub seq: u64,
+    /// Seconds since the unix epoch
+    pub time: u64,
+    pub msg: ServerMessage,
+}
+
+impl Snapshot {
+    /// The snapshot in `storage_dir`, if there is one. An unreadable snapshot is reported and
+    /// otherwise treated as missing, so the server still starts.
+    pub fn load(storage_dir: &Path) -> Option<Self> {
+        let path = snapshot_path(storage_dir);
+        let contents = match fs::read_to_string(&path) {
+            Ok(contents) => contents,
+            Err(e) if e.kind() == io::ErrorKind::NotFound => return None,
+            Err(e) => {
+                println!("[server] Unable to read the snapshot at {}: {e}", path.display());
+                return None;
+            },
+        };
+
+        match serde_json::from_str(&contents) {
+            Ok(snapshot) => Some(snapshot),
+            Err(e) => {
+                println!("[server] Ignoring the snapshot at {}, it's invalid: {e}", path.display());
+                None
+            },
+        }
+    }
+
+    /// Write the snapshot to `storage_dir`, replacing the last one. It's written to a temporary
+    /// file first, so crashing partway through leaves the last snapshot intact.
+    pub fn save(&self, storage_dir: &Path) -> io::Result<()> {
+        fs::create_dir_all(storage_dir)?;
+
+        let path = snapshot_path(storage_dir);
+        let temporary = path.with_extension("json.tmp");
+        fs::write(&temporary, serde_json::to_string(self)?)?;
+        fs::rename(temporary, path)
+    }
+}
+
+fn snapshot_path(storage_dir: &Path) -> PathBuf {
+    storage_dir.join(SNAPSHOT_FILE_NAME)
+}
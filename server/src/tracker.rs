@@ -0,0 +1,94 @@
+// Running a tracker, and announcing the room to one
+
+use std::collections::HashMap;
+use std::net::{SocketAddr, TcpListener, TcpStream};
+use std::thread;
+use std::time::{Duration, Instant};
+
+use tcp_chat_proto::constants::*;
+use tcp_chat_proto::http;
+use tcp_chat_proto::tracker::{Announcement, PublicRoom};
+
+
+
+/// Rooms that haven't announced themselves for this long are assumed to be gone
+const ROOM_EXPIRY: Duration = Duration::from_secs(3 * 60);
+
+/// How often servers announce themselves. Comfortably shorter than `ROOM_EXPIRY` so one missed
+/// announcement doesn't drop the room from the list.
+const ANNOUNCE_INTERVAL: Duration = Duration::from_secs(60);
+
+/// Run a tracker on `port` forever
+pub fn tracker(port: u16) {
+    let listener = TcpListener::bind(SocketAddr::new(BIND_ADDR, port)).unwrap_or_else(|_| panic!(
+        "[error] Unable to bind to port {port}",
+    ));
+
+    println!("[tracker] Listening on port {port}");
+
+    let mut rooms = HashMap::<SocketAddr, (PublicRoom, Instant)>::new();
+
+    for mut stream in listener.incoming().flatten() {
+        rooms.retain(|_, (_, announced)| announced.elapsed() < ROOM_EXPIRY);
+
+        if let Err(e) = tracker_handle_request(&mut stream, &mut rooms) {
+            println!("[tracker] Unable to handle request: {e}");
+        }
+    }
+}
+
+fn tracker_handle_request(
+    stream: &mut TcpStream,
+    rooms: &mut HashMap<SocketAddr, (PublicRoom, Instant)>
+) -> std::io::Result<()> {
+
+    let Ok(request) = http::read_request(stream) else {
+        return http::respond(stream, "400 Bad Request");
+    };
+
+    match (request.method.as_str(), request.path.as_str()) {
+        ("POST", "/announce") => {
+            let Ok(announcement) = serde_json::from_slice::<Announcement>(&request.body) else {
+                return http::respond(stream, "400 Bad Request");
+            };
+
+            let address = SocketAddr::new(stream.peer_addr()?.ip(), announcement.port);
+            let room = PublicRoom {
+                name: announcement.name,
+                address,
+                user_count: announcement.user_count,
+            };
+            rooms.insert(address, (room, Instant::now()));
+
+            http::respond(stream, "204 No Content")
+        },
+        ("GET", "/rooms") => {
+            let list: Vec<_> = rooms.values().map(|(room, _)| room).collect();
+            http::respond_json(stream, &serde_json::to_string(&list)?)
+        },
+        _ => http::respond(stream, "404 Not Found"),
+    }
+}
+
+/// Spawn a thread that keeps announcing the room to the tracker at `tracker_url`. `user_count` is
+/// called before each announcement so the tracker's numbers stay current.
+pub fn announce<F>(tracker_url: String, name: String, user_count: F)
+where F: Fn() -> usize + Send + 'static {
+
+    thread::Builder::new()
+        .name(String::from("server tracker announcer"))
+        .spawn(move || loop {
+            let announcement = Announcement { name: name.clone(), port: PORT, user_count: user_count() };
+
+            let result = serde_json::to_string(&announcement)
+                .map_err(Into::into)
+                .and_then(|body| http::post_json(&format!("{tracker_url}/announce"), &body));
+
+            if let Err(e) = result {
+                println!("[server] Unable to announce the room to the tracker: {e}");
+            }
+
+            thread::sleep(ANNOUNCE_INTERVAL);
+        })
+        .unwrap();
+}
@@ -0,0 +1,195 @@
+use std::net::{TcpListener, TcpStream};
+use std::sync::Arc;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::thread;
+
+use serde::{Serialize, Deserialize};
+
+use tcp_chat_proto::http;
+use tcp_chat_proto::helpers::unix_time;
+use tcp_chat_proto::secrets::SecretStore;
+use crate::constants::MAX_WEBHOOK_REQUESTS;
+
+
+
+/// A URL that receives an HTTP POST for chat messages
+#[derive(Deserialize, Debug, Clone)]
+pub struct OutgoingWebhook {
+    pub url: String,
+
+    /// Only messages containing at least one of these (case-insensitive) are sent. An empty list
+    /// means every message is sent.
+    #[serde(default)]
+    pub keywords: Vec<String>,
+}
+
+impl OutgoingWebhook {
+    /// Whether this webhook wants to hear about `text`
+    fn matches(&self, text: &str) -> bool {
+        if self.keywords.is_empty() {
+            return true;
+        }
+        let text = text.to_lowercase();
+        self.keywords.iter().any(|k| text.contains(&k.to_lowercase()))
+    }
+}
+
+/// The JSON body POSTed to outgoing webhooks
+#[derive(Serialize, Debug)]
+struct WebhookEvent<'a> {
+    sender: &'a str,
+    text: &'a str,
+    room: &'a str,
+    /// Seconds since the unix epoch
+    timestamp: u64,
+}
+
+/// Notify every matching webhook about a message. Each request is made on its own thread so a slow
+/// or unreachable webhook can't hold up the room.
+pub fn fire_webhooks(webhooks: &[OutgoingWebhook], sender: &str, text: &str, room: &str) {
+    let event = WebhookEvent { sender, text, room, timestamp: unix_time() };
+    let Ok(body) = serde_json::to_string(&event) else {
+        println!("[server] Unable to serialize webhook event");
+        return;
+    };
+
+    for webhook in webhooks.iter().filter(|w| w.matches(text)) {
+        let url = webhook.url.clone();
+        let body = body.clone();
+        thread::Builder::new()
+            .name(String::from("server webhook"))
+            .spawn(move || {
+                if let Err(e) = http::post_json(&url, &body) {
+                    println!("[server] Webhook to {url} failed: {e}");
+                }
+            })
+            .unwrap();
+    }
+}
+
+
+/// A named endpoint that external systems can POST text to, which then gets broadcast to the room
+#[derive(Deserialize, Debug, Clone)]
+pub struct IncomingWebhook {
+    /// Shown as the sender, e.g. `[webhook/ci]`, and used in the URL path `/webhook/<name>`
+    pub name: String,
+
+    /// Requests must include the header `Authorization: Bearer <token>`. Better left out of the
+    /// config file and stored with `tcp_chat --set-secret webhook/<name>` instead, in which case
+    /// it's filled in by `load_token`.
+    pub token: Option<String>,
+}
+
+impl IncomingWebhook {
+    /// What the token is stored under in the `SecretStore`
+    pub fn secret_key(&self) -> String {
+        format!("webhook/{}", self.name)
+    }
+
+    /// Fill in the token from `secrets` if the config didn't give one. A webhook still without a
+    /// token afterwards rejects every request.
+    pub fn load_token(&mut self, secrets: &SecretStore) {
+        if self.token.is_some() {
+            return;
+        }
+        match secrets.get(&self.secret_key()) {
+            Ok(Some(token)) => self.token = Some(token),
+            Ok(None) => println!(
+                "[server] Incoming webhook `{}` has no token, set one with `tcp_chat --set-secret {}`",
+                self.name,
+                self.secret_key()
+            ),
+            Err(e) => println!("[server] Unable to load the token for incoming webhook `{}`: {e}", self.name),
+        }
+    }
+}
+
+/// Accept incoming webhook requests forever, calling `broadcast` with the sender name and text of
+/// every authenticated request. Each request is read on its own thread so a slow client can't hold
+/// up the others, and once `MAX_WEBHOOK_REQUESTS` are being read the rest are turned away.
+pub fn accept_webhooks<F>(listener: TcpListener, webhooks: Vec<IncomingWebhook>, broadcast: F)
+where F: Fn(String, String) + Send + Sync + 'static {
+
+    let webhooks = Arc::new(webhooks);
+    let broadcast = Arc::new(broadcast);
+    let active = Arc::new(AtomicUsize::new(0));
+
+    for mut stream in listener.incoming().flatten() {
+        if active.fetch_add(1, Ordering::SeqCst) >= MAX_WEBHOOK_REQUESTS {
+            active.fetch_sub(1, Ordering::SeqCst);
+            if let Err(e) = http::respond(&mut stream, "503 Service Unavailable") {
+                println!("[server] Unable to respond to webhook request: {e}");
+            }
+            continue;
+        }
+
+        let webhooks = Arc::clone(&webhooks);
+        let broadcast = Arc::clone(&broadcast);
+        let active = Arc::clone(&active);
+        thread::Builder::new()
+            .name(String::from("server webhook request thread"))
+            .spawn(move || {
+                let status = match handle_incoming(&mut stream, &webhooks) {
+                    Ok((name, text)) => {
+                        broadcast(format!("[webhook/{name}]"), text);
+                        "204 No Content"
+                    },
+                    Err(status) => status,
+                };
+
+                if let Err(e) = http::respond(&mut stream, status) {
+                    println!("[server] Unable to respond to webhook request: {e}");
+                }
+                active.fetch_sub(1, Ordering::SeqCst);
+            })
+            .unwrap();
+    }
+}
+
+/// Validate a webhook request, returning the webhook's name and the text to broadcast, or the HTTP
+/// status to respond with if the request is rejected.
+fn handle_incoming<'a>(
+    stream: &mut TcpStream,
+    webhooks: &'a [IncomingWebhook]
+) -> Result<(&'a str, String), &'static str> {
+
+    let request = http::read_request(stream).map_err(|_| "400 Bad Request")?;
+
+    if request.method != "POST" {
+        return Err("405 Method Not Allowed");
+    }
+
+    let webhook = request.path
+        .strip_prefix("/webhook/")
+        .and_then(|name| webhooks.iter().find(|w| w.name == name))
+        .ok_or("404 Not Found")?;
+
+    let authorized = request.header("Authorization")
+        .and_then(|v| v.strip_prefix("Bearer "))
+        .zip(webhook.token.as_deref())
+        .is_some_and(|(given, token)| constant_time_eq(given.as_bytes(), token.as_bytes()));
+
+    if !authorized {
+        return Err("401 Unauthorized");
+    }
+
+    let text = String::from_utf8(request.body).map_err(|_| "400 Bad Request")?;
+    let text = text.trim();
+
+    if text.is_empty() {
+        return Err("400 Bad Request");
+    }
+
+    Ok((&webhook.name, text.to_string()))
+}
+
+
+/// Compare two byte strings without returning early at the first difference, so how long the
+/// comparison takes doesn't tell an attacker how much of a token they guessed right. Only the
+/// length can be learned this way.
+fn constant_time_eq(a: &[u8], b: &[u8]) -> bool {
+    if a.len() != b.len() {
+        return false;
+    }
+    a.iter().zip(b).fold(0, |diff, (x, y)| diff | (x ^ y)) == 0
+}
@@ -0,0 +1,417 @@
+// A WebSocket gateway for browsers, which can't open plain TCP connections. Every text message a
+// browser sends is one `ClientMessage` as JSON, and every `ServerMessage` goes back as one, so a
+// browser client only needs `JSON.stringify` and `JSON.parse`. Attachments are followed by their
+// bytes as a binary message, in both directions. Behind the gateway, each browser is joined to the
+// room like any other client, over an in-process connection. The gateway agrees on capabilities
+// with the room itself, and answers a browser's `ClientCapabilities` with the ones both it and the
+// room can do.
+
+use std::io::{self, Read, Write};
+use std::net::{Shutdown, TcpListener, TcpStream};
+use std::sync::{Arc, Mutex};
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::thread;
+use std::time::Instant;
+
+use base64::Engine;
+use base64::engine::general_purpose::STANDARD;
+use sha1::{Digest, Sha1};
+
+use tcp_chat_proto::capability::{Capabilities, Capability};
+use tcp_chat_proto::codec::Json;
+use tcp_chat_proto::constants::{ATTACHMENT_CHANNEL, HEARTBEAT_INTERVAL};
+use tcp_chat_proto::http::{self, Request};
+use tcp_chat_proto::local::LocalConnector;
+use tcp_chat_proto::packet::{ClientMessage::{self, *}, ServerMessage::{self, *}};
+use tcp_chat_proto::tcp_conn::{RecvError, TcpConn, TcpConnBuilder};
+use tcp_chat_proto::transport::Socket;
+use tcp_chat_proto::typed_conn::{ClientConn, ClientReader, ClientWriter};
+
+use crate::constants::{MAX_WEBSOCKET_CONNECTIONS, MAX_WEBSOCKET_MESSAGE};
+
+
+
+/// Appended to the browser's key before hashing it, as RFC 6455 says
+const HANDSHAKE_GUID: &str = "258EAFA5-E914-47DA-95CA-C5AB0DC85B11";
+
+const OP_CONTINUATION: u8 = 0x0;
+const OP_TEXT: u8 = 0x1;
+const OP_BINARY: u8 = 0x2;
+const OP_CLOSE: u8 = 0x8;
+const OP_PING: u8 = 0x9;
+const OP_PONG: u8 = 0xA;
+
+/// Set in the opcodes of control frames (close, ping, and pong)
+const CONTROL_BIT: u8 = 0x8;
+
+/// The longest payload a control frame is allowed
+const MAX_CONTROL_PAYLOAD: u64 = 125;
+
+/// A whole message from the browser, put back together from its frames
+enum Message {
+    Text(String),
+    Binary(Vec<u8>),
+    Close,
+}
+
+/// Accept browsers forever, joining each one to the room through `room` in a thread of its own.
+/// Their sockets get the same options as everyone else's, from `socket`. Only pages from `origins`
+/// are let in, see `ServerConfig::websocket_origins`. Once `MAX_WEBSOCKET_CONNECTIONS` browsers are
+/// connected, the rest are turned away until some leave.
+pub fn accept_websockets(listener: TcpListener, room: LocalConnector, socket: TcpConnBuilder, origins: Vec<String>) {
+    let origins = Arc::new(origins);
+    let active = Arc::new(AtomicUsize::new(0));
+
+    for mut stream in listener.incoming().flatten() {
+        if active.fetch_add(1, Ordering::SeqCst) >= MAX_WEBSOCKET_CONNECTIONS {
+            active.fetch_sub(1, Ordering::SeqCst);
+            if let Err(e) = http::respond(&mut stream, "503 Service Unavailable") {
+                println!("[server] Unable to turn away a WebSocket connection: {e}");
+            }
+            continue;
+        }
+        if let Err(e) = socket.configure_tcp(&stream) {
+            active.fetch_sub(1, Ordering::SeqCst);
+            println!("[server] Unable to set up a WebSocket connection's socket: {e}");
+            continue;
+        }
+
+        let room = room.clone();
+        let origins = Arc::clone(&origins);
+        let active = Arc::clone(&active);
+        thread::Builder::new()
+            .name(String::from("server websocket thread"))
+            .spawn(move || {
+                let peer = stream.peer_addr();
+                match serve(stream, &room, &origins) {
+                    // browsers that close the tab without saying so just stop
+                    Err(e) if e.kind() != io::ErrorKind::UnexpectedEof => match peer {
+                        Ok(peer) => println!("[server] WebSocket connection from {peer} ended: {e}"),
+                        Err(_) => println!("[server] WebSocket connection ended: {e}"),
+                    },
+                    _ => {},
+                }
+                active.fetch_sub(1, Ordering::SeqCst);
+            })
+            .unwrap();
+    }
+}
+
+/// Upgrade `stream` to a WebSocket and pass messages between it and the room until either end
+/// goes away
+fn serve(mut stream: TcpStream, room: &LocalConnector, origins: &[String]) -> io::Result<()> {
+    let peer = stream.peer_addr()?;
+    let request = http::read_request(&mut stream)?;
+    let key = match handshake_key(&request, origins) {
+        Ok(key) => key,
+        Err(status) => return http::respond(&mut stream, status),
+    };
+    let response = format!(
+        "HTTP/1.1 101 Switching Protocols\r\nUpgrade: websocket\r\nConnection: Upgrade\r\nSec-WebSocket-Accept: {}\r\n\r\n",
+        accept_key(key)
+    );
+    stream.write_all(response.as_bytes())?;
+    stream.flush()?;
+    stream.set_read_timeout(None)?;
+
+    let mut conn = ClientConn::new(TcpConn::new(room.connect_from(peer)?)?);
+    let agreed = negotiate(&mut conn)?;
+    let (receiver, sender) = conn.split()?;
+    let browser = Arc::new(Mutex::new(stream.try_clone()?));
+    // none until the browser asks for some, the same as with the room
+    let wanted = Arc::new(Mutex::new(Capabilities::default()));
+
+    let forwarding = Arc::clone(&browser);
+    let forwarding_wanted = Arc::clone(&wanted);
+    thread::Builder::new()
+        .name(String::from("server websocket forwarding thread"))
+        .spawn(move || forward_to_browser(receiver, &forwarding, &forwarding_wanted))
+        .unwrap();
+
+    let result = forward_to_room(&mut stream, &browser, &sender, agreed, &wanted);
+
+    // the room hangs up once it hears this, which ends the forwarding thread too
+    let _ = sender.send(&ClientGoodbye);
+    result
+}
+
+/// Offer the room every capability the gateway knows, the same as a client connecting, and return
+/// the ones it agreed to. The gateway needs `Capability::Heartbeats` for itself, since it stands in
+/// for the browser's heartbeats whether or not the browser asks for them.
+///
+/// # Errors
+/// Fails if the room can't be reached or doesn't answer with `ServerHello`.
+fn negotiate(conn: &mut ClientConn<Json, Socket>) -> io::Result<Capabilities> {
+    conn.send(&ClientCapabilities(Capabilities::all().names()))?;
+    match conn.receive()? {
+        ServerHello(names) => Ok(Capabilities::from_names(&names)),
+        other => Err(io::Error::new(
+            io::ErrorKind::InvalidData,
+            format!("Expected the room to agree to capabilities, got `{other:?}`")
+        )),
+    }
+}
+
+/// The key the browser opened with, or the HTTP status to turn it away with if this isn't a
+/// WebSocket handshake from an allowed origin
+fn handshake_key<'a>(request: &'a Request, origins: &[String]) -> Result<&'a str, &'static str> {
+    if request.method != "GET" {
+        return Err("405 Method Not Allowed");
+    }
+    let upgrade = request.header("Upgrade").is_some_and(|v| v.eq_ignore_ascii_case("websocket"));
+    if !upgrade {
+        return Err("426 Upgrade Required");
+    }
+    if !origin_allowed(request, origins) {
+        return Err("403 Forbidden");
+    }
+    request.header("Sec-WebSocket-Key").ok_or("400 Bad Request")
+}
+
+/// Whether the page opening the WebSocket is one that's allowed to. Anything that isn't a browser
+/// doesn't send an origin, and isn't stopped here since it can't be made to connect by a page the
+/// way a browser can.
+fn origin_allowed(request: &Request, origins: &[String]) -> bool {
+    let Some(origin) = request.header("Origin") else {
+        return true;
+    };
+    let origin = origin.trim_end_matches('/');
+
+    if !origins.is_empty() {
+        return origins.iter().any(|allowed| allowed.trim_end_matches('/').eq_ignore_ascii_case(origin));
+    }
+
+    // a page from the same host and port the gateway was reached at
+    let origin_host = origin.strip_prefix("http://").or_else(|| origin.strip_prefix("https://"));
+    origin_host.zip(request.header("Host")).is_some_and(|(origin_host, host)| origin_host.eq_ignore_ascii_case(host))
+}
+
+/// What the server answers the browser's key with, to show it understood the handshake
+fn accept_key(key: &str) -> String {
+    let mut hasher = Sha1::new();
+    hasher.update(key.as_bytes());
+    hasher.update(HANDSHAKE_GUID.as_bytes());
+    STANDARD.encode(hasher.finalize())
+}
+
+/// Send the browser everything the room sends until the room hangs up, then close the WebSocket.
+/// Messages that only come with a capability are left out unless the browser is in `wanted`. The
+/// browser is pinged every `HEARTBEAT_INTERVAL` along the way, and its pongs stand in for the
+/// heartbeats the room expects, see `read_message`.
+fn forward_to_browser(mut receiver: ClientReader<Json, Socket>, browser: &Mutex<TcpStream>, wanted: &Mutex<Capabilities>) {
+    let mut last_ping = Instant::now();
+    loop {
+        if last_ping.elapsed() >= HEARTBEAT_INTERVAL {
+            if write_frame(&mut *browser.lock().unwrap(), OP_PING, &[]).is_err() {
+                return;
+            }
+            last_ping = Instant::now();
+        }
+
+        let msg = match receiver.receive_timeout(HEARTBEAT_INTERVAL.saturating_sub(last_ping.elapsed())) {
+            Ok(msg) => msg,
+            Err(RecvError::TimedOut | RecvError::Incomplete) => continue,
+            Err(_) => break,
+        };
+
+        if !wanted_by(*wanted.lock().unwrap(), &msg) {
+            continue;
+        }
+
+        // the browser can't be sent an attachment without its bytes, and there's no telling what
+        // comes next from the room if they can't be read, so the connection can't go on
+        let attachment = match msg {
+            ServerAttachment(..) => match receiver.receive_raw_on(ATTACHMENT_CHANNEL) {
+                Ok(data) => Some(data),
+                Err(_) => break,
+            },
+            _ => None,
+        };
+        let Ok(json) = serde_json::to_string(&msg) else { continue };
+
+        let mut browser = browser.lock().unwrap();
+        if write_frame(&mut *browser, OP_TEXT, json.as_bytes()).is_err() {
+            return;
+        }
+        if let Some(data) = attachment {
+            if write_frame(&mut *browser, OP_BINARY, &data).is_err() {
+                return;
+            }
+        }
+    }
+
+    let mut browser = browser.lock().unwrap();
+    let _ = write_frame(&mut *browser, OP_CLOSE, &[]);
+    let _ = browser.shutdown(Shutdown::Both);
+}
+
+/// Whether the browser asked for what `msg` comes with, if anything, out of `wanted`
+fn wanted_by(wanted: Capabilities, msg: &ServerMessage) -> bool {
+    match msg {
+        ServerLimits(_) => wanted.contains(Capability::Limits),
+        ServerAck(_) => wanted.contains(Capability::Acks),
+        ServerPong => wanted.contains(Capability::Heartbeats),
+        ServerWelcome(_) => wanted.contains(Capability::Welcome),
+        _ => true,
+    }
+}
+
+/// Send the room everything the browser sends until it closes the WebSocket. The browser's
+/// `ClientCapabilities` is answered here with the ones the room `agreed` to as well, which are
+/// kept in `wanted`.
+///
+/// # Errors
+/// Fails if the browser breaks the protocol, sends something that isn't a `ClientMessage`, or
+/// either end can't be reached.
+fn forward_to_room(
+    stream: &mut TcpStream,
+    browser: &Mutex<TcpStream>,
+    sender: &ClientWriter<Json, Socket>,
+    agreed: Capabilities,
+    wanted: &Mutex<Capabilities>
+) -> io::Result<()> {
+    let heartbeats = agreed.contains(Capability::Heartbeats);
+    loop {
+        let text = match read_message(stream, browser, sender, heartbeats)? {
+            Message::Text(text) => text,
+            Message::Binary(_) => return Err(protocol_error("Expected a text message")),
+            Message::Close => {
+                let _ = write_frame(&mut *browser.lock().unwrap(), OP_CLOSE, &[]);
+                return Ok(());
+            },
+        };
+
+        let msg: ClientMessage = serde_json::from_str(&text).map_err(|e| protocol_error(format!(
+            "Expected a `ClientMessage` as JSON: {e}"
+        )))?;
+
+        match msg {
+            ClientCapabilities(offered) => {
+                let both = Capabilities::from_names(&offered).intersection(agreed);
+                *wanted.lock().unwrap() = both;
+                let json = serde_json::to_string(&ServerHello(both.names())).map_err(io::Error::other)?;
+                write_frame(&mut *browser.lock().unwrap(), OP_TEXT, json.as_bytes())?;
+            },
+            ClientAttachment(_) => {
+                let Message::Binary(data) = read_message(stream, browser, sender, heartbeats)? else {
+                    return Err(protocol_error("Expected the attachment's bytes as a binary message"));
+                };
+                sender.send_with_raw(&msg, ATTACHMENT_CHANNEL, &data)?;
+            },
+            _ => sender.send(&msg)?,
+        }
+    }
+}
+
+/// Read frames from the browser until they make up a whole message. Pings are answered on the way,
+/// and pongs passed on to the room through `sender` as heartbeats, if it agreed to `heartbeats`.
+///
+/// # Errors
+/// Fails if the stream can't be read, or the frames aren't what a browser would send.
+fn read_message(
+    stream: &mut TcpStream,
+    browser: &Mutex<TcpStream>,
+    sender: &ClientWriter<Json, Socket>,
+    heartbeats: bool
+) -> io::Result<Message> {
+    let mut message = Vec::new();
+    let mut kind = None;
+
+    loop {
+        let mut head = [0u8; 2];
+        stream.read_exact(&mut head)?;
+        let fin = head[0] & 0x80 != 0;
+        let opcode = head[0] & 0x0F;
+
+        // browsers always mask what they send
+        if head[1] & 0x80 == 0 {
+            return Err(protocol_error("Frame isn't masked"));
+        }
+        let len = match head[1] & 0x7F {
+            126 => {
+                let mut len = [0u8; 2];
+                stream.read_exact(&mut len)?;
+                u16::from_be_bytes(len) as u64
+            },
+            127 => {
+                let mut len = [0u8; 8];
+                stream.read_exact(&mut len)?;
+                u64::from_be_bytes(len)
+            },
+            len => len as u64,
+        };
+        // control frames have to fit in one short frame, since they can come in the middle of a
+        // message
+        if opcode & CONTROL_BIT != 0 && (len > MAX_CONTROL_PAYLOAD || !fin) {
+            return Err(protocol_error(format!("Control frame {opcode:#x} is fragmented or too long")));
+        }
+        if len.saturating_add(message.len() as u64) > MAX_WEBSOCKET_MESSAGE as u64 {
+            return Err(protocol_error(format!("Message is over {MAX_WEBSOCKET_MESSAGE} bytes")));
+        }
+
+        let mut mask = [0u8; 4];
+        stream.read_exact(&mut mask)?;
+        // read as it arrives rather than making room for all of it up front, since the length is
+        // only the browser's word
+        let mut payload = Vec::new();
+        (&mut *stream).take(len).read_to_end(&mut payload)?;
+        if (payload.len() as u64) < len {
+            return Err(io::ErrorKind::UnexpectedEof.into());
+        }
+        for (i, byte) in payload.iter_mut().enumerate() {
+            *byte ^= mask[i % 4];
+        }
+
+        // control frames can come between the frames of a message
+        match opcode {
+            OP_PING => {
+                write_frame(&mut *browser.lock().unwrap(), OP_PONG, &payload)?;
+                continue;
+            },
+            OP_PONG => {
+                if heartbeats {
+                    sender.send(&ClientPing)?;
+                }
+                continue;
+            },
+            OP_CLOSE => return Ok(Message::Close),
+            OP_CONTINUATION if kind.is_some() => {},
+            OP_TEXT | OP_BINARY if kind.is_none() => kind = Some(opcode),
+            _ => return Err(protocol_error(format!("Unexpected frame type {opcode:#x}"))),
+        }
+
+        message.extend(payload);
+        if fin {
+            return match kind {
+                Some(OP_TEXT) => String::from_utf8(message)
+                    .map(Message::Text)
+                    .map_err(|_| protocol_error("Text message isn't UTF-8")),
+                _ => Ok(Message::Binary(message)),
+            };
+        }
+    }
+}
+
+/// Send `payload` to the browser as a single unmasked frame
+fn write_frame(stream: &mut impl Write, opcode: u8, payload: &[u8]) -> io::Result<()> {
+    let mut frame = Vec::with_capacity(payload.len() + 10);
+    frame.push(0x80 | opcode);
+    match payload.len() {
+        len @ 0..=125 => frame.push(len as u8),
+        len @ 126..=0xFFFF => {
+            frame.push(126);
+            frame.extend((len as u16).to_be_bytes());
+        },
+        len => {
+            frame.push(127);
+            frame.extend((len as u64).to_be_bytes());
+        },
+    }
+    frame.extend(payload);
+    stream.write_all(&frame)?;
+    stream.flush()
+}
+
+fn protocol_error(reason: impl Into<String>) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidData, reason.into())
+}
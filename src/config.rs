@@ -0,0 +1,60 @@
+use std::fs;
+use std::io;
+use std::path::PathBuf;
+
+use serde::Deserialize;
+
+use tcp_chat_server::ServerConfig;
+use tcp_chat_client::{ClientConfig, config_path, profile};
+
+
+
+/// A config file in the current directory, which takes priority over the one in the config
+/// directory unless a profile was chosen
+pub const LOCAL_CONFIG_PATH: &str = "tcp_chat.json";
+
+/// Settings read from the config file. Every field has a default so the file is optional and only
+/// needs to mention the settings someone actually wants to change.
+#[derive(Deserialize, Debug, Clone, Default)]
+#[serde(default)]
+pub struct Config {
+    pub server: ServerConfig,
+    pub client: ClientConfig,
+}
+
+impl Config {
+    /// Load the config file. A missing file is not a problem and just means the defaults are used,
+    /// but a file that exists and can't be parsed is reported so typos don't go unnoticed.
+    pub fn load() -> Self {
+        let Some(path) = Self::path() else {
+            return Self::default();
+        };
+
+        match fs::read_to_string(&path) {
+            Ok(contents) => serde_json::from_str(&contents).unwrap_or_else(|e| {
+                println!("[error] Unable to parse {}, using defaults. Reason: {e}", path.display());
+                Self::default()
+            }),
+            Err(e) if e.kind() == io::ErrorKind::NotFound => Self::default(),
+            Err(e) => {
+                println!("[error] Unable to read {}, using defaults. Reason: {e}", path.display());
+                Self::default()
+            }
+        }
+    }
+
+    /// Where to write a config file if there isn't one anywhere yet, which means this is the first
+    /// time tcp_chat is run with the current profile
+    pub fn first_run() -> Option<PathBuf> {
+        Self::path().filter(|path| !path.exists())
+    }
+
+    /// Which config file to read, if there's anywhere to look for one
+    fn path() -> Option<PathBuf> {
+        let local = PathBuf::from(LOCAL_CONFIG_PATH);
+        if profile().is_none() && local.exists() {
+            return Some(local);
+        }
+        config_path()
+    }
+}
@@ -1,48 +1,187 @@
-#![feature(fn_traits, const_socketaddr, core_intrinsics, iterator_try_collect, io_error_uncategorized)]
-
 use std::net::{TcpListener, TcpStream};
+use std::path::Path;
 use std::process::exit;
-use std::thread;
 
-use constants::*;
-use helpers::*;
-use result_repeat::*;
-use packet::Message::{self, *};
-use tcp_conn::TcpConn;
-use server::server;
-use client::client;
+use tcp_chat_proto::constants::*;
+use tcp_chat_proto::packet::{ClientMessage::{self, *}, ServerMessage::{self, *}};
+use tcp_chat_proto::tcp_conn::TcpConn;
+use tcp_chat_server::{replay, server, tracker};
+use tcp_chat_client::{client, interrupt, secret_store, set_profile, ClientConfig};
+use tcp_chat_client::constants::{EXIT_USAGE, EXIT_ERROR};
+use tcp_chat_client::helpers::*;
+use tcp_chat_client::result_repeat::*;
+use config::Config;
+
+mod config;
+mod wizard;
 
-mod constants;
-mod client;
-mod server;
-mod helpers;
-mod result_repeat;
-mod packet;
-mod tcp_conn;
-mod commands;
+/// Flags followed by a value, which isn't a positional argument of its own
+const FLAGS_WITH_VALUES: [&str; 5] = ["--profile", "--set-secret", "--delete-secret", "--replay", "--speed"];
 
 
 
 fn main() {
-    ctrlc::set_handler(|| exit(0)).expect("Unable to set Ctrl-C handler");
+    ctrlc::set_handler(|| interrupt()).expect("Unable to set Ctrl-C handler");
+
+    // a tracker only lists rooms, it doesn't take part in any
+    if std::env::args().any(|arg| arg == "--tracker") {
+        tracker::tracker(TRACKER_PORT);
+        return;
+    }
+
+    // everything else depends on which profile's config and data are used
+    if let Some(name) = flag_value("--profile") {
+        if let Err(e) = set_profile(&name) {
+            eprintln!("[error] {e}");
+            exit(EXIT_USAGE);
+        }
+    }
+
+    // secrets are managed on their own, without joining anything
+    if let Some(key) = flag_value("--set-secret") {
+        set_secret(&key);
+        return;
+    }
+    if let Some(key) = flag_value("--delete-secret") {
+        delete_secret(&key);
+        return;
+    }
+
+    // a replay runs its own server from the recording, nobody joins it
+    if let Some(path) = flag_value("--replay") {
+        replay_recording(&path);
+        return;
+    }
+
+    // scripts can't answer prompts, so everything needed to join is given up front
+    let json = std::env::args().any(|arg| arg == "--json");
+
+    if !json {
+        println!("Welcome to TCP chat!");
+        if let Some(path) = Config::first_run() {
+            wizard::run(&path);
+        }
+    }
+
+    let mut config = Config::load();
 
-    println!("Welcome to TCP chat!");
-    println!("Please enter your username");
-    let name = input();
+    // command line flags take priority over the config file
+    if std::env::args().any(|arg| arg == "--accessible") {
+        config.client.accessible = true;
+    }
+    // the QR code is just noise to a screen reader
+    if config.client.accessible {
+        config.server.show_qr = false;
+    }
+
+    let Config { server: mut server_config, client: client_config } = config;
+
+    if json {
+        let args = positional_args();
+        let [name, address] = &args[..] else {
+            eprintln!("Usage: tcp_chat [--profile <name>] --json <name> <address>");
+            exit(EXIT_USAGE);
+        };
+        client(name, None, Some(address), ClientConfig { json: true, ..client_config });
+        return;
+    }
+
+    let name = match client_config.name.clone() {
+        Some(name) => name,
+        None => {
+            println!("Please enter your username");
+            input()
+        },
+    };
 
     println!("Are you going to host the room? (y/n)");
 
     let will_host = input.until_valid(validate_yn).is_yes(); // traits are cool
 
-    // Spawn the server thread if user wishes to host
-    if will_host {
-        thread::Builder::new()
-            .name(String::from("server main"))
-            .spawn(server)
-            .unwrap();
-    }
+    // Start the server if user wishes to host
+    let host = will_host.then(|| {
+        let secrets = secret_store();
+        for webhook in &mut server_config.incoming_webhooks {
+            webhook.load_token(&secrets);
+        }
+        server_config.load_noise_key(&secrets);
+        server(server_config).connector()
+    });
     
-    client(name.as_str(), will_host);
+    client(name.as_str(), host, None, client_config);
+}
+
+
+/// The argument following `flag`, if it was given
+fn flag_value(flag: &str) -> Option<String> {
+    let mut args = std::env::args();
+    args.position(|arg| arg == flag)?;
+    let Some(value) = args.next() else {
+        eprintln!("Usage: tcp_chat {flag} <key>");
+        exit(EXIT_USAGE);
+    };
+    Some(value)
+}
+
+/// The arguments that aren't flags or the value of one
+fn positional_args() -> Vec<String> {
+    let mut positional = Vec::new();
+    let mut args = std::env::args().skip(1);
+    while let Some(arg) = args.next() {
+        if FLAGS_WITH_VALUES.contains(&arg.as_str()) {
+            args.next();
+        } else if !arg.starts_with("--") {
+            positional.push(arg);
+        }
+    }
+    positional
+}
+
+/// Play a recorded session into a fresh server, as fast as `--speed` says (10 times as fast
+/// unless given)
+fn replay_recording(path: &str) {
+    let speed = match flag_value("--speed").map(|speed| speed.parse::<f64>()) {
+        None => 10.0,
+        Some(Ok(speed)) if speed > 0.0 => speed,
+        Some(_) => {
+            eprintln!("[error] The speed has to be a number above 0, like 10 or 0.5");
+            exit(EXIT_USAGE);
+        },
+    };
+
+    if let Err(e) = replay::replay(Path::new(path), speed, Config::load().server) {
+        eprintln!("[error] Unable to replay {path}: {e}");
+        exit(EXIT_ERROR);
+    }
+}
+
+/// Read a secret from stdin and store it under `key`, so it never has to be written in the config
+fn set_secret(key: &str) {
+    println!("Enter the secret to store as {key}");
+    let secret = input();
+    if secret.is_empty() {
+        eprintln!("[error] No secret given, nothing was stored");
+        exit(EXIT_USAGE);
+    }
+
+    match secret_store().set(key, &secret) {
+        Ok(place) => println!("Stored {key} in {place}"),
+        Err(e) => {
+            eprintln!("[error] Unable to store {key}: {e}");
+            exit(EXIT_ERROR);
+        },
+    }
+}
+
+fn delete_secret(key: &str) {
+    match secret_store().delete(key) {
+        Ok(true) => println!("Deleted {key}"),
+        Ok(false) => println!("There was no secret stored as {key}"),
+        Err(e) => {
+            eprintln!("[error] Unable to delete {key}: {e}");
+            exit(EXIT_ERROR);
+        },
+    }
 }
 
 
@@ -58,8 +197,8 @@ fn demonstrate() -> Result<(), Box<dyn std::error::Error>> {
 
         conn.send(&ClientText(String::from("Hello, server! I am sending this to you because it is a really long message and I just wanted to see if you like that I'm sending long messages. Also, I just wanted to tell you that I kind of like the way that you send me handshake messages and I was kind of um wondering if you would like to maybe possibly consider entering a long-term connection with me. Thanks bye.")))?;
 
-        let msg1: Message = conn.receive()?;
-        let msg2: Message = conn.receive()?;
+        let msg1: ServerMessage = conn.receive()?;
+        let msg2: ServerMessage = conn.receive()?;
         println!("{:?}\n{:?}", msg1, msg2);
 
     } else {
@@ -69,7 +208,7 @@ fn demonstrate() -> Result<(), Box<dyn std::error::Error>> {
         let (stream, _) = listen.accept()?;
         let mut conn = TcpConn::new(stream)?;
 
-        let client_message: Message = conn.receive()?;
+        let client_message: ClientMessage = conn.receive()?;
 
         println!("{:?}", client_message);
 
@@ -0,0 +1,81 @@
+// The first time tcp_chat runs (per profile), it asks for the handful of settings most people want
+// to change and writes them to a config file, instead of asking for a username every single run.
+
+use std::fs;
+use std::path::Path;
+
+use serde_json::{json, Map, Value};
+
+use tcp_chat_client::{secret_store, Identity};
+use tcp_chat_client::helpers::*;
+use tcp_chat_client::result_repeat::*;
+
+
+
+/// Ask the first-run questions and write the answers to a new config file at `path`. Anything not
+/// asked about is left out of the file and keeps its default.
+pub fn run(path: &Path) {
+    println!("Since this is your first time, let's set a few things up.");
+
+    println!("What username should you join rooms with?");
+    let name = input.until_valid(|name: &String| {
+        if name.is_empty() {
+            println!("Your username can't be empty");
+        }
+        !name.is_empty()
+    });
+
+    println!("Show messages in color? (y/n)");
+    let color = input.until_valid(validate_yn).is_yes();
+
+    let mut notifications = Map::new();
+    println!("Ring the terminal bell when someone mentions you? (y/n)");
+    if input.until_valid(validate_yn).is_yes() {
+        notifications.insert(String::from("on_mention"), json!({ "bell": 1 }));
+    }
+    println!("Ring it for messages sent to you alone? (y/n)");
+    if input.until_valid(validate_yn).is_yes() {
+        notifications.insert(String::from("on_dm"), json!({ "bell": 1 }));
+    }
+
+    println!("Generate an identity keypair? Nothing requires one yet. (y/n)");
+    if input.until_valid(validate_yn).is_yes() {
+        generate_identity();
+    }
+
+    let config = json!({
+        "client": {
+            "name": name,
+            "color": color,
+            "notifications": Value::Object(notifications),
+        }
+    });
+
+    let written = path.parent().map_or(Ok(()), fs::create_dir_all)
+        .and_then(|_| fs::write(path, serde_json::to_string_pretty(&config).unwrap()));
+    match written {
+        Ok(()) => println!("Saved your settings to {}. Edit it any time to change them.", path.display()),
+        Err(e) => println!("[error] Unable to save your settings to {}: {e}", path.display()),
+    }
+}
+
+/// Make a new identity and keep it in the secret store, unless there already is one
+fn generate_identity() {
+    let secrets = secret_store();
+    if let Ok(Some(identity)) = Identity::load(&secrets) {
+        println!("You already have an identity: {}", identity.fingerprint());
+        return;
+    }
+
+    let saved = Identity::generate().and_then(|identity| {
+        let place = identity.save(&secrets)?;
+        Ok((identity, place))
+    });
+    match saved {
+        Ok((identity, place)) => {
+            println!("Your identity is {}", identity.fingerprint());
+            println!("Its private key is kept in {place}");
+        },
+        Err(e) => println!("[error] Unable to create an identity: {e}"),
+    }
+}